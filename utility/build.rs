@@ -2,6 +2,9 @@ use std::env;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
+#[path = "../wm-common/build_info.rs"]
+mod build_info;
+
 #[allow(dead_code)]
 struct CommonPaths {
     pub project_dir: PathBuf,
@@ -106,6 +109,7 @@ fn create_client_certificate(paths: &CommonPaths) {
 
 fn main() {
     let paths = CommonPaths::new();
+    build_info::emit(&paths.workspace_dir);
 
     create_client_certificate(&paths);
 }
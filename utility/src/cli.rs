@@ -1,11 +1,13 @@
 use clap::{Parser, Subcommand, crate_description, crate_version};
 use reqwest::Url;
+use wm_common::version_info;
 
 #[derive(Debug, Parser)]
 #[command(
     long_about = crate_description!(),
     propagate_version = true,
     version = crate_version!(),
+    long_version = version_info!(),
 )]
 pub struct Arguments {
     #[command(subcommand)]
@@ -47,4 +49,7 @@ pub enum Utility {
         /// The name of the Registry entry to update
         key_name: String,
     },
+
+    /// Print the version, git commit, build time and target triple
+    Version,
 }
@@ -33,7 +33,9 @@ impl EventGenerator {
                 },
                 CPUInfo {
                     usage: (index as f64 % 100.0).max(0.1),
+                    cores: vec![],
                 },
+                None,
                 format!("x86_64-{}", index % 10),
                 format!("DESKTOP-{:06X}", index),
             ));
@@ -67,6 +69,7 @@ impl EventGenerator {
                     image_size: 0x100000 + (index * 0x1000),
                     image_checksum: (index as u32).wrapping_mul(31),
                     file_name: format!("C:\\Program Files\\app_{}.dll", index),
+                    sha256: None,
                 },
                 5 => EventData::Process {
                     unique_process_key: 0x5000 + index,
@@ -77,6 +80,8 @@ impl EventGenerator {
                     directory_table_base: 0x6000 + index,
                     image_file_name: format!("process_{}.exe", index),
                     command_line: format!("process_{}.exe --arg{}", index, index),
+                    sha256: None,
+                    command_line_sha256: None,
                 },
                 _ => EventData::Registry {
                     initial_time: 132000000000000000 + (index as i64 * 10000000),
@@ -95,6 +100,7 @@ impl EventGenerator {
                 event_id: (index as u16 % 1000) + 1,
                 opcode: (index as u8 % 100) + 1,
                 data: event_data,
+                user: None,
             };
 
             let captured_event = CapturedEventRecord {
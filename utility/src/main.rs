@@ -17,6 +17,7 @@ use utility::cli::{Arguments, Utility};
 use utility::generator::EventGenerator;
 use wm_common::registry::RegistryKey;
 use wm_common::utils::to_c_string;
+use wm_common::version_info;
 
 async fn request(
     client: Client,
@@ -186,6 +187,7 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
             key.store(env!("WINDOWS_MONITOR_PASSWORD").as_bytes())
                 .expect("Failed to store registry value");
         }
+        Utility::Version => println!("{} {}", env!("CARGO_PKG_NAME"), version_info!()),
     }
 
     Ok(())
@@ -1,6 +1,9 @@
 use std::path::Path;
 use std::{env, fs};
 
+#[path = "../wm-common/build_info.rs"]
+mod build_info;
+
 fn main() {
     let env_cargo_manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
     let env_profile = env::var("PROFILE").unwrap();
@@ -17,4 +20,6 @@ fn main() {
         println!("cargo:rerun-if-changed={}", source.display());
         fs::copy(&source, exe_dir.join(source.file_name().unwrap())).unwrap();
     }
+
+    build_info::emit(workspace_dir);
 }
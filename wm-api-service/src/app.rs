@@ -5,35 +5,154 @@ use std::io;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use http_body_util::combinators::BoxBody;
-use hyper::StatusCode;
 use hyper::body::{Bytes, Incoming};
+use hyper::header::{ALLOW, HeaderValue};
 use hyper::service::service_fn;
-use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper::{Method, Response, StatusCode};
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
 use hyper_util::server::conn::auto::Builder;
-use lapin::options::QueueDeclareOptions;
-use lapin::types::FieldTable;
-use log::{debug, error, info};
-use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use lapin::options::{ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions};
+use lapin::types::{AMQPValue, FieldTable};
+use lapin::{BasicProperties, ExchangeKind};
+use log::{debug, error, info, warn};
+use rustls::pki_types::{CertificateDer, CertificateRevocationListDer, PrivateKeyDer};
 use rustls::server::WebPkiClientVerifier;
 use rustls::{RootCertStore, ServerConfig};
 use tokio::net::TcpListener;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{interval, sleep, timeout};
 use tokio::{signal, task};
 use tokio_rustls::TlsAcceptor;
+use wm_common::cipher::FrameCipher;
+use wm_common::error::RuntimeError;
 use wm_common::once_cell_no_retry::OnceCellNoRetry;
+use wm_common::telemetry::{Span, TRACEPARENT_HEADER};
 
 use crate::configuration::Configuration;
+use crate::forwarded::{self, IpNetwork};
+use crate::publisher::Publisher;
+use crate::request_id::RequestId;
 use crate::responses::ResponseBuilder;
 use crate::routes::abc::Service;
 use crate::routes::backup::BackupService;
 use crate::routes::health_check::HealthCheckService;
+use crate::routes::heartbeat::HeartbeatService;
+use crate::routes::livez::LivenessService;
+use crate::routes::readyz::ReadinessService;
 use crate::routes::trace::TraceService;
 
+/// Requests of a single connection, used to close connections that stay idle for too long.
+struct _ConnectionActivity {
+    _started: Instant,
+    _in_flight: AtomicUsize,
+
+    /// Milliseconds since `_started` at which the last request completed
+    _last_active: AtomicU64,
+}
+
+impl _ConnectionActivity {
+    fn new() -> Self {
+        Self {
+            _started: Instant::now(),
+            _in_flight: AtomicUsize::new(0),
+            _last_active: AtomicU64::new(0),
+        }
+    }
+
+    /// Mark a request as in flight until the returned guard is dropped, which also happens when
+    /// the request is cancelled.
+    fn begin(self: &Arc<Self>) -> _ActiveRequest {
+        self._in_flight.fetch_add(1, Ordering::Relaxed);
+        _ActiveRequest(self.clone())
+    }
+
+    /// Time since the last request completed, or zero while a request is in flight.
+    fn idle(&self) -> Duration {
+        if self._in_flight.load(Ordering::Relaxed) > 0 {
+            return Duration::ZERO;
+        }
+
+        self._started
+            .elapsed()
+            .saturating_sub(Duration::from_millis(
+                self._last_active.load(Ordering::Relaxed),
+            ))
+    }
+}
+
+struct _ActiveRequest(Arc<_ConnectionActivity>);
+
+impl Drop for _ActiveRequest {
+    fn drop(&mut self) {
+        let activity = &self.0;
+        activity._last_active.store(
+            activity._started.elapsed().as_millis() as u64,
+            Ordering::Relaxed,
+        );
+        activity._in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Resources held while a request is served: its activity on the connection and the permit of its
+/// route.
+///
+/// Services that keep working after responding take a clone of the guard from the request
+/// extensions, so that the resources are only released once that work is done.
+#[derive(Clone)]
+pub struct RequestGuard(Arc<_RequestResources>);
+
+struct _RequestResources {
+    _active: _ActiveRequest,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl RequestGuard {
+    fn new(active: _ActiveRequest, permit: Option<OwnedSemaphorePermit>) -> Self {
+        Self(Arc::new(_RequestResources {
+            _active: active,
+            _permit: permit,
+        }))
+    }
+}
+
+/// Find the service serving `method` requests to `path`, or the response rejecting the request.
+fn _route(
+    services: &HashMap<String, Arc<dyn Service>>,
+    path: &str,
+    method: &Method,
+) -> Result<Arc<dyn Service>, Response<BoxBody<Bytes, hyper::Error>>> {
+    match services.get(path) {
+        Some(service) if service.methods().contains(method) => Ok(service.clone()),
+        Some(service) => Err(App::_method_not_allowed(service.methods())),
+        None => Err(ResponseBuilder::default(StatusCode::NOT_FOUND)),
+    }
+}
+
+/// Routes also served without TLS on the probe port, for orchestrators that cannot present a
+/// client certificate.
+const _PROBE_ROUTES: [&str; 2] = ["/livez", "/readyz"];
+
 pub struct App {
     _config: Arc<Configuration>,
     _services: HashMap<String, Arc<dyn Service>>,
     _rabbitmq: OnceCellNoRetry<Arc<lapin::Channel>>,
+    _publisher: Option<Arc<dyn Publisher>>,
+    _backup_cipher: Option<FrameCipher>,
+    _trusted_proxies: Vec<IpNetwork>,
+
+    /// Prefix of connection ids, distinguishing connections across restarts of the service
+    _instance_id: String,
+    _connections_count: AtomicU64,
+
+    /// Permits of concurrently served connections
+    _connection_permits: Arc<Semaphore>,
+
+    /// Permits of concurrently served requests of routes with a concurrency limit
+    _route_permits: HashMap<String, Arc<Semaphore>>,
 }
 
 impl App {
@@ -57,6 +176,78 @@ impl App {
         rustls_pemfile::private_key(&mut reader).map(|key| key.unwrap())
     }
 
+    /// Load certificate revocation lists from file.
+    fn _load_crls(filename: &PathBuf) -> io::Result<Vec<CertificateRevocationListDer<'static>>> {
+        let crlfile = File::open(filename)?;
+        let mut reader = io::BufReader::new(crlfile);
+        rustls_pemfile::crls(&mut reader).collect()
+    }
+
+    /// Build the TLS acceptor from the certificate, private key, client CAs and revocation lists
+    /// currently on disk.
+    fn _load_tls(&self) -> Result<TlsAcceptor, Box<dyn Error + Send + Sync>> {
+        let certs = Self::_load_certs(&self._config.certificate)
+            .map_err(|e| RuntimeError::new(format!("Failed to load certificate: {e}")))?;
+        let key = Self::_load_private_key(&self._config.private_key)
+            .map_err(|e| RuntimeError::new(format!("Failed to load private key: {e}")))?;
+
+        let client_roots = match &self._config.client_ca_bundle {
+            Some(bundle) => {
+                let mut roots = RootCertStore::empty();
+                let (added, ignored) =
+                    roots.add_parsable_certificates(Self::_load_certs(bundle).map_err(|e| {
+                        RuntimeError::new(format!("Failed to load client CA bundle: {e}"))
+                    })?);
+                if ignored > 0 {
+                    warn!("Ignored {ignored} unparsable certificate(s) in the client CA bundle");
+                }
+                if added == 0 {
+                    Err(RuntimeError::new(
+                        "Client CA bundle does not contain any usable certificate",
+                    ))?;
+                }
+
+                debug!("Trusting {added} client CA(s) from {}", bundle.display());
+                roots
+            }
+            None => {
+                let root_ca =
+                    webpki::anchor_from_trusted_cert(certs.last().ok_or_else(|| {
+                        RuntimeError::new("There should be at least 1 certificate")
+                    })?)
+                    .map_err(|e| RuntimeError::new(format!("Failed to create root CA: {e}")))?
+                    .to_owned();
+
+                RootCertStore {
+                    roots: vec![root_ca],
+                }
+            }
+        };
+
+        let mut crls = vec![];
+        for path in &self._config.client_crls {
+            crls.extend(Self::_load_crls(path).map_err(|e| {
+                RuntimeError::new(format!("Failed to load CRL {}: {e}", path.display()))
+            })?);
+        }
+
+        let mut verifier = WebPkiClientVerifier::builder(Arc::new(client_roots));
+        if !crls.is_empty() {
+            debug!("Checking client certificates against {} CRL(s)", crls.len());
+
+            // Revoking a host's certificate is what matters, intermediate CAs are not expected to
+            // be covered by the lists
+            verifier = verifier.with_crls(crls).only_check_end_entity_revocation();
+        }
+
+        let mut cfg = ServerConfig::builder()
+            .with_client_cert_verifier(verifier.build()?)
+            .with_single_cert(certs, key)?;
+        cfg.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec(), b"http/1.0".to_vec()];
+
+        Ok(TlsAcceptor::from(Arc::new(cfg)))
+    }
+
     async fn _initialize_rabbitmq(
         &self,
     ) -> Result<Arc<lapin::Channel>, Box<dyn Error + Send + Sync>> {
@@ -80,6 +271,29 @@ impl App {
                     auto_delete: false,
                     nowait: false,
                 },
+                self._config.rabbitmq.queue_arguments(),
+            )
+            .await?;
+
+        // Publishers go through a fanout exchange, so that `tail` can subscribe its own queue to
+        // the events without taking them away from the data service
+        rabbitmq
+            .exchange_declare(
+                "events",
+                ExchangeKind::Fanout,
+                ExchangeDeclareOptions {
+                    durable: true,
+                    ..ExchangeDeclareOptions::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+        rabbitmq
+            .queue_bind(
+                "events",
+                "events",
+                "",
+                QueueBindOptions::default(),
                 FieldTable::default(),
             )
             .await?;
@@ -89,27 +303,79 @@ impl App {
     }
 
     pub fn new(config: Arc<Configuration>) -> Arc<Self> {
+        Self::_new(config, None)
+    }
+
+    /// Construct an application publishing messages to `publisher` instead of RabbitMQ.
+    #[cfg(test)]
+    pub fn with_publisher(config: Arc<Configuration>, publisher: Arc<dyn Publisher>) -> Arc<Self> {
+        Self::_new(config, Some(publisher))
+    }
+
+    fn _new(config: Arc<Configuration>, publisher: Option<Arc<dyn Publisher>>) -> Arc<Self> {
         let mut services = HashMap::new();
 
         for service in [
             Arc::new(BackupService {}) as Arc<dyn Service>,
             Arc::new(HealthCheckService {}) as Arc<dyn Service>,
+            Arc::new(HeartbeatService {}) as Arc<dyn Service>,
+            Arc::new(LivenessService {}) as Arc<dyn Service>,
+            Arc::new(ReadinessService {}) as Arc<dyn Service>,
             Arc::new(TraceService {}) as Arc<dyn Service>,
         ] {
             services.insert(service.route().to_string(), service);
         }
 
+        let backup_cipher = config
+            .backup_encryption_key
+            .as_deref()
+            .map(FrameCipher::from_hex)
+            .transpose()
+            .expect("Invalid backup encryption key");
+
+        let trusted_proxies = config
+            .trusted_proxies
+            .iter()
+            .map(|proxy| proxy.parse::<IpNetwork>())
+            .collect::<Result<Vec<_>, _>>()
+            .expect("Invalid trusted proxy");
+
+        let connection_permits = Arc::new(Semaphore::new(config.max_connections));
+        let mut route_permits = HashMap::new();
+        for (route, limit) in &config.route_concurrency_limits {
+            if services.contains_key(route) {
+                route_permits.insert(route.clone(), Arc::new(Semaphore::new(*limit)));
+            } else {
+                warn!("Ignoring concurrency limit of unknown route {route}");
+            }
+        }
+
         let this = Arc::new(Self {
             _config: config,
             _services: services,
             _rabbitmq: OnceCellNoRetry::new(),
+            _publisher: publisher,
+            _backup_cipher: backup_cipher,
+            _trusted_proxies: trusted_proxies,
+            _instance_id: format!(
+                "{:x}",
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis()
+            ),
+            _connections_count: AtomicU64::new(0),
+            _connection_permits: connection_permits,
+            _route_permits: route_permits,
         });
 
         // Try initializing RabbitMQ connection
-        let this_cloned = this.clone();
-        tokio::spawn(async move {
-            let _ = this_cloned.rabbitmq().await;
-        });
+        if this._publisher.is_none() {
+            let this_cloned = this.clone();
+            tokio::spawn(async move {
+                let _ = this_cloned.rabbitmq().await;
+            });
+        }
 
         this
     }
@@ -126,79 +392,356 @@ impl App {
             .cloned()
     }
 
+    /// Destination of published messages, which is RabbitMQ unless overridden.
+    pub async fn publisher(&self) -> Option<Arc<dyn Publisher>> {
+        match &self._publisher {
+            Some(publisher) => Some(publisher.clone()),
+            None => self
+                .rabbitmq()
+                .await
+                .map(|rabbitmq| rabbitmq as Arc<dyn Publisher>),
+        }
+    }
+
+    fn _method_not_allowed(methods: &[Method]) -> Response<BoxBody<Bytes, hyper::Error>> {
+        let mut response = ResponseBuilder::default(StatusCode::METHOD_NOT_ALLOWED);
+        let allow = methods
+            .iter()
+            .map(Method::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+        if let Ok(value) = HeaderValue::from_str(&allow) {
+            response.headers_mut().insert(ALLOW, value);
+        }
+
+        response
+    }
+
+    /// Seconds clients should wait before retrying a request the server is too busy for,
+    /// growing with the share of connection permits in use.
+    pub fn retry_after_seconds(&self) -> u64 {
+        let max_connections = self._config.max_connections;
+        let in_use = max_connections.saturating_sub(self._connection_permits.available_permits());
+        let load = in_use as f64 / max_connections as f64;
+
+        let min = self._config.retry_after.min_seconds;
+        let max = self._config.retry_after.max_seconds;
+        min + ((max - min) as f64 * load).round() as u64
+    }
+
+    pub fn max_event_bytes(&self) -> usize {
+        self._config.max_event_bytes
+    }
+
+    pub fn backup_cipher(&self) -> Option<&FrameCipher> {
+        self._backup_cipher.as_ref()
+    }
+
+    /// Properties of messages published while serving a request, tying them to the request, to
+    /// the collector that received them and to the publishing `span`.
+    pub fn message_properties(&self, request_id: &RequestId, span: &Span) -> BasicProperties {
+        let mut properties =
+            BasicProperties::default().with_correlation_id(request_id.as_str().into());
+        if let Some(collector_id) = &self._config.collector_id {
+            properties = properties.with_app_id(collector_id.as_str().into());
+        }
+
+        // Lets the data service link its bulk requests to the spans of this service
+        if let Some(traceparent) = span.traceparent() {
+            let mut headers = FieldTable::default();
+            headers.insert(
+                TRACEPARENT_HEADER.into(),
+                AMQPValue::LongString(traceparent.into()),
+            );
+            properties = properties.with_headers(headers);
+        }
+
+        properties
+    }
+
+    /// HTTP connection builder timing out clients that are too slow to send request headers.
+    fn _connection_builder(&self) -> Builder<TokioExecutor> {
+        let mut builder = Builder::new(TokioExecutor::new());
+        builder
+            .http1()
+            .timer(TokioTimer::new())
+            .header_read_timeout(Duration::from_secs(
+                self._config.request_header_timeout_seconds,
+            ));
+        builder
+    }
+
+    /// Serve [`_PROBE_ROUTES`] over plain HTTP on `listener`, until the task is aborted.
+    async fn _serve_probes(self: Arc<Self>, listener: TcpListener) {
+        let builder = self._connection_builder();
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Unable to accept probe connection: {e}");
+                    continue;
+                }
+            };
+
+            // Probe connections count towards the same limit as the API connections
+            let Ok(permit) = self._connection_permits.clone().try_acquire_owned() else {
+                debug!("Dropped probe connection {peer}");
+                continue;
+            };
+
+            let ptr = self.clone();
+            let service = service_fn(move |request: hyper::Request<Incoming>| {
+                let path = request.uri().path();
+                let routed = if _PROBE_ROUTES.contains(&path) {
+                    _route(&ptr._services, path, request.method())
+                } else {
+                    Err(ResponseBuilder::default(StatusCode::NOT_FOUND))
+                };
+
+                let ptr = ptr.clone();
+                async move {
+                    let response = match routed {
+                        Ok(service) => service.serve(ptr, peer, request).await,
+                        Err(response) => response,
+                    };
+
+                    Ok::<hyper::Response<BoxBody<Bytes, hyper::Error>>, hyper::Error>(response)
+                }
+            });
+
+            let builder = builder.clone();
+            task::spawn(async move {
+                // Hold the permit until the connection is closed
+                let _permit = permit;
+                if let Err(e) = builder
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    debug!("Error serving probe connection {peer}: {e}");
+                }
+            });
+        }
+    }
+
     pub async fn run(self: &Arc<Self>) -> Result<(), Box<dyn Error + Send + Sync>> {
         let addr = SocketAddr::from(([0, 0, 0, 0], self._config.port));
-        let certs =
-            Self::_load_certs(&self._config.certificate).expect("Failed to load certificate");
-        let key =
-            Self::_load_private_key(&self._config.private_key).expect("Failed to load private key");
-
-        let root_ca = webpki::anchor_from_trusted_cert(
-            certs
-                .last()
-                .expect("There should be at least 1 certificate"),
-        )
-        .expect("Failed to create root CA")
-        .to_owned();
-
         let listener = TcpListener::bind(addr).await?;
-        let mut cfg = ServerConfig::builder()
-            .with_client_cert_verifier(
-                WebPkiClientVerifier::builder(Arc::new(RootCertStore {
-                    roots: vec![root_ca],
-                }))
-                .build()
-                .expect("Unable to create WebPkiClientVerifier"),
-            )
-            .with_single_cert(certs, key)?;
-        cfg.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec(), b"http/1.0".to_vec()];
 
-        let tls = TlsAcceptor::from(Arc::new(cfg));
+        let probes = match self._config.probe_port {
+            Some(port) => {
+                let addr = SocketAddr::from(([0, 0, 0, 0], port));
+                let listener = TcpListener::bind(addr).await?;
+                info!("Serving liveness and readiness probes at http://{addr}");
+                Some(task::spawn(self.clone()._serve_probes(listener)))
+            }
+            None => None,
+        };
+
+        self.serve(listener, async {
+            if let Err(e) = signal::ctrl_c().await {
+                error!("Unable to listen for Ctrl+C signal: {e}");
+            }
+
+            info!("Received Ctrl+C signal");
+        })
+        .await?;
+
+        if let Some(probes) = probes {
+            probes.abort();
+        }
+
+        Ok(())
+    }
+
+    /// Serve requests from an already bound `listener` until `shutdown` completes.
+    ///
+    /// Unlike [`App::run`], the listener may be bound to an ephemeral port, which allows
+    /// embedding the server in a test harness.
+    pub async fn serve<F>(
+        self: &Arc<Self>,
+        listener: TcpListener,
+        shutdown: F,
+    ) -> Result<(), Box<dyn Error + Send + Sync>>
+    where
+        F: Future<Output = ()>,
+    {
+        let mut tls = self._load_tls()?;
+        let handshake_timeout = Duration::from_secs(self._config.tls_handshake_timeout_seconds);
+        let idle_timeout = Duration::from_secs(self._config.connection_idle_timeout_seconds);
+
+        let mut builder = self._connection_builder();
+
+        // Detect HTTP/2 peers that vanished without closing the connection
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .keep_alive_interval(idle_timeout);
 
+        // Whether the last connection was dropped due to the connection limit, so that a flood of
+        // connections is logged only once
+        let mut saturated = false;
+
+        // Revocation lists are republished periodically, pick up new revocations without a restart
+        let mut crl_reload = interval(Duration::from_secs(
+            self._config.crl_reload_interval_seconds,
+        ));
+        crl_reload.tick().await;
+
+        tokio::pin!(shutdown);
         loop {
             tokio::select! {
-                _ = signal::ctrl_c() => {
-                    info!("Received Ctrl+C signal");
+                _ = &mut shutdown => {
+                    info!("Shutting down server");
                     break;
                 }
+                _ = crl_reload.tick(), if !self._config.client_crls.is_empty() => {
+                    match self._load_tls() {
+                        Ok(acceptor) => {
+                            debug!("Reloaded client certificate revocation lists");
+                            tls = acceptor;
+                        }
+                        Err(e) => error!("Unable to reload TLS configuration, keeping the previous one: {e}"),
+                    }
+                }
                 Ok((stream, peer)) = listener.accept() => {
-                    debug!("New connection {peer}");
+                    let permit = match self._connection_permits.clone().try_acquire_owned() {
+                        Ok(permit) => {
+                            if saturated {
+                                info!("Accepting connections again");
+                                saturated = false;
+                            }
+
+                            permit
+                        }
+                        Err(_) => {
+                            if !saturated {
+                                warn!(
+                                    "Reached the limit of {} connections, dropping new connections",
+                                    self._config.max_connections
+                                );
+                                saturated = true;
+                            }
+
+                            debug!("Dropped connection {peer}");
+                            continue;
+                        }
+                    };
+
+                    let connection_id = format!(
+                        "{}.{:x}",
+                        self._instance_id,
+                        self._connections_count.fetch_add(1, Ordering::Relaxed),
+                    );
+                    debug!("New connection {peer} ({connection_id})");
                     let tls = tls.clone();
+                    let builder = builder.clone();
 
                     let ptr = self.clone();
-                    let service = service_fn(move |request: hyper::Request<Incoming>| {
+                    let requests_count = Arc::new(AtomicU64::new(0));
+                    let activity = Arc::new(_ConnectionActivity::new());
+                    let activity_cloned = activity.clone();
+                    let service = service_fn(move |mut request: hyper::Request<Incoming>| {
                         let path = request.uri().path().to_string();
                         let method = request.method().clone();
-                        let service = ptr._services.get(&path).cloned();
+                        let routed = _route(&ptr._services, &path, &method);
+                        let route_permits = ptr._route_permits.get(&path).cloned();
+
+                        // Behind a trusted reverse proxy, events are attributed to the client
+                        let peer = SocketAddr::new(
+                            forwarded::client_ip(
+                                peer.ip(),
+                                request.headers(),
+                                ptr._config.forwarding_header,
+                                &ptr._trusted_proxies,
+                            ),
+                            peer.port(),
+                        );
+
+                        let request_id = RequestId::new(
+                            &connection_id,
+                            requests_count.fetch_add(1, Ordering::Relaxed),
+                        );
+                        request.extensions_mut().insert(request_id.clone());
 
                         let ptr = ptr.clone();
+                        let active = activity_cloned.begin();
                         async move {
-                            let response = if let Some(service) = service {
-                                service.serve(ptr, peer, request).await
-                            } else {
-                                ResponseBuilder::default(StatusCode::NOT_FOUND)
+                            let mut span = Span::start("request");
+                            span.set_str("request_id", request_id.as_str());
+                            span.set_str("http.method", method.as_str());
+                            span.set_str("http.route", path.as_str());
+
+                            let mut response = match routed {
+                                Ok(service) => {
+                                    // Hold the permit of a limited route until the request is served
+                                    match route_permits.map(Semaphore::try_acquire_owned).transpose() {
+                                        Ok(permit) => {
+                                            let guard = RequestGuard::new(active, permit);
+                                            request.extensions_mut().insert(guard.clone());
+                                            let response = service.serve(ptr, peer, request).await;
+                                            drop(guard);
+                                            response
+                                        }
+                                        Err(_) => {
+                                            debug!("[{request_id}] Too many concurrent requests to {path}");
+                                            ResponseBuilder::unavailable(ptr.retry_after_seconds())
+                                        }
+                                    }
+                                }
+                                Err(response) => response,
                             };
 
-                            debug!("[{} {}] {}", method, path, response.status());
+                            if let Ok(value) = HeaderValue::from_str(request_id.as_str()) {
+                                response.headers_mut().insert("X-Request-Id", value);
+                            }
+
+                            span.set_i64("http.status_code", i64::from(response.status().as_u16()));
+                            debug!("[{request_id}] [{} {}] {}", method, path, response.status());
                             Ok::<hyper::Response<BoxBody<Bytes, hyper::Error>>, hyper::Error>(response)
                         }
                     });
 
                     // Spawn a tokio task to serve multiple connections concurrently
                     task::spawn(async move {
-                        let tls_stream = match tls.accept(stream).await {
-                            Ok(s) => s,
-                            Err(e) => {
-                                error!("TLS accept error: {e}");
-                                return;
+                        // Hold the permit until the connection is closed
+                        let _permit = permit;
+
+                        let tls_stream = {
+                            let mut span = Span::start("tls.accept");
+                            span.set_str("client.address", peer.ip().to_string());
+                            match timeout(handshake_timeout, tls.accept(stream)).await {
+                                Ok(Ok(s)) => s,
+                                Ok(Err(e)) => {
+                                    error!("TLS accept error from {peer}: {e}");
+                                    return;
+                                }
+                                Err(_) => {
+                                    warn!("TLS handshake with {peer} timed out");
+                                    return;
+                                }
                             }
                         };
 
-                        if let Err(err) = Builder::new(TokioExecutor::new())
-                            .serve_connection(TokioIo::new(tls_stream), service)
-                            .await
-                        {
-                            error!("Error serving connection: {err:?} {err}");
+                        let connection = builder.serve_connection(TokioIo::new(tls_stream), service);
+                        tokio::pin!(connection);
+
+                        let mut closing = false;
+                        loop {
+                            tokio::select! {
+                                result = connection.as_mut() => {
+                                    if let Err(err) = result {
+                                        error!("Error serving connection: {err:?} {err}");
+                                    }
+                                    break;
+                                }
+                                () = sleep(idle_timeout.saturating_sub(activity.idle())), if !closing => {
+                                    if activity.idle() >= idle_timeout {
+                                        debug!("Closing idle connection {peer}");
+                                        connection.as_mut().graceful_shutdown();
+                                        closing = true;
+                                    }
+                                }
+                            }
                         }
                     });
                 }
@@ -208,3 +751,77 @@ impl App {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    use hyper::header::ALLOW;
+    use hyper::{Method, StatusCode};
+    use tokio::sync::Semaphore;
+
+    use super::{_ConnectionActivity, _route, RequestGuard};
+    use crate::routes::abc::Service;
+    use crate::routes::livez::LivenessService;
+    use crate::routes::trace::TraceService;
+
+    fn _services() -> HashMap<String, Arc<dyn Service>> {
+        [
+            Arc::new(LivenessService {}) as Arc<dyn Service>,
+            Arc::new(TraceService {}) as Arc<dyn Service>,
+        ]
+        .into_iter()
+        .map(|service| (service.route().to_string(), service))
+        .collect()
+    }
+
+    #[test]
+    fn routes_supported_methods() {
+        let services = _services();
+        let service = _route(&services, "/trace", &Method::POST).unwrap();
+        assert_eq!(service.route(), "/trace");
+
+        let service = _route(&services, "/livez", &Method::GET).unwrap();
+        assert_eq!(service.route(), "/livez");
+    }
+
+    #[test]
+    fn rejects_unsupported_methods() {
+        let services = _services();
+        for method in [Method::GET, Method::PUT, Method::DELETE] {
+            let response = _route(&services, "/trace", &method).err().unwrap();
+            assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+            assert_eq!(response.headers()[ALLOW], "POST");
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_routes() {
+        let response = _route(&_services(), "/unknown", &Method::GET)
+            .err()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn holds_request_resources_until_every_guard_is_dropped() {
+        let activity = Arc::new(_ConnectionActivity::new());
+        let permits = Arc::new(Semaphore::new(1));
+        let guard = RequestGuard::new(
+            activity.begin(),
+            Some(permits.clone().try_acquire_owned().unwrap()),
+        );
+        let background = guard.clone();
+
+        drop(guard);
+        assert_eq!(activity.idle(), Duration::ZERO);
+        assert_eq!(permits.available_permits(), 0);
+
+        drop(background);
+        assert_eq!(activity._in_flight.load(Ordering::Relaxed), 0);
+        assert_eq!(permits.available_permits(), 1);
+    }
+}
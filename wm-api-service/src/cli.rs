@@ -1,10 +1,14 @@
+use std::path::PathBuf;
+
 use clap::{Parser, Subcommand, crate_description, crate_version};
+use wm_common::version_info;
 
 #[derive(Debug, Parser)]
 #[command(
     long_about = crate_description!(),
     propagate_version = true,
     version = crate_version!(),
+    long_version = version_info!(),
 )]
 pub struct Arguments {
     #[command(subcommand)]
@@ -16,4 +20,13 @@ pub struct Arguments {
 pub enum ServiceAction {
     /// Start the API service
     Start,
+
+    /// Write a commented default configuration file
+    GenerateConfig {
+        /// Path to write the configuration to, defaults to stdout
+        output: Option<PathBuf>,
+    },
+
+    /// Print the version, git commit, build time and target triple
+    Version,
 }
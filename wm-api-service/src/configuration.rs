@@ -1,19 +1,410 @@
+use std::collections::HashMap;
 use std::path::PathBuf;
 
+use lapin::types::{AMQPValue, FieldTable};
 use serde::{Deserialize, Serialize};
 use url::Url;
+use wm_common::cipher::FrameCipher;
+use wm_common::config::{ConfigErrors, DefaultConfig};
 use wm_common::logger::LogLevel;
 
+use crate::forwarded::IpNetwork;
+
+/// What the broker does with messages published to a full events queue.
+///
+/// The API service does not use publisher confirms, so either way the dropped events are lost
+/// without the agents being told.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum QueueOverflow {
+    /// Discard the oldest queued messages, keeping the most recent events
+    DropHead,
+
+    /// Discard newly published messages, keeping the oldest events
+    RejectPublish,
+}
+
+/// Header carrying the client address through the reverse proxies in `trusted_proxies`.
+///
+/// Only the configured header is read: a proxy appends to one of them, and a client could forge
+/// the other one to spoof its address.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum ForwardingHeader {
+    /// The standard `Forwarded` header (RFC 7239), its `for=` parameters
+    Forwarded,
+
+    /// The de facto `X-Forwarded-For` header
+    XForwardedFor,
+}
+
+/// Connection to RabbitMQ and limits of the events queue.
+///
+/// The API and data services both declare the queue, and RabbitMQ refuses a declaration whose
+/// limits differ from those of the existing queue, so both must be configured identically.
 #[derive(Deserialize, Serialize)]
 pub struct RabbitMQ {
     pub host: Url,
+
+    /// Messages queued for longer are discarded, never if not specified
+    pub message_ttl_ms: Option<u32>,
+
+    /// Maximum number of queued messages, unbounded if not specified
+    pub max_length: Option<u32>,
+
+    pub overflow: QueueOverflow,
+}
+
+impl RabbitMQ {
+    /// Arguments declaring the limits of the events queue.
+    pub fn queue_arguments(&self) -> FieldTable {
+        let mut arguments = FieldTable::default();
+        if let Some(ttl) = self.message_ttl_ms {
+            arguments.insert("x-message-ttl".into(), AMQPValue::LongLongInt(ttl.into()));
+        }
+        if let Some(length) = self.max_length {
+            arguments.insert("x-max-length".into(), AMQPValue::LongLongInt(length.into()));
+            arguments.insert(
+                "x-overflow".into(),
+                AMQPValue::LongString(
+                    match self.overflow {
+                        QueueOverflow::DropHead => "drop-head",
+                        QueueOverflow::RejectPublish => "reject-publish",
+                    }
+                    .into(),
+                ),
+            );
+        }
+
+        arguments
+    }
+}
+
+/// Range of the `Retry-After` delay sent with `503 Service Unavailable`, interpolated by the
+/// share of connection permits in use.
+#[derive(Deserialize, Serialize)]
+pub struct RetryAfter {
+    pub min_seconds: u64,
+    pub max_seconds: u64,
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct Configuration {
+    /// Name of this instance, stamped onto the events it receives, unnamed if not specified
+    pub collector_id: Option<String>,
+
     pub port: u16,
+
+    /// Port serving `/livez` and `/readyz` over plain HTTP, disabled if not specified
+    pub probe_port: Option<u16>,
+
+    /// Maximum number of concurrently served connections, including probe connections, further
+    /// connections are dropped
+    pub max_connections: usize,
+
+    /// Connections not completing the TLS handshake in time are dropped
+    pub tls_handshake_timeout_seconds: u64,
+
+    /// HTTP/1 connections not sending complete request headers in time are closed
+    pub request_header_timeout_seconds: u64,
+
+    /// Connections without any request in progress for this long are closed
+    pub connection_idle_timeout_seconds: u64,
+
+    /// Maximum numbers of concurrently served requests of specific routes, further requests are
+    /// rejected with `503 Service Unavailable`
+    pub route_concurrency_limits: HashMap<String, usize>,
+
+    pub retry_after: RetryAfter,
+
+    /// Maximum size of a decompressed event, requests carrying a longer line are rejected
+    pub max_event_bytes: usize,
+
     pub log_level: LogLevel,
+
+    /// Log levels of specific modules, overriding `log_level`
+    pub log_overrides: HashMap<String, LogLevel>,
+
+    /// OTLP/HTTP endpoint to export spans to, requires the `otel` feature
+    pub otlp_endpoint: Option<String>,
+
     pub certificate: PathBuf,
     pub private_key: PathBuf,
+
+    /// PEM bundle of the CAs issuing client certificates, defaults to the last certificate of
+    /// the server chain
+    pub client_ca_bundle: Option<PathBuf>,
+
+    /// PEM certificate revocation lists, client certificates revoked by any of them are rejected
+    pub client_crls: Vec<PathBuf>,
+
+    /// Interval of reloading `client_crls` from disk
+    pub crl_reload_interval_seconds: u64,
+
+    /// Networks (CIDR) of reverse proxies whose `forwarding_header` is trusted to carry the
+    /// client address, which is the peer address if empty
+    pub trusted_proxies: Vec<String>,
+
+    /// Forwarding header set by the proxies in `trusted_proxies`, the other one is ignored
+    pub forwarding_header: ForwardingHeader,
+
     pub rabbitmq: RabbitMQ,
+
+    /// Hex-encoded 256-bit AES-GCM key shared with clients encrypting their backups
+    pub backup_encryption_key: Option<String>,
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Self {
+            collector_id: None,
+            port: 12110,
+            probe_port: None,
+            max_connections: 1024,
+            tls_handshake_timeout_seconds: 10,
+            request_header_timeout_seconds: 30,
+            connection_idle_timeout_seconds: 300,
+            route_concurrency_limits: HashMap::from([("/backup".to_string(), 4)]),
+            retry_after: RetryAfter {
+                min_seconds: 5,
+                max_seconds: 60,
+            },
+            max_event_bytes: 1 << 20,
+            log_level: LogLevel::Info,
+            log_overrides: HashMap::new(),
+            otlp_endpoint: None,
+            certificate: PathBuf::from(r"cert\server.pem"),
+            private_key: PathBuf::from(r"cert\server.rsa"),
+            client_ca_bundle: None,
+            client_crls: vec![],
+            crl_reload_interval_seconds: 3600,
+            trusted_proxies: vec![],
+            forwarding_header: ForwardingHeader::XForwardedFor,
+            rabbitmq: RabbitMQ {
+                host: Url::parse("amqp://localhost:5672").expect("Invalid default RabbitMQ URL"),
+                message_ttl_ms: None,
+                max_length: None,
+                overflow: QueueOverflow::DropHead,
+            },
+            backup_encryption_key: None,
+        }
+    }
+}
+
+impl DefaultConfig for Configuration {
+    const FIELD_DOCS: &'static [(&'static str, &'static str)] = &[
+        (
+            "collector_id",
+            "Name of this instance, indexed as observer.name of the events it receives to tell collectors apart, not indexed if null",
+        ),
+        ("port", "Port to accept client connections on"),
+        (
+            "probe_port",
+            "Port serving the /livez (process up) and /readyz (RabbitMQ reachable) probes over plain HTTP without client certificates, e.g. for Kubernetes, disabled if null. Both are always served on port as well",
+        ),
+        (
+            "max_connections",
+            "Maximum number of concurrently served connections, including probe connections, further connections are dropped",
+        ),
+        (
+            "tls_handshake_timeout_seconds",
+            "Connections not completing the TLS handshake in time are dropped",
+        ),
+        (
+            "request_header_timeout_seconds",
+            "HTTP/1 connections not sending complete request headers in time are closed",
+        ),
+        (
+            "connection_idle_timeout_seconds",
+            "Connections without any request in progress for this long are closed",
+        ),
+        (
+            "route_concurrency_limits",
+            "Maximum numbers of concurrently served requests of specific routes, further requests are rejected with 503",
+        ),
+        (
+            "retry_after",
+            "Retry-After delay sent with 503 responses, growing with the share of connections in use",
+        ),
+        ("retry_after.min_seconds", "Delay while the server is idle"),
+        (
+            "retry_after.max_seconds",
+            "Delay while all connections are in use",
+        ),
+        (
+            "max_event_bytes",
+            "Maximum size of a decompressed event, bounding the memory held per request. Batches containing a longer line are rejected, or cut short if already acknowledged",
+        ),
+        ("log_level", "One of Off, Error, Warn, Info, Debug, Trace"),
+        (
+            "log_overrides",
+            "Log levels of specific modules, e.g. wm_api_service::app: Debug",
+        ),
+        (
+            "otlp_endpoint",
+            "OTLP/HTTP endpoint to export spans to, requires the otel feature",
+        ),
+        (
+            "certificate",
+            "PEM certificate chain of the server, relative to the executable",
+        ),
+        (
+            "private_key",
+            "PEM private key of the server, relative to the executable",
+        ),
+        (
+            "client_ca_bundle",
+            "PEM bundle of the CAs issuing client certificates, defaults to the root of the server chain",
+        ),
+        (
+            "client_crls",
+            "PEM certificate revocation lists, client certificates revoked by any of them are rejected",
+        ),
+        (
+            "crl_reload_interval_seconds",
+            "Interval of reloading client_crls from disk",
+        ),
+        (
+            "trusted_proxies",
+            "Networks in CIDR notation (e.g. 10.0.0.0/8) of reverse proxies whose forwarding_header determines the client address of events. Headers are ignored if empty, and from any other peer",
+        ),
+        (
+            "forwarding_header",
+            "Header the trusted proxies append the client address to, Forwarded or XForwardedFor (X-Forwarded-For). The other header is ignored, as clients may forge it",
+        ),
+        ("rabbitmq", "Message queue receiving trace events"),
+        ("rabbitmq.host", "AMQP URL of the RabbitMQ broker"),
+        (
+            "rabbitmq.message_ttl_ms",
+            "Events queued for longer (e.g. during an Elasticsearch outage) are discarded, never if null. Must match the other service",
+        ),
+        (
+            "rabbitmq.max_length",
+            "Maximum number of queued events bounding broker memory, unbounded if null. Must match the other service",
+        ),
+        (
+            "rabbitmq.overflow",
+            "DropHead (lose the oldest events) or RejectPublish (lose the newest events) once max_length is reached. Must match the other service",
+        ),
+        (
+            "backup_encryption_key",
+            "Hex-encoded 256-bit AES-GCM key shared with clients encrypting their backups",
+        ),
+    ];
+}
+
+impl Configuration {
+    /// Check value ranges and cross-field constraints that deserialization cannot express.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = ConfigErrors::new();
+
+        for module in self.log_overrides.keys() {
+            errors.require(
+                !module.is_empty() && !module.starts_with(':') && !module.ends_with(':'),
+                format!("log_overrides: invalid module path \"{module}\""),
+            );
+        }
+
+        if let Some(collector_id) = &self.collector_id {
+            errors.require(
+                !collector_id.is_empty(),
+                "collector_id: must not be empty, use null for no name",
+            );
+        }
+        errors.require(self.port > 0, "port: must be positive");
+        errors.require(
+            self.probe_port
+                .is_none_or(|port| port > 0 && port != self.port),
+            "probe_port: must be positive and differ from port",
+        );
+        errors.require(
+            self.max_connections > 0,
+            "max_connections: must be positive",
+        );
+        errors.require(
+            self.tls_handshake_timeout_seconds > 0,
+            "tls_handshake_timeout_seconds: must be positive",
+        );
+        errors.require(
+            self.request_header_timeout_seconds > 0,
+            "request_header_timeout_seconds: must be positive",
+        );
+        errors.require(
+            self.connection_idle_timeout_seconds > 0,
+            "connection_idle_timeout_seconds: must be positive",
+        );
+        for (route, limit) in &self.route_concurrency_limits {
+            errors.require(
+                route.starts_with('/'),
+                format!("route_concurrency_limits: route \"{route}\" must start with /"),
+            );
+            errors.require(
+                *limit > 0,
+                format!("route_concurrency_limits.{route}: must be positive"),
+            );
+        }
+        errors.require(
+            self.retry_after.min_seconds <= self.retry_after.max_seconds,
+            "retry_after: min_seconds must not exceed max_seconds",
+        );
+        errors.require(
+            self.max_event_bytes > 0,
+            "max_event_bytes: must be positive",
+        );
+        if let Some(endpoint) = &self.otlp_endpoint
+            && let Err(e) = Url::parse(endpoint)
+        {
+            errors.push(format!("otlp_endpoint: {e}"));
+        }
+
+        errors.require(
+            !self.certificate.as_os_str().is_empty(),
+            "certificate: must not be empty",
+        );
+        errors.require(
+            !self.private_key.as_os_str().is_empty(),
+            "private_key: must not be empty",
+        );
+        if let Some(bundle) = &self.client_ca_bundle {
+            errors.require(
+                !bundle.as_os_str().is_empty(),
+                "client_ca_bundle: must not be empty",
+            );
+        }
+        for crl in &self.client_crls {
+            errors.require(
+                !crl.as_os_str().is_empty(),
+                "client_crls: paths must not be empty",
+            );
+        }
+        errors.require(
+            self.crl_reload_interval_seconds > 0,
+            "crl_reload_interval_seconds: must be positive",
+        );
+        for proxy in &self.trusted_proxies {
+            if let Err(e) = proxy.parse::<IpNetwork>() {
+                errors.push(format!("trusted_proxies: {e}"));
+            }
+        }
+        errors.require(
+            matches!(self.rabbitmq.host.scheme(), "amqp" | "amqps"),
+            format!(
+                "rabbitmq.host: expected an AMQP URL, got {}",
+                self.rabbitmq.host
+            ),
+        );
+        errors.require(
+            self.rabbitmq.message_ttl_ms != Some(0),
+            "rabbitmq.message_ttl_ms: must be positive",
+        );
+        errors.require(
+            self.rabbitmq.max_length != Some(0),
+            "rabbitmq.max_length: must be positive",
+        );
+        if let Some(key) = &self.backup_encryption_key
+            && let Err(e) = FrameCipher::from_hex(key)
+        {
+            errors.push(format!("backup_encryption_key: {e}"));
+        }
+
+        errors.into_result()
+    }
 }
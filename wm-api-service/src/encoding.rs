@@ -0,0 +1,121 @@
+use std::pin::Pin;
+
+use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use hyper::Request;
+use hyper::header::CONTENT_ENCODING;
+use tokio::io::{AsyncBufRead, AsyncRead};
+
+/// Compression of a request body, as declared by its `Content-Encoding` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Zstd,
+    Gzip,
+    Identity,
+}
+
+impl ContentEncoding {
+    /// Get the encoding of a request body.
+    ///
+    /// Clients predating content negotiation always send zstd without the header, so zstd is
+    /// assumed when it is missing.
+    pub fn of<T>(request: &Request<T>) -> Result<Self, String> {
+        let Some(value) = request.headers().get(CONTENT_ENCODING) else {
+            return Ok(Self::Zstd);
+        };
+
+        match value.to_str().map(str::trim) {
+            Ok(encoding) if encoding.eq_ignore_ascii_case("zstd") => Ok(Self::Zstd),
+            Ok(encoding) if encoding.eq_ignore_ascii_case("gzip") => Ok(Self::Gzip),
+            Ok(encoding) if encoding.eq_ignore_ascii_case("identity") => Ok(Self::Identity),
+            _ => Err(format!(
+                "Unsupported content encoding {value:?}, expected zstd, gzip or identity"
+            )),
+        }
+    }
+
+    pub fn decode<R>(self, reader: R) -> Pin<Box<dyn AsyncRead + Send>>
+    where
+        R: AsyncBufRead + Send + 'static,
+    {
+        match self {
+            Self::Zstd => Box::pin(ZstdDecoder::new(reader)),
+            Self::Gzip => Box::pin(GzipDecoder::new(reader)),
+            Self::Identity => Box::pin(reader),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use async_compression::tokio::write::{GzipEncoder, ZstdEncoder};
+    use hyper::Request;
+    use hyper::header::CONTENT_ENCODING;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::ContentEncoding;
+
+    const _EVENTS: &[u8] = b"{\"event\":1}\n{\"event\":2}\n";
+
+    fn _request(encoding: Option<&str>) -> Request<()> {
+        let mut builder = Request::builder();
+        if let Some(encoding) = encoding {
+            builder = builder.header(CONTENT_ENCODING, encoding);
+        }
+
+        builder.body(()).unwrap()
+    }
+
+    async fn _decode(encoding: ContentEncoding, body: Vec<u8>) -> Vec<u8> {
+        let mut decoded = vec![];
+        encoding
+            .decode(Cursor::new(body))
+            .read_to_end(&mut decoded)
+            .await
+            .unwrap();
+        decoded
+    }
+
+    #[test]
+    fn parses_content_encoding_header() {
+        assert_eq!(
+            ContentEncoding::of(&_request(None)),
+            Ok(ContentEncoding::Zstd)
+        );
+        for (header, expected) in [
+            ("zstd", ContentEncoding::Zstd),
+            ("ZSTD", ContentEncoding::Zstd),
+            (" gzip ", ContentEncoding::Gzip),
+            ("Identity", ContentEncoding::Identity),
+        ] {
+            assert_eq!(ContentEncoding::of(&_request(Some(header))), Ok(expected));
+        }
+
+        for header in ["br", "deflate", "zstd, gzip", ""] {
+            assert!(
+                ContentEncoding::of(&_request(Some(header))).is_err(),
+                "{header:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn decodes_each_encoding() {
+        let mut zstd = ZstdEncoder::new(vec![]);
+        zstd.write_all(_EVENTS).await.unwrap();
+        zstd.shutdown().await.unwrap();
+
+        let mut gzip = GzipEncoder::new(vec![]);
+        gzip.write_all(_EVENTS).await.unwrap();
+        gzip.shutdown().await.unwrap();
+
+        for (encoding, body) in [
+            (ContentEncoding::Zstd, zstd.into_inner()),
+            (ContentEncoding::Gzip, gzip.into_inner()),
+            (ContentEncoding::Identity, _EVENTS.to_vec()),
+        ] {
+            assert_eq!(_decode(encoding, body).await, _EVENTS, "{encoding:?}");
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use hyper::HeaderMap;
+use hyper::header::FORWARDED;
+
+use crate::configuration::ForwardingHeader;
+
+/// Range of addresses in CIDR notation, e.g. `10.0.0.0/8` or `fd00::/8`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IpNetwork {
+    _address: IpAddr,
+    _prefix: u8,
+}
+
+impl IpNetwork {
+    fn _bits(address: IpAddr) -> (u128, u8) {
+        match address {
+            IpAddr::V4(ipv4) => (u128::from(ipv4.to_bits()), 32),
+            IpAddr::V6(ipv6) => (ipv6.to_bits(), 128),
+        }
+    }
+
+    fn _mask(prefix: u8, width: u8) -> u128 {
+        let mask = u128::MAX
+            .checked_shl(u32::from(width - prefix))
+            .unwrap_or(0);
+        mask & (u128::MAX >> (128 - u32::from(width)))
+    }
+
+    pub fn contains(&self, address: IpAddr) -> bool {
+        // Clients connecting over IPv6 sockets with IPv4 addresses are reported as mapped
+        let address = match address {
+            IpAddr::V6(ipv6) => ipv6.to_ipv4_mapped().map_or(address, IpAddr::V4),
+            IpAddr::V4(_) => address,
+        };
+        if address.is_ipv4() != self._address.is_ipv4() {
+            return false;
+        }
+
+        let (network, width) = Self::_bits(self._address);
+        let (address, _) = Self::_bits(address);
+        let mask = Self::_mask(self._prefix, width);
+        address & mask == network & mask
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = String;
+
+    /// Parse a network, rejecting addresses with bits set beyond the prefix (e.g. `10.0.0.1/8`),
+    /// which usually denote a typo rather than the intended range.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+
+        let address = address
+            .parse::<IpAddr>()
+            .map_err(|e| format!("invalid address in {s:?}: {e}"))?;
+        let (bits, width) = Self::_bits(address);
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= width)
+                .ok_or_else(|| format!("invalid prefix length in {s:?}, expected 0-{width}"))?,
+            None => width,
+        };
+
+        if bits & !Self::_mask(prefix, width) != 0 {
+            return Err(format!("{s:?} has host bits set beyond the prefix length"));
+        }
+
+        Ok(Self {
+            _address: address,
+            _prefix: prefix,
+        })
+    }
+}
+
+/// Parse a node of a `Forwarded` `for=` parameter or an `X-Forwarded-For` entry, e.g. `192.0.2.1`,
+/// `"[2001:db8::1]:4711"` or `192.0.2.1:80`. Obfuscated and `unknown` nodes yield `None`.
+fn _parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(address) = node.parse::<IpAddr>() {
+        return Some(address);
+    }
+
+    if let Ok(address) = node.parse::<SocketAddr>() {
+        return Some(address.ip());
+    }
+
+    node.strip_prefix('[')
+        .and_then(|node| node.strip_suffix(']'))
+        .and_then(|node| node.parse::<IpAddr>().ok())
+}
+
+/// Addresses a request was forwarded for, from the client to the last proxy, according to
+/// `header` only.
+///
+/// `None` stands for a node that cannot be parsed.
+fn _forwarded_chain(headers: &HeaderMap, header: ForwardingHeader) -> Vec<Option<IpAddr>> {
+    match header {
+        ForwardingHeader::Forwarded => headers
+            .get_all(FORWARDED)
+            .iter()
+            .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| _parse_node(node))
+            })
+            .collect(),
+        ForwardingHeader::XForwardedFor => headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+            .map(_parse_node)
+            .collect(),
+    }
+}
+
+/// Resolve the address of the client behind the proxies in `trusted`, which append it to
+/// `header`.
+///
+/// Forwarding headers can be forged by anyone, so they are only honored when `peer` is a
+/// trusted proxy. The chain is then walked from the nearest hop and the first address not
+/// belonging to a trusted proxy is the client. A malformed hop stops the walk at the last
+/// address vouched for by a trusted proxy.
+pub fn client_ip(
+    peer: IpAddr,
+    headers: &HeaderMap,
+    header: ForwardingHeader,
+    trusted: &[IpNetwork],
+) -> IpAddr {
+    let is_trusted = |address: IpAddr| trusted.iter().any(|network| network.contains(address));
+
+    let mut client = peer;
+    if !is_trusted(client) {
+        return client;
+    }
+
+    for hop in _forwarded_chain(headers, header).into_iter().rev() {
+        let Some(hop) = hop else {
+            break;
+        };
+
+        client = hop;
+        if !is_trusted(client) {
+            break;
+        }
+    }
+
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use hyper::HeaderMap;
+    use hyper::header::{FORWARDED, HeaderValue};
+
+    use super::{IpNetwork, client_ip};
+    use crate::configuration::ForwardingHeader;
+
+    const _PROXY: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    const _CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7));
+    const _FORGED: IpAddr = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 9));
+
+    fn _trusted() -> Vec<IpNetwork> {
+        vec!["10.0.0.0/8".parse().unwrap()]
+    }
+
+    /// Headers of a request forwarded by [`_PROXY`] through `header`, with the other header
+    /// forged by the client.
+    fn _headers(header: ForwardingHeader) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let (forwarded, x_forwarded_for) = match header {
+            ForwardingHeader::Forwarded => (_CLIENT, _FORGED),
+            ForwardingHeader::XForwardedFor => (_FORGED, _CLIENT),
+        };
+        headers.insert(
+            FORWARDED,
+            HeaderValue::from_str(&format!("for={forwarded};proto=https")).unwrap(),
+        );
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_str(&x_forwarded_for.to_string()).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn reads_the_configured_header_only() {
+        for header in [ForwardingHeader::Forwarded, ForwardingHeader::XForwardedFor] {
+            assert_eq!(
+                client_ip(_PROXY, &_headers(header), header, &_trusted()),
+                _CLIENT,
+                "{header:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn ignores_the_other_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert(FORWARDED, HeaderValue::from_static("for=203.0.113.9"));
+        assert_eq!(
+            client_ip(
+                _PROXY,
+                &headers,
+                ForwardingHeader::XForwardedFor,
+                &_trusted()
+            ),
+            _PROXY
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_static("203.0.113.9"));
+        assert_eq!(
+            client_ip(_PROXY, &headers, ForwardingHeader::Forwarded, &_trusted()),
+            _PROXY
+        );
+    }
+
+    #[test]
+    fn ignores_headers_from_untrusted_peers() {
+        let header = ForwardingHeader::XForwardedFor;
+        assert_eq!(
+            client_ip(_FORGED, &_headers(header), header, &_trusted()),
+            _FORGED
+        );
+    }
+
+    #[test]
+    fn walks_chains_of_trusted_proxies() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            "x-forwarded-for",
+            HeaderValue::from_static("203.0.113.9, 198.51.100.7"),
+        );
+        headers.append("x-forwarded-for", HeaderValue::from_static("10.1.2.3"));
+        assert_eq!(
+            client_ip(
+                _PROXY,
+                &headers,
+                ForwardingHeader::XForwardedFor,
+                &_trusted()
+            ),
+            _CLIENT
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            FORWARDED,
+            HeaderValue::from_static(r#"for=198.51.100.7, for="[2001:db8::1]:4711", for=10.1.2.3"#),
+        );
+        assert_eq!(
+            client_ip(_PROXY, &headers, ForwardingHeader::Forwarded, &_trusted()),
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+    }
+}
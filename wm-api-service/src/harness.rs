@@ -0,0 +1,401 @@
+//! End-to-end tests serving the API over mutual TLS on an ephemeral port, with certificates
+//! issued by a throwaway CA and messages published to a [`RecordingPublisher`].
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{env, fs, process};
+
+use async_compression::tokio::write::ZstdEncoder;
+use chrono::Utc;
+use rcgen::{BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+use reqwest::{Certificate, Client, Identity, StatusCode};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, sleep};
+use wm_common::schema::event::{CapturedEventRecord, Event, EventData};
+use wm_common::schema::sysinfo::{CPUInfo, MemoryInfo, OSInfo, SystemInfo};
+
+use crate::app::App;
+use crate::configuration::Configuration;
+use crate::publisher::RecordingPublisher;
+
+/// Certificates of a throwaway PKI, in PEM.
+struct _Pki {
+    _ca: String,
+    _server_chain: String,
+    _server_key: String,
+    _client: String,
+    _client_key: String,
+}
+
+impl _Pki {
+    fn generate() -> Self {
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "wm-api-service test CA");
+        let ca_key = KeyPair::generate().unwrap();
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let issue = |names: Vec<String>, purpose: ExtendedKeyUsagePurpose| {
+            let mut params = CertificateParams::new(names).unwrap();
+            params.extended_key_usages = vec![purpose];
+            let key = KeyPair::generate().unwrap();
+            let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+            (cert.pem(), key.serialize_pem())
+        };
+
+        let (server, server_key) = issue(
+            vec!["localhost".to_string(), "127.0.0.1".to_string()],
+            ExtendedKeyUsagePurpose::ServerAuth,
+        );
+        let (client, client_key) = issue(
+            vec!["wm-client".to_string()],
+            ExtendedKeyUsagePurpose::ClientAuth,
+        );
+
+        Self {
+            _ca: ca.pem(),
+            _server_chain: format!("{server}{}", ca.pem()),
+            _server_key: server_key,
+            _client: client,
+            _client_key: client_key,
+        }
+    }
+}
+
+/// API server running in the background until dropped.
+struct _Server {
+    _directory: PathBuf,
+    _address: SocketAddr,
+    _pki: _Pki,
+    _publisher: Arc<RecordingPublisher>,
+    _shutdown: Option<oneshot::Sender<()>>,
+    _task: JoinHandle<()>,
+}
+
+impl _Server {
+    async fn start(name: &str) -> Self {
+        let directory = env::temp_dir().join(format!("wm-api-service-{name}-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+
+        let pki = _Pki::generate();
+        fs::write(directory.join("server.pem"), &pki._server_chain).unwrap();
+        fs::write(directory.join("server.key"), &pki._server_key).unwrap();
+        fs::write(directory.join("ca.pem"), &pki._ca).unwrap();
+
+        let config = Configuration {
+            certificate: directory.join("server.pem"),
+            private_key: directory.join("server.key"),
+            client_ca_bundle: Some(directory.join("ca.pem")),
+            ..Configuration::default()
+        };
+
+        let publisher = Arc::new(RecordingPublisher::new());
+        let app = App::with_publisher(Arc::new(config), publisher.clone());
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (shutdown, signal) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            app.serve(listener, async {
+                let _ = signal.await;
+            })
+            .await
+            .unwrap();
+        });
+
+        Self {
+            _directory: directory,
+            _address: address,
+            _pki: pki,
+            _publisher: publisher,
+            _shutdown: Some(shutdown),
+            _task: task,
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("https://localhost:{}{path}", self._address.port())
+    }
+
+    /// Client trusting the test CA, presenting the client certificate if `identity` is set.
+    fn client(&self, identity: bool) -> Client {
+        let mut builder = Client::builder()
+            .use_native_tls()
+            .tls_built_in_root_certs(false)
+            .add_root_certificate(Certificate::from_pem(self._pki._ca.as_bytes()).unwrap())
+            .resolve("localhost", self._address)
+            .timeout(Duration::from_secs(10));
+        if identity {
+            builder = builder.identity(
+                Identity::from_pkcs8_pem(
+                    self._pki._client.as_bytes(),
+                    self._pki._client_key.as_bytes(),
+                )
+                .unwrap(),
+            );
+        }
+
+        builder.build().unwrap()
+    }
+
+    /// Wait until at least `count` messages were published, returning all of them.
+    async fn published(&self, count: usize) -> Vec<Vec<u8>> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let messages = self._publisher.messages().await;
+            if messages.len() >= count || Instant::now() >= deadline {
+                return messages.into_iter().map(|(payload, _)| payload).collect();
+            }
+
+            sleep(Duration::from_millis(20)).await;
+        }
+    }
+}
+
+impl Drop for _Server {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self._shutdown.take() {
+            let _ = shutdown.send(());
+        }
+
+        self._task.abort();
+        let _ = fs::remove_dir_all(&self._directory);
+    }
+}
+
+fn _events(count: usize) -> Vec<String> {
+    let system = Arc::new(SystemInfo::new(
+        Arc::new(OSInfo {
+            full: "Windows 11 Pro 24H2".to_string(),
+            kernel: "26100".to_string(),
+            name: "Windows".to_string(),
+            platform: "windows".to_string(),
+            version: "11 (26100)".to_string(),
+        }),
+        MemoryInfo {
+            memory_load: 50,
+            total_physical: 17_179_869_184,
+            available_physical: 8_589_934_592,
+            total_page_file: 21_474_836_480,
+            available_page_file: 10_737_418_240,
+            total_virtual: 140_737_488_355_328,
+            available_virtual: 140_737_488_355_328,
+        },
+        CPUInfo {
+            usage: 12.5,
+            cores: vec![10.0, 15.0],
+        },
+        None,
+        "x86_64".to_string(),
+        "TEST-HOST".to_string(),
+    ));
+
+    (0..count)
+        .map(|i| {
+            CapturedEventRecord {
+                event: Event {
+                    guid: "00000000-0000-0000-0000-000000000000".to_string(),
+                    raw_timestamp: 133_000_000_000_000_000,
+                    process_id: 1234,
+                    thread_id: 5678,
+                    event_id: 0,
+                    opcode: 10,
+                    data: EventData::UdpIp {
+                        pid: 1234,
+                        size: 64,
+                        daddr: IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
+                        saddr: IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2)),
+                        dport: 53,
+                        sport: 50000 + i as u16,
+                    },
+                    user: None,
+                },
+                system: system.clone(),
+                captured: Utc::now(),
+            }
+            .serialize_to_string()
+        })
+        .collect()
+}
+
+async fn _zstd(events: &[String]) -> Vec<u8> {
+    let mut encoder = ZstdEncoder::new(vec![]);
+    for event in events {
+        encoder.write_all(event.as_bytes()).await.unwrap();
+        encoder.write_all(b"\n").await.unwrap();
+    }
+
+    encoder.shutdown().await.unwrap();
+    encoder.into_inner()
+}
+
+/// Split the client address appended by the server off a published message.
+fn _strip_client_ip(message: &[u8]) -> (&[u8], IpAddr) {
+    let (event, suffix) = message.split_at(message.len() - 17);
+    let bits = u128::from_be_bytes(suffix[..16].try_into().unwrap());
+    let ip = if suffix[16] == 1 {
+        IpAddr::V4(Ipv4Addr::from_bits(bits as u32))
+    } else {
+        IpAddr::V6(bits.into())
+    };
+
+    (event, ip)
+}
+
+#[tokio::test]
+async fn serves_routes_over_mutual_tls() {
+    let server = _Server::start("routes").await;
+    let client = server.client(true);
+
+    let response = client
+        .get(server.url("/health-check"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(response.headers().contains_key("X-Request-Id"));
+
+    let response = client.get(server.url("/livez")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = client.get(server.url("/trace")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+    let response = client.get(server.url("/unknown")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn publishes_compressed_events() {
+    let server = _Server::start("trace").await;
+    let events = _events(16);
+
+    let response = server
+        .client(true)
+        .post(server.url("/trace"))
+        .header("Content-Encoding", "zstd")
+        .body(_zstd(&events).await)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let published = server.published(events.len()).await;
+    assert_eq!(published.len(), events.len());
+    for (message, event) in published.iter().zip(&events) {
+        let (payload, ip) = _strip_client_ip(message);
+        assert_eq!(payload, event.as_bytes());
+        assert_eq!(ip, IpAddr::V4(Ipv4Addr::LOCALHOST));
+    }
+}
+
+#[tokio::test]
+async fn publishes_identity_encoded_events() {
+    let server = _Server::start("identity").await;
+    let events = _events(4);
+    let body = events
+        .iter()
+        .map(|event| format!("{event}\n"))
+        .collect::<String>();
+
+    let response = server
+        .client(true)
+        .post(server.url("/trace"))
+        .header("Content-Encoding", "identity")
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let published = server.published(events.len()).await;
+    assert_eq!(published.len(), events.len());
+    for (message, event) in published.iter().zip(&events) {
+        assert_eq!(_strip_client_ip(message).0, event.as_bytes());
+    }
+}
+
+#[tokio::test]
+async fn rejects_unsupported_encodings() {
+    let server = _Server::start("encoding").await;
+
+    let response = server
+        .client(true)
+        .post(server.url("/trace"))
+        .header("Content-Encoding", "br")
+        .body(_zstd(&_events(1)).await)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert!(server._publisher.messages().await.is_empty());
+}
+
+#[tokio::test]
+async fn rejects_clients_without_certificate() {
+    let server = _Server::start("anonymous").await;
+
+    let result = server
+        .client(false)
+        .get(server.url("/health-check"))
+        .send()
+        .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn validates_dummy_traces_without_publishing() {
+    let server = _Server::start("dummy").await;
+    let client = server.client(true);
+
+    let response = client
+        .post(server.url("/trace?dummy"))
+        .header("Content-Encoding", "zstd")
+        .body(_zstd(&_events(4)).await)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Invalid events are dropped rather than failing the batch, as when publishing
+    let response = client
+        .post(server.url("/trace?dummy"))
+        .header("Content-Encoding", "zstd")
+        .body(_zstd(&["not an event".to_string()]).await)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    assert!(server._publisher.messages().await.is_empty());
+}
+
+#[tokio::test]
+async fn rejects_oversized_events() {
+    let server = _Server::start("oversized").await;
+    let max_event_bytes = Configuration::default().max_event_bytes;
+
+    let response = server
+        .client(true)
+        .post(server.url("/trace?dummy"))
+        .header("Content-Encoding", "zstd")
+        .body(
+            _zstd(&[format!(
+                "{{\"padding\": \"{}\"}}",
+                "x".repeat(4 * max_event_bytes)
+            )])
+            .await,
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(server._publisher.messages().await.is_empty());
+}
@@ -1,6 +1,12 @@
 pub mod app;
 pub mod cli;
 pub mod configuration;
+pub mod encoding;
+pub mod forwarded;
+#[cfg(test)]
+mod harness;
+pub mod publisher;
+pub mod request_id;
 pub mod responses;
 pub mod routes;
 pub mod utils;
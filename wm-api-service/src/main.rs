@@ -11,11 +11,20 @@ use tokio::fs;
 use wm_api_service::app::App;
 use wm_api_service::cli::{Arguments, ServiceAction};
 use wm_api_service::configuration::Configuration;
-use wm_common::logger::initialize_logger;
+use wm_common::logger::{initialize_logger, install_panic_hook};
+use wm_common::telemetry::Telemetry;
+use wm_common::{config, version_info};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let arguments = Arguments::parse();
+    if let ServiceAction::GenerateConfig { output } = &arguments.command {
+        return config::write_default_config::<Configuration>(output.as_deref());
+    }
+    if let ServiceAction::Version = arguments.command {
+        println!("{} {}", env!("CARGO_PKG_NAME"), version_info!());
+        return Ok(());
+    }
 
     let executable_path = env::current_exe().expect("Failed to get current executable path");
     let app_directory = executable_path
@@ -24,8 +33,16 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         .to_path_buf();
 
     let configuration = Arc::new(
-        Configuration::from_config_file(app_directory.join("api-service-config.yml"))
-            .expect("Failed to load configuration"),
+        Configuration::from_config_file(config::find_config_file(
+            &app_directory,
+            "api-service-config",
+        ))
+        .expect("Failed to load configuration"),
+    );
+    config::exit_if_invalid(
+        configuration.validate(),
+        &app_directory.join("logs"),
+        "wm-api-service",
     );
 
     let log_directory = app_directory.join("logs");
@@ -35,19 +52,30 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
 
     initialize_logger(
         configuration.log_level,
+        &configuration.log_overrides,
         File::create(log_directory.join(format!(
                 "wm-api-service-{}.log",
                 SystemTime::now()
                     .duration_since(UNIX_EPOCH)
                     .expect("Time went backwards").as_millis()
             )))?,
+        None,
     )?;
     debug!("Initialized logger");
 
+    install_panic_hook(|| {});
+
+    let telemetry =
+        Telemetry::initialize("wm-api-service", configuration.otlp_endpoint.as_deref())?;
+
     let app = App::new(configuration);
     match arguments.command {
         ServiceAction::Start => app.run().await?,
+        ServiceAction::GenerateConfig { .. } | ServiceAction::Version => {
+            // Handled before loading the configuration
+        }
     }
 
+    telemetry.shutdown();
     Ok(())
 }
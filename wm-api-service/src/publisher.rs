@@ -0,0 +1,84 @@
+use async_trait::async_trait;
+use lapin::BasicProperties;
+use lapin::options::BasicPublishOptions;
+#[cfg(test)]
+use tokio::sync::Mutex;
+
+/// Destination of the messages published while serving requests.
+///
+/// This abstraction allows exercising the routes against [`RecordingPublisher`] instead of a
+/// real RabbitMQ broker.
+#[async_trait]
+pub trait Publisher: Send + Sync {
+    /// Publish `payload` to the events exchange.
+    async fn publish(
+        &self,
+        payload: &[u8],
+        properties: BasicProperties,
+    ) -> Result<(), lapin::Error>;
+
+    /// Whether messages can still be published, i.e. the connection was not lost.
+    fn connected(&self) -> bool;
+}
+
+#[async_trait]
+impl Publisher for lapin::Channel {
+    async fn publish(
+        &self,
+        payload: &[u8],
+        properties: BasicProperties,
+    ) -> Result<(), lapin::Error> {
+        self.basic_publish(
+            "events",
+            "",
+            BasicPublishOptions::default(),
+            payload,
+            properties,
+        )
+        .await?;
+        Ok(())
+    }
+
+    fn connected(&self) -> bool {
+        self.status().connected()
+    }
+}
+
+/// In-memory publisher recording the messages it receives.
+#[cfg(test)]
+#[derive(Default)]
+pub struct RecordingPublisher {
+    _messages: Mutex<Vec<(Vec<u8>, BasicProperties)>>,
+}
+
+#[cfg(test)]
+impl RecordingPublisher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Messages published so far, in order.
+    pub async fn messages(&self) -> Vec<(Vec<u8>, BasicProperties)> {
+        self._messages.lock().await.clone()
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Publisher for RecordingPublisher {
+    async fn publish(
+        &self,
+        payload: &[u8],
+        properties: BasicProperties,
+    ) -> Result<(), lapin::Error> {
+        self._messages
+            .lock()
+            .await
+            .push((payload.to_vec(), properties));
+        Ok(())
+    }
+
+    fn connected(&self) -> bool {
+        true
+    }
+}
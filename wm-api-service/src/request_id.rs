@@ -0,0 +1,35 @@
+use std::fmt;
+
+use hyper::Request;
+
+/// Identifier tying a request to its downstream operations (e.g. RabbitMQ publishes) in logs.
+///
+/// The router attaches it to each request as an extension and echoes it back to the client
+/// in the `X-Request-Id` response header.
+#[derive(Clone, Debug)]
+pub struct RequestId(String);
+
+impl RequestId {
+    pub fn new(connection_id: &str, sequence: u64) -> Self {
+        Self(format!("{connection_id}-{sequence}"))
+    }
+
+    /// Get the id attached to a request by the router.
+    pub fn of<T>(request: &Request<T>) -> Self {
+        request
+            .extensions()
+            .get::<Self>()
+            .cloned()
+            .unwrap_or_else(|| Self("-".to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
@@ -1,6 +1,7 @@
 use http_body_util::combinators::BoxBody;
 use http_body_util::{BodyExt, Empty, Full};
 use hyper::body::Bytes;
+use hyper::header::{HeaderValue, RETRY_AFTER};
 use hyper::{Response, StatusCode};
 use serde::Serialize;
 
@@ -48,6 +49,15 @@ impl ResponseBuilder {
         )
     }
 
+    /// `503 Service Unavailable`, asking the client to retry after `retry_after_seconds`.
+    pub fn unavailable(retry_after_seconds: u64) -> Response<BoxBody<Bytes, hyper::Error>> {
+        let mut response = Self::default(StatusCode::SERVICE_UNAVAILABLE);
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(retry_after_seconds));
+        response
+    }
+
     pub fn default(status: StatusCode) -> Response<BoxBody<Bytes, hyper::Error>> {
         Self::message(
             status,
@@ -4,13 +4,18 @@ use std::sync::Arc;
 use async_trait::async_trait;
 use http_body_util::combinators::BoxBody;
 use hyper::body::{Bytes, Incoming};
-use hyper::{Request, Response};
+use hyper::{Method, Request, Response};
 
 use crate::app::App;
 
 #[async_trait]
 pub trait Service: Send + Sync {
     fn route(&self) -> &'static str;
+
+    /// HTTP methods accepted by this service, other methods are rejected by the router with
+    /// `405 Method Not Allowed` before reaching [`Service::serve`].
+    fn methods(&self) -> &'static [Method];
+
     async fn serve(
         &self,
         app: Arc<App>,
@@ -1,24 +1,26 @@
 use std::io;
 use std::net::SocketAddr;
+use std::pin::Pin;
 use std::sync::Arc;
 
-use async_compression::tokio::bufread::ZstdDecoder;
 use async_trait::async_trait;
-use futures_util::stream::TryStreamExt;
+use futures_util::stream::{self, TryStreamExt};
 use http_body_util::BodyExt;
 use http_body_util::combinators::BoxBody;
 use hyper::body::{Bytes, Incoming};
 use hyper::{Method, Request, Response, StatusCode};
-use lapin::BasicProperties;
-use lapin::options::BasicPublishOptions;
-use log::error;
-use tokio::io::AsyncReadExt;
+use log::{error, warn};
+use tokio::io::{AsyncBufRead, AsyncReadExt};
 use tokio_util::io::StreamReader;
+use wm_common::cipher::BACKUP_ENCRYPTION;
+use wm_common::telemetry::Span;
 
 use crate::app::App;
+use crate::encoding::ContentEncoding;
+use crate::request_id::RequestId;
 use crate::responses::ResponseBuilder;
 use crate::routes::abc::Service;
-use crate::utils::append_client_ip;
+use crate::utils::{append_client_ip, parse_events, parse_query_map, read_event, validate_event};
 
 pub struct BackupService;
 
@@ -28,57 +30,133 @@ impl Service for BackupService {
         "/backup"
     }
 
+    fn methods(&self) -> &'static [Method] {
+        &[Method::POST]
+    }
+
     async fn serve(
         &self,
         app: Arc<App>,
         peer: SocketAddr,
         request: Request<Incoming>,
     ) -> Response<BoxBody<Bytes, hyper::Error>> {
-        if request.method() == Method::POST {
-            let stream = request
+        let request_id = RequestId::of(&request);
+        let dummy = parse_query_map(&request).contains_key("dummy");
+        let encoding = match ContentEncoding::of(&request) {
+            Ok(encoding) => encoding,
+            Err(e) => return ResponseBuilder::message(StatusCode::UNSUPPORTED_MEDIA_TYPE, e),
+        };
+
+        let opener = match request.headers().get("X-Backup-Encryption") {
+            Some(value) if value == BACKUP_ENCRYPTION => match app.backup_cipher() {
+                Some(cipher) => Some(cipher.opener()),
+                None => {
+                    return ResponseBuilder::message(
+                        StatusCode::BAD_REQUEST,
+                        "Backup encryption is not configured on this server",
+                    );
+                }
+            },
+            Some(_) => {
+                return ResponseBuilder::message(
+                    StatusCode::BAD_REQUEST,
+                    "Unsupported backup encryption",
+                );
+            }
+            None => None,
+        };
+
+        let body = StreamReader::new(
+            request
                 .into_body()
                 .into_data_stream()
-                .map_err(io::Error::other);
-            let decompressor = ZstdDecoder::new(StreamReader::new(stream));
-            let mut chained = decompressor.chain(b"\n".as_ref());
-
-            match app.rabbitmq().await {
-                Some(rabbitmq) => {
-                    let mut buffer = vec![];
-                    let options = BasicPublishOptions::default();
-                    let properties = BasicProperties::default();
-                    while let Ok(byte) = chained.read_u8().await {
-                        if byte == b'\n' {
-                            if buffer.is_empty() {
-                                continue;
-                            }
-
-                            append_client_ip(&mut buffer, peer.ip());
-
-                            if let Err(e) = rabbitmq
-                                .basic_publish("", "events", options, &buffer, properties.clone())
-                                .await
-                            {
-                                error!(
-                                    "RabbitMQ error when backing up, events may have been lost: {e}"
-                                );
-                                return ResponseBuilder::default(StatusCode::SERVICE_UNAVAILABLE);
-                            }
-
-                            buffer.clear();
-                        } else {
-                            buffer.push(byte);
-                        }
+                .map_err(io::Error::other),
+        );
+        let reader: Pin<Box<dyn AsyncBufRead + Send>> = match opener {
+            Some(opener) => Box::pin(StreamReader::new(stream::try_unfold(
+                (body, opener),
+                |(mut body, mut opener)| async move {
+                    let frame = opener.open_next(&mut body).await?;
+                    Ok::<_, io::Error>(
+                        frame.map(|plaintext| (Bytes::from(plaintext), (body, opener))),
+                    )
+                },
+            ))),
+            None => Box::pin(body),
+        };
+        let decompressor = encoding.decode(reader);
+        let mut chained = decompressor.chain(b"\n".as_ref());
+
+        if dummy {
+            // Connectivity tests: validate the payload without publishing anything
+            return match parse_events(&mut chained, app.max_event_bytes()).await {
+                Ok((_, invalid)) => {
+                    if invalid > 0 {
+                        warn!(
+                            "[{request_id}] Dummy batch from {peer} has {invalid} invalid events, which would be dropped"
+                        );
                     }
+
+                    ResponseBuilder::empty(StatusCode::NO_CONTENT)
                 }
-                None => {
-                    return ResponseBuilder::default(StatusCode::SERVICE_UNAVAILABLE);
+                Err(e) => ResponseBuilder::message(StatusCode::BAD_REQUEST, e),
+            };
+        }
+
+        match app.publisher().await {
+            Some(publisher) => {
+                let max_event_bytes = app.max_event_bytes();
+                let mut buffer = vec![];
+                let mut span = Span::start("backup.publish");
+                span.set_str("request_id", request_id.as_str());
+                let properties = app.message_properties(&request_id, &span);
+
+                let mut events = 0;
+                let mut invalid = 0;
+                loop {
+                    match read_event(&mut chained, &mut buffer, max_event_bytes).await {
+                        Ok(true) => {}
+                        Ok(false) => break,
+                        Err(e) => {
+                            // Unlike invalid events, a corrupted payload may be a transfer error
+                            error!("[{request_id}] Invalid payload from {peer}: {e}");
+                            return ResponseBuilder::message(
+                                StatusCode::BAD_REQUEST,
+                                format!("Invalid payload: {e}"),
+                            );
+                        }
+                    }
+
+                    span.lap("decompress");
+                    if let Err(e) = validate_event(&buffer) {
+                        warn!(
+                            "[{request_id}] Dropping invalid event #{} from {peer}: {e}",
+                            events + invalid
+                        );
+                        invalid += 1;
+                        continue;
+                    }
+
+                    append_client_ip(&mut buffer, peer.ip());
+                    if let Err(e) = publisher.publish(&buffer, properties.clone()).await {
+                        error!(
+                            "[{request_id}] RabbitMQ error when backing up, events may have been lost: {e}"
+                        );
+                        return ResponseBuilder::unavailable(app.retry_after_seconds());
+                    }
+
+                    span.lap("publish");
+                    events += 1;
                 }
-            }
 
-            ResponseBuilder::empty(StatusCode::NO_CONTENT)
-        } else {
-            ResponseBuilder::default(StatusCode::METHOD_NOT_ALLOWED)
+                span.set_i64("events", events);
+                span.set_i64("invalid", invalid);
+            }
+            None => {
+                return ResponseBuilder::unavailable(app.retry_after_seconds());
+            }
         }
+
+        ResponseBuilder::empty(StatusCode::NO_CONTENT)
     }
 }
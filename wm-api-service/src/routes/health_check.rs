@@ -4,7 +4,7 @@ use std::sync::Arc;
 use async_trait::async_trait;
 use http_body_util::combinators::BoxBody;
 use hyper::body::{Bytes, Incoming};
-use hyper::{Request, Response, StatusCode};
+use hyper::{Method, Request, Response, StatusCode};
 
 use crate::app::App;
 use crate::responses::ResponseBuilder;
@@ -18,6 +18,10 @@ impl Service for HealthCheckService {
         "/health-check"
     }
 
+    fn methods(&self) -> &'static [Method] {
+        &[Method::GET]
+    }
+
     async fn serve(
         &self,
         _: Arc<App>,
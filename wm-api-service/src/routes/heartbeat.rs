@@ -0,0 +1,89 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::{Method, Request, Response, StatusCode};
+use log::error;
+use wm_common::schema::heartbeat::{HEARTBEAT_MESSAGE_KIND, Heartbeat};
+use wm_common::telemetry::Span;
+
+use crate::app::App;
+use crate::request_id::RequestId;
+use crate::responses::ResponseBuilder;
+use crate::routes::abc::Service;
+use crate::utils::append_client_ip;
+
+/// Heartbeats are tiny, anything larger is not worth reading.
+const _MAX_BODY_SIZE: usize = 64 << 10;
+
+pub struct HeartbeatService;
+
+#[async_trait]
+impl Service for HeartbeatService {
+    fn route(&self) -> &'static str {
+        "/heartbeat"
+    }
+
+    fn methods(&self) -> &'static [Method] {
+        &[Method::POST]
+    }
+
+    async fn serve(
+        &self,
+        app: Arc<App>,
+        peer: SocketAddr,
+        request: Request<Incoming>,
+    ) -> Response<BoxBody<Bytes, hyper::Error>> {
+        let request_id = RequestId::of(&request);
+        let body = match Limited::new(request.into_body(), _MAX_BODY_SIZE)
+            .collect()
+            .await
+        {
+            Ok(body) => body.to_bytes(),
+            Err(e) => {
+                return ResponseBuilder::message(
+                    StatusCode::BAD_REQUEST,
+                    format!("Unable to read heartbeat: {e}"),
+                );
+            }
+        };
+
+        // Unlike event batches, a heartbeat is a single mandatory document
+        if body.trim_ascii().is_empty() {
+            return ResponseBuilder::message(StatusCode::BAD_REQUEST, "Empty heartbeat");
+        }
+
+        // Validate here rather than in the data service, which cannot reject the request
+        if let Err(e) = serde_json::from_slice::<Heartbeat>(&body) {
+            return ResponseBuilder::message(
+                StatusCode::BAD_REQUEST,
+                format!("Invalid heartbeat: {e}"),
+            );
+        }
+
+        let Some(publisher) = app.publisher().await else {
+            error!(
+                "[{request_id}] RabbitMQ connection is not available. Heartbeat is lost from {peer}"
+            );
+            return ResponseBuilder::unavailable(app.retry_after_seconds());
+        };
+
+        let mut buffer = body.to_vec();
+        append_client_ip(&mut buffer, peer.ip());
+
+        let mut span = Span::start("heartbeat.publish");
+        span.set_str("request_id", request_id.as_str());
+        let properties = app
+            .message_properties(&request_id, &span)
+            .with_kind(HEARTBEAT_MESSAGE_KIND.into());
+        if let Err(e) = publisher.publish(&buffer, properties).await {
+            error!("[{request_id}] RabbitMQ error when publishing heartbeat: {e}");
+            return ResponseBuilder::unavailable(app.retry_after_seconds());
+        }
+
+        ResponseBuilder::empty(StatusCode::NO_CONTENT)
+    }
+}
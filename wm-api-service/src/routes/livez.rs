@@ -0,0 +1,34 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use http_body_util::combinators::BoxBody;
+use hyper::body::{Bytes, Incoming};
+use hyper::{Method, Request, Response, StatusCode};
+
+use crate::app::App;
+use crate::responses::ResponseBuilder;
+use crate::routes::abc::Service;
+
+/// Liveness probe: the process is up and serving requests, whatever the state of its backends.
+pub struct LivenessService;
+
+#[async_trait]
+impl Service for LivenessService {
+    fn route(&self) -> &'static str {
+        "/livez"
+    }
+
+    fn methods(&self) -> &'static [Method] {
+        &[Method::GET]
+    }
+
+    async fn serve(
+        &self,
+        _: Arc<App>,
+        _: SocketAddr,
+        _: Request<Incoming>,
+    ) -> Response<BoxBody<Bytes, hyper::Error>> {
+        ResponseBuilder::empty(StatusCode::NO_CONTENT)
+    }
+}
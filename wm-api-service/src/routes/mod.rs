@@ -1,4 +1,7 @@
 pub mod abc;
 pub mod backup;
 pub mod health_check;
+pub mod heartbeat;
+pub mod livez;
+pub mod readyz;
 pub mod trace;
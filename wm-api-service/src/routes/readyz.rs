@@ -0,0 +1,48 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use http_body_util::combinators::BoxBody;
+use hyper::body::{Bytes, Incoming};
+use hyper::{Method, Request, Response, StatusCode};
+
+use crate::app::App;
+use crate::responses::ResponseBuilder;
+use crate::routes::abc::Service;
+
+/// Readiness probe: events can be accepted, i.e. RabbitMQ is reachable.
+///
+/// Connecting is attempted again on each probe while RabbitMQ is unreachable, so an instance
+/// started before the broker becomes ready without receiving any traffic.
+pub struct ReadinessService;
+
+#[async_trait]
+impl Service for ReadinessService {
+    fn route(&self) -> &'static str {
+        "/readyz"
+    }
+
+    fn methods(&self) -> &'static [Method] {
+        &[Method::GET]
+    }
+
+    async fn serve(
+        &self,
+        app: Arc<App>,
+        _: SocketAddr,
+        _: Request<Incoming>,
+    ) -> Response<BoxBody<Bytes, hyper::Error>> {
+        match app.publisher().await {
+            Some(publisher) if publisher.connected() => {
+                ResponseBuilder::empty(StatusCode::NO_CONTENT)
+            }
+            Some(_) => ResponseBuilder::message(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "RabbitMQ connection was lost",
+            ),
+            None => {
+                ResponseBuilder::message(StatusCode::SERVICE_UNAVAILABLE, "RabbitMQ is unreachable")
+            }
+        }
+    }
+}
@@ -2,24 +2,24 @@ use std::io;
 use std::net::SocketAddr;
 use std::sync::Arc;
 
-use async_compression::tokio::bufread::ZstdDecoder;
 use async_trait::async_trait;
 use futures_util::stream::TryStreamExt;
 use http_body_util::BodyExt;
 use http_body_util::combinators::BoxBody;
 use hyper::body::{Bytes, Incoming};
 use hyper::{Method, Request, Response, StatusCode};
-use lapin::BasicProperties;
-use lapin::options::BasicPublishOptions;
-use log::error;
+use log::{error, warn};
 use tokio::io::AsyncReadExt;
 use tokio_util::io::StreamReader;
 use wm_common::schema::responses::TraceResponse;
+use wm_common::telemetry::Span;
 
-use crate::app::App;
+use crate::app::{App, RequestGuard};
+use crate::encoding::ContentEncoding;
+use crate::request_id::RequestId;
 use crate::responses::ResponseBuilder;
 use crate::routes::abc::Service;
-use crate::utils::append_client_ip;
+use crate::utils::{append_client_ip, parse_events, parse_query_map, read_event, validate_event};
 
 pub struct TraceService;
 
@@ -29,64 +29,102 @@ impl Service for TraceService {
         "/trace"
     }
 
+    fn methods(&self) -> &'static [Method] {
+        &[Method::POST]
+    }
+
     async fn serve(
         &self,
         app: Arc<App>,
         peer: SocketAddr,
         request: Request<Incoming>,
     ) -> Response<BoxBody<Bytes, hyper::Error>> {
-        if request.method() == Method::POST {
-            let stream = request
-                .into_body()
-                .into_data_stream()
-                .map_err(io::Error::other);
-            let decompressor = ZstdDecoder::new(StreamReader::new(stream));
-            let mut chained = decompressor.chain(b"\n".as_ref());
-
-            tokio::spawn(async move {
-                match app.rabbitmq().await {
-                    Some(rabbitmq) => {
-                        let mut buffer = vec![];
-                        let options = BasicPublishOptions::default();
-                        let properties = BasicProperties::default();
-                        while let Ok(byte) = chained.read_u8().await {
-                            if byte == b'\n' {
-                                if buffer.is_empty() {
-                                    continue;
-                                }
-
-                                append_client_ip(&mut buffer, peer.ip());
-
-                                if let Err(e) = rabbitmq
-                                    .basic_publish(
-                                        "",
-                                        "events",
-                                        options,
-                                        &buffer,
-                                        properties.clone(),
-                                    )
-                                    .await
-                                {
-                                    error!(
-                                        "RabbitMQ error when tracing, events may have been lost: {e}"
-                                    );
-                                }
-
-                                buffer.clear();
-                            } else {
-                                buffer.push(byte);
-                            }
-                        }
+        let request_id = RequestId::of(&request);
+        let dummy = parse_query_map(&request).contains_key("dummy");
+        let encoding = match ContentEncoding::of(&request) {
+            Ok(encoding) => encoding,
+            Err(e) => return ResponseBuilder::message(StatusCode::UNSUPPORTED_MEDIA_TYPE, e),
+        };
+
+        // Publishing outlives the response, so it keeps the resources of the request until done
+        let guard = request.extensions().get::<RequestGuard>().cloned();
+        let stream = request
+            .into_body()
+            .into_data_stream()
+            .map_err(io::Error::other);
+        let decompressor = encoding.decode(StreamReader::new(stream));
+        let mut chained = decompressor.chain(b"\n".as_ref());
+
+        if dummy {
+            // Connectivity tests: validate the payload without publishing anything
+            return match parse_events(&mut chained, app.max_event_bytes()).await {
+                Ok((_, invalid)) => {
+                    if invalid > 0 {
+                        warn!(
+                            "[{request_id}] Dummy batch from {peer} has {invalid} invalid events, which would be dropped"
+                        );
                     }
-                    None => {
-                        error!("RabbitMQ connection is not available. Events are lost from {peer}");
+
+                    ResponseBuilder::json(StatusCode::OK, TraceResponse {})
+                }
+                Err(e) => ResponseBuilder::message(StatusCode::BAD_REQUEST, e),
+            };
+        }
+
+        // Reject the batch rather than losing it, so that the client backs it up instead
+        let Some(publisher) = app.publisher().await else {
+            error!(
+                "[{request_id}] RabbitMQ connection is not available. Rejecting events from {peer}"
+            );
+            return ResponseBuilder::unavailable(app.retry_after_seconds());
+        };
+
+        let mut span = Span::start("trace.publish");
+        span.set_str("request_id", request_id.as_str());
+        let properties = app.message_properties(&request_id, &span);
+        let max_event_bytes = app.max_event_bytes();
+        tokio::spawn(async move {
+            let _guard = guard;
+            let mut buffer = vec![];
+
+            let mut events = 0;
+            let mut invalid = 0;
+            loop {
+                match read_event(&mut chained, &mut buffer, max_event_bytes).await {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(e) => {
+                        // The response was already sent, so the rest of the batch is lost
+                        error!("[{request_id}] Invalid payload from {peer}: {e}");
+                        break;
                     }
                 }
-            });
 
-            ResponseBuilder::json(StatusCode::OK, TraceResponse {})
-        } else {
-            ResponseBuilder::default(StatusCode::METHOD_NOT_ALLOWED)
-        }
+                span.lap("decompress");
+                if let Err(e) = validate_event(&buffer) {
+                    warn!(
+                        "[{request_id}] Dropping invalid event #{} from {peer}: {e}",
+                        events + invalid
+                    );
+                    invalid += 1;
+                    continue;
+                }
+
+                append_client_ip(&mut buffer, peer.ip());
+                if let Err(e) = publisher.publish(&buffer, properties.clone()).await {
+                    error!(
+                        "[{request_id}] RabbitMQ error when tracing, events may have been lost: {e}"
+                    );
+                }
+
+                span.lap("publish");
+                events += 1;
+            }
+
+            span.set_i64("events", events);
+            span.set_i64("invalid", invalid);
+        });
+
+        ResponseBuilder::json(StatusCode::OK, TraceResponse {})
     }
 }
@@ -1,7 +1,10 @@
 use std::collections::HashMap;
+use std::io;
 use std::net::IpAddr;
 
 use hyper::Request;
+use serde::de::{Error as _, IgnoredAny};
+use tokio::io::{AsyncRead, AsyncReadExt};
 use url::form_urlencoded;
 
 pub fn parse_query<T>(request: &Request<T>) -> Vec<(String, String)> {
@@ -27,6 +30,93 @@ pub fn append_client_ip(buffer: &mut Vec<u8>, ip: IpAddr) {
     buffer.push(u8::from(matches!(ip, IpAddr::V4(_))));
 }
 
+/// Read the next event of a newline-delimited stream into `buffer`, returning `false` once the
+/// stream is exhausted.
+///
+/// Blank lines, including whitespace-only ones (e.g. `\r` of CRLF line endings), are skipped, so
+/// an empty body is a valid batch of zero events.
+///
+/// Lines longer than `max_size` bytes fail with [`io::ErrorKind::InvalidData`], so that a
+/// single line cannot grow `buffer` without bound.
+pub async fn read_event<R>(
+    reader: &mut R,
+    buffer: &mut Vec<u8>,
+    max_size: usize,
+) -> io::Result<bool>
+where
+    R: AsyncRead + Unpin,
+{
+    buffer.clear();
+    loop {
+        let byte = match reader.read_u8().await {
+            Ok(byte) => byte,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Ok(!buffer.trim_ascii().is_empty());
+            }
+            Err(e) => return Err(e),
+        };
+
+        if byte == b'\n' {
+            if buffer.trim_ascii().is_empty() {
+                buffer.clear();
+                continue;
+            }
+
+            return Ok(true);
+        }
+
+        if buffer.len() >= max_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Event exceeds {max_size} bytes"),
+            ));
+        }
+
+        buffer.push(byte);
+    }
+}
+
+/// Check that `line` is a JSON object, so that garbage is dropped here rather than reaching the
+/// data service.
+///
+/// The JSON is only scanned, not deserialized into an event: the data service parses each event
+/// anyway, and skips those that do not match the schema.
+pub fn validate_event(line: &[u8]) -> Result<(), serde_json::Error> {
+    if !line.trim_ascii_start().starts_with(b"{") {
+        return Err(serde_json::Error::custom("expected a JSON object"));
+    }
+
+    serde_json::from_slice::<IgnoredAny>(line).map(|_| ())
+}
+
+/// Parse newline-delimited events from `reader` without publishing them, returning the number
+/// of valid and invalid events or a description of the corrupted payload.
+///
+/// Like the publishing routes, invalid events are counted and skipped rather than failing the batch.
+pub async fn parse_events<R>(
+    reader: &mut R,
+    max_event_size: usize,
+) -> Result<(usize, usize), String>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut buffer = vec![];
+    let mut events = 0;
+    let mut invalid = 0;
+    while read_event(reader, &mut buffer, max_event_size)
+        .await
+        .map_err(|e| format!("Invalid payload: {e}"))?
+    {
+        if validate_event(&buffer).is_ok() {
+            events += 1;
+        } else {
+            invalid += 1;
+        }
+    }
+
+    Ok((events, invalid))
+}
+
 #[macro_export]
 macro_rules! required_header {
     ($request:expr, $header:expr) => {
@@ -43,3 +133,108 @@ macro_rules! required_header {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{parse_events, read_event, validate_event};
+
+    const _MAX_EVENT_SIZE: usize = 1 << 20;
+
+    #[test]
+    fn accepts_json_objects() {
+        for line in [
+            "{}",
+            r#"{"event": {"guid": "00000000-0000-0000-0000-000000000000"}, "captured": 1}"#,
+            " \t{\"nested\": [1, 2, {\"a\": null}]} ",
+        ] {
+            assert!(validate_event(line.as_bytes()).is_ok(), "{line}");
+        }
+    }
+
+    #[test]
+    fn rejects_other_lines() {
+        for line in [
+            "not an event",
+            "[1, 2, 3]",
+            "\"string\"",
+            "42",
+            "null",
+            "{\"truncated\": ",
+            "{} trailing",
+            "{}{}",
+        ] {
+            assert!(validate_event(line.as_bytes()).is_err(), "{line}");
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_lines_up_to_the_size_limit() {
+        let line = vec![b'x'; _MAX_EVENT_SIZE];
+        let mut payload = line.clone();
+        payload.extend_from_slice(b"\n\r\n\n{}");
+
+        let mut reader = payload.as_slice();
+        let mut buffer = vec![];
+        assert!(
+            read_event(&mut reader, &mut buffer, _MAX_EVENT_SIZE)
+                .await
+                .unwrap()
+        );
+        assert_eq!(buffer, line);
+        assert!(
+            read_event(&mut reader, &mut buffer, _MAX_EVENT_SIZE)
+                .await
+                .unwrap()
+        );
+        assert_eq!(buffer, b"{}");
+        assert!(
+            !read_event(&mut reader, &mut buffer, _MAX_EVENT_SIZE)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_lines_over_the_size_limit() {
+        let mut payload = vec![b'x'; 4 * _MAX_EVENT_SIZE];
+        payload.push(b'\n');
+
+        let mut reader = payload.as_slice();
+        let mut buffer = vec![];
+        let error = read_event(&mut reader, &mut buffer, _MAX_EVENT_SIZE)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(buffer.len() <= _MAX_EVENT_SIZE);
+    }
+
+    #[tokio::test]
+    async fn parses_multi_megabyte_batches() {
+        let event = format!("{{\"padding\": \"{}\"}}\n", "x".repeat(1000));
+        let payload = event.repeat(4096);
+        assert!(payload.len() > 4 * _MAX_EVENT_SIZE);
+
+        let counts = parse_events(&mut payload.as_bytes(), _MAX_EVENT_SIZE)
+            .await
+            .unwrap();
+        assert_eq!(counts, (4096, 0));
+
+        let mut oversized = payload.clone();
+        oversized.push_str(&"x".repeat(_MAX_EVENT_SIZE + 1));
+        let error = parse_events(&mut oversized.as_bytes(), _MAX_EVENT_SIZE)
+            .await
+            .unwrap_err();
+        assert!(error.contains("exceeds"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn counts_invalid_events_without_failing() {
+        let payload = "{}\nnot an event\n[1, 2, 3]\n{\"a\": 1}\n";
+        let counts = parse_events(&mut payload.as_bytes(), _MAX_EVENT_SIZE)
+            .await
+            .unwrap();
+        assert_eq!(counts, (2, 2));
+    }
+}
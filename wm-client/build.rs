@@ -4,6 +4,9 @@ use std::{env, fs};
 
 use winresource::WindowsResource;
 
+#[path = "../wm-common/build_info.rs"]
+mod build_info;
+
 #[allow(dead_code)]
 struct CommonPaths {
     pub project_dir: PathBuf,
@@ -118,6 +121,7 @@ fn create_client_certificate(paths: &CommonPaths) {
 
 fn main() {
     let paths = CommonPaths::new();
+    build_info::emit(&paths.workspace_dir);
 
     copy_deploy_directory(&paths);
     create_client_certificate(&paths);
@@ -6,13 +6,19 @@ use async_trait::async_trait;
 use log::{error, info};
 use tokio::sync::{Mutex, SetOnce, mpsc};
 use tokio::task::JoinHandle;
+use wm_common::logger::LogBuffer;
 
 use crate::backup::Backup;
 use crate::configuration::Configuration;
 use crate::http::HttpClient;
 use crate::module::Module;
+use crate::module::admin_server::AdminServer;
 use crate::module::backup::BackupSender;
 use crate::module::connector::Connector;
+use crate::module::event_summary::EventSummaryLogger;
+use crate::module::heartbeat::HeartbeatSender;
+use crate::module::log_server::LogServer;
+use crate::module::overload::OverloadController;
 use crate::module::tracer::EventTracer;
 
 type _ModuleTask = JoinHandle<Result<(), Box<dyn Error + Send + Sync>>>;
@@ -22,6 +28,11 @@ pub struct Agent {
     _tracer: Arc<EventTracer>,
     _backup_sender: Arc<BackupSender>,
     _connector: Arc<Connector>,
+    _heartbeat_sender: Option<Arc<HeartbeatSender>>,
+    _log_server: Option<Arc<LogServer>>,
+    _admin_server: Option<Arc<AdminServer>>,
+    _overload_controller: Option<Arc<OverloadController>>,
+    _event_summary_logger: Option<Arc<EventSummaryLogger>>,
 
     _config: Arc<Configuration>,
     _app_directory: PathBuf,
@@ -36,24 +47,50 @@ impl Agent {
         config: Arc<Configuration>,
         app_directory: PathBuf,
         password: &str,
-    ) -> Self {
+        log_buffer: Option<Arc<LogBuffer>>,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
         let backup_directory = app_directory.join(&config.backup_directory);
-        let backup = Arc::new(Mutex::new(Backup::async_new(backup_directory).await));
+        let backup = Arc::new(Mutex::new(
+            Backup::async_new(config.clone(), backup_directory).await?,
+        ));
 
         let http = Arc::new(HttpClient::new(&config, password));
         let (sender, receiver) = mpsc::channel(config.message_queue_limit);
 
-        Self {
+        let backup_sender = Arc::new(BackupSender::new(backup.clone(), http.clone()));
+        let connector = Connector::new(config.clone(), receiver, backup.clone(), http.clone());
+
+        Ok(Self {
             _tracer: Arc::new(EventTracer::async_new(config.clone(), sender, backup.clone()).await),
-            _backup_sender: Arc::new(BackupSender::new(backup.clone(), http.clone())),
-            _connector: Connector::new(config.clone(), receiver, backup.clone(), http.clone()),
+            _admin_server: config.admin_server.enabled.then(|| {
+                Arc::new(AdminServer::new(
+                    config.clone(),
+                    connector.clone(),
+                    backup_sender.clone(),
+                ))
+            }),
+            _backup_sender: backup_sender,
+            _connector: connector,
+            _heartbeat_sender: config
+                .heartbeat
+                .enabled
+                .then(|| Arc::new(HeartbeatSender::new(config.clone(), http.clone()))),
+            _log_server: log_buffer.map(|buffer| Arc::new(LogServer::new(config.clone(), buffer))),
+            _overload_controller: config
+                .overload
+                .enabled
+                .then(|| Arc::new(OverloadController::new(config.clone()))),
+            _event_summary_logger: config
+                .event_summary
+                .enabled
+                .then(|| Arc::new(EventSummaryLogger::new(config.clone()))),
             _config: config.clone(),
             _app_directory: app_directory,
             _stopped: Arc::new(SetOnce::new()),
             _backup: backup,
             _http: http,
             _tasks: Arc::new(Mutex::new(vec![])),
-        }
+        })
     }
 }
 
@@ -90,6 +127,21 @@ impl Module for Agent {
         tasks.push(tokio::spawn(self._tracer.clone().run()));
         tasks.push(tokio::spawn(self._backup_sender.clone().run()));
         tasks.push(tokio::spawn(self._connector.clone().run()));
+        if let Some(heartbeat_sender) = &self._heartbeat_sender {
+            tasks.push(tokio::spawn(heartbeat_sender.clone().run()));
+        }
+        if let Some(log_server) = &self._log_server {
+            tasks.push(tokio::spawn(log_server.clone().run()));
+        }
+        if let Some(admin_server) = &self._admin_server {
+            tasks.push(tokio::spawn(admin_server.clone().run()));
+        }
+        if let Some(overload_controller) = &self._overload_controller {
+            tasks.push(tokio::spawn(overload_controller.clone().run()));
+        }
+        if let Some(event_summary_logger) = &self._event_summary_logger {
+            tasks.push(tokio::spawn(event_summary_logger.clone().run()));
+        }
 
         Ok(())
     }
@@ -98,6 +150,21 @@ impl Module for Agent {
         self._tracer.stop();
         self._backup_sender.stop();
         self._connector.stop();
+        if let Some(heartbeat_sender) = &self._heartbeat_sender {
+            heartbeat_sender.stop();
+        }
+        if let Some(log_server) = &self._log_server {
+            log_server.stop();
+        }
+        if let Some(admin_server) = &self._admin_server {
+            admin_server.stop();
+        }
+        if let Some(overload_controller) = &self._overload_controller {
+            overload_controller.stop();
+        }
+        if let Some(event_summary_logger) = &self._event_summary_logger {
+            event_summary_logger.stop();
+        }
 
         let mut tasks = self._tasks.lock().await;
         for task in tasks.drain(..) {
@@ -108,6 +175,10 @@ impl Module for Agent {
             }
         }
 
+        // Modules may have written their remaining data to the backup file during shutdown,
+        // finish it so that it is uploaded as a complete file on the next start
+        self._backup.lock().await.close().await?;
+
         Ok(())
     }
 }
@@ -1,41 +1,180 @@
 use std::error::Error;
+use std::io::{self, ErrorKind, SeekFrom};
+use std::mem;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
-use async_compression::tokio::write::ZstdEncoder;
-use log::{error, info, warn};
+use async_compression::Level;
+use async_compression::tokio::write::{GzipEncoder, ZstdEncoder};
+use chrono::Utc;
+use log::{debug, error, info, warn};
+use reqwest::Body;
+use reqwest::header::CONTENT_ENCODING;
 use tokio::fs;
-use tokio::io::{AsyncWriteExt, BufWriter};
-use tokio::sync::{Mutex, SetOnce};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt, BufReader};
+use tokio::sync::{Mutex, SetOnce, mpsc};
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+use tokio_util::io::ReaderStream;
+use wm_common::cipher::{BACKUP_ENCRYPTION, FrameCipher, FrameSealer};
+use wm_common::error::RuntimeError;
 use wm_common::file;
 use wm_common::schema::event::CapturedEventRecord;
 
-use crate::http::HttpClient;
+use crate::configuration::{BackupCompression, Configuration};
+use crate::http::{self, HttpClient};
+use crate::module::heartbeat::EVENT_COUNTERS;
 
-pub struct Backup {
-    _backup_directory: PathBuf,
+const _WRITE_ATTEMPTS: u32 = 5;
+const _WRITE_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Size of chunks read from a backup file while uploading it
+const _UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Extension of backup files encrypted with [`FrameCipher`]
+const _ENCRYPTED_EXTENSION: &str = "enc";
+
+/// Maximum number of events written to the backup file per lock of it
+const _WRITER_BATCH_SIZE: usize = 1024;
+
+fn _extension(compression: BackupCompression) -> &'static str {
+    match compression {
+        BackupCompression::Zstd => "zst",
+        BackupCompression::Gzip => "gz",
+    }
+}
+
+fn _content_encoding(compression: BackupCompression) -> &'static str {
+    match compression {
+        BackupCompression::Zstd => "zstd",
+        BackupCompression::Gzip => "gzip",
+    }
+}
+
+/// Get the compression of a backup file and whether it is encrypted from its name, so that
+/// files written before a configuration change are still uploaded with the right encoding.
+fn _file_format(path: &Path) -> Option<(BackupCompression, bool)> {
+    let (stem, encrypted) = match path.extension() {
+        Some(s) if s == _ENCRYPTED_EXTENSION => (Path::new(path.file_stem()?), true),
+        _ => (path, false),
+    };
+
+    let compression = match stem.extension() {
+        Some(s) if s == _extension(BackupCompression::Zstd) => BackupCompression::Zstd,
+        Some(s) if s == _extension(BackupCompression::Gzip) => BackupCompression::Gzip,
+        _ => return None,
+    };
+
+    Some((compression, encrypted))
+}
+
+/// Compressor of backup data staged in memory
+enum _Encoder {
+    Zstd(ZstdEncoder<Vec<u8>>),
+    Gzip(GzipEncoder<Vec<u8>>),
+}
+
+impl _Encoder {
+    fn new(config: &Configuration) -> Self {
+        match config.backup.compression {
+            BackupCompression::Zstd => Self::Zstd(ZstdEncoder::with_quality(
+                vec![],
+                Level::Precise(config.backup.zstd_compression_level),
+            )),
+            BackupCompression::Gzip => Self::Gzip(GzipEncoder::new(vec![])),
+        }
+    }
+
+    fn compression(&self) -> BackupCompression {
+        match self {
+            Self::Zstd(_) => BackupCompression::Zstd,
+            Self::Gzip(_) => BackupCompression::Gzip,
+        }
+    }
+
+    fn staged(&mut self) -> &mut Vec<u8> {
+        match self {
+            Self::Zstd(encoder) => encoder.get_mut(),
+            Self::Gzip(encoder) => encoder.get_mut(),
+        }
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            Self::Zstd(encoder) => encoder.write_all(data).await,
+            Self::Gzip(encoder) => encoder.write_all(data).await,
+        }
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Zstd(encoder) => encoder.flush().await,
+            Self::Gzip(encoder) => encoder.flush().await,
+        }
+    }
+
+    async fn shutdown(&mut self) -> io::Result<()> {
+        match self {
+            Self::Zstd(encoder) => encoder.shutdown().await,
+            Self::Gzip(encoder) => encoder.shutdown().await,
+        }
+    }
+}
+
+/// Backup file being written.
+struct _BackupFile {
     _path: PathBuf,
-    _zstd: ZstdEncoder<BufWriter<fs::File>>,
+    _file: fs::File,
+
+    /// Length of the file up to the end of the last complete write
+    _committed: u64,
+
+    /// Compressed data is staged in memory so that it can be sealed before reaching the disk
+    _encoder: _Encoder,
+    _sealer: Option<FrameSealer>,
 }
 
-impl Backup {
-    fn _get_log_file_path(backup_directory: &Path, index: i32) -> PathBuf {
-        backup_directory.join(format!("backup-{index}.zst"))
+impl _BackupFile {
+    fn _get_log_file_path(
+        backup_directory: &Path,
+        index: i32,
+        compression: BackupCompression,
+        encrypted: bool,
+    ) -> PathBuf {
+        let extension = _extension(compression);
+        if encrypted {
+            backup_directory.join(format!("backup-{index}.{extension}.{_ENCRYPTED_EXTENSION}"))
+        } else {
+            backup_directory.join(format!("backup-{index}.{extension}"))
+        }
     }
 
-    async fn _switch_to_new_path(
+    async fn create(
+        config: &Configuration,
         backup_directory: &Path,
-    ) -> (PathBuf, ZstdEncoder<BufWriter<fs::File>>) {
-        let _ = fs::create_dir_all(backup_directory).await;
+        cipher: Option<&FrameCipher>,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let encoder = _Encoder::new(config);
+
+        fs::create_dir_all(backup_directory).await?;
         let mut index = 0;
         let (file, mut path) = loop {
-            let backup_path = Self::_get_log_file_path(backup_directory, index);
+            let backup_path = Self::_get_log_file_path(
+                backup_directory,
+                index,
+                encoder.compression(),
+                cipher.is_some(),
+            );
             match file::create_new_exclusively(&backup_path) {
                 Ok(f) => break (f, backup_path),
-                Err(_) => {
+                Err(e) => {
                     index += 1;
                     if index == 1000 {
-                        panic!("Failed to create a new backup file after 1000 attempts");
+                        return Err(RuntimeError::new(format!(
+                            "Failed to create a new backup file after 1000 attempts: {e}"
+                        ))
+                        .into());
                     }
                 }
             }
@@ -43,99 +182,407 @@ impl Backup {
 
         path = path.canonicalize().unwrap_or(path);
         info!("Switched to backup file: {}", path.display());
-        (path, ZstdEncoder::new(BufWriter::new(file)))
+        Ok(Self {
+            _path: path,
+            _file: file,
+            _committed: 0,
+            _encoder: encoder,
+            _sealer: cipher.map(FrameCipher::sealer),
+        })
     }
 
-    pub async fn async_new(backup_directory: PathBuf) -> Self {
-        let (path, zstd) = Self::_switch_to_new_path(&backup_directory).await;
+    /// Move the compressed data staged in memory to the file, sealing it first if encryption is
+    /// enabled. Staged data is kept on failure.
+    async fn _drain(&mut self, last: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let staged = self._encoder.staged();
 
-        Self {
-            _backup_directory: backup_directory,
-            _path: path,
-            _zstd: zstd,
+        // The final frame of an encrypted file marks it as complete, so it is written even if empty
+        if staged.is_empty() && !(last && self._sealer.is_some()) {
+            return Ok(());
         }
+
+        let sealed = match &self._sealer {
+            Some(sealer) => Some(sealer.seal(staged, last)?),
+            None => None,
+        };
+        let data = sealed.as_deref().unwrap_or(staged.as_slice());
+
+        // Write errors only surface once the data is flushed
+        let written = match self._file.write_all(data).await {
+            Ok(()) => self._file.flush().await,
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = written {
+            // Part of the data may have reached the disk, cut it off so that the next attempt
+            // does not leave a duplicated prefix (or a truncated frame) in the middle of the file
+            self._file.set_len(self._committed).await?;
+            self._file.seek(SeekFrom::Start(self._committed)).await?;
+            return Err(e.into());
+        }
+
+        self._committed += data.len() as u64;
+        if let Some(sealer) = &mut self._sealer {
+            sealer.advance()?;
+        }
+
+        staged.clear();
+        Ok(())
+    }
+
+    /// Drain the staged data, retrying on I/O errors (e.g. disk full).
+    ///
+    /// The caller holds the backup lock while we wait between attempts, which stalls the
+    /// connector and the tracer fallbacks and therefore applies backpressure to event capturing.
+    async fn _commit(&mut self, last: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut attempt = 1;
+        loop {
+            match self._drain(last).await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    error!(
+                        "Failed to write to backup {} (attempt {attempt}/{_WRITE_ATTEMPTS}): {e}",
+                        self._path.display()
+                    );
+
+                    if attempt == _WRITE_ATTEMPTS {
+                        return Err(e);
+                    }
+
+                    sleep(_WRITE_RETRY_DELAY * attempt).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self._encoder.write_all(data).await?;
+        self._commit(false).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self._encoder.flush().await?;
+        self._drain(false).await?;
+        self._file.flush().await?;
+        Ok(())
+    }
+
+    /// End the compressed stream and, if encrypted, seal the final frame that tells the server
+    /// the file was not truncated. Nothing can be written to the file afterwards.
+    async fn finish(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self._encoder.shutdown().await?;
+        self._commit(true).await?;
+        self._file.sync_all().await?;
+        Ok(())
+    }
+
+    /// Seal the final frame of encrypted files left unfinished (e.g. because the agent crashed)
+    /// so that the server accepts them. A frame cut short is discarded.
+    async fn finish_abandoned(
+        backup_directory: &Path,
+        cipher: &FrameCipher,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut entries = match fs::read_dir(backup_directory).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if !matches!(_file_format(&path), Some((_, true))) {
+                continue;
+            }
+
+            let mut file = fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .await?;
+
+            let mut opener = cipher.opener();
+            let mut reader = BufReader::new(&mut file);
+            while let Ok(Some(_)) = opener.open_next(&mut reader).await {}
+            drop(reader);
+
+            let position = opener.position();
+            if position == 0 && file.metadata().await?.len() > 0 {
+                // Nothing can be authenticated, e.g. the file was sealed with another key
+                warn!(
+                    "Unable to read encrypted backup {}, leaving it as is",
+                    path.display()
+                );
+                continue;
+            }
+
+            if let Some(sealer) = opener.into_sealer() {
+                warn!("Sealing unfinished backup {}", path.display());
+                file.set_len(position).await?;
+                file.seek(SeekFrom::Start(position)).await?;
+                file.write_all(&sealer.seal(&[], true)?).await?;
+                file.sync_all().await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Backup {
+    _config: Arc<Configuration>,
+    _cipher: Option<FrameCipher>,
+    _backup_directory: PathBuf,
+    _current: _BackupFile,
+}
+
+impl Backup {
+    pub async fn async_new(
+        config: Arc<Configuration>,
+        backup_directory: PathBuf,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let cipher = match &config.backup.encryption_key {
+            Some(key) => Some(FrameCipher::from_hex(key)?),
+            None => None,
+        };
+
+        if let Some(cipher) = &cipher
+            && let Err(e) = _BackupFile::finish_abandoned(&backup_directory, cipher).await
+        {
+            error!("Unable to finish abandoned backups: {e}");
+        }
+
+        let current = _BackupFile::create(&config, &backup_directory, cipher.as_ref()).await?;
+        Ok(Self {
+            _config: config,
+            _cipher: cipher,
+            _backup_directory: backup_directory,
+            _current: current,
+        })
     }
 
     pub fn path(&self) -> &Path {
-        &self._path
+        &self._current._path
     }
 
-    pub async fn switch_backup(&mut self) {
-        self.flush().await;
+    /// Finish the current backup file and switch to a new one.
+    ///
+    /// On failure to create the new file, the current backup file is kept and may be switched
+    /// again later.
+    pub async fn switch_backup(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.flush().await?;
+
+        let min_free_space = self._config.backup.min_free_space_mb * 1024 * 1024;
+        match file::available_space(&self._backup_directory) {
+            Ok(available) if available < min_free_space => {
+                // A new file would only add overhead on an almost full disk
+                warn!(
+                    "Only {available} bytes of disk space left, keep writing to {}",
+                    self.path().display()
+                );
+                return Ok(());
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Unable to query available disk space: {e}"),
+        }
+
+        let current = _BackupFile::create(
+            &self._config,
+            &self._backup_directory,
+            self._cipher.as_ref(),
+        )
+        .await?;
+        let mut previous = mem::replace(&mut self._current, current);
+        if let Err(e) = previous.finish().await {
+            // Encrypted backups are sealed on the next start at the latest
+            error!("Unable to finish backup {}: {e}", previous._path.display());
+        }
+
+        Ok(())
+    }
 
-        let (path, zstd) = Self::_switch_to_new_path(&self._backup_directory).await;
-        self._path = path;
-        self._zstd = zstd;
+    /// Finish the current backup file on shutdown, a new one is created on the next start.
+    ///
+    /// Nothing can be written to the backup afterwards.
+    pub async fn close(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self._current.finish().await
     }
 
-    pub async fn write_one(&mut self, data: &CapturedEventRecord) {
-        self._zstd
-            .write_all(&data.serialize_to_vec())
-            .await
-            .unwrap();
-        self._zstd.write_u8(b'\n').await.unwrap();
+    /// Write a single event, returning whether it was written rather than discarded for being
+    /// older than the maximum event age.
+    pub async fn write_one(
+        &mut self,
+        data: &CapturedEventRecord,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        if let Some(max_age) = self._config.max_event_age()
+            && (Utc::now() - data.captured)
+                .to_std()
+                .is_ok_and(|age| age > max_age)
+        {
+            EVENT_COUNTERS.expired(1);
+            return Ok(false);
+        }
+
+        let mut line = data.serialize_to_vec();
+        line.push(b'\n');
+        self._current.write_all(&line).await?;
+        Ok(true)
     }
 
-    pub async fn write_many(&mut self, data: &[CapturedEventRecord]) {
+    pub async fn write_many(
+        &mut self,
+        data: &[CapturedEventRecord],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
         for record in data {
-            self.write_one(record).await;
+            self.write_one(record).await?;
         }
+
+        Ok(())
     }
 
-    pub async fn write(&mut self, data: &[u8]) {
-        self._zstd.write_all(data).await.unwrap();
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self._current.write_all(data).await
     }
 
-    pub async fn flush(&mut self) {
-        self._zstd.flush().await.unwrap();
-        self._zstd.get_mut().flush().await.unwrap();
+    pub async fn flush(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self._current.flush().await
     }
 
+    /// Upload every backup file except the current one, returning the number of uploaded files.
     pub async fn upload(
         backup: Arc<Mutex<Self>>,
         http: Arc<HttpClient>,
         stopped: Arc<SetOnce<()>>,
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let backup_directory = backup.lock().await._backup_directory.clone();
+    ) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        let (backup_directory, current, max_age, max_event_age) = {
+            let backup = backup.lock().await;
+            (
+                backup._backup_directory.clone(),
+                backup.path().to_path_buf(),
+                Duration::from_secs(backup._config.backup.max_age_hours * 3600),
+                backup._config.max_event_age(),
+            )
+        };
 
+        let mut backups = vec![];
         let mut entries = fs::read_dir(&backup_directory).await?;
-        while let Ok(Some(entry)) = entries.next_entry().await
-            && stopped.get().is_none()
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let Some((compression, encrypted)) = _file_format(&entry.path()) else {
+                continue;
+            };
+
+            if entry.path() == current {
+                continue;
+            }
+
+            // Backup files are never modified after we switched away from them
+            let modified = entry
+                .metadata()
+                .await
+                .and_then(|m| m.modified())
+                .unwrap_or_else(|_| SystemTime::now());
+            backups.push((modified, entry, compression, encrypted));
+        }
+
+        // Upload oldest backups first
+        backups.sort_unstable_by_key(|(modified, ..)| *modified);
+        EVENT_COUNTERS.oldest_backup_age(
+            backups
+                .first()
+                .map(|(modified, ..)| modified.elapsed().unwrap_or_default()),
+        );
+
+        if let Some((modified, entry, ..)) = backups.first()
+            && let Ok(age) = modified.elapsed()
+            && age > max_age
         {
-            if entry.path().extension().is_none_or(|s| s != "zst")
-                || entry.path() == backup.lock().await._path
+            warn!(
+                "Oldest backup {} is {} hours old, the server may have been unreachable for too long",
+                entry.path().display(),
+                age.as_secs() / 3600,
+            );
+        }
+
+        let mut uploaded = 0;
+        for (modified, entry, compression, encrypted) in backups {
+            if stopped.get().is_some() {
+                break;
+            }
+
+            // Every event of a backup was captured before its last modification
+            if let Some(max_event_age) = max_event_age
+                && modified.elapsed().is_ok_and(|age| age > max_event_age)
             {
+                warn!(
+                    "Deleting backup {} without uploading, its events are older than {} hours",
+                    entry.path().display(),
+                    max_event_age.as_secs() / 3600,
+                );
+                if let Err(e) = fs::remove_file(entry.path()).await {
+                    error!(
+                        "Failed to delete expired backup {}: {e}",
+                        entry.path().display()
+                    );
+                }
+
                 continue;
             }
 
             info!("Sending backup {}", entry.path().display());
 
             match file::open_exclusively(entry.path()) {
-                Ok(file) => match http.api().post("/backup").body(file).send().await {
-                    Ok(response) => {
-                        if response.status() == 204 {
-                            info!("Uploaded backup {}", entry.path().display());
-                            if let Err(e) = fs::remove_file(entry.path()).await {
+                Ok(file) => {
+                    // Stream the file in bounded chunks, backups may have accumulated to hundreds
+                    // of megabytes while the server was unreachable
+                    let body =
+                        Body::wrap_stream(ReaderStream::with_capacity(file, _UPLOAD_CHUNK_SIZE));
+                    let mut request = http
+                        .api()
+                        .post("/backup")
+                        .header(CONTENT_ENCODING, _content_encoding(compression))
+                        .body(body);
+                    if encrypted {
+                        request = request.header("X-Backup-Encryption", BACKUP_ENCRYPTION);
+                    }
+
+                    match request.send().await {
+                        Ok(response) => {
+                            // Leave the remaining backups for a later round rather than piling
+                            // onto a busy server
+                            if let Some(delay) = http::retry_after(&response) {
+                                warn!(
+                                    "Postponing upload of backup {}: server is busy, retry after {} seconds",
+                                    entry.path().display(),
+                                    delay.as_secs(),
+                                );
+                                break;
+                            }
+
+                            if response.status() == 204 {
+                                info!("Uploaded backup {}", entry.path().display());
+                                uploaded += 1;
+                                if let Err(e) = fs::remove_file(entry.path()).await {
+                                    error!(
+                                        "Failed to delete backup {} after upload: {e}",
+                                        entry.path().display()
+                                    );
+                                }
+                            } else {
                                 error!(
-                                    "Failed to delete backup {} after upload: {e}",
+                                    "Backup response {} for {}",
+                                    response.status(),
                                     entry.path().display()
                                 );
                             }
-                        } else {
+                        }
+                        Err(e) => {
                             error!(
-                                "Backup response {} for {}",
-                                response.status(),
+                                "Failed to send backup {} to server: {e}",
                                 entry.path().display()
                             );
                         }
                     }
-                    Err(e) => {
-                        error!(
-                            "Failed to send backup {} to server: {e}",
-                            entry.path().display()
-                        );
-                    }
-                },
+                }
                 Err(e) => {
                     warn!(
                         "Unable to open backup {} for reading. Skipping: {e}",
@@ -145,6 +592,207 @@ impl Backup {
             }
         }
 
-        Ok(())
+        Ok(uploaded)
+    }
+}
+
+/// Handle to the task writing events that cannot be queued (e.g. the message queue is full) to
+/// the backup file.
+///
+/// A single task owns the writes and drains its bounded queue in batches, so a storm of
+/// overflowing events neither spawns a task per event nor contends for the backup lock.
+#[derive(Clone)]
+pub struct BackupWriter {
+    _sender: mpsc::Sender<Arc<CapturedEventRecord>>,
+    _closed: Arc<SetOnce<()>>,
+    _task: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl BackupWriter {
+    /// Spawn the writer task, which stops once [`BackupWriter::close`] is called or every handle
+    /// is dropped.
+    pub fn spawn(backup: Arc<Mutex<Backup>>, config: &Configuration) -> Self {
+        let (sender, receiver) = mpsc::channel(config.backup.writer_queue_limit);
+        let closed = Arc::new(SetOnce::new());
+        let task = tokio::spawn(Self::_write(backup, receiver, closed.clone()));
+
+        Self {
+            _sender: sender,
+            _closed: closed,
+            _task: Arc::new(Mutex::new(Some(task))),
+        }
+    }
+
+    async fn _write(
+        backup: Arc<Mutex<Backup>>,
+        mut receiver: mpsc::Receiver<Arc<CapturedEventRecord>>,
+        closed: Arc<SetOnce<()>>,
+    ) {
+        let mut batch = Vec::with_capacity(_WRITER_BATCH_SIZE);
+        loop {
+            let count = tokio::select! {
+                count = receiver.recv_many(&mut batch, _WRITER_BATCH_SIZE) => count,
+                _ = closed.wait(), if !receiver.is_closed() => {
+                    // Reject further events but keep draining the queued ones
+                    receiver.close();
+                    continue;
+                }
+            };
+            if count == 0 {
+                break;
+            }
+
+            let mut backup = backup.lock().await;
+            for data in batch.drain(..) {
+                match backup.write_one(&data).await {
+                    Ok(true) => EVENT_COUNTERS.backed_up(1),
+                    Ok(false) => {}
+                    Err(e) => error!("Unable to back up event: {e}"),
+                }
+            }
+        }
+    }
+
+    /// Stop accepting events and wait until the writer task has written every queued one.
+    pub async fn close(&self) {
+        let _ = self._closed.set(());
+        if let Some(task) = self._task.lock().await.take()
+            && let Err(e) = task.await
+        {
+            error!("Backup writer panicked: {e}");
+        }
+    }
+
+    /// Hand `data` over to the writer task, dropping it if the writer queue is full.
+    pub fn submit(&self, data: Arc<CapturedEventRecord>) {
+        match self._sender.try_send(data) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                debug!("Backup writer queue is full, dropping event");
+                EVENT_COUNTERS.dropped(1);
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                error!("Backup writer has stopped, dropping event");
+                EVENT_COUNTERS.dropped(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::Arc;
+    use std::{env, process};
+
+    use async_compression::tokio::bufread::ZstdDecoder;
+    use chrono::Utc;
+    use tokio::fs;
+    use tokio::io::{AsyncReadExt, BufReader};
+    use tokio::sync::Mutex;
+    use wm_common::schema::event::{CapturedEventRecord, Event, EventData};
+    use wm_common::schema::sysinfo::{CPUInfo, MemoryInfo, OSInfo, SystemInfo};
+
+    use super::{Backup, BackupWriter};
+    use crate::configuration::Configuration;
+
+    fn _record(sport: u16) -> Arc<CapturedEventRecord> {
+        let system = SystemInfo::new(
+            Arc::new(OSInfo {
+                full: "Windows 11 Pro 24H2".to_string(),
+                kernel: "26100".to_string(),
+                name: "Windows".to_string(),
+                platform: "windows".to_string(),
+                version: "11 (26100)".to_string(),
+            }),
+            MemoryInfo {
+                memory_load: 50,
+                total_physical: 17_179_869_184,
+                available_physical: 8_589_934_592,
+                total_page_file: 21_474_836_480,
+                available_page_file: 10_737_418_240,
+                total_virtual: 140_737_488_355_328,
+                available_virtual: 140_737_488_355_328,
+            },
+            CPUInfo {
+                usage: 12.5,
+                cores: vec![10.0, 15.0],
+            },
+            None,
+            "x86_64".to_string(),
+            "TEST-HOST".to_string(),
+        );
+
+        Arc::new(CapturedEventRecord {
+            event: Event {
+                guid: "00000000-0000-0000-0000-000000000000".to_string(),
+                raw_timestamp: 133_000_000_000_000_000,
+                process_id: 1234,
+                thread_id: 5678,
+                event_id: 0,
+                opcode: 10,
+                data: EventData::UdpIp {
+                    pid: 1234,
+                    size: 64,
+                    daddr: IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
+                    saddr: IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2)),
+                    dport: 53,
+                    sport,
+                },
+                user: None,
+            },
+            system: Arc::new(system),
+            captured: Utc::now(),
+        })
+    }
+
+    #[tokio::test]
+    async fn closing_the_writer_writes_queued_events() {
+        let config = Arc::new(Configuration::default());
+        let directory = env::temp_dir().join(format!("wm-client-writer-{}", process::id()));
+        let backup = Arc::new(Mutex::new(
+            Backup::async_new(config.clone(), directory.clone())
+                .await
+                .unwrap(),
+        ));
+
+        // Hold the backup lock, so that every event is still queued when the writer is closed
+        let guard = backup.lock().await;
+        let writer = BackupWriter::spawn(backup.clone(), &config);
+        let records = (0..100).map(_record).collect::<Vec<_>>();
+        for record in &records {
+            writer.submit(record.clone());
+        }
+
+        let closing = tokio::spawn({
+            let writer = writer.clone();
+            async move { writer.close().await }
+        });
+        drop(guard);
+        closing.await.unwrap();
+
+        // Events submitted after closing are rejected rather than left unwritten
+        writer.submit(_record(0));
+
+        let mut backup = backup.lock().await;
+        backup.close().await.unwrap();
+
+        let file = fs::File::open(backup.path()).await.unwrap();
+        let mut decoder = ZstdDecoder::new(BufReader::new(file));
+        let mut content = vec![];
+        decoder.read_to_end(&mut content).await.unwrap();
+
+        let expected = records
+            .iter()
+            .flat_map(|record| {
+                let mut line = record.serialize_to_vec();
+                line.push(b'\n');
+                line
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(content, expected);
+
+        drop(backup);
+        let _ = fs::remove_dir_all(directory).await;
     }
 }
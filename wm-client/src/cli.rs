@@ -1,12 +1,14 @@
 use std::path::PathBuf;
 
 use clap::{Parser, Subcommand, crate_description, crate_version};
+use wm_common::version_info;
 
 #[derive(Debug, Parser)]
 #[command(
     long_about = crate_description!(),
     propagate_version = true,
     version = crate_version!(),
+    long_version = version_info!(),
 )]
 pub struct Arguments {
     #[command(subcommand)]
@@ -31,12 +33,28 @@ pub enum ServiceAction {
     /// Update the password stored in Windows Credential Manager
     Password,
 
-    /// Extract a zstd-compressed binary file
+    /// Write a commented default configuration file
+    GenerateConfig {
+        /// Path to write the configuration to, defaults to stdout
+        output: Option<PathBuf>,
+    },
+
+    /// Run a synthetic event through enrichment, serialization and compression
+    SelfTest {
+        /// Also post the event to the configured server, which validates it without storing it
+        #[arg(long)]
+        send: bool,
+    },
+
+    /// Extract a zstd-compressed binary file, or a gzip-compressed one with a .gz extension
     Zstd {
-        /// Path to the file containing zstd-compressed binary data
+        /// Path to the file containing zstd- or gzip-compressed binary data, e.g. a backup
         source: PathBuf,
 
         /// Path to write the extracted binary data to
         dest: PathBuf,
     },
+
+    /// Print the version, git commit, build time and target triple
+    Version,
 }
@@ -1,10 +1,19 @@
 use std::collections::HashMap;
 use std::net::IpAddr;
 use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
 
+use fancy_regex::Regex;
 use serde::{Deserialize, Serialize};
 use url::Url;
+use wm_common::cipher::FrameCipher;
+use wm_common::config::{ConfigErrors, DefaultConfig};
 use wm_common::logger::LogLevel;
+use wm_common::schema::event::EventData;
+
+use crate::module::tracer::providers::kernel::default_opcodes;
+use crate::module::tracer::providers::user;
 
 fn _service_name() -> String {
     "Windows Monitor Agent Service".to_string()
@@ -21,10 +30,273 @@ fn _password_registry_key() -> String {
     r"SOFTWARE\WindowsMonitor\CertificatePassword".to_string()
 }
 
+/// Events are batched into requests, a batch is sent as soon as it holds `flush_limit` bytes or
+/// `max_batch_records` events, or its oldest event is `max_batch_age_ms` old, whichever comes
+/// first.
 #[derive(Deserialize, Serialize)]
 pub struct EventPostSettings {
     pub concurrency_limit: usize,
     pub flush_limit: usize,
+    pub max_batch_records: usize,
+    pub max_batch_age_ms: u64,
+
+    /// Compress events with zstd, disable for agents colocated with the server to save CPU time
+    pub compress: bool,
+
+    /// Batches smaller than this many bytes are sent uncompressed, as zstd framing and CPU time
+    /// outweigh the savings
+    pub min_compress_bytes: usize,
+
+    /// Number of reusable buffers holding compressed requests, defaults to `concurrency_limit`
+    pub compressed_buffers: Option<usize>,
+}
+
+impl EventPostSettings {
+    /// Size of the compressed buffer pool.
+    ///
+    /// At most `concurrency_limit` sends are in flight (one per uncompressed buffer), each
+    /// holding a single compressed buffer until the server responds. A smaller pool would make
+    /// sends wait for each other instead of running concurrently.
+    pub fn compressed_buffers(&self) -> usize {
+        self.compressed_buffers.unwrap_or(self.concurrency_limit)
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct HttpTimeoutSettings {
+    pub connect_seconds: u64,
+
+    /// Time limit of whole requests including backup uploads, unlimited if not specified
+    pub request_seconds: Option<u64>,
+
+    pub pool_idle_seconds: u64,
+}
+
+/// Compression of backup files.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum BackupCompression {
+    Zstd,
+
+    /// For servers predating zstd support, uploaded with `Content-Encoding: gzip`
+    Gzip,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct BackupSettings {
+    pub compression: BackupCompression,
+    pub zstd_compression_level: i32,
+    pub min_free_space_mb: u64,
+    pub max_age_hours: u64,
+
+    /// Hex-encoded 256-bit AES-GCM key, backups are stored unencrypted if not specified
+    pub encryption_key: Option<String>,
+
+    /// Maximum number of events waiting for the backup writer, further events are dropped
+    pub writer_queue_limit: usize,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ResourceLimitSettings {
+    /// Maximum share of the total CPU time of all processors, no limit if not specified
+    pub cpu_limit_percent: Option<f64>,
+
+    /// Maximum committed memory, no limit if not specified
+    pub memory_limit_mb: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct EnrichmentSettings {
+    pub hash_executables: bool,
+    pub hash_cache_size: usize,
+
+    /// Report the usage of each logical processor in addition to the aggregate
+    pub per_core_cpu_usage: bool,
+
+    /// Sample disk and network throughput along with CPU and memory usage
+    pub io_metrics: bool,
+
+    /// Attach the account each process runs as (ECS `user.*`)
+    pub user_context: bool,
+    pub user_cache_size: usize,
+
+    /// Number of workers enriching events off the ETW callback threads, inline if zero
+    pub offload_workers: usize,
+
+    /// Maximum number of events waiting for an enrichment worker
+    pub offload_queue_limit: usize,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SelfExclusionSettings {
+    pub enabled: bool,
+    pub descendants: bool,
+}
+
+/// How the process filter treats events of the configured processes.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum ProcessFilterMode {
+    /// Capture events of every process
+    Disabled,
+
+    /// Capture events of the configured processes only
+    Allow,
+
+    /// Capture events of every process except the configured ones
+    Deny,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ProcessFilterSettings {
+    pub mode: ProcessFilterMode,
+
+    /// Executable names (e.g. `powershell.exe`) or full paths, case-insensitive
+    pub processes: Vec<String>,
+}
+
+/// Event field a redaction rule applies to.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum RedactedField {
+    /// Command line of started processes (ECS `process.command_line` and `process.args`)
+    CommandLine,
+
+    /// Paths of file events and loaded images (ECS `file.*` and `dll.*`)
+    FilePath,
+}
+
+/// How a redaction rule replaces the matched text.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum RedactionAction {
+    /// Replace with a fixed placeholder
+    Mask,
+
+    /// Replace with its salted SHA-256 hash, so equal values can still be correlated
+    Hash,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RedactionRule {
+    pub field: RedactedField,
+
+    /// Regular expression matching the text to redact, e.g. `(?i)password\S*` or `.+`
+    pub pattern: String,
+    pub action: RedactionAction,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RedactionSettings {
+    /// Rules applied in order to every captured event before it leaves the host
+    pub rules: Vec<RedactionRule>,
+
+    /// Attach the salted SHA-256 hash of each command line (ECS `labels.command_line_sha256`),
+    /// computed before `rules` apply
+    pub hash_command_line: bool,
+
+    /// Secret prepended to hashed text, so that hashes cannot be reversed by hashing guesses
+    pub salt: Option<String>,
+}
+
+/// What to do with a captured event when the message queue is full.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum QueueFullPolicy {
+    /// Write the event to the persistent backup file
+    Backup,
+
+    /// Discard the event
+    Drop,
+
+    /// Wait for room in the queue, slowing down the ETW callback
+    Block,
+}
+
+/// How events are routed when secondary servers are configured.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum ServerRouting {
+    /// Send to the primary server and, best-effort, to every secondary server
+    Mirror,
+
+    /// Send to a single server, moving on to the next one when it fails
+    Failover,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct QueueFullSettings {
+    pub default: QueueFullPolicy,
+
+    /// Policies of specific event types (e.g. `file`, `process`), overriding the default
+    pub overrides: HashMap<String, QueueFullPolicy>,
+}
+
+impl QueueFullSettings {
+    pub fn policy(&self, event_type: &str) -> QueueFullPolicy {
+        self.overrides
+            .get(event_type)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// Keyword masks of an ETW provider, filtering events before they reach the callback.
+#[derive(Clone, Copy, Deserialize, Serialize)]
+pub struct KeywordMask {
+    /// Events matching any of these keywords are captured, all events if zero
+    pub any: u64,
+
+    /// Captured events must also match all of these keywords
+    pub all: u64,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct HeartbeatSettings {
+    pub enabled: bool,
+    pub interval_seconds: u64,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct EventSummarySettings {
+    pub enabled: bool,
+    pub interval_seconds: u64,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct OverloadSettings {
+    pub enabled: bool,
+
+    /// Backed up events per second over a window that trigger a suspension
+    pub backup_rate_threshold: f64,
+    pub window_seconds: u64,
+
+    /// Event types whose providers may be suspended, earlier entries are suspended first on ties
+    pub suspend_order: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ImageDedupSettings {
+    pub enabled: bool,
+
+    /// Repeated loads of an image by a process are suppressed for this long after a reported one
+    pub window_seconds: u64,
+
+    /// Number of (process, image) pairs remembered
+    pub cache_size: usize,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct LogBufferSettings {
+    pub enabled: bool,
+
+    /// Number of most recent log lines kept in memory
+    pub capacity: usize,
+
+    /// Port of the HTTP endpoint serving the buffered lines, bound to localhost only
+    pub port: u16,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct AdminServerSettings {
+    pub enabled: bool,
+
+    /// Port of the administrative HTTP endpoint, bound to localhost only
+    pub port: u16,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -33,6 +305,14 @@ pub struct TraceName {
     pub user: String,
 }
 
+impl TraceName {
+    /// Name of a session started from `base` (e.g. [`TraceName::kernel`]) by the agent whose
+    /// session suffix is `suffix`.
+    pub fn session(base: &str, suffix: &str) -> String {
+        format!("{base} [{suffix}]")
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct Configuration {
     #[serde(skip, default = "_service_name")]
@@ -42,12 +322,870 @@ pub struct Configuration {
     #[serde(skip, default = "_password_registry_key")]
     pub password_registry_key: String,
     pub server: Url,
+
+    /// Additional API services receiving events, routed according to `server_routing`
+    pub secondary_servers: Vec<Url>,
+
+    pub server_routing: ServerRouting,
     pub zstd_compression_level: i32,
     pub system_refresh_interval_seconds: f64,
     pub backup_directory: PathBuf,
     pub log_level: LogLevel,
+
+    /// Log levels of specific modules, overriding `log_level`
+    pub log_overrides: HashMap<String, LogLevel>,
+
+    pub log_buffer: LogBufferSettings,
+    pub admin_server: AdminServerSettings,
     pub message_queue_limit: usize,
+    pub queue_full: QueueFullSettings,
+
+    /// Opcodes captured by the providers of specific event types, overriding the defaults
+    pub provider_opcodes: HashMap<String, Vec<u8>>,
+
+    /// Keyword masks of the user providers of specific event types
+    pub provider_keywords: HashMap<String, KeywordMask>,
+
+    /// Case-insensitive prefixes of the registry key paths to capture, all paths if empty
+    pub registry_path_prefixes: Vec<String>,
+
     pub dns_resolver: HashMap<String, IpAddr>,
+
+    /// Fail to start if any ETW provider is unavailable, instead of continuing without it
+    pub require_all_providers: bool,
+
+    /// Suffix distinguishing the ETW sessions of this agent, defaults to the process id
+    pub trace_session_suffix: Option<String>,
+
+    /// Events older than this are discarded instead of being backed up or uploaded, kept
+    /// indefinitely if not specified
+    pub max_event_age_hours: Option<u64>,
+
     pub event_post: EventPostSettings,
+    pub http_timeouts: HttpTimeoutSettings,
+    pub backup: BackupSettings,
+    pub enrichment: EnrichmentSettings,
+    pub self_exclusion: SelfExclusionSettings,
+    pub process_filter: ProcessFilterSettings,
+    pub redaction: RedactionSettings,
+    pub resource_limits: ResourceLimitSettings,
+    pub heartbeat: HeartbeatSettings,
+    pub event_summary: EventSummarySettings,
+    pub overload: OverloadSettings,
+    pub image_dedup: ImageDedupSettings,
     pub runtime_threads: usize,
 }
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Self {
+            service_name: _service_name(),
+            trace_name: _trace_name(),
+            password_registry_key: _password_registry_key(),
+            server: Url::parse("https://localhost:12110").expect("Invalid default server URL"),
+            secondary_servers: vec![],
+            server_routing: ServerRouting::Mirror,
+            zstd_compression_level: 3,
+            system_refresh_interval_seconds: 3.0,
+            backup_directory: PathBuf::from("backup"),
+            log_level: LogLevel::Info,
+            log_overrides: HashMap::new(),
+            log_buffer: LogBufferSettings {
+                enabled: false,
+                capacity: 1000,
+                port: 12111,
+            },
+            admin_server: AdminServerSettings {
+                enabled: false,
+                port: 12112,
+            },
+            message_queue_limit: 1000,
+            queue_full: QueueFullSettings {
+                default: QueueFullPolicy::Backup,
+                overrides: HashMap::new(),
+            },
+            provider_opcodes: HashMap::new(),
+            provider_keywords: HashMap::new(),
+            registry_path_prefixes: vec![],
+            dns_resolver: HashMap::new(),
+            require_all_providers: false,
+            trace_session_suffix: None,
+            max_event_age_hours: None,
+            event_post: EventPostSettings {
+                concurrency_limit: 3,
+                flush_limit: 102400,
+                max_batch_records: 10000,
+                max_batch_age_ms: 1000,
+                compress: true,
+                min_compress_bytes: 1024,
+                compressed_buffers: None,
+            },
+            http_timeouts: HttpTimeoutSettings {
+                connect_seconds: 3,
+                request_seconds: None,
+                pool_idle_seconds: 90,
+            },
+            backup: BackupSettings {
+                compression: BackupCompression::Zstd,
+                zstd_compression_level: 9,
+                min_free_space_mb: 512,
+                max_age_hours: 24,
+                encryption_key: None,
+                writer_queue_limit: 10000,
+            },
+            enrichment: EnrichmentSettings {
+                hash_executables: false,
+                hash_cache_size: 1000,
+                per_core_cpu_usage: false,
+                io_metrics: false,
+                user_context: false,
+                user_cache_size: 1000,
+                offload_workers: 0,
+                offload_queue_limit: 1000,
+            },
+            self_exclusion: SelfExclusionSettings {
+                enabled: true,
+                descendants: true,
+            },
+            process_filter: ProcessFilterSettings {
+                mode: ProcessFilterMode::Disabled,
+                processes: vec![],
+            },
+            redaction: RedactionSettings {
+                rules: vec![],
+                hash_command_line: false,
+                salt: None,
+            },
+            resource_limits: ResourceLimitSettings {
+                cpu_limit_percent: None,
+                memory_limit_mb: None,
+            },
+            heartbeat: HeartbeatSettings {
+                enabled: true,
+                interval_seconds: 60,
+            },
+            event_summary: EventSummarySettings {
+                enabled: true,
+                interval_seconds: 300,
+            },
+            overload: OverloadSettings {
+                enabled: false,
+                backup_rate_threshold: 1000.0,
+                window_seconds: 30,
+                suspend_order: ["file", "registry", "image", "tcpip", "udpip"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+            },
+            image_dedup: ImageDedupSettings {
+                enabled: false,
+                window_seconds: 3600,
+                cache_size: 10000,
+            },
+            runtime_threads: 4,
+        }
+    }
+}
+
+impl DefaultConfig for Configuration {
+    const FIELD_DOCS: &'static [(&'static str, &'static str)] = &[
+        ("server", "URL of the (primary) API service"),
+        (
+            "secondary_servers",
+            "URLs of additional API services receiving trace events and heartbeats",
+        ),
+        (
+            "server_routing",
+            "Mirror (primary must accept, secondaries are best-effort with at most event_post.concurrency_limit requests in flight each) or Failover (try servers in order until one accepts)",
+        ),
+        (
+            "zstd_compression_level",
+            "Compression level (1-22) of trace events sent to the server",
+        ),
+        (
+            "system_refresh_interval_seconds",
+            "Interval between refreshes of system information (processes, CPU usage, ...)",
+        ),
+        (
+            "backup_directory",
+            "Directory storing events that could not be sent, relative to the executable",
+        ),
+        ("log_level", "One of Off, Error, Warn, Info, Debug, Trace"),
+        (
+            "log_overrides",
+            "Log levels of specific modules, e.g. wm_client::module::connector: Debug",
+        ),
+        (
+            "log_buffer",
+            "Recent log lines kept in memory and served at http://127.0.0.1:<port>/logs",
+        ),
+        ("log_buffer.enabled", "Keep recent log lines in memory"),
+        (
+            "log_buffer.capacity",
+            "Number of most recent log lines kept in memory",
+        ),
+        (
+            "log_buffer.port",
+            "Port of the HTTP endpoint serving the buffered lines, bound to localhost only",
+        ),
+        (
+            "admin_server",
+            "Administrative endpoint at http://127.0.0.1:<port>, POST /flush (with header X-Windows-Monitor-Admin: 1 and Host 127.0.0.1:<port> or localhost:<port>) sends buffered events and uploads backups immediately",
+        ),
+        ("admin_server.enabled", "Serve the administrative endpoint"),
+        (
+            "admin_server.port",
+            "Port of the administrative endpoint, bound to localhost only",
+        ),
+        (
+            "message_queue_limit",
+            "Maximum number of captured events waiting to be sent, extra events are backed up",
+        ),
+        (
+            "queue_full",
+            "Handling of captured events while the message queue is full",
+        ),
+        (
+            "queue_full.default",
+            "One of Backup (write to the backup file), Drop (discard) or Block (slow down capture)",
+        ),
+        (
+            "queue_full.overrides",
+            "Policies of specific event types: file, image, process, registry, tcpip, udpip",
+        ),
+        (
+            "provider_opcodes",
+            "Opcodes captured by the providers of specific event types (e.g. registry: [14] for set-value only), a subset of the defaults",
+        ),
+        (
+            "provider_keywords",
+            "Keyword masks ({any, all}) of user providers of specific event types (dns), applied by ETW before events reach the agent. Kernel providers are enabled by flags and only support provider_opcodes",
+        ),
+        (
+            "registry_path_prefixes",
+            "Case-insensitive prefixes of the registry key paths to capture (e.g. \\REGISTRY\\MACHINE\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Run), all paths if empty",
+        ),
+        ("dns_resolver", "Static hostname to IP address overrides"),
+        (
+            "require_all_providers",
+            "Fail to start if any ETW provider is unavailable, instead of continuing without it",
+        ),
+        (
+            "trace_session_suffix",
+            "Suffix distinguishing the ETW sessions of this agent, defaults to the process id if null",
+        ),
+        (
+            "max_event_age_hours",
+            "Events older than this are discarded instead of being backed up, and backups last written before then are deleted without uploading. Kept indefinitely if null",
+        ),
+        ("event_post", "Sending trace events to the server"),
+        (
+            "event_post.concurrency_limit",
+            "Maximum number of concurrent requests to the server",
+        ),
+        (
+            "event_post.flush_limit",
+            "Size in bytes of uncompressed events triggering a request",
+        ),
+        (
+            "event_post.max_batch_records",
+            "Number of events triggering a request",
+        ),
+        (
+            "event_post.max_batch_age_ms",
+            "Age in milliseconds of the oldest unsent event triggering a request, bounding the latency of low-volume hosts",
+        ),
+        (
+            "event_post.compress",
+            "Compress events with zstd, disable for agents colocated with the server to save CPU time",
+        ),
+        (
+            "event_post.min_compress_bytes",
+            "Size in bytes of uncompressed events below which a request is sent uncompressed",
+        ),
+        (
+            "event_post.compressed_buffers",
+            "Number of reusable buffers holding compressed requests, at least concurrency_limit (the default if null)",
+        ),
+        ("http_timeouts", "Timeouts of requests to the server"),
+        (
+            "http_timeouts.connect_seconds",
+            "Time limit of establishing a connection, raise for high-latency links",
+        ),
+        (
+            "http_timeouts.request_seconds",
+            "Time limit of whole requests including large backup uploads, unlimited if null",
+        ),
+        (
+            "http_timeouts.pool_idle_seconds",
+            "Time after which idle connections are closed",
+        ),
+        (
+            "backup",
+            "Persistent backup of events while the server is unreachable",
+        ),
+        (
+            "backup.compression",
+            "Zstd or Gzip (for servers without zstd support), applies to new backup files",
+        ),
+        (
+            "backup.zstd_compression_level",
+            "Compression level (1-22) of zstd backup files",
+        ),
+        (
+            "backup.min_free_space_mb",
+            "Keep writing to the current backup file when the disk has less free space",
+        ),
+        (
+            "backup.max_age_hours",
+            "Warn when the oldest backup waiting for upload is older than this",
+        ),
+        (
+            "backup.encryption_key",
+            "Hex-encoded 256-bit AES-GCM key, backups are stored unencrypted if null",
+        ),
+        (
+            "backup.writer_queue_limit",
+            "Maximum number of events waiting to be backed up while the message queue is full, further events are dropped (counted in heartbeats)",
+        ),
+        ("enrichment", "Additional data attached to captured events"),
+        (
+            "enrichment.hash_executables",
+            "Compute SHA-256 hashes of started executables and loaded images, requires offload_workers",
+        ),
+        (
+            "enrichment.hash_cache_size",
+            "Number of file hashes kept in memory",
+        ),
+        (
+            "enrichment.per_core_cpu_usage",
+            "Report the usage of each logical processor in addition to the aggregate",
+        ),
+        (
+            "enrichment.io_metrics",
+            "Sample disk and network throughput along with CPU and memory usage",
+        ),
+        (
+            "enrichment.user_context",
+            "Attach the account each process runs as (user.id, user.name, user.domain), at the cost of a token query per new process",
+        ),
+        (
+            "enrichment.user_cache_size",
+            "Number of process accounts kept in memory, also used for processes that have exited",
+        ),
+        (
+            "enrichment.offload_workers",
+            "Number of workers enriching events so that ETW callbacks only hand them over, enrichment runs in the callbacks if 0",
+        ),
+        (
+            "enrichment.offload_queue_limit",
+            "Maximum number of events waiting for an enrichment worker, the callbacks wait or drop events (per queue_full) beyond it",
+        ),
+        (
+            "self_exclusion",
+            "Dropping events caused by the agent itself",
+        ),
+        ("self_exclusion.enabled", "Drop events of the agent process"),
+        (
+            "self_exclusion.descendants",
+            "Also drop events of processes spawned by the agent, requires process start and end events (provider_opcodes.process 1 and 2)",
+        ),
+        (
+            "process_filter",
+            "Restricting file, registry, network and image events to processes of interest, process events are always captured",
+        ),
+        (
+            "process_filter.mode",
+            "Disabled, Allow (only capture events of the listed processes) or Deny (capture events of every process except the listed ones)",
+        ),
+        (
+            "process_filter.processes",
+            "Executable names (e.g. powershell.exe) or full paths (e.g. C:\\Windows\\System32\\cmd.exe), case-insensitive",
+        ),
+        (
+            "redaction",
+            "Removing sensitive data from events before they leave the host",
+        ),
+        (
+            "redaction.rules",
+            "Rules applied in order, each with a field (CommandLine or FilePath), a regular expression pattern and an action (Mask replaces the matched text with <redacted>, Hash with its salted SHA-256 hash)",
+        ),
+        (
+            "redaction.hash_command_line",
+            "Attach the salted SHA-256 hash of each command line as labels.command_line_sha256, so that distinct command lines can be correlated. Combine with a CommandLine rule matching .+ to ship the hash only",
+        ),
+        (
+            "redaction.salt",
+            "Secret prepended to hashed text so that hashes cannot be reversed by hashing guessed values, unsalted if null. Use the same salt on every agent for hashes to be comparable across hosts",
+        ),
+        ("resource_limits", "Resource caps of the agent process"),
+        (
+            "resource_limits.cpu_limit_percent",
+            "Maximum share of the total CPU time of all processors, no limit if null",
+        ),
+        (
+            "resource_limits.memory_limit_mb",
+            "Maximum committed memory in megabytes, no limit if null",
+        ),
+        ("heartbeat", "Periodic liveness reports sent to the server"),
+        ("heartbeat.enabled", "Send heartbeats"),
+        ("heartbeat.interval_seconds", "Interval between heartbeats"),
+        (
+            "event_summary",
+            "Periodic log summaries of captured events per type",
+        ),
+        ("event_summary.enabled", "Log event summaries"),
+        (
+            "event_summary.interval_seconds",
+            "Interval between summaries, each counting the events captured since the previous one",
+        ),
+        (
+            "overload",
+            "Suspending the busiest provider while events are backed up faster than the agent can send them",
+        ),
+        (
+            "overload.enabled",
+            "Suspend providers under sustained overload",
+        ),
+        (
+            "overload.backup_rate_threshold",
+            "Backed up events per second over a window that suspend a provider, one is resumed per window below half of it",
+        ),
+        (
+            "overload.window_seconds",
+            "Interval between evaluations of the backup rate",
+        ),
+        (
+            "overload.suspend_order",
+            "Event types whose providers may be suspended, the busiest is picked first with earlier entries winning ties",
+        ),
+        (
+            "image_dedup",
+            "Suppressing repeated loads of the same image by the same process",
+        ),
+        (
+            "image_dedup.enabled",
+            "Suppress repeated image loads, counted as suppressed events in heartbeats",
+        ),
+        (
+            "image_dedup.window_seconds",
+            "Repeated loads of an image (same path and checksum) by a process are suppressed for this long after a reported one",
+        ),
+        (
+            "image_dedup.cache_size",
+            "Number of (process, image) pairs remembered",
+        ),
+        ("runtime_threads", "Number of async runtime worker threads"),
+    ];
+}
+
+impl Configuration {
+    pub fn trace_session_suffix(&self) -> String {
+        self.trace_session_suffix
+            .clone()
+            .unwrap_or_else(|| process::id().to_string())
+    }
+
+    /// Opcodes captured by the provider of `event_type`, `default` unless overridden.
+    pub fn provider_opcodes<'a>(&'a self, event_type: &str, default: &'a [u8]) -> &'a [u8] {
+        self.provider_opcodes
+            .get(event_type)
+            .map_or(default, Vec::as_slice)
+    }
+
+    /// Age beyond which events are discarded, if any.
+    pub fn max_event_age(&self) -> Option<Duration> {
+        self.max_event_age_hours
+            .map(|hours| Duration::from_secs(hours * 3600))
+    }
+
+    /// Check value ranges and cross-field constraints that deserialization cannot express.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = ConfigErrors::new();
+
+        for module in self.log_overrides.keys() {
+            errors.require(
+                !module.is_empty() && !module.starts_with(':') && !module.ends_with(':'),
+                format!("log_overrides: invalid module path \"{module}\""),
+            );
+        }
+
+        errors.require(
+            !self.log_buffer.enabled || self.log_buffer.capacity > 0,
+            "log_buffer.capacity: must be positive when the log buffer is enabled",
+        );
+        errors.require(
+            !(self.log_buffer.enabled
+                && self.admin_server.enabled
+                && self.log_buffer.port == self.admin_server.port),
+            "admin_server.port: must differ from log_buffer.port",
+        );
+
+        errors.require(
+            self.server.scheme() == "https" && self.server.has_host(),
+            format!("server: expected an HTTPS URL, got {}", self.server),
+        );
+        for server in &self.secondary_servers {
+            errors.require(
+                server.scheme() == "https" && server.has_host(),
+                format!("secondary_servers: expected an HTTPS URL, got {server}"),
+            );
+            errors.require(
+                *server != self.server,
+                format!("secondary_servers: {server} is already the primary server"),
+            );
+        }
+        for (name, level) in [
+            ("zstd_compression_level", self.zstd_compression_level),
+            (
+                "backup.zstd_compression_level",
+                self.backup.zstd_compression_level,
+            ),
+        ] {
+            errors.require(
+                (1..=22).contains(&level),
+                format!("{name}: expected a value from 1 to 22, got {level}"),
+            );
+        }
+
+        errors.require(
+            self.system_refresh_interval_seconds.is_finite()
+                && self.system_refresh_interval_seconds > 0.0,
+            "system_refresh_interval_seconds: must be positive",
+        );
+        errors.require(
+            !self.backup_directory.as_os_str().is_empty(),
+            "backup_directory: must not be empty",
+        );
+        errors.require(
+            self.message_queue_limit > 0,
+            "message_queue_limit: must be positive",
+        );
+        for event_type in self.queue_full.overrides.keys() {
+            errors.require(
+                EventData::EVENT_TYPES.contains(&event_type.as_str()),
+                format!(
+                    "queue_full.overrides: unknown event type {event_type}, expected one of {}",
+                    EventData::EVENT_TYPES.join(", ")
+                ),
+            );
+        }
+
+        for (event_type, opcodes) in &self.provider_opcodes {
+            match default_opcodes(event_type) {
+                Some(known) => {
+                    errors.require(
+                        !opcodes.is_empty(),
+                        format!("provider_opcodes.{event_type}: must not be empty"),
+                    );
+                    for opcode in opcodes {
+                        errors.require(
+                            known.contains(opcode),
+                            format!(
+                                "provider_opcodes.{event_type}: unknown opcode {opcode}, expected one of {known:?}"
+                            ),
+                        );
+                    }
+                }
+                None => errors.push(format!(
+                    "provider_opcodes: unknown event type {event_type}, expected one of {}",
+                    EventData::EVENT_TYPES.join(", ")
+                )),
+            }
+        }
+
+        for event_type in self.provider_keywords.keys() {
+            if default_opcodes(event_type).is_some() {
+                errors.push(format!(
+                    "provider_keywords: {event_type} is a kernel provider, which does not support keywords"
+                ));
+            } else {
+                errors.require(
+                    user::EVENT_TYPES.contains(&event_type.as_str()),
+                    format!(
+                        "provider_keywords: unknown user provider {event_type}, expected one of [{}]",
+                        user::EVENT_TYPES.join(", ")
+                    ),
+                );
+            }
+        }
+
+        for prefix in &self.registry_path_prefixes {
+            errors.require(
+                prefix.to_uppercase().starts_with("\\REGISTRY\\"),
+                format!(
+                    "registry_path_prefixes: expected a kernel path starting with \\REGISTRY\\, got {prefix}"
+                ),
+            );
+        }
+
+        if let Some(suffix) = &self.trace_session_suffix {
+            errors.require(
+                !suffix.is_empty() && suffix.len() <= 64 && !suffix.contains(['[', ']']),
+                "trace_session_suffix: expected 1 to 64 characters without brackets",
+            );
+        }
+
+        errors.require(
+            self.max_event_age_hours != Some(0),
+            "max_event_age_hours: must be positive",
+        );
+        errors.require(
+            self.event_post.concurrency_limit > 0,
+            "event_post.concurrency_limit: must be positive",
+        );
+        errors.require(
+            self.event_post.flush_limit > 0,
+            "event_post.flush_limit: must be positive",
+        );
+        errors.require(
+            self.event_post.max_batch_records > 0,
+            "event_post.max_batch_records: must be positive",
+        );
+        errors.require(
+            self.event_post.max_batch_age_ms > 0,
+            "event_post.max_batch_age_ms: must be positive",
+        );
+        if let Some(buffers) = self.event_post.compressed_buffers {
+            errors.require(
+                buffers >= self.event_post.concurrency_limit,
+                format!(
+                    "event_post.compressed_buffers: expected at least concurrency_limit ({}), got {buffers}",
+                    self.event_post.concurrency_limit
+                ),
+            );
+        }
+        errors.require(
+            self.http_timeouts.connect_seconds > 0,
+            "http_timeouts.connect_seconds: must be positive",
+        );
+        errors.require(
+            self.http_timeouts.request_seconds != Some(0),
+            "http_timeouts.request_seconds: must be positive",
+        );
+        errors.require(
+            self.backup.max_age_hours > 0,
+            "backup.max_age_hours: must be positive",
+        );
+        errors.require(
+            self.backup.writer_queue_limit > 0,
+            "backup.writer_queue_limit: must be positive",
+        );
+        if let Some(key) = &self.backup.encryption_key
+            && let Err(e) = FrameCipher::from_hex(key)
+        {
+            errors.push(format!("backup.encryption_key: {e}"));
+        }
+
+        errors.require(
+            !self.enrichment.hash_executables || self.enrichment.hash_cache_size > 0,
+            "enrichment.hash_cache_size: must be positive when hash_executables is enabled",
+        );
+        errors.require(
+            !self.enrichment.hash_executables || self.enrichment.offload_workers > 0,
+            "enrichment.hash_executables: requires offload_workers, hashing whole files would stall the ETW callbacks",
+        );
+        errors.require(
+            !self.enrichment.user_context || self.enrichment.user_cache_size > 0,
+            "enrichment.user_cache_size: must be positive when user_context is enabled",
+        );
+        errors.require(
+            self.enrichment.offload_workers == 0 || self.enrichment.offload_queue_limit > 0,
+            "enrichment.offload_queue_limit: must be positive when offload_workers is set",
+        );
+        errors.require(
+            !self.self_exclusion.descendants || self.self_exclusion.enabled,
+            "self_exclusion.descendants: requires self_exclusion.enabled",
+        );
+        if self.self_exclusion.descendants {
+            // Descendants are tracked from process starts and forgotten on process ends, without
+            // the latter the set grows forever and recycled PIDs would be excluded
+            let opcodes =
+                self.provider_opcodes("process", default_opcodes("process").unwrap_or_default());
+            errors.require(
+                opcodes.contains(&1) && opcodes.contains(&2),
+                "provider_opcodes.process: must include 1 and 2 when self_exclusion.descendants is enabled",
+            );
+        }
+        errors.require(
+            self.process_filter.mode != ProcessFilterMode::Allow
+                || !self.process_filter.processes.is_empty(),
+            "process_filter.processes: must not be empty in Allow mode",
+        );
+        errors.require(
+            self.redaction
+                .salt
+                .as_ref()
+                .is_none_or(|salt| !salt.is_empty()),
+            "redaction.salt: must not be empty, use null for no salt",
+        );
+        for (index, rule) in self.redaction.rules.iter().enumerate() {
+            if let Err(e) = Regex::new(&rule.pattern) {
+                errors.push(format!("redaction.rules[{index}].pattern: {e}"));
+            }
+        }
+        errors.require(
+            !self.heartbeat.enabled || self.heartbeat.interval_seconds > 0,
+            "heartbeat.interval_seconds: must be positive when heartbeats are enabled",
+        );
+        errors.require(
+            !self.event_summary.enabled || self.event_summary.interval_seconds > 0,
+            "event_summary.interval_seconds: must be positive when event summaries are enabled",
+        );
+        if self.overload.enabled {
+            errors.require(
+                self.overload.backup_rate_threshold.is_finite()
+                    && self.overload.backup_rate_threshold > 0.0,
+                "overload.backup_rate_threshold: must be positive",
+            );
+            errors.require(
+                self.overload.window_seconds > 0,
+                "overload.window_seconds: must be positive",
+            );
+        }
+        for event_type in &self.overload.suspend_order {
+            errors.require(
+                EventData::EVENT_TYPES.contains(&event_type.as_str()),
+                format!(
+                    "overload.suspend_order: unknown event type {event_type}, expected one of {}",
+                    EventData::EVENT_TYPES.join(", ")
+                ),
+            );
+        }
+
+        if self.image_dedup.enabled {
+            errors.require(
+                self.image_dedup.window_seconds > 0,
+                "image_dedup.window_seconds: must be positive",
+            );
+            errors.require(
+                self.image_dedup.cache_size > 0,
+                "image_dedup.cache_size: must be positive",
+            );
+        }
+
+        if let Some(percent) = self.resource_limits.cpu_limit_percent {
+            errors.require(
+                percent > 0.0 && percent <= 100.0,
+                format!(
+                    "resource_limits.cpu_limit_percent: expected a value in (0, 100], got {percent}"
+                ),
+            );
+        }
+        if let Some(megabytes) = self.resource_limits.memory_limit_mb {
+            errors.require(
+                megabytes > 0 && megabytes.checked_mul(1 << 20).is_some(),
+                format!(
+                    "resource_limits.memory_limit_mb: expected a value from 1 to {}, got {megabytes}",
+                    usize::MAX >> 20
+                ),
+            );
+        }
+
+        errors.require(
+            self.runtime_threads > 0,
+            "runtime_threads: must be positive",
+        );
+
+        errors.into_result()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::{
+        Configuration, KeywordMask, RedactedField, RedactionAction, RedactionRule, TraceName,
+    };
+
+    #[test]
+    fn suffixes_session_names_per_agent() {
+        let mut config = Configuration::default();
+        assert_eq!(config.trace_session_suffix(), process::id().to_string());
+
+        config.trace_session_suffix = Some("agent-1".to_string());
+        let first = TraceName::session(&config.trace_name.kernel, &config.trace_session_suffix());
+        config.trace_session_suffix = Some("agent-2".to_string());
+        let second = TraceName::session(&config.trace_name.kernel, &config.trace_session_suffix());
+
+        assert_eq!(first, format!("{} [agent-1]", config.trace_name.kernel));
+        assert_ne!(first, second);
+        assert_ne!(first, config.trace_name.kernel);
+        assert_ne!(
+            TraceName::session(&config.trace_name.user, "agent-1"),
+            first
+        );
+    }
+
+    #[test]
+    fn validates_trace_session_suffix() {
+        let mut config = Configuration::default();
+        for suffix in ["agent-1", "1234", &"x".repeat(64)] {
+            config.trace_session_suffix = Some(suffix.to_string());
+            assert!(config.validate().is_ok(), "{suffix}");
+        }
+
+        for suffix in ["", "[1234]", "agent]", &"x".repeat(65)] {
+            config.trace_session_suffix = Some(suffix.to_string());
+            let errors = config.validate().unwrap_err();
+            assert!(
+                errors.iter().any(|e| e.starts_with("trace_session_suffix")),
+                "{suffix}: {errors:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn validates_redaction_patterns() {
+        let mut config = Configuration::default();
+        config.redaction.rules = vec![
+            RedactionRule {
+                field: RedactedField::CommandLine,
+                pattern: r"(?i)password=\S+".to_string(),
+                action: RedactionAction::Mask,
+            },
+            RedactionRule {
+                field: RedactedField::FilePath,
+                pattern: "(unclosed".to_string(),
+                action: RedactionAction::Hash,
+            },
+        ];
+
+        let errors = config.validate().unwrap_err();
+        assert_eq!(
+            errors
+                .iter()
+                .filter(|e| e.starts_with("redaction.rules"))
+                .count(),
+            1,
+            "{errors:?}"
+        );
+        assert!(
+            errors
+                .iter()
+                .any(|e| e.starts_with("redaction.rules[1].pattern")),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn validates_keyword_masks_of_user_providers_only() {
+        let mut config = Configuration::default();
+        let mask = KeywordMask { any: 0x1, all: 0 };
+        config.provider_keywords.insert("dns".to_string(), mask);
+        assert!(config.validate().is_ok());
+
+        for event_type in ["process", "unknown"] {
+            config.provider_keywords.clear();
+            config
+                .provider_keywords
+                .insert(event_type.to_string(), mask);
+            let errors = config.validate().unwrap_err();
+            assert!(
+                errors.iter().any(|e| e.starts_with("provider_keywords")),
+                "{event_type}: {errors:?}"
+            );
+        }
+    }
+}
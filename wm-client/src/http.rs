@@ -1,12 +1,89 @@
+use std::iter;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
 
-use reqwest::{Certificate, Client, Identity};
+use async_trait::async_trait;
+use bytes::Bytes;
+use log::{debug, info, warn};
+use reqwest::header::{CONTENT_ENCODING, CONTENT_TYPE, RETRY_AFTER};
+use reqwest::{Certificate, Client, Identity, Response, StatusCode};
+use tokio::sync::Semaphore;
 use url::Url;
+use wm_common::error::ServiceError;
+use wm_common::schema::heartbeat::Heartbeat;
+use wm_common::schema::responses::TraceResponse;
 
-use crate::configuration::Configuration;
+use crate::configuration::{Configuration, ServerRouting};
+use crate::module::heartbeat::EVENT_COUNTERS;
 
-#[derive(Debug)]
+/// Server endpoints used by the [`Connector`](crate::module::connector::Connector).
+///
+/// This abstraction decouples the connector's buffering, backup and reconnection logic from
+/// the underlying HTTP client.
+#[async_trait]
+pub trait ServerApi: Send + Sync {
+    /// Post a batch of newline-delimited events to the `/trace` endpoint, zstd-compressed if
+    /// `compressed` is set.
+    async fn trace(&self, payload: Bytes, compressed: bool) -> Result<TraceResponse, ServiceError>;
+
+    /// Check whether the server is reachable via the `/health-check` endpoint.
+    async fn health_check(&self) -> Result<(), ServiceError>;
+
+    /// Report liveness of the agent via the `/heartbeat` endpoint.
+    async fn heartbeat(&self, heartbeat: &Heartbeat) -> Result<(), ServiceError>;
+}
+
+/// Extract the `Retry-After` delay of a `503 Service Unavailable` response, if any.
+pub fn retry_after(response: &Response) -> Option<Duration> {
+    if response.status() != StatusCode::SERVICE_UNAVAILABLE {
+        return None;
+    }
+
+    // The server always sends a number of seconds rather than an HTTP date
+    let seconds = response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+/// Classify an error of [`reqwest`], which is either a malformed response body or a failure
+/// to exchange data with the server.
+fn _request_error(error: reqwest::Error) -> ServiceError {
+    if error.is_decode() {
+        ServiceError::serialization(error)
+    } else {
+        ServiceError::network(error)
+    }
+}
+
+/// Check the status of a response, `expected` being the status of a successful request.
+fn _check_status(
+    response: &Response,
+    expected: StatusCode,
+    endpoint: &str,
+) -> Result<(), ServiceError> {
+    if let Some(delay) = retry_after(response) {
+        return Err(ServiceError::Busy(delay));
+    }
+
+    if response.status() != expected {
+        return Err(ServiceError::backend(format!(
+            "Unexpected {endpoint} response status {}",
+            response.status()
+        )));
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
 pub struct ApiClient {
     _base_url: Url,
     _client: Client,
@@ -46,9 +123,64 @@ impl ApiClient {
     }
 }
 
+impl ApiClient {
+    async fn _trace(
+        &self,
+        payload: Bytes,
+        compressed: bool,
+    ) -> Result<TraceResponse, ServiceError> {
+        let response = self
+            .post("/trace")
+            .header(
+                CONTENT_ENCODING,
+                if compressed { "zstd" } else { "identity" },
+            )
+            .body(payload)
+            .send()
+            .await
+            .map_err(_request_error)?;
+        _check_status(&response, StatusCode::OK, "trace")?;
+
+        response
+            .json::<TraceResponse>()
+            .await
+            .map_err(_request_error)
+    }
+
+    async fn _health_check(&self) -> Result<(), ServiceError> {
+        let response = self
+            .get("/health-check")
+            .send()
+            .await
+            .map_err(_request_error)?;
+        _check_status(&response, StatusCode::NO_CONTENT, "health check")
+    }
+
+    /// Post a heartbeat already serialized to JSON, so that it can be shared between servers.
+    async fn _heartbeat(&self, heartbeat: Bytes) -> Result<(), ServiceError> {
+        let response = self
+            .post("/heartbeat")
+            .header(CONTENT_TYPE, "application/json")
+            .body(heartbeat)
+            .send()
+            .await
+            .map_err(_request_error)?;
+        _check_status(&response, StatusCode::NO_CONTENT, "heartbeat")
+    }
+}
+
 #[derive(Debug)]
 pub struct HttpClient {
-    _api: ApiClient,
+    /// The primary server followed by the secondary servers
+    _apis: Vec<ApiClient>,
+    _routing: ServerRouting,
+
+    /// Index in `_apis` of the server currently receiving events in failover mode
+    _active: AtomicUsize,
+
+    /// Bounds the number of requests in flight to each secondary server in mirror mode
+    _mirror_permits: Vec<Arc<Semaphore>>,
+
     _client: Client,
 }
 
@@ -66,7 +198,16 @@ impl HttpClient {
                 )
                 .expect("Failed to load client identity"),
             )
-            .connect_timeout(Duration::from_secs(3));
+            .connect_timeout(Duration::from_secs(
+                configuration.http_timeouts.connect_seconds,
+            ))
+            .pool_idle_timeout(Duration::from_secs(
+                configuration.http_timeouts.pool_idle_seconds,
+            ));
+
+        if let Some(seconds) = configuration.http_timeouts.request_seconds {
+            builder = builder.timeout(Duration::from_secs(seconds));
+        }
 
         for (domain, ip) in &configuration.dns_resolver {
             builder = builder.resolve(domain, SocketAddr::new(*ip, 0));
@@ -74,20 +215,426 @@ impl HttpClient {
 
         let client = builder.build().expect("Failed to create HTTP client");
 
-        Self {
-            _api: ApiClient {
-                _base_url: configuration.server.clone(),
+        let apis = iter::once(&configuration.server)
+            .chain(&configuration.secondary_servers)
+            .map(|url| ApiClient {
+                _base_url: url.clone(),
                 _client: client.clone(),
-            },
+            })
+            .collect();
+
+        Self::_from_apis(
+            apis,
+            configuration.server_routing,
+            configuration.event_post.concurrency_limit,
+            client,
+        )
+    }
+
+    /// `apis` holds the primary server followed by the secondary servers, each of which has at
+    /// most `mirror_limit` mirrored requests in flight.
+    fn _from_apis(
+        apis: Vec<ApiClient>,
+        routing: ServerRouting,
+        mirror_limit: usize,
+        client: Client,
+    ) -> Self {
+        let mirror_permits = apis[1..]
+            .iter()
+            .map(|_| Arc::new(Semaphore::new(mirror_limit)))
+            .collect();
+
+        Self {
+            _apis: apis,
+            _routing: routing,
+            _active: AtomicUsize::new(0),
+            _mirror_permits: mirror_permits,
             _client: client,
         }
     }
 
+    /// Client of the primary server.
+    ///
+    /// Requests other than trace events, health checks and heartbeats (e.g. backup uploads)
+    /// always go to the primary server.
     pub fn api(&self) -> &ApiClient {
-        &self._api
+        &self._apis[0]
     }
 
     pub fn client(&self) -> &Client {
         &self._client
     }
+
+    /// Secondary servers, all of which are sent a copy of each request in mirror mode.
+    fn _secondaries(&self) -> &[ApiClient] {
+        &self._apis[1..]
+    }
+
+    /// Send a copy of a request to every secondary server in the background.
+    ///
+    /// A secondary server that is slow or hangs must not accumulate tasks and payload copies, so
+    /// the copy is dropped (and counted in heartbeats) while it has too many requests in flight.
+    fn _mirror<F, Fut>(&self, what: &'static str, request: F)
+    where
+        F: Fn(ApiClient) -> Fut,
+        Fut: Future<Output = Result<(), ServiceError>> + Send + 'static,
+    {
+        for (api, permits) in self._secondaries().iter().zip(&self._mirror_permits) {
+            let Ok(permit) = permits.clone().try_acquire_owned() else {
+                debug!(
+                    "Too many requests in flight to {}, not mirroring {what}",
+                    api._base_url
+                );
+                EVENT_COUNTERS.unmirrored(1);
+                continue;
+            };
+
+            let url = api._base_url.clone();
+            let request = request(api.clone());
+            tokio::spawn(async move {
+                if let Err(e) = request.await {
+                    warn!("Failed to mirror {what} to {url}: {e}");
+                }
+
+                drop(permit);
+            });
+        }
+    }
+
+    fn _activate(&self, index: usize) {
+        let previous = self._active.swap(index, Ordering::Relaxed);
+        if previous != index {
+            info!(
+                "Switching server from {} to {}",
+                self._apis[previous]._base_url, self._apis[index]._base_url
+            );
+        }
+    }
+
+    /// Indices in `_apis` of the servers to try in failover mode, starting from the active one.
+    fn _failover_order(&self) -> impl Iterator<Item = usize> {
+        let start = self._active.load(Ordering::Relaxed);
+        (0..self._apis.len()).map(move |offset| (start + offset) % self._apis.len())
+    }
+}
+
+#[async_trait]
+impl ServerApi for HttpClient {
+    async fn trace(&self, payload: Bytes, compressed: bool) -> Result<TraceResponse, ServiceError> {
+        match self._routing {
+            ServerRouting::Mirror => {
+                self._mirror("trace events", |api| {
+                    let payload = payload.clone();
+                    async move { api._trace(payload, compressed).await.map(|_| ()) }
+                });
+
+                self.api()._trace(payload, compressed).await
+            }
+            ServerRouting::Failover => {
+                let mut last_error = None;
+                for index in self._failover_order() {
+                    match self._apis[index]._trace(payload.clone(), compressed).await {
+                        Ok(response) => {
+                            self._activate(index);
+                            return Ok(response);
+                        }
+                        Err(e) => {
+                            warn!(
+                                "Failed to send trace events to {}: {e}",
+                                self._apis[index]._base_url
+                            );
+                            last_error = Some(e);
+                        }
+                    }
+                }
+
+                Err(last_error.expect("At least the primary server must be configured"))
+            }
+        }
+    }
+
+    async fn health_check(&self) -> Result<(), ServiceError> {
+        match self._routing {
+            // Secondaries are best-effort, so only the primary decides whether to send events
+            ServerRouting::Mirror => self.api()._health_check().await,
+
+            // Check servers in order of preference, so that events return to the primary server
+            // once it recovers
+            ServerRouting::Failover => {
+                let mut last_error = None;
+                for (index, api) in self._apis.iter().enumerate() {
+                    match api._health_check().await {
+                        Ok(()) => {
+                            self._activate(index);
+                            return Ok(());
+                        }
+                        Err(e) => last_error = Some(e),
+                    }
+                }
+
+                Err(last_error.expect("At least the primary server must be configured"))
+            }
+        }
+    }
+
+    async fn heartbeat(&self, heartbeat: &Heartbeat) -> Result<(), ServiceError> {
+        let heartbeat = Bytes::from(serde_json::to_vec(heartbeat)?);
+        match self._routing {
+            ServerRouting::Mirror => {
+                self._mirror("heartbeat", |api| {
+                    let heartbeat = heartbeat.clone();
+                    async move { api._heartbeat(heartbeat).await }
+                });
+
+                self.api()._heartbeat(heartbeat).await
+            }
+
+            // Report liveness to the server receiving the events
+            ServerRouting::Failover => {
+                let index = self._active.load(Ordering::Relaxed);
+                self._apis[index]._heartbeat(heartbeat).await
+            }
+        }
+    }
+}
+
+/// Test double of [`ServerApi`] answering from scripted results and recording what it was sent.
+#[cfg(test)]
+pub mod mock {
+    use std::collections::VecDeque;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use parking_lot::Mutex as BlockingMutex;
+    use tokio::time::sleep;
+    use wm_common::error::ServiceError;
+    use wm_common::schema::heartbeat::Heartbeat;
+    use wm_common::schema::responses::TraceResponse;
+
+    use super::ServerApi;
+
+    #[derive(Default)]
+    pub struct MockServer {
+        /// Results of the next trace requests, which succeed once these are exhausted
+        _trace_results: BlockingMutex<VecDeque<Result<TraceResponse, ServiceError>>>,
+
+        /// Results of the next health checks, which succeed once these are exhausted
+        _health_check_results: BlockingMutex<VecDeque<Result<(), ServiceError>>>,
+
+        /// Time each trace request takes before completing
+        _trace_delay: BlockingMutex<Duration>,
+
+        /// Payloads of trace requests along with whether they were compressed
+        _traced: BlockingMutex<Vec<(Bytes, bool)>>,
+        _health_checks: AtomicUsize,
+    }
+
+    impl MockServer {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn push_trace(&self, result: Result<TraceResponse, ServiceError>) {
+            self._trace_results.lock().push_back(result);
+        }
+
+        pub fn push_health_check(&self, result: Result<(), ServiceError>) {
+            self._health_check_results.lock().push_back(result);
+        }
+
+        pub fn delay_traces(&self, delay: Duration) {
+            *self._trace_delay.lock() = delay;
+        }
+
+        pub fn traced(&self) -> Vec<(Bytes, bool)> {
+            self._traced.lock().clone()
+        }
+
+        pub fn health_checks(&self) -> usize {
+            self._health_checks.load(Ordering::Relaxed)
+        }
+    }
+
+    #[async_trait]
+    impl ServerApi for MockServer {
+        async fn trace(
+            &self,
+            payload: Bytes,
+            compressed: bool,
+        ) -> Result<TraceResponse, ServiceError> {
+            let delay = *self._trace_delay.lock();
+            sleep(delay).await;
+
+            self._traced.lock().push((payload, compressed));
+            self._trace_results
+                .lock()
+                .pop_front()
+                .unwrap_or(Ok(TraceResponse))
+        }
+
+        async fn health_check(&self) -> Result<(), ServiceError> {
+            self._health_checks.fetch_add(1, Ordering::Relaxed);
+            self._health_check_results
+                .lock()
+                .pop_front()
+                .unwrap_or(Ok(()))
+        }
+
+        async fn heartbeat(&self, _: &Heartbeat) -> Result<(), ServiceError> {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use reqwest::Client;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::time::{Instant, sleep};
+    use url::Url;
+
+    use super::{ApiClient, HttpClient, ServerApi};
+    use crate::configuration::ServerRouting;
+
+    const _TRACE_RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 4\r\nConnection: close\r\n\r\nnull";
+
+    /// Read a whole request, returning its head and body.
+    async fn _read_request(stream: &mut TcpStream) -> (String, Vec<u8>) {
+        let mut request = vec![];
+        let mut chunk = [0; 4096];
+        loop {
+            let read = stream.read(&mut chunk).await.unwrap();
+            assert!(
+                read > 0,
+                "Connection closed before the request was complete"
+            );
+            request.extend_from_slice(&chunk[..read]);
+
+            let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") else {
+                continue;
+            };
+            let head = String::from_utf8(request[..end].to_vec()).unwrap();
+            let length = head
+                .lines()
+                .find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.eq_ignore_ascii_case("content-length")
+                        .then(|| value.trim().parse::<usize>().unwrap())
+                })
+                .unwrap_or(0);
+            if request.len() >= end + 4 + length {
+                return (head, request[end + 4..end + 4 + length].to_vec());
+            }
+        }
+    }
+
+    fn _api(listener: &TcpListener) -> ApiClient {
+        ApiClient {
+            _base_url: Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap(),
+            _client: Client::new(),
+        }
+    }
+
+    /// Serve trace requests in the background, counting them. Requests are answered if
+    /// `respond` is set, and left hanging otherwise.
+    fn _serve(listener: TcpListener, respond: bool) -> Arc<AtomicUsize> {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let counter = counter.clone();
+                tokio::spawn(async move {
+                    _read_request(&mut stream).await;
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    if respond {
+                        stream.write_all(_TRACE_RESPONSE).await.unwrap();
+                    } else {
+                        sleep(Duration::MAX).await;
+                    }
+                });
+            }
+        });
+
+        requests
+    }
+
+    /// Post a trace to a bare HTTP listener, returning the request head and body it received.
+    async fn _capture_trace(payload: &'static [u8], compressed: bool) -> (String, Vec<u8>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api = _api(&listener);
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let request = _read_request(&mut stream).await;
+            stream.write_all(_TRACE_RESPONSE).await.unwrap();
+            request
+        });
+
+        api._trace(Bytes::from_static(payload), compressed)
+            .await
+            .unwrap();
+        server.await.unwrap()
+    }
+
+    fn _content_encoding(head: &str) -> Option<&str> {
+        head.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("content-encoding")
+                .then_some(value.trim())
+        })
+    }
+
+    #[tokio::test]
+    async fn declares_content_encoding_of_traces() {
+        let (head, body) = _capture_trace(b"{\"event\":1}\n", false).await;
+        assert!(head.starts_with("POST /trace "), "{head}");
+        assert_eq!(_content_encoding(&head), Some("identity"));
+        assert_eq!(body, b"{\"event\":1}\n");
+
+        let (head, body) = _capture_trace(b"\x28\xb5\x2f\xfd", true).await;
+        assert_eq!(_content_encoding(&head), Some("zstd"));
+        assert_eq!(body, b"\x28\xb5\x2f\xfd");
+    }
+
+    #[tokio::test]
+    async fn bounds_requests_in_flight_to_hanging_secondaries() {
+        const LIMIT: usize = 2;
+
+        let primary = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let secondary = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = HttpClient::_from_apis(
+            vec![_api(&primary), _api(&secondary)],
+            ServerRouting::Mirror,
+            LIMIT,
+            Client::new(),
+        );
+        let primary = _serve(primary, true);
+        let secondary = _serve(secondary, false);
+
+        // The primary keeps accepting events while the secondary never answers
+        for _ in 0..LIMIT * 5 {
+            client
+                .trace(Bytes::from_static(b"{\"event\":1}\n"), false)
+                .await
+                .unwrap();
+        }
+        assert_eq!(primary.load(Ordering::Relaxed), LIMIT * 5);
+        assert_eq!(client._mirror_permits[0].available_permits(), 0);
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while secondary.load(Ordering::Relaxed) < LIMIT && Instant::now() < deadline {
+            sleep(Duration::from_millis(20)).await;
+        }
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(secondary.load(Ordering::Relaxed), LIMIT);
+    }
 }
@@ -4,3 +4,4 @@ pub mod cli;
 pub mod configuration;
 pub mod http;
 pub mod module;
+pub mod self_test;
@@ -1,18 +1,22 @@
 use std::error::Error;
+use std::fmt::Display;
 use std::fs::File as BlockingFile;
 use std::io::{Write, stdout};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use std::{env, panic};
+use std::{env, process};
 
-use async_compression::tokio::write::ZstdDecoder;
+use async_compression::tokio::write::{GzipDecoder, ZstdDecoder};
 use clap::Parser;
 use config_file::FromConfigFile;
 use log::{debug, error, info, warn};
 use mimalloc::MiMalloc;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 use tokio::runtime::Builder;
+use tokio::signal::windows::{ctrl_close, ctrl_logoff, ctrl_shutdown};
+use tokio::sync::SetOnce;
 use tokio::time::sleep;
 use tokio::{fs, io, signal, task};
 use windows::Win32::System::Services::SC_MANAGER_ALL_ACCESS;
@@ -20,22 +24,70 @@ use windows_services::{Command, Service};
 use wm_client::agent::Agent;
 use wm_client::cli::{Arguments, ServiceAction};
 use wm_client::configuration::Configuration;
+use wm_client::http::HttpClient;
 use wm_client::module::Module;
-use wm_common::error::RuntimeError;
-use wm_common::logger::initialize_logger;
+use wm_client::self_test::run_self_test;
+use wm_common::error::{RuntimeError, WindowsError};
+use wm_common::job::AssignJobGuard;
+use wm_common::logger::{LogBuffer, initialize_logger, install_panic_hook};
 use wm_common::registry::RegistryKey;
 use wm_common::service::service_manager::ServiceManager;
 use wm_common::service::status::ServiceState;
 use wm_common::utils::to_c_string;
+use wm_common::{config, version_info};
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
+/// Set when any thread panics, so that the agent stops gracefully and flushes buffered events
+/// instead of continuing in a degraded state
+static PANICKED: SetOnce<()> = SetOnce::const_new();
+
+/// Exit codes of startup failures that an operator has to fix, so that they can be told apart in
+/// the service control manager
+const _EXIT_RUNTIME_ERROR: i32 = 1;
+const _EXIT_PASSWORD_UNAVAILABLE: i32 = 2;
+const _EXIT_PASSWORD_INVALID: i32 = 3;
+const _EXIT_SERVICE_STATE: i32 = 4;
+
+/// Log an unrecoverable error and exit with `code`.
+fn _exit_with(code: i32, message: impl Display) -> ! {
+    error!("{message}");
+    log::logger().flush();
+    process::exit(code);
+}
+
 fn _open_registry_password(config: &Configuration) -> RegistryKey {
     RegistryKey::new(&to_c_string(config.password_registry_key.clone()))
         .expect("Failed to open registry key")
 }
 
+fn _apply_resource_limits(config: &Configuration) -> Result<Option<AssignJobGuard>, WindowsError> {
+    let limits = &config.resource_limits;
+    if limits.cpu_limit_percent.is_none() && limits.memory_limit_mb.is_none() {
+        return Ok(None);
+    }
+
+    let job = AssignJobGuard::new(c"wm-client-job-object")?;
+    if let Some(percent) = limits.cpu_limit_percent {
+        info!("Limiting CPU usage to {percent}%");
+        job.cpu_limit(percent / 100.0)?;
+    }
+
+    if let Some(megabytes) = limits.memory_limit_mb {
+        info!("Limiting memory usage to {megabytes} MB");
+        let bytes = megabytes.checked_mul(1 << 20).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Memory limit of {megabytes} MB is out of range"),
+            )
+        })?;
+        job.memory_limit(bytes)?;
+    }
+
+    Ok(Some(job))
+}
+
 fn _read_password(prompt: &str) -> String {
     let mut stdout = stdout();
     print!("{prompt}");
@@ -44,21 +96,66 @@ fn _read_password(prompt: &str) -> String {
     rpassword::read_password().expect("Unable to read password")
 }
 
+/// Copy `source` through `decompressor`, returning the number of compressed bytes read.
+///
+/// The decompressor is shut down at the end, failing if the compressed stream is truncated.
+async fn _decompress<R, W>(source: &mut R, mut decompressor: W) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let bytes = io::copy(source, &mut decompressor).await?;
+    decompressor.shutdown().await?;
+    Ok(bytes)
+}
+
+/// Wait for a signal requesting the agent to terminate gracefully.
+///
+/// Besides Ctrl+C, this also handles console close and system shutdown events so that buffered
+/// events are flushed before the process is terminated. User logoff events are ignored when
+/// running as a service, since services receive them whenever any user logs off.
+async fn _wait_for_termination(is_service: bool) -> io::Result<&'static str> {
+    let mut close = ctrl_close()?;
+    let mut shutdown = ctrl_shutdown()?;
+    let mut logoff = ctrl_logoff()?;
+
+    tokio::select! {
+        result = signal::ctrl_c() => result.map(|_| "Ctrl+C"),
+        _ = close.recv() => Ok("console close"),
+        _ = shutdown.recv() => Ok("system shutdown"),
+        _ = logoff.recv(), if !is_service => Ok("user logoff"),
+    }
+}
+
 fn main() {
-    let original_panic_hook = panic::take_hook();
-    panic::set_hook(Box::new(move |p| {
-        error!("Panic: {p}");
-        original_panic_hook(p);
-    }));
+    install_panic_hook(|| {
+        let _ = PANICKED.set(());
+    });
 
     let arguments = Arguments::parse();
+    if let ServiceAction::GenerateConfig { output } = &arguments.command {
+        config::write_default_config::<Configuration>(output.as_deref())
+            .expect("Failed to write default configuration");
+        return;
+    }
+    if let ServiceAction::Version = arguments.command {
+        println!("{} {}", env!("CARGO_PKG_NAME"), version_info!());
+        return;
+    }
+
     let executable_path = env::current_exe().expect("Failed to get current executable path");
     let app_directory = executable_path
         .parent()
         .expect("Failed to get application directory")
         .to_path_buf();
-    let configuration = Configuration::from_config_file(app_directory.join("client-config.yml"))
-        .expect("Failed to load configuration");
+    let configuration =
+        Configuration::from_config_file(config::find_config_file(&app_directory, "client-config"))
+            .expect("Failed to load configuration");
+    config::exit_if_invalid(
+        configuration.validate(),
+        &app_directory.join("logs"),
+        "wm-client",
+    );
 
     let rt = Builder::new_multi_thread()
         .enable_all()
@@ -71,13 +168,17 @@ fn main() {
         .build()
         .expect("Failed to create Tokio runtime");
 
-    rt.block_on(async_main(
+    if let Err(e) = rt.block_on(async_main(
         arguments,
         executable_path,
         app_directory,
         configuration,
-    ))
-    .expect("Runtime completed with error");
+    )) {
+        _exit_with(
+            _EXIT_RUNTIME_ERROR,
+            format!("Runtime completed with error: {e}"),
+        );
+    }
 }
 
 async fn async_main(
@@ -93,14 +194,20 @@ async fn async_main(
         .await
         .expect("Failed to create log directory");
 
+    let log_buffer = configuration
+        .log_buffer
+        .enabled
+        .then(|| Arc::new(LogBuffer::new(configuration.log_buffer.capacity)));
     initialize_logger(
         configuration.log_level,
+        &configuration.log_overrides,
         BlockingFile::create(log_directory.join(format!(
                 "wm-client-{}.log",
                 SystemTime::now()
                     .duration_since(UNIX_EPOCH)
                     .expect("Time went backwards").as_millis()
             )))?,
+        log_buffer.clone(),
     )?;
     debug!("Initialized logger");
 
@@ -135,28 +242,66 @@ async fn async_main(
             );
         }
         ServiceAction::Start => {
-            // let job = AssignJobGuard::new("wm-client-job-object")?;
-            // job.cpu_limit(0.01)?;
-
-            let key = _open_registry_password(&configuration);
-            let value = key.read().expect("Failed to read registry value");
-            let password = String::from_utf8(value).expect("Registry password is not valid UTF-8");
+            let _job = _apply_resource_limits(&configuration)?;
 
-            let agent =
-                Arc::new(Agent::async_new(configuration.clone(), app_directory, &password).await);
-            let s_handle = if windows_service_detector::is_running_as_windows_service() == Ok(true)
+            // Running unattended as a service, so failures must be actionable from the log alone
+            let value = match RegistryKey::new(&to_c_string(
+                configuration.password_registry_key.clone(),
+            ))
+            .and_then(|key| key.read())
             {
+                Ok(value) => value,
+                Err(e) => _exit_with(
+                    _EXIT_PASSWORD_UNAVAILABLE,
+                    format!(
+                        "Unable to read the certificate password from HKLM\\{}: {e}. Store it with \"{} password\"",
+                        configuration.password_registry_key,
+                        executable_path.display(),
+                    ),
+                ),
+            };
+            let password = match String::from_utf8(value) {
+                Ok(password) => password,
+                Err(_) => _exit_with(
+                    _EXIT_PASSWORD_INVALID,
+                    format!(
+                        "The certificate password in HKLM\\{} is not valid UTF-8. Store it again with \"{} password\"",
+                        configuration.password_registry_key,
+                        executable_path.display(),
+                    ),
+                ),
+            };
+
+            let agent = Arc::new(
+                Agent::async_new(configuration.clone(), app_directory, &password, log_buffer)
+                    .await?,
+            );
+            let is_service = windows_service_detector::is_running_as_windows_service() == Ok(true);
+            let s_handle = if is_service {
                 info!("Checking service {}", configuration.service_name);
 
-                let scm = ServiceManager::new(SC_MANAGER_ALL_ACCESS)?;
-                let status =
-                    scm.query_service_status(&to_c_string(configuration.service_name.clone()))?;
+                let status = match ServiceManager::new(SC_MANAGER_ALL_ACCESS).and_then(|scm| {
+                    scm.query_service_status(&to_c_string(configuration.service_name.clone()))
+                }) {
+                    Ok(status) => status,
+                    Err(e) => _exit_with(
+                        _EXIT_SERVICE_STATE,
+                        format!(
+                            "Unable to query the status of service {}: {e}. Make sure it was created with \"{} create\"",
+                            configuration.service_name,
+                            executable_path.display(),
+                        ),
+                    ),
+                };
                 info!("Service status: {status:?}");
                 if status.current_state != ServiceState::StartPending {
-                    Err(RuntimeError::new(format!(
-                        "Invalid state {:?}",
-                        status.current_state
-                    )))?;
+                    _exit_with(
+                        _EXIT_SERVICE_STATE,
+                        format!(
+                            "Service {} is in state {:?} instead of StartPending. Start it with \"sc start\" instead of running the start command directly",
+                            configuration.service_name, status.current_state,
+                        ),
+                    );
                 }
 
                 info!("Starting service {}", configuration.service_name);
@@ -187,8 +332,12 @@ async fn async_main(
             let mut a_handle = tokio::spawn(agent_cloned.run());
 
             tokio::select! {
-                _ = signal::ctrl_c() => {
-                    info!("Received Ctrl+C signal");
+                reason = _wait_for_termination(is_service) => {
+                    info!("Received {} signal", reason?);
+                    agent.stop();
+                },
+                _ = PANICKED.wait() => {
+                    error!("Stopping agent after a panic");
                     agent.stop();
                 },
                 _ = &mut a_handle => {
@@ -200,6 +349,10 @@ async fn async_main(
                 s_handle.await??;
             }
             a_handle.await??;
+
+            if PANICKED.initialized() {
+                Err(RuntimeError::new("Agent stopped after a panic"))?;
+            }
         }
         ServiceAction::Stop => {
             info!("Stopping service {}", configuration.service_name);
@@ -249,14 +402,37 @@ async fn async_main(
         })
         .await
         .expect("Unable to set password"),
+        ServiceAction::SelfTest { send } => {
+            let http = if send {
+                let key = _open_registry_password(&configuration);
+                let value = key.read().expect("Failed to read registry value");
+                let password =
+                    String::from_utf8(value).expect("Registry password is not valid UTF-8");
+                Some(HttpClient::new(&configuration, &password))
+            } else {
+                None
+            };
+
+            run_self_test(&configuration, http.as_ref()).await?;
+            info!("Self-test completed successfully");
+        }
+        ServiceAction::GenerateConfig { .. } | ServiceAction::Version => {
+            // Handled before loading the configuration
+        }
         ServiceAction::Zstd { source, dest } => {
             let mut source_file = fs::File::open(&source).await?;
             let mut dest_file = fs::File::create_new(&dest).await?;
 
-            let mut decompressor = ZstdDecoder::new(&mut dest_file);
-            let bytes = io::copy(&mut source_file, &mut decompressor)
-                .await
-                .expect("Failure during decompression");
+            // Backups are named after their compression, gzip ones with a .gz extension
+            let gzip = source
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("gz"));
+            let bytes = if gzip {
+                _decompress(&mut source_file, GzipDecoder::new(&mut dest_file)).await
+            } else {
+                _decompress(&mut source_file, ZstdDecoder::new(&mut dest_file)).await
+            }
+            .expect("Failure during decompression");
 
             info!(
                 "Decompressed {bytes} bytes from {} to {}",
@@ -0,0 +1,167 @@
+use std::error::Error;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use log::{debug, info, warn};
+use serde_json::json;
+use tokio::io;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::SetOnce;
+
+use crate::configuration::Configuration;
+use crate::module::Module;
+use crate::module::backup::BackupSender;
+use crate::module::connector::Connector;
+use crate::module::log_server::{is_loopback_host, read_request_head, write_response};
+
+/// Header every request must carry. Browsers cannot add it to a cross-origin request without a
+/// CORS preflight, which this server never approves, so web pages cannot trigger actions.
+const _REQUIRED_HEADER: &str = "x-windows-monitor-admin: 1";
+
+/// Whether the request `head` received on loopback `port` may perform administrative actions.
+///
+/// The header alone does not stop DNS rebinding: a page whose domain is rebound to 127.0.0.1 is
+/// same-origin with this server and may set any header, but its requests carry its own host.
+fn _authorized(head: &str, port: u16) -> bool {
+    is_loopback_host(head, port)
+        && head
+            .lines()
+            .skip(1)
+            .any(|line| line.trim().eq_ignore_ascii_case(_REQUIRED_HEADER))
+}
+
+/// Serves administrative actions on the loopback interface:
+/// - `POST /flush`: send every buffered event and upload every backup immediately
+pub struct AdminServer {
+    _config: Arc<Configuration>,
+    _connector: Arc<Connector>,
+    _backup_sender: Arc<BackupSender>,
+    _listener: SetOnce<TcpListener>,
+    _stopped: Arc<SetOnce<()>>,
+}
+
+impl AdminServer {
+    pub fn new(
+        config: Arc<Configuration>,
+        connector: Arc<Connector>,
+        backup_sender: Arc<BackupSender>,
+    ) -> Self {
+        Self {
+            _config: config,
+            _connector: connector,
+            _backup_sender: backup_sender,
+            _listener: SetOnce::new(),
+            _stopped: Arc::new(SetOnce::new()),
+        }
+    }
+
+    async fn _flush(&self) -> (&'static str, String) {
+        let events = self._connector.flush().await;
+        info!("Flushed {events} buffered events on request");
+
+        match self._backup_sender.flush().await {
+            Ok(backups) => {
+                info!("Uploaded {backups} backups on request");
+                let body = json!({ "events_flushed": events, "backups_uploaded": backups });
+                ("200 OK", body.to_string())
+            }
+            Err(e) => {
+                warn!("Unable to upload backups on request: {e}");
+                let body = json!({ "events_flushed": events, "error": e.to_string() });
+                ("500 Internal Server Error", body.to_string())
+            }
+        }
+    }
+
+    async fn _serve(self: Arc<Self>, mut stream: TcpStream) -> io::Result<()> {
+        let port = stream.local_addr()?.port();
+        let head = read_request_head(&mut stream).await?.unwrap_or_default();
+        let mut parts = head.lines().next().unwrap_or_default().split(' ');
+
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some(_), Some(_)) if !_authorized(&head, port) => ("403 Forbidden", String::new()),
+            (Some("POST"), Some("/flush")) => self._flush().await,
+            (Some(_), Some("/flush")) => ("405 Method Not Allowed", String::new()),
+            (Some(_), Some(_)) => ("404 Not Found", String::new()),
+            _ => ("400 Bad Request", String::new()),
+        };
+
+        write_response(&mut stream, status, "application/json", &body).await
+    }
+}
+
+#[async_trait]
+impl Module for AdminServer {
+    type EventType = io::Result<TcpStream>;
+
+    fn name(&self) -> &str {
+        "AdminServer"
+    }
+
+    fn stopped(&self) -> Arc<SetOnce<()>> {
+        self._stopped.clone()
+    }
+
+    async fn listen(self: Arc<Self>) -> Self::EventType {
+        let listener = self._listener.wait().await;
+        listener.accept().await.map(|(stream, _)| stream)
+    }
+
+    async fn handle(
+        self: Arc<Self>,
+        event: Self::EventType,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match event {
+            Ok(stream) => {
+                tokio::spawn(async move {
+                    if let Err(e) = self._serve(stream).await {
+                        debug!("Unable to serve admin request: {e}");
+                    }
+                });
+            }
+            Err(e) => warn!("Unable to accept admin request: {e}"),
+        }
+
+        Ok(())
+    }
+
+    async fn before_hook(self: Arc<Self>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, self._config.admin_server.port));
+        let listener = TcpListener::bind(addr).await?;
+        info!("Serving administrative actions at http://{addr}");
+
+        self._listener.set(listener)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::_authorized;
+
+    #[test]
+    fn authorizes_loopback_requests_with_header() {
+        assert!(_authorized(
+            "POST /flush HTTP/1.1\r\nHost: 127.0.0.1:8081\r\nX-Windows-Monitor-Admin: 1\r\n\r\n",
+            8081
+        ));
+        assert!(_authorized(
+            "POST /flush HTTP/1.1\r\nhost: localhost:8081\r\nx-windows-monitor-admin: 1\r\n\r\n",
+            8081
+        ));
+    }
+
+    #[test]
+    fn rejects_rebound_or_unmarked_requests() {
+        for head in [
+            "POST /flush HTTP/1.1\r\nHost: attacker.example:8081\r\nX-Windows-Monitor-Admin: 1\r\n\r\n",
+            "POST /flush HTTP/1.1\r\nHost: 127.0.0.1:9090\r\nX-Windows-Monitor-Admin: 1\r\n\r\n",
+            "POST /flush HTTP/1.1\r\nX-Windows-Monitor-Admin: 1\r\n\r\n",
+            "POST /flush HTTP/1.1\r\nHost: 127.0.0.1:8081\r\n\r\n",
+            "POST /flush HTTP/1.1\r\nHost: 127.0.0.1:8081\r\nX-Windows-Monitor-Admin: 0\r\n\r\n",
+        ] {
+            assert!(!_authorized(head, 8081), "{head:?}");
+        }
+    }
+}
@@ -28,6 +28,18 @@ impl BackupSender {
             _last_backup_switch: Mutex::new(Instant::now()),
         }
     }
+
+    /// Switch to a new backup file and upload every backup now, including the events written
+    /// to the current file so far. Returns the number of uploaded files.
+    pub async fn flush(&self) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        {
+            let mut backup = self._backup.lock().await;
+            backup.switch_backup().await?;
+            *self._last_backup_switch.lock().await = Instant::now();
+        }
+
+        Backup::upload(self._backup.clone(), self._http.clone(), self.stopped()).await
+    }
 }
 
 #[async_trait]
@@ -65,8 +77,10 @@ impl Module for BackupSender {
         {
             // We switch backup files at most once every 1 minute or if the file exceeds 5 MB
         } else {
-            backup.switch_backup().await;
-            *last_backup_switch = Instant::now();
+            match backup.switch_backup().await {
+                Ok(()) => *last_backup_switch = Instant::now(),
+                Err(e) => error!("Unable to switch backup file: {e}"),
+            }
         }
 
         Ok(())
@@ -1,26 +1,28 @@
 use std::error::Error;
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Weak};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use async_compression::Level;
 use async_compression::tokio::bufread::ZstdEncoder;
 use async_trait::async_trait;
 use bytes::BytesMut;
-use log::{debug, error};
+use log::{debug, error, info};
+use parking_lot::Mutex as BlockingMutex;
 use tokio::io::AsyncReadExt;
-use tokio::sync::{Mutex, OwnedMutexGuard, RwLock, SetOnce, mpsc};
-use tokio::task::JoinHandle;
+use tokio::sync::{Mutex, OwnedMutexGuard, RwLock, Semaphore, SetOnce, mpsc};
+use tokio::task::{JoinHandle, JoinSet};
 use tokio::time::error::Elapsed;
 use tokio::time::{sleep, timeout};
+use wm_common::error::ServiceError;
 use wm_common::pool::Pool;
 use wm_common::schema::event::CapturedEventRecord;
-use wm_common::schema::responses::TraceResponse;
 
 use crate::backup::Backup;
 use crate::configuration::Configuration;
-use crate::http::HttpClient;
+use crate::http::ServerApi;
 use crate::module::Module;
+use crate::module::heartbeat::EVENT_COUNTERS;
 
 pub struct Connector {
     _config: Arc<Configuration>,
@@ -28,7 +30,7 @@ pub struct Connector {
     _stopped: Arc<SetOnce<()>>,
     _backup: Arc<Mutex<Backup>>,
 
-    _http: Arc<HttpClient>,
+    _server: Arc<dyn ServerApi>,
 
     _errors_count: Arc<RwLock<usize>>,
     _reconnect: Arc<Reconnector>,
@@ -36,7 +38,15 @@ pub struct Connector {
 
     _uncompressed_buffer_pool: Vec<Arc<Mutex<Vec<u8>>>>,
     _uncompressed_buffer_pool_index: AtomicUsize,
+
+    /// When the first event of the current uncompressed buffer was added, if any
+    _batch_started: BlockingMutex<Option<Instant>>,
+    _batch_records: AtomicUsize,
     _compressed_buffer_pool: Arc<Pool<Option<BytesMut>>>,
+
+    /// Bounds the number of concurrent sends to `concurrency_limit`
+    _send_permits: Arc<Semaphore>,
+    _send_tasks: Mutex<JoinSet<()>>,
 }
 
 impl Connector {
@@ -48,12 +58,17 @@ impl Connector {
         configuration: Arc<Configuration>,
         receiver: mpsc::Receiver<Arc<CapturedEventRecord>>,
         backup: Arc<Mutex<Backup>>,
-        http: Arc<HttpClient>,
+        server: Arc<dyn ServerApi>,
     ) -> Arc<Self>
     where
         Self: Sized,
     {
         let concurrency_limit = configuration.event_post.concurrency_limit;
+        let compressed_buffers = configuration.event_post.compressed_buffers();
+        assert!(
+            compressed_buffers >= concurrency_limit,
+            "Compressed buffer pool ({compressed_buffers}) is smaller than the number of concurrent sends ({concurrency_limit})"
+        );
         let errors_count = Arc::new(RwLock::new(0));
 
         let mut uncompressed_buffer_pool = vec![];
@@ -69,18 +84,88 @@ impl Connector {
             _receiver: Mutex::new(receiver),
             _stopped: Arc::new(SetOnce::new()),
             _backup: backup,
-            _http: http,
+            _server: server,
             _errors_count: errors_count,
             _reconnect: Arc::new(Reconnector::new(weak.clone())),
             _reconnect_task: Mutex::new(None),
             _uncompressed_buffer_pool: uncompressed_buffer_pool,
             _uncompressed_buffer_pool_index: AtomicUsize::new(0),
-            _compressed_buffer_pool: Arc::new(Pool::new(concurrency_limit, |_| {
+            _batch_started: BlockingMutex::new(None),
+            _batch_records: AtomicUsize::new(0),
+            _compressed_buffer_pool: Arc::new(Pool::new(compressed_buffers, |_| {
                 Some(Self::_new_compressed_buffer())
             })),
+            _send_permits: Arc::new(Semaphore::new(concurrency_limit)),
+            _send_tasks: Mutex::new(JoinSet::new()),
         })
     }
 
+    /// Send `payload` in the background and move on to the next uncompressed buffer.
+    ///
+    /// Waits for a send permit first, so that a burst of events slows down `handle` (and in turn
+    /// fills the message queue in front of the tracer) instead of piling up send tasks.
+    async fn _spawn_send(
+        self: &Arc<Self>,
+        index: usize,
+        payload: OwnedMutexGuard<Vec<u8>>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let permit = self._send_permits.clone().acquire_owned().await?;
+        let ptr = self.clone();
+        let mut tasks = self._send_tasks.lock().await;
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn(async move {
+            ptr._send_payload_utils(payload).await;
+            drop(permit);
+        });
+
+        self._uncompressed_buffer_pool_index.store(
+            (index + 1) % self._uncompressed_buffer_pool.len(),
+            Ordering::Relaxed,
+        );
+        self._batch_started.lock().take();
+        self._batch_records.store(0, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Send every buffered event now instead of waiting for a flush trigger, returning the
+    /// number of events handed over to the server (or the backup if it is unreachable).
+    pub async fn flush(self: &Arc<Self>) -> u64 {
+        let mut events = 0;
+        let mut tasks = vec![];
+        for payload in &self._uncompressed_buffer_pool {
+            let payload = payload.clone().lock_owned().await;
+            if payload.is_empty() {
+                continue;
+            }
+
+            // Flushed buffers count towards the concurrency limit, as in `_spawn_send`
+            let permit = match self._send_permits.clone().acquire_owned().await {
+                Ok(permit) => permit,
+                Err(e) => {
+                    error!("Unable to acquire a send permit for flushing: {e}");
+                    break;
+                }
+            };
+
+            events += payload.iter().filter(|&&b| b == b'\n').count() as u64;
+            let ptr = self.clone();
+            tasks.push(tokio::spawn(async move {
+                ptr._send_payload_utils(payload).await;
+                drop(permit);
+            }));
+        }
+
+        for task in tasks {
+            if let Err(e) = task.await {
+                error!("Flush task panicked: {e}");
+            }
+        }
+
+        self._batch_started.lock().take();
+        self._batch_records.store(0, Ordering::Relaxed);
+        events
+    }
+
     async fn _disconnected(&self) -> bool {
         *self._errors_count.read().await == self._config.event_post.concurrency_limit
     }
@@ -90,12 +175,14 @@ impl Connector {
             return;
         }
 
+        // Events are newline-delimited
+        let events = raw_payload.iter().filter(|&&b| b == b'\n').count() as u64;
+
         let mut write_to_backup = self._disconnected().await;
         if !write_to_backup {
-            let mut compressor = ZstdEncoder::with_quality(
-                raw_payload.as_slice(),
-                Level::Precise(self._config.zstd_compression_level),
-            );
+            if self._compressed_buffer_pool.metrics().available == 0 {
+                debug!("All compressed buffers are in use, waiting for a send to complete");
+            }
 
             let mut buffer = self._compressed_buffer_pool.acquire().await;
             let mut compressed = match buffer.take() {
@@ -110,37 +197,41 @@ impl Connector {
 
             compressed.clear();
 
-            let (compressed, success) = match compressor.read_buf(&mut compressed).await {
-                Ok(_) => {
+            // Tiny batches are not worth the zstd framing and CPU time
+            let compress = self._config.event_post.compress
+                && raw_payload.len() >= self._config.event_post.min_compress_bytes;
+            let encoded = if compress {
+                let mut compressor = ZstdEncoder::with_quality(
+                    raw_payload.as_slice(),
+                    Level::Precise(self._config.zstd_compression_level),
+                );
+                compressor.read_buf(&mut compressed).await.map(|_| ())
+            } else {
+                // Agents colocated with the server save CPU time by sending raw NDJSON
+                compressed.extend_from_slice(&raw_payload);
+                Ok(())
+            };
+
+            // Delay the server asked for when it rejected the payload as too busy
+            let mut retry_after = None;
+            let (compressed, success) = match encoded {
+                Ok(()) => {
                     debug!(
-                        "Sending {} bytes of uncompressed data (compressed to {} bytes)",
+                        "Sending {} bytes of uncompressed data (encoded to {} bytes)",
                         raw_payload.len(),
                         compressed.len(),
                     );
 
                     let compressed = compressed.freeze();
-                    let success = match self
-                        ._http
-                        .api()
-                        .post("/trace")
-                        .body(compressed.clone())
-                        .send()
-                        .await
-                    {
-                        Ok(response) => {
-                            response.status() == 200
-                                && match response.json::<TraceResponse>().await {
-                                    Ok(data) => {
-                                        debug!("Server response {data:?}");
-                                        true
-                                    }
-                                    Err(e) => {
-                                        error!("Invalid server JSON response: {e}");
-                                        false
-                                    }
-                                }
+                    let success = match self._server.trace(compressed.clone(), compress).await {
+                        Ok(data) => {
+                            debug!("Server response {data:?}");
+                            true
                         }
                         Err(e) => {
+                            if let ServiceError::Busy(delay) = e {
+                                retry_after = Some(delay);
+                            }
                             error!(
                                 "Failed to send trace event to server: {e}, writing to backup instead"
                             );
@@ -167,9 +258,24 @@ impl Connector {
 
             *buffer = Some(compressed);
 
-            if !success {
+            if success {
+                EVENT_COUNTERS.sent(events);
+
+                // A successful send proves the server is reachable, so intermittent failures
+                // must not accumulate until the connector is considered disconnected.
+                if *self._errors_count.read().await > 0 {
+                    *self._errors_count.write().await = 0;
+                }
+            } else {
                 let mut errors_count = self._errors_count.write().await;
-                *errors_count = (*errors_count + 1).min(self._config.event_post.concurrency_limit);
+                *errors_count = match retry_after {
+                    // Stop sending altogether until the server is ready again
+                    Some(delay) => {
+                        self._reconnect.defer(delay);
+                        self._config.event_post.concurrency_limit
+                    }
+                    None => (*errors_count + 1).min(self._config.event_post.concurrency_limit),
+                };
                 write_to_backup = true;
             }
         }
@@ -182,7 +288,13 @@ impl Connector {
             );
 
             let mut backup = self._backup.lock().await;
-            backup.write(raw_payload.as_slice()).await;
+            match backup.write(raw_payload.as_slice()).await {
+                Ok(()) => EVENT_COUNTERS.backed_up(events),
+                Err(e) => error!(
+                    "Unable to back up {} bytes of uncompressed data: {e}",
+                    raw_payload.len()
+                ),
+            }
         }
 
         raw_payload.clear();
@@ -202,8 +314,16 @@ impl Module for Connector {
     }
 
     async fn listen(self: Arc<Self>) -> Self::EventType {
+        // Time out when the current batch becomes too old, so that it is sent even if no further
+        // event arrives
+        let max_age = Duration::from_millis(self._config.event_post.max_batch_age_ms);
+        let remaining = self
+            ._batch_started
+            .lock()
+            .map_or(max_age, |started| max_age.saturating_sub(started.elapsed()));
+
         let mut receiver = self._receiver.lock().await;
-        timeout(Duration::from_secs(1), receiver.recv()).await
+        timeout(remaining, receiver.recv()).await
     }
 
     async fn before_hook(self: Arc<Self>) -> Result<(), Box<dyn Error + Send + Sync>> {
@@ -221,19 +341,25 @@ impl Module for Connector {
             reconnect_task.await?;
         }
 
-        // Flush any remaining data in the buffers
-        let mut tasks = vec![];
-        for payload in &self._uncompressed_buffer_pool {
-            let payload = payload.clone().lock_owned().await;
-            let ptr = self.clone();
-            tasks.push(tokio::spawn(async move {
-                ptr._send_payload_utils(payload).await
-            }));
+        // Wait for in-flight sends, so that the flush below sees every buffer in its final state
+        let mut send_tasks = self._send_tasks.lock().await;
+        while let Some(result) = send_tasks.join_next().await {
+            if let Err(e) = result {
+                error!("Send task panicked: {e}");
+            }
         }
 
-        for task in tasks {
-            task.await?;
-        }
+        // Flush any remaining data in the buffers
+        self.flush().await;
+
+        // All compressed buffers checked out means compression stalls waiting for a send to finish
+        let metrics = self._compressed_buffer_pool.metrics();
+        info!(
+            "Compressed buffer pool: {} acquisitions, {:?} average wait, {:?} longest wait",
+            metrics.acquisitions,
+            metrics.average_wait(),
+            metrics.max_wait,
+        );
 
         Ok(())
     }
@@ -249,7 +375,6 @@ impl Module for Connector {
             .lock_owned()
             .await;
 
-        let ptr = self.clone();
         match event {
             Ok(Some(event)) => {
                 if let Err(e) = event.serialize_to_writer(&mut *payload) {
@@ -257,23 +382,21 @@ impl Module for Connector {
                     payload.clear();
                 } else {
                     payload.push(b'\n');
-                    if payload.len() > self._config.event_post.flush_limit {
-                        tokio::spawn(async move { ptr._send_payload_utils(payload).await });
-                        self._uncompressed_buffer_pool_index.store(
-                            (index + 1) % self._uncompressed_buffer_pool.len(),
-                            Ordering::Relaxed,
-                        );
+
+                    let records = self._batch_records.fetch_add(1, Ordering::Relaxed) + 1;
+                    let started = *self._batch_started.lock().get_or_insert_with(Instant::now);
+
+                    let settings = &self._config.event_post;
+                    if payload.len() > settings.flush_limit
+                        || records >= settings.max_batch_records
+                        || started.elapsed() >= Duration::from_millis(settings.max_batch_age_ms)
+                    {
+                        self._spawn_send(index, payload).await?;
                     }
                 }
             }
             Ok(None) => {}
-            Err(_) => {
-                tokio::spawn(async move { ptr._send_payload_utils(payload).await });
-                self._uncompressed_buffer_pool_index.store(
-                    (index + 1) % self._uncompressed_buffer_pool.len(),
-                    Ordering::Relaxed,
-                );
-            }
+            Err(_) => self._spawn_send(index, payload).await?,
         }
 
         Ok(())
@@ -284,6 +407,9 @@ struct Reconnector {
     _parent: Weak<Connector>,
     _stopped: Arc<SetOnce<()>>,
     _sleep_secs: AtomicU64,
+
+    /// No reconnection is attempted before this instant, as requested by the server
+    _resume_at: BlockingMutex<Option<Instant>>,
 }
 
 impl Reconnector {
@@ -292,6 +418,32 @@ impl Reconnector {
             _parent: parent,
             _stopped: Arc::new(SetOnce::new()),
             _sleep_secs: AtomicU64::new(5),
+            _resume_at: BlockingMutex::new(None),
+        }
+    }
+
+    /// Postpone reconnection attempts by at least `delay`, honoring the server's `Retry-After`.
+    pub fn defer(&self, delay: Duration) {
+        let resume_at = Instant::now() + delay;
+        let mut current = self._resume_at.lock();
+        if current.is_none_or(|current| current < resume_at) {
+            info!(
+                "Server is busy, pausing sends for {} seconds",
+                delay.as_secs()
+            );
+            *current = Some(resume_at);
+        }
+    }
+
+    fn _deferred(&self) -> bool {
+        let mut resume_at = self._resume_at.lock();
+        match *resume_at {
+            Some(instant) if instant > Instant::now() => true,
+            Some(_) => {
+                *resume_at = None;
+                false
+            }
+            None => false,
         }
     }
 }
@@ -325,22 +477,542 @@ impl Module for Reconnector {
             None => return Ok(()),
         };
 
-        if parent._disconnected().await {
+        if parent._disconnected().await && !self._deferred() {
             debug!("Attempting to reconnect to server...");
-            if let Ok(response) = parent._http.api().get("/health-check").send().await
-                && response.status() == 204
-            {
-                *parent._errors_count.write().await = 0;
-                self._sleep_secs.store(5, Ordering::Relaxed);
-            } else {
-                let _ = self
-                    ._sleep_secs
-                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
-                        Some((v * 3 / 2).min(60))
-                    });
+            match parent._server.health_check().await {
+                Ok(()) => {
+                    *parent._errors_count.write().await = 0;
+                    self._sleep_secs.store(5, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    if let ServiceError::Busy(delay) = e {
+                        self.defer(delay);
+                    }
+
+                    let _ =
+                        self._sleep_secs
+                            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
+                                Some((v * 3 / 2).min(60))
+                            });
+                }
             }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use std::time::{Duration, Instant};
+    use std::{env, process};
+
+    use async_compression::tokio::bufread::ZstdDecoder;
+    use bytes::Bytes;
+    use chrono::Utc;
+    use tokio::fs;
+    use tokio::io::AsyncReadExt;
+    use tokio::sync::{Mutex, mpsc};
+    use tokio::time::{sleep, timeout};
+    use wm_common::error::ServiceError;
+    use wm_common::schema::event::{CapturedEventRecord, Event, EventData};
+    use wm_common::schema::responses::TraceResponse;
+    use wm_common::schema::sysinfo::{CPUInfo, MemoryInfo, OSInfo, SystemInfo};
+
+    use super::Connector;
+    use crate::backup::Backup;
+    use crate::configuration::Configuration;
+    use crate::http::mock::MockServer;
+    use crate::module::Module;
+
+    /// Below `min_compress_bytes`, so that the payload is sent as is
+    const _EVENTS: &[u8] = b"{\"event\":1}\n{\"event\":2}\n";
+
+    async fn _connector(name: &str, server: Arc<MockServer>) -> (Arc<Connector>, PathBuf) {
+        _connector_with(name, server, Configuration::default()).await
+    }
+
+    async fn _connector_with(
+        name: &str,
+        server: Arc<MockServer>,
+        config: Configuration,
+    ) -> (Arc<Connector>, PathBuf) {
+        let (connector, _, directory) = _connector_with_sender(name, server, config).await;
+        (connector, directory)
+    }
+
+    /// Like [`_connector_with`], keeping the sender open so that `listen` waits for events.
+    async fn _connector_with_sender(
+        name: &str,
+        server: Arc<MockServer>,
+        config: Configuration,
+    ) -> (
+        Arc<Connector>,
+        mpsc::Sender<Arc<CapturedEventRecord>>,
+        PathBuf,
+    ) {
+        let config = Arc::new(config);
+        let directory = env::temp_dir().join(format!("wm-client-{name}-{}", process::id()));
+        let backup = Backup::async_new(config.clone(), directory.clone())
+            .await
+            .unwrap();
+
+        let (sender, receiver) = mpsc::channel(1);
+        let connector = Connector::new(config, receiver, Arc::new(Mutex::new(backup)), server);
+        (connector, sender, directory)
+    }
+
+    async fn _send(connector: &Arc<Connector>) {
+        let payload = Arc::new(Mutex::new(_EVENTS.to_vec())).lock_owned().await;
+        connector._send_payload_utils(payload).await;
+    }
+
+    /// Size of the current backup file after flushing it.
+    async fn _backup_size(connector: &Connector) -> u64 {
+        let mut backup = connector._backup.lock().await;
+        backup.flush().await.unwrap();
+        fs::metadata(backup.path()).await.unwrap().len()
+    }
+
+    /// Remove the backup directory, closing the backup file first.
+    async fn _cleanup(connector: Arc<Connector>, directory: PathBuf) {
+        drop(connector);
+        let _ = fs::remove_dir_all(directory).await;
+    }
+
+    #[tokio::test]
+    async fn sends_events_to_server() {
+        let server = Arc::new(MockServer::new());
+        let (connector, directory) = _connector("connector-success", server.clone()).await;
+
+        _send(&connector).await;
+
+        assert_eq!(server.traced(), vec![(Bytes::from_static(_EVENTS), false)]);
+        assert_eq!(*connector._errors_count.read().await, 0);
+        assert_eq!(_backup_size(&connector).await, 0);
+
+        _cleanup(connector, directory).await;
+    }
+
+    #[tokio::test]
+    async fn compresses_batches_from_min_compress_bytes() {
+        let server = Arc::new(MockServer::new());
+        let mut config = Configuration::default();
+        config.event_post.min_compress_bytes = _EVENTS.len();
+        let (connector, directory) =
+            _connector_with("connector-compress", server.clone(), config).await;
+
+        _send(&connector).await;
+
+        let traced = server.traced();
+        assert_eq!(traced.len(), 1);
+        let (payload, compressed) = &traced[0];
+        assert!(compressed);
+
+        let mut decoded = vec![];
+        ZstdDecoder::new(payload.as_ref())
+            .read_to_end(&mut decoded)
+            .await
+            .unwrap();
+        assert_eq!(decoded, _EVENTS);
+
+        _cleanup(connector, directory).await;
+    }
+
+    #[tokio::test]
+    async fn backs_up_events_on_failure() {
+        let server = Arc::new(MockServer::new());
+        server.push_trace(Err(ServiceError::network("connection refused")));
+        let (connector, directory) = _connector("connector-failure", server.clone()).await;
+
+        _send(&connector).await;
+
+        assert_eq!(server.traced().len(), 1);
+        assert_eq!(*connector._errors_count.read().await, 1);
+        assert!(!connector._disconnected().await);
+        assert!(_backup_size(&connector).await > 0);
+
+        // A successful send resets the error count
+        _send(&connector).await;
+        assert_eq!(server.traced().len(), 2);
+        assert_eq!(*connector._errors_count.read().await, 0);
+
+        _cleanup(connector, directory).await;
+    }
+
+    #[tokio::test]
+    async fn intermittent_failures_do_not_accumulate() {
+        let server = Arc::new(MockServer::new());
+        let limit = Configuration::default().event_post.concurrency_limit;
+        for _ in 0..limit * 10 {
+            server.push_trace(Err(ServiceError::network("connection reset")));
+            server.push_trace(Ok(TraceResponse));
+        }
+        let (connector, directory) = _connector("connector-creep", server.clone()).await;
+
+        // Far more failures than the concurrency limit, each followed by a successful send
+        for _ in 0..limit * 10 {
+            _send(&connector).await;
+            assert!(!connector._disconnected().await);
+            _send(&connector).await;
+            assert_eq!(*connector._errors_count.read().await, 0);
+        }
+
+        assert_eq!(server.traced().len(), limit * 20);
+        _cleanup(connector, directory).await;
+    }
+
+    #[tokio::test]
+    async fn pauses_sends_when_server_is_busy() {
+        let server = Arc::new(MockServer::new());
+        server.push_trace(Err(ServiceError::Busy(Duration::from_secs(60))));
+        let (connector, directory) = _connector("connector-busy", server.clone()).await;
+
+        _send(&connector).await;
+
+        // A single 503 stops sending altogether until the Retry-After delay elapsed
+        assert!(connector._disconnected().await);
+        assert!(connector._reconnect._deferred());
+        assert!(_backup_size(&connector).await > 0);
+
+        // Further events go to the backup without reaching the server
+        let backed_up = _backup_size(&connector).await;
+        _send(&connector).await;
+        assert_eq!(server.traced().len(), 1);
+        assert!(_backup_size(&connector).await > backed_up);
+
+        // No health check before the delay elapsed either
+        connector._reconnect.clone().handle(()).await.unwrap();
+        assert_eq!(server.health_checks(), 0);
+
+        _cleanup(connector, directory).await;
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_health_check() {
+        let server = Arc::new(MockServer::new());
+        for _ in 0..Configuration::default().event_post.concurrency_limit {
+            server.push_trace(Err(ServiceError::network("connection refused")));
+        }
+        server.push_health_check(Err(ServiceError::network("connection refused")));
+        let (connector, directory) = _connector("connector-reconnect", server.clone()).await;
+
+        while !connector._disconnected().await {
+            _send(&connector).await;
+        }
+
+        connector._reconnect.clone().handle(()).await.unwrap();
+        assert_eq!(server.health_checks(), 1);
+        assert!(connector._disconnected().await);
+
+        connector._reconnect.clone().handle(()).await.unwrap();
+        assert_eq!(server.health_checks(), 2);
+        assert!(!connector._disconnected().await);
+
+        _cleanup(connector, directory).await;
+    }
+
+    /// Queue `_EVENTS` in the next uncompressed buffer and send it, as `handle` does.
+    async fn _spawn_send(connector: &Arc<Connector>) {
+        let index = connector
+            ._uncompressed_buffer_pool_index
+            .load(Ordering::Relaxed);
+        let mut payload = connector._uncompressed_buffer_pool[index]
+            .clone()
+            .lock_owned()
+            .await;
+        payload.extend_from_slice(_EVENTS);
+        connector._spawn_send(index, payload).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_sends_never_wait_for_compressed_buffers() {
+        const DELAY: Duration = Duration::from_millis(50);
+
+        let server = Arc::new(MockServer::new());
+        server.delay_traces(DELAY);
+        let mut config = Configuration::default();
+        config.event_post.concurrency_limit = 8;
+        let (connector, directory) =
+            _connector_with("connector-stress", server.clone(), config).await;
+
+        // Keep every send slot busy for far longer than a single round of sends
+        let batches = 8 * 20;
+        let completed = timeout(Duration::from_secs(60), async {
+            for _ in 0..batches {
+                _spawn_send(&connector).await;
+            }
+
+            let mut tasks = connector._send_tasks.lock().await;
+            while tasks.join_next().await.is_some() {}
+        })
+        .await;
+        assert!(completed.is_ok(), "Sends did not complete");
+        assert_eq!(server.traced().len(), batches);
+
+        // Each in-flight send holds a single compressed buffer, so with one buffer per send slot
+        // no send ever waited for another one to return its buffer
+        let metrics = connector._compressed_buffer_pool.metrics();
+        assert_eq!(metrics.acquisitions, batches as u64);
+        assert!(metrics.max_wait < DELAY, "{metrics:?}");
+
+        _cleanup(connector, directory).await;
+    }
+
+    fn _record() -> Arc<CapturedEventRecord> {
+        let system = SystemInfo::new(
+            Arc::new(OSInfo {
+                full: "Windows 11 Pro 24H2".to_string(),
+                kernel: "26100".to_string(),
+                name: "Windows".to_string(),
+                platform: "windows".to_string(),
+                version: "11 (26100)".to_string(),
+            }),
+            MemoryInfo {
+                memory_load: 50,
+                total_physical: 17_179_869_184,
+                available_physical: 8_589_934_592,
+                total_page_file: 21_474_836_480,
+                available_page_file: 10_737_418_240,
+                total_virtual: 140_737_488_355_328,
+                available_virtual: 140_737_488_355_328,
+            },
+            CPUInfo {
+                usage: 12.5,
+                cores: vec![10.0, 15.0],
+            },
+            None,
+            "x86_64".to_string(),
+            "TEST-HOST".to_string(),
+        );
+
+        Arc::new(CapturedEventRecord {
+            event: Event {
+                guid: "00000000-0000-0000-0000-000000000000".to_string(),
+                raw_timestamp: 133_000_000_000_000_000,
+                process_id: 1234,
+                thread_id: 5678,
+                event_id: 0,
+                opcode: 10,
+                data: EventData::UdpIp {
+                    pid: 1234,
+                    size: 64,
+                    daddr: IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
+                    saddr: IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2)),
+                    dport: 53,
+                    sport: 50000,
+                },
+                user: None,
+            },
+            system: Arc::new(system),
+            captured: Utc::now(),
+        })
+    }
+
+    /// Uncompressed batches, so that the events of each request can be counted.
+    fn _batching_config(
+        flush_limit: usize,
+        max_batch_records: usize,
+        max_batch_age: Duration,
+    ) -> Configuration {
+        let mut config = Configuration::default();
+        config.event_post.compress = false;
+        config.event_post.flush_limit = flush_limit;
+        config.event_post.max_batch_records = max_batch_records;
+        config.event_post.max_batch_age_ms = max_batch_age.as_millis() as u64;
+        config
+    }
+
+    async fn _handle(connector: &Arc<Connector>, count: usize) {
+        for _ in 0..count {
+            connector.clone().handle(Ok(Some(_record()))).await.unwrap();
+        }
+    }
+
+    /// Number of events in each request sent so far, once every send completed.
+    async fn _batches(connector: &Connector, server: &MockServer) -> Vec<usize> {
+        let mut tasks = connector._send_tasks.lock().await;
+        while tasks.join_next().await.is_some() {}
+
+        let mut batches = server
+            .traced()
+            .iter()
+            .map(|(payload, _)| payload.iter().filter(|&&b| b == b'\n').count())
+            .collect::<Vec<_>>();
+        batches.sort();
+        batches
+    }
+
+    #[tokio::test]
+    async fn sends_batches_at_max_batch_records() {
+        let server = Arc::new(MockServer::new());
+        let config = _batching_config(1 << 20, 3, Duration::from_secs(60));
+        let (connector, directory) =
+            _connector_with("connector-records", server.clone(), config).await;
+
+        _handle(&connector, 7).await;
+        assert_eq!(_batches(&connector, &server).await, vec![3, 3]);
+        assert_eq!(connector._batch_records.load(Ordering::Relaxed), 1);
+
+        _cleanup(connector, directory).await;
+    }
+
+    #[tokio::test]
+    async fn sends_batches_at_flush_limit() {
+        let server = Arc::new(MockServer::new());
+        let event_size = _record().serialize_to_vec().len() + 1;
+        let config = _batching_config(2 * event_size, 1000, Duration::from_secs(60));
+        let (connector, directory) =
+            _connector_with("connector-bytes", server.clone(), config).await;
+
+        // A batch is sent once it grows past the limit, i.e. with its third event
+        _handle(&connector, 7).await;
+        assert_eq!(_batches(&connector, &server).await, vec![3, 3]);
+
+        _cleanup(connector, directory).await;
+    }
+
+    #[tokio::test]
+    async fn sends_old_batches_with_the_next_event() {
+        const MAX_AGE: Duration = Duration::from_millis(50);
+
+        let server = Arc::new(MockServer::new());
+        let config = _batching_config(1 << 20, 1000, MAX_AGE);
+        let (connector, directory) = _connector_with("connector-age", server.clone(), config).await;
+
+        _handle(&connector, 1).await;
+        sleep(MAX_AGE * 2).await;
+        _handle(&connector, 1).await;
+        assert_eq!(_batches(&connector, &server).await, vec![2]);
+
+        _cleanup(connector, directory).await;
+    }
+
+    #[tokio::test]
+    async fn sends_old_batches_without_further_events() {
+        const MAX_AGE: Duration = Duration::from_millis(200);
+
+        let server = Arc::new(MockServer::new());
+        let config = _batching_config(1 << 20, 1000, MAX_AGE);
+        let (connector, _sender, directory) =
+            _connector_with_sender("connector-idle", server.clone(), config).await;
+
+        _handle(&connector, 1).await;
+        sleep(MAX_AGE / 2).await;
+
+        // Listening times out once the pending batch reaches its maximum age, not a full window
+        // after the last event
+        let started = Instant::now();
+        let event = connector.clone().listen().await;
+        assert!(event.is_err());
+        assert!(started.elapsed() < MAX_AGE, "{:?}", started.elapsed());
+        assert!(server.traced().is_empty());
+
+        connector.clone().handle(event).await.unwrap();
+        assert_eq!(_batches(&connector, &server).await, vec![1]);
+
+        _cleanup(connector, directory).await;
+    }
+
+    #[tokio::test]
+    async fn shutdown_sends_pending_buffers_exactly_once() {
+        const PENDING: &[u8] = b"{\"event\":3}\n";
+
+        let server = Arc::new(MockServer::new());
+        server.delay_traces(Duration::from_millis(200));
+        let (connector, directory) = _connector("connector-shutdown", server.clone()).await;
+
+        // Every buffer but the last one is still being sent when shutdown starts
+        let in_flight = connector._uncompressed_buffer_pool.len() - 1;
+        for _ in 0..in_flight {
+            _spawn_send(&connector).await;
+        }
+
+        let index = connector
+            ._uncompressed_buffer_pool_index
+            .load(Ordering::Relaxed);
+        connector._uncompressed_buffer_pool[index]
+            .lock()
+            .await
+            .extend_from_slice(PENDING);
+
+        connector.clone().after_hook().await.unwrap();
+
+        let mut traced = server.traced();
+        traced.sort();
+        let mut expected = vec![(Bytes::from_static(_EVENTS), false); in_flight];
+        expected.push((Bytes::from_static(PENDING), false));
+        expected.sort();
+        assert_eq!(traced, expected);
+
+        for payload in &connector._uncompressed_buffer_pool {
+            assert!(payload.lock().await.is_empty());
+        }
+        assert_eq!(_backup_size(&connector).await, 0);
+
+        _cleanup(connector, directory).await;
+    }
+
+    #[tokio::test]
+    async fn flushes_within_the_concurrency_limit() {
+        let server = Arc::new(MockServer::new());
+        let (connector, directory) = _connector("connector-flush-permits", server.clone()).await;
+        connector._uncompressed_buffer_pool[0]
+            .lock()
+            .await
+            .extend_from_slice(_EVENTS);
+
+        // Sends in progress elsewhere hold every permit
+        let limit = u32::try_from(connector._config.event_post.concurrency_limit).unwrap();
+        let permits = connector
+            ._send_permits
+            .clone()
+            .acquire_many_owned(limit)
+            .await
+            .unwrap();
+
+        let flush = tokio::spawn({
+            let connector = connector.clone();
+            async move { connector.flush().await }
+        });
+        sleep(Duration::from_millis(100)).await;
+        assert!(server.traced().is_empty());
+
+        drop(permits);
+        assert_eq!(flush.await.unwrap(), 2);
+        assert_eq!(server.traced(), vec![(Bytes::from_static(_EVENTS), false)]);
+
+        _cleanup(connector, directory).await;
+    }
+
+    #[tokio::test]
+    async fn rejects_undersized_compressed_buffer_pool() {
+        let mut config = Configuration::default();
+        config.event_post.compressed_buffers = Some(config.event_post.concurrency_limit - 1);
+        assert!(config.validate().is_err());
+
+        let config = Arc::new(config);
+        let directory =
+            env::temp_dir().join(format!("wm-client-connector-undersized-{}", process::id()));
+        let backup = Backup::async_new(config.clone(), directory.clone())
+            .await
+            .unwrap();
+        let (_, receiver) = mpsc::channel(1);
+        let created = tokio::spawn(async move {
+            Connector::new(
+                config,
+                receiver,
+                Arc::new(Mutex::new(backup)),
+                Arc::new(MockServer::new()),
+            );
+        })
+        .await;
+        assert!(created.is_err_and(|e| e.is_panic()));
+
+        let _ = fs::remove_dir_all(directory).await;
+    }
+}
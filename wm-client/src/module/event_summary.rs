@@ -0,0 +1,83 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use log::info;
+use parking_lot::Mutex as BlockingMutex;
+use tokio::sync::SetOnce;
+use tokio::time::sleep;
+use wm_common::schema::event::EventData;
+
+use crate::configuration::Configuration;
+use crate::module::Module;
+use crate::module::heartbeat::EVENT_COUNTERS;
+
+/// Periodically logs the number of captured events per [`EventData`] variant.
+pub struct EventSummaryLogger {
+    _config: Arc<Configuration>,
+    _stopped: Arc<SetOnce<()>>,
+    _last: BlockingMutex<[(&'static str, u64); EventData::VARIANT_NAMES.len()]>,
+}
+
+impl EventSummaryLogger {
+    pub fn new(config: Arc<Configuration>) -> Self {
+        Self {
+            _config: config,
+            _stopped: Arc::new(SetOnce::new()),
+            _last: BlockingMutex::new(EVENT_COUNTERS.load_by_variant()),
+        }
+    }
+}
+
+#[async_trait]
+impl Module for EventSummaryLogger {
+    type EventType = ();
+
+    fn name(&self) -> &str {
+        "EventSummaryLogger"
+    }
+
+    fn stopped(&self) -> Arc<SetOnce<()>> {
+        self._stopped.clone()
+    }
+
+    async fn listen(self: Arc<Self>) -> Self::EventType {
+        sleep(Duration::from_secs(
+            self._config.event_summary.interval_seconds,
+        ))
+        .await;
+    }
+
+    async fn handle(
+        self: Arc<Self>,
+        _: Self::EventType,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let current = EVENT_COUNTERS.load_by_variant();
+        let mut counts = {
+            let mut last = self._last.lock();
+            let counts = current
+                .iter()
+                .zip(last.iter())
+                .map(|((name, current), (_, last))| (*name, current - last))
+                .collect::<Vec<_>>();
+            *last = current;
+            counts
+        };
+
+        counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+        let total = counts.iter().map(|(_, count)| count).sum::<u64>();
+        let summary = counts
+            .iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(name, count)| format!("{name}={count}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        info!(
+            "Captured {total} events in the last {}s: {summary}",
+            self._config.event_summary.interval_seconds
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use log::{debug, warn};
+use tokio::sync::SetOnce;
+use tokio::time::sleep;
+use wm_common::schema::event::EventData;
+use wm_common::schema::heartbeat::Heartbeat;
+use wm_common::utils::get_computer_name;
+
+use crate::configuration::Configuration;
+use crate::http::ServerApi;
+use crate::module::Module;
+
+/// Process-wide event counters reported in heartbeats.
+pub struct EventCounters {
+    _captured: AtomicU64,
+    _sent: AtomicU64,
+    _backed_up: AtomicU64,
+    _dropped: AtomicU64,
+    _suppressed: AtomicU64,
+    _expired: AtomicU64,
+
+    /// Requests not mirrored to a secondary server that had too many requests in flight
+    _unmirrored: AtomicU64,
+
+    /// Age in seconds of the oldest backup waiting for upload, [`u64::MAX`] if there is none
+    _oldest_backup_age: AtomicU64,
+    _by_variant: [AtomicU64; EventData::VARIANT_NAMES.len()],
+}
+
+impl EventCounters {
+    const fn new() -> Self {
+        Self {
+            _captured: AtomicU64::new(0),
+            _sent: AtomicU64::new(0),
+            _backed_up: AtomicU64::new(0),
+            _dropped: AtomicU64::new(0),
+            _suppressed: AtomicU64::new(0),
+            _expired: AtomicU64::new(0),
+            _unmirrored: AtomicU64::new(0),
+            _oldest_backup_age: AtomicU64::new(u64::MAX),
+            _by_variant: [const { AtomicU64::new(0) }; EventData::VARIANT_NAMES.len()],
+        }
+    }
+
+    pub fn captured(&self, count: u64) {
+        self._captured.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn sent(&self, count: u64) {
+        self._sent.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn backed_up(&self, count: u64) {
+        self._backed_up.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn dropped(&self, count: u64) {
+        self._dropped.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn suppressed(&self, count: u64) {
+        self._suppressed.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn expired(&self, count: u64) {
+        self._expired.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn unmirrored(&self, count: u64) {
+        self._unmirrored.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Record the age of the oldest backup waiting for upload, `None` if every backup was uploaded.
+    pub fn oldest_backup_age(&self, age: Option<Duration>) {
+        self._oldest_backup_age
+            .store(age.map_or(u64::MAX, |age| age.as_secs()), Ordering::Relaxed);
+    }
+
+    /// Count an event produced by a provider callback, before it is enriched or queued.
+    pub fn captured_variant(&self, data: &EventData) {
+        if let Some(i) = EventData::VARIANT_NAMES
+            .iter()
+            .position(|name| *name == data.variant_name())
+        {
+            self._by_variant[i].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Number of events produced so far per [`EventData`] variant.
+    pub fn load_by_variant(&self) -> [(&'static str, u64); EventData::VARIANT_NAMES.len()] {
+        let mut counts = EventData::VARIANT_NAMES.map(|name| (name, 0));
+        for ((_, count), counter) in counts.iter_mut().zip(&self._by_variant) {
+            *count = counter.load(Ordering::Relaxed);
+        }
+
+        counts
+    }
+
+    /// Total number of events backed up so far.
+    pub fn load_backed_up(&self) -> u64 {
+        self._backed_up.load(Ordering::Relaxed)
+    }
+}
+
+pub static EVENT_COUNTERS: EventCounters = EventCounters::new();
+
+pub struct HeartbeatSender {
+    _config: Arc<Configuration>,
+    _server: Arc<dyn ServerApi>,
+    _stopped: Arc<SetOnce<()>>,
+    _started: Instant,
+    _agent_id: String,
+}
+
+impl HeartbeatSender {
+    pub fn new(config: Arc<Configuration>, server: Arc<dyn ServerApi>) -> Self {
+        Self {
+            _config: config,
+            _server: server,
+            _stopped: Arc::new(SetOnce::new()),
+            _started: Instant::now(),
+            _agent_id: get_computer_name().unwrap_or_else(|_| "unknown".to_string()),
+        }
+    }
+}
+
+#[async_trait]
+impl Module for HeartbeatSender {
+    type EventType = ();
+
+    fn name(&self) -> &str {
+        "HeartbeatSender"
+    }
+
+    fn stopped(&self) -> Arc<SetOnce<()>> {
+        self._stopped.clone()
+    }
+
+    async fn listen(self: Arc<Self>) -> Self::EventType {
+        sleep(Duration::from_secs(self._config.heartbeat.interval_seconds)).await;
+    }
+
+    async fn handle(
+        self: Arc<Self>,
+        _: Self::EventType,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let heartbeat = Heartbeat {
+            agent_id: self._agent_id.clone(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            uptime_seconds: self._started.elapsed().as_secs(),
+            events_captured: EVENT_COUNTERS._captured.load(Ordering::Relaxed),
+            events_sent: EVENT_COUNTERS._sent.load(Ordering::Relaxed),
+            events_backed_up: EVENT_COUNTERS._backed_up.load(Ordering::Relaxed),
+            events_dropped: EVENT_COUNTERS._dropped.load(Ordering::Relaxed),
+            events_suppressed: EVENT_COUNTERS._suppressed.load(Ordering::Relaxed),
+            events_expired: EVENT_COUNTERS._expired.load(Ordering::Relaxed),
+            mirror_requests_dropped: EVENT_COUNTERS._unmirrored.load(Ordering::Relaxed),
+            events_by_variant: EVENT_COUNTERS
+                .load_by_variant()
+                .into_iter()
+                .map(|(name, count)| (name.to_string(), count))
+                .collect(),
+            oldest_backup_age_seconds: match EVENT_COUNTERS
+                ._oldest_backup_age
+                .load(Ordering::Relaxed)
+            {
+                u64::MAX => None,
+                age => Some(age),
+            },
+            timestamp: Utc::now(),
+        };
+
+        // Heartbeats are not backed up, a missing one is exactly what the server alerts on
+        match self._server.heartbeat(&heartbeat).await {
+            Ok(()) => debug!("Sent heartbeat {heartbeat:?}"),
+            Err(e) => warn!("Unable to send heartbeat: {e}"),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+use std::error::Error;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use log::{debug, info, warn};
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::SetOnce;
+use tokio::time::timeout;
+use wm_common::logger::LogBuffer;
+
+use crate::configuration::Configuration;
+use crate::module::Module;
+
+/// Maximum size of a request head, longer requests are rejected
+const _MAX_REQUEST_SIZE: usize = 8192;
+
+/// Time allowed for a client to send the whole request head
+const _READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Read the head (request line and headers) of an HTTP request, the body is ignored.
+///
+/// Fails with [`io::ErrorKind::TimedOut`] if the head is not complete within [`_READ_TIMEOUT`],
+/// so idle connections do not hold their serving task forever.
+pub(super) async fn read_request_head(stream: &mut TcpStream) -> io::Result<Option<String>> {
+    let mut request = vec![];
+    let mut chunk = [0; 1024];
+    let read = async {
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            if request.len() > _MAX_REQUEST_SIZE {
+                return Ok(false);
+            }
+
+            let read = stream.read(&mut chunk).await?;
+            if read == 0 {
+                return Ok(false);
+            }
+            request.extend_from_slice(&chunk[..read]);
+        }
+
+        io::Result::Ok(true)
+    };
+
+    match timeout(_READ_TIMEOUT, read).await {
+        Ok(Ok(true)) => Ok(Some(String::from_utf8_lossy(&request).into_owned())),
+        Ok(Ok(false)) => Ok(None),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "Timed out reading request head",
+        )),
+    }
+}
+
+/// Whether the `Host` header of a request `head` names the loopback listener on `port`.
+///
+/// A web page whose domain is rebound to 127.0.0.1 makes the browser send requests here with
+/// that domain as the host, so any other host is rejected to keep pages from reading responses.
+pub(super) fn is_loopback_host(head: &str, port: u16) -> bool {
+    head.lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("host"))
+        .is_some_and(|(_, value)| {
+            let value = value.trim();
+            value == format!("127.0.0.1:{port}")
+                || value.eq_ignore_ascii_case(&format!("localhost:{port}"))
+        })
+}
+
+/// Write a complete HTTP response and close the connection.
+pub(super) async fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Serves the in-memory log buffer at `GET /logs` on the loopback interface.
+pub struct LogServer {
+    _config: Arc<Configuration>,
+    _buffer: Arc<LogBuffer>,
+    _listener: SetOnce<TcpListener>,
+    _stopped: Arc<SetOnce<()>>,
+}
+
+impl LogServer {
+    pub fn new(config: Arc<Configuration>, buffer: Arc<LogBuffer>) -> Self {
+        Self {
+            _config: config,
+            _buffer: buffer,
+            _listener: SetOnce::new(),
+            _stopped: Arc::new(SetOnce::new()),
+        }
+    }
+
+    async fn _serve(self: Arc<Self>, mut stream: TcpStream) -> io::Result<()> {
+        let port = stream.local_addr()?.port();
+        let head = read_request_head(&mut stream).await?.unwrap_or_default();
+        let mut parts = head.lines().next().unwrap_or_default().split(' ');
+
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some(_), Some(_)) if !is_loopback_host(&head, port) => {
+                ("403 Forbidden", String::new())
+            }
+            (Some("GET"), Some("/logs")) => {
+                let mut body = self._buffer.lines().join("\n");
+                body.push('\n');
+                ("200 OK", body)
+            }
+            (Some(_), Some(_)) => ("404 Not Found", String::new()),
+            _ => ("400 Bad Request", String::new()),
+        };
+
+        write_response(&mut stream, status, "text/plain; charset=utf-8", &body).await
+    }
+}
+
+#[async_trait]
+impl Module for LogServer {
+    type EventType = io::Result<TcpStream>;
+
+    fn name(&self) -> &str {
+        "LogServer"
+    }
+
+    fn stopped(&self) -> Arc<SetOnce<()>> {
+        self._stopped.clone()
+    }
+
+    async fn listen(self: Arc<Self>) -> Self::EventType {
+        let listener = self._listener.wait().await;
+        listener.accept().await.map(|(stream, _)| stream)
+    }
+
+    async fn handle(
+        self: Arc<Self>,
+        event: Self::EventType,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match event {
+            Ok(stream) => {
+                tokio::spawn(async move {
+                    if let Err(e) = self._serve(stream).await {
+                        debug!("Unable to serve log request: {e}");
+                    }
+                });
+            }
+            Err(e) => warn!("Unable to accept log request: {e}"),
+        }
+
+        Ok(())
+    }
+
+    async fn before_hook(self: Arc<Self>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, self._config.log_buffer.port));
+        let listener = TcpListener::bind(addr).await?;
+        info!("Serving recent logs at http://{addr}/logs");
+
+        self._listener.set(listener)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use tokio::io::{self, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::time::Instant;
+
+    use super::{_READ_TIMEOUT, is_loopback_host, read_request_head};
+
+    /// Connected pair of (client, server) streams on the loopback interface.
+    async fn _pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (client, server)
+    }
+
+    #[tokio::test]
+    async fn reads_request_heads() {
+        let (mut client, mut server) = _pair().await;
+        client
+            .write_all(b"GET /logs HTTP/1.1\r\nHost: 127.0.0.1:8080\r\n\r\n")
+            .await
+            .unwrap();
+
+        let head = read_request_head(&mut server).await.unwrap().unwrap();
+        assert!(head.starts_with("GET /logs HTTP/1.1\r\n"));
+    }
+
+    #[tokio::test]
+    async fn times_out_idle_connections() {
+        let (mut client, mut server) = _pair().await;
+        client.write_all(b"GET /logs HTTP/1.1\r\n").await.unwrap();
+
+        let started = Instant::now();
+        let error = read_request_head(&mut server).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() >= _READ_TIMEOUT);
+    }
+
+    #[test]
+    fn accepts_loopback_hosts() {
+        assert!(is_loopback_host(
+            "GET /logs HTTP/1.1\r\nHost: 127.0.0.1:8080\r\n\r\n",
+            8080
+        ));
+        assert!(is_loopback_host(
+            "GET /logs HTTP/1.1\r\nhost:  LOCALHOST:8080 \r\n\r\n",
+            8080
+        ));
+    }
+
+    #[test]
+    fn rejects_other_hosts() {
+        for head in [
+            "GET /logs HTTP/1.1\r\nHost: attacker.example:8080\r\n\r\n",
+            "GET /logs HTTP/1.1\r\nHost: 127.0.0.1:9090\r\n\r\n",
+            "GET /logs HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "GET /logs HTTP/1.1\r\n\r\n",
+            "GET http://127.0.0.1:8080/logs HTTP/1.1\r\nX-Host: 127.0.0.1:8080\r\n\r\n",
+        ] {
+            assert!(!is_loopback_host(head, 8080), "{head:?}");
+        }
+    }
+}
@@ -1,5 +1,10 @@
+pub mod admin_server;
 pub mod backup;
 pub mod connector;
+pub mod event_summary;
+pub mod heartbeat;
+pub mod log_server;
+pub mod overload;
 pub mod tracer;
 
 use std::error::Error;
@@ -0,0 +1,246 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use log::{debug, info, warn};
+use parking_lot::Mutex as BlockingMutex;
+use tokio::sync::SetOnce;
+use tokio::time::sleep;
+use wm_common::schema::event::EventData;
+
+use crate::configuration::Configuration;
+use crate::module::Module;
+use crate::module::heartbeat::EVENT_COUNTERS;
+
+const _EVENT_TYPES: usize = EventData::EVENT_TYPES.len();
+
+/// Process-wide per-provider event volumes and suspensions, keyed by event type.
+///
+/// Suspended providers stay attached to their traces, their events are discarded as soon as
+/// they reach the callback.
+pub struct ProviderThrottle {
+    _volumes: [AtomicU64; _EVENT_TYPES],
+    _suspended: [AtomicBool; _EVENT_TYPES],
+}
+
+impl ProviderThrottle {
+    const fn new() -> Self {
+        Self {
+            _volumes: [const { AtomicU64::new(0) }; _EVENT_TYPES],
+            _suspended: [const { AtomicBool::new(false) }; _EVENT_TYPES],
+        }
+    }
+
+    fn _index(event_type: &str) -> Option<usize> {
+        EventData::EVENT_TYPES.iter().position(|t| *t == event_type)
+    }
+
+    /// Whether events of `event_type` are currently discarded.
+    pub fn suspended(&self, event_type: &str) -> bool {
+        Self::_index(event_type).is_some_and(|i| self._suspended[i].load(Ordering::Relaxed))
+    }
+
+    /// Count an event of `event_type` passing the provider filter.
+    pub fn record(&self, event_type: &str) {
+        if let Some(i) = Self::_index(event_type) {
+            self._volumes[i].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn _take_volume(&self, event_type: &str) -> u64 {
+        Self::_index(event_type).map_or(0, |i| self._volumes[i].swap(0, Ordering::Relaxed))
+    }
+
+    fn _set_suspended(&self, event_type: &str, suspended: bool) {
+        if let Some(i) = Self::_index(event_type) {
+            self._suspended[i].store(suspended, Ordering::Relaxed);
+        }
+    }
+}
+
+pub static PROVIDER_THROTTLE: ProviderThrottle = ProviderThrottle::new();
+
+/// Suspends the busiest provider while events are backed up faster than the configured rate
+/// for a whole window, and resumes providers one at a time once the rate falls below half of it.
+pub struct OverloadController {
+    _config: Arc<Configuration>,
+    _stopped: Arc<SetOnce<()>>,
+    _last_backed_up: BlockingMutex<u64>,
+
+    /// Currently suspended event types, most recently suspended last
+    _suspended: BlockingMutex<Vec<String>>,
+}
+
+impl OverloadController {
+    pub fn new(config: Arc<Configuration>) -> Self {
+        Self {
+            _config: config,
+            _stopped: Arc::new(SetOnce::new()),
+            _last_backed_up: BlockingMutex::new(EVENT_COUNTERS.load_backed_up()),
+            _suspended: BlockingMutex::new(vec![]),
+        }
+    }
+
+    fn _evaluate(&self, rate: f64, candidates: &[(&str, u64)]) {
+        let threshold = self._config.overload.backup_rate_threshold;
+        let mut suspended = self._suspended.lock();
+
+        if rate > threshold {
+            // Earlier entries of the suspension order win ties
+            let busiest = candidates
+                .iter()
+                .filter(|(event_type, _)| !suspended.iter().any(|s| s == event_type))
+                .rev()
+                .max_by_key(|(_, volume)| *volume);
+
+            match busiest {
+                Some((event_type, volume)) => {
+                    warn!(
+                        "Backing up {rate:.1} events/s, suspending provider {event_type} ({volume} events in the last window)"
+                    );
+                    PROVIDER_THROTTLE._set_suspended(event_type, true);
+                    suspended.push(event_type.to_string());
+                }
+                None => {
+                    warn!("Backing up {rate:.1} events/s with all suspendable providers suspended")
+                }
+            }
+        } else if rate < threshold / 2.0
+            && let Some(event_type) = suspended.pop()
+        {
+            info!("Backing up {rate:.1} events/s, resuming provider {event_type}");
+            PROVIDER_THROTTLE._set_suspended(&event_type, false);
+        }
+    }
+}
+
+#[async_trait]
+impl Module for OverloadController {
+    type EventType = ();
+
+    fn name(&self) -> &str {
+        "OverloadController"
+    }
+
+    fn stopped(&self) -> Arc<SetOnce<()>> {
+        self._stopped.clone()
+    }
+
+    async fn listen(self: Arc<Self>) -> Self::EventType {
+        sleep(Duration::from_secs(self._config.overload.window_seconds)).await;
+    }
+
+    async fn handle(
+        self: Arc<Self>,
+        _: Self::EventType,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let backed_up = EVENT_COUNTERS.load_backed_up();
+        let window = {
+            let mut last = self._last_backed_up.lock();
+            let window = backed_up.saturating_sub(*last);
+            *last = backed_up;
+            window
+        };
+
+        let volumes = EventData::EVENT_TYPES
+            .map(|event_type| (event_type, PROVIDER_THROTTLE._take_volume(event_type)));
+        let candidates = self
+            ._config
+            .overload
+            .suspend_order
+            .iter()
+            .filter_map(|event_type| {
+                volumes
+                    .iter()
+                    .find(|(t, _)| *t == event_type.as_str())
+                    .copied()
+            })
+            .collect::<Vec<_>>();
+
+        let rate = window as f64 / self._config.overload.window_seconds as f64;
+        debug!("Backed up {rate:.1} events/s, provider volumes {volumes:?}");
+        self._evaluate(rate, &candidates);
+
+        Ok(())
+    }
+
+    async fn after_hook(self: Arc<Self>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        for event_type in self._suspended.lock().drain(..) {
+            PROVIDER_THROTTLE._set_suspended(&event_type, false);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::OverloadController;
+    use crate::configuration::Configuration;
+
+    // Event types unknown to PROVIDER_THROTTLE, so that tests do not suspend real providers
+    const _FIRST: &str = "test-first";
+    const _SECOND: &str = "test-second";
+
+    fn _controller(threshold: f64) -> OverloadController {
+        let mut config = Configuration::default();
+        config.overload.enabled = true;
+        config.overload.backup_rate_threshold = threshold;
+        OverloadController::new(Arc::new(config))
+    }
+
+    fn _suspended(controller: &OverloadController) -> Vec<String> {
+        controller._suspended.lock().clone()
+    }
+
+    #[test]
+    fn suspends_the_busiest_provider_above_the_threshold() {
+        let controller = _controller(100.0);
+
+        controller._evaluate(100.0, &[(_FIRST, 10), (_SECOND, 50)]);
+        assert!(_suspended(&controller).is_empty());
+
+        controller._evaluate(150.0, &[(_FIRST, 10), (_SECOND, 50)]);
+        assert_eq!(_suspended(&controller), [_SECOND]);
+
+        // Suspended providers are not candidates anymore
+        controller._evaluate(150.0, &[(_FIRST, 10), (_SECOND, 50)]);
+        assert_eq!(_suspended(&controller), [_SECOND, _FIRST]);
+
+        controller._evaluate(150.0, &[(_FIRST, 10), (_SECOND, 50)]);
+        assert_eq!(_suspended(&controller), [_SECOND, _FIRST]);
+    }
+
+    #[test]
+    fn resumes_providers_below_half_the_threshold() {
+        let controller = _controller(100.0);
+        controller._evaluate(150.0, &[(_FIRST, 10), (_SECOND, 50)]);
+        controller._evaluate(150.0, &[(_FIRST, 10), (_SECOND, 50)]);
+        assert_eq!(_suspended(&controller), [_SECOND, _FIRST]);
+
+        controller._evaluate(50.0, &[(_FIRST, 0), (_SECOND, 0)]);
+        assert_eq!(_suspended(&controller), [_SECOND, _FIRST]);
+
+        // The most recently suspended provider is resumed first, one per window
+        controller._evaluate(49.0, &[(_FIRST, 0), (_SECOND, 0)]);
+        assert_eq!(_suspended(&controller), [_SECOND]);
+
+        controller._evaluate(0.0, &[(_FIRST, 0), (_SECOND, 0)]);
+        assert!(_suspended(&controller).is_empty());
+    }
+
+    #[test]
+    fn suspends_the_earlier_provider_on_ties() {
+        let controller = _controller(100.0);
+        controller._evaluate(150.0, &[(_FIRST, 50), (_SECOND, 50)]);
+        assert_eq!(_suspended(&controller), [_FIRST]);
+
+        let controller = _controller(100.0);
+        controller._evaluate(150.0, &[(_SECOND, 50), (_FIRST, 50)]);
+        assert_eq!(_suspended(&controller), [_SECOND]);
+    }
+}
@@ -1,32 +1,58 @@
 use std::env::consts::OS;
+use std::fs::File;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
+use std::{fs, io};
 
-use log::warn;
-use sysinfo::{MINIMUM_CPU_UPDATE_INTERVAL, System};
+use log::{debug, warn};
+use lru::LruCache;
+use sha2::{Digest, Sha256};
+use sysinfo::{Disks, MINIMUM_CPU_UPDATE_INTERVAL, Networks, System};
 use tokio::time::sleep;
-use wm_common::schema::sysinfo::{CPUInfo, OSInfo, SystemInfo};
-use wm_common::sysinfo::{get_system_times, memory_status};
-use wm_common::utils::get_computer_name;
+use wm_common::privilege::process_user;
+use wm_common::schema::event::{Event, EventData, EventUser};
+use wm_common::schema::sysinfo::{CPUInfo, IOInfo, OSInfo, SystemInfo};
+use wm_common::sysinfo::{get_processor_times, get_system_times, memory_status};
+use wm_common::utils::{device_path_to_win32, get_computer_name, process_image_path};
+
+use crate::configuration::Configuration;
+use crate::module::tracer::redaction::EventRedactor;
+
+/// Idle, kernel and user times, see [`get_system_times`].
+type _CpuCheckpoint = (u64, u64, u64);
 
 pub struct BlockingSystemInfo {
     _system_refresh: Duration,
     _last_update: Instant,
     _info: Arc<SystemInfo>,
     _os_info: Arc<OSInfo>,
-    _last_cpu_ckpt: (u64, u64, u64),
+    _last_cpu_ckpt: _CpuCheckpoint,
+    _last_core_ckpts: Option<Vec<_CpuCheckpoint>>,
+    _io: Option<(Disks, Networks)>,
 }
 
 impl BlockingSystemInfo {
-    pub async fn async_new(refresh: Duration) -> Self {
-        if refresh < MINIMUM_CPU_UPDATE_INTERVAL {
-            warn!(
-                "System info refresh interval is too low (should be at least {}s)",
-                MINIMUM_CPU_UPDATE_INTERVAL.as_secs_f64()
-            );
-        }
+    /// Construct a system info cache refreshed every `refresh`.
+    ///
+    /// CPU usage is computed from the difference between two checkpoints of system times, which
+    /// is meaningless over too short a window, so `refresh` is clamped to
+    /// [`MINIMUM_CPU_UPDATE_INTERVAL`].
+    ///
+    /// If `per_core` is set, the usage of each logical processor is reported as well. If
+    /// `io_metrics` is set, disk and network throughput are sampled on the same interval.
+    pub async fn async_new(refresh: Duration, per_core: bool, io_metrics: bool) -> Self {
+        let refresh = Self::_clamp_refresh(refresh);
 
         let cpu_ckpt = get_system_times().unwrap_or_default();
+        let core_ckpts = per_core.then(|| get_processor_times().unwrap_or_default());
+        let mut io = io_metrics.then(|| {
+            (
+                Disks::new_with_refreshed_list(),
+                Networks::new_with_refreshed_list(),
+            )
+        });
         let os_info = Arc::new(OSInfo {
             full: System::long_os_version().unwrap_or_default(),
             kernel: System::kernel_version().unwrap_or_default(),
@@ -36,8 +62,9 @@ impl BlockingSystemInfo {
         });
 
         sleep(MINIMUM_CPU_UPDATE_INTERVAL).await;
-        let (cpu_ckpt, sysinfo) = Self::_fetch_sysinfo(&cpu_ckpt, &os_info)
-            .expect("Failed to calculate initial system info");
+        let (cpu_ckpt, core_ckpts, sysinfo) =
+            Self::_fetch_sysinfo(&cpu_ckpt, core_ckpts.as_deref(), io.as_mut(), &os_info)
+                .expect("Failed to calculate initial system info");
 
         Self {
             _system_refresh: refresh,
@@ -45,13 +72,30 @@ impl BlockingSystemInfo {
             _info: sysinfo,
             _os_info: os_info,
             _last_cpu_ckpt: cpu_ckpt,
+            _last_core_ckpts: core_ckpts,
+            _io: io,
+        }
+    }
+
+    fn _clamp_refresh(refresh: Duration) -> Duration {
+        if refresh < MINIMUM_CPU_UPDATE_INTERVAL {
+            warn!(
+                "System info refresh interval is too low ({}s), using {}s instead",
+                refresh.as_secs_f64(),
+                MINIMUM_CPU_UPDATE_INTERVAL.as_secs_f64()
+            );
+            MINIMUM_CPU_UPDATE_INTERVAL
+        } else {
+            refresh
         }
     }
 
     fn _fetch_sysinfo(
-        last_cpu_ckpt: &(u64, u64, u64),
+        last_cpu_ckpt: &_CpuCheckpoint,
+        last_core_ckpts: Option<&[_CpuCheckpoint]>,
+        io: Option<&mut (Disks, Networks)>,
         os_info: &Arc<OSInfo>,
-    ) -> Option<((u64, u64, u64), Arc<SystemInfo>)> {
+    ) -> Option<(_CpuCheckpoint, Option<Vec<_CpuCheckpoint>>, Arc<SystemInfo>)> {
         let cpu_ckpt = match get_system_times() {
             Ok(ckpt) => ckpt,
             Err(e) => {
@@ -59,7 +103,22 @@ impl BlockingSystemInfo {
                 return None;
             }
         };
-        let cpu = CPUInfo::from_ckpt(last_cpu_ckpt, &cpu_ckpt);
+        let mut cpu = CPUInfo::from_ckpt(last_cpu_ckpt, &cpu_ckpt);
+
+        let core_ckpts = match last_core_ckpts {
+            Some(last_core_ckpts) => match get_processor_times() {
+                Ok(ckpts) => {
+                    cpu = cpu.with_cores(last_core_ckpts, &ckpts);
+                    Some(ckpts)
+                }
+                Err(e) => {
+                    warn!("Failed to get per-core CPU times: {e}");
+                    Some(last_core_ckpts.to_vec())
+                }
+            },
+            None => None,
+        };
+
         let memory = match memory_status() {
             Ok(mem) => mem,
             Err(e) => {
@@ -70,10 +129,12 @@ impl BlockingSystemInfo {
 
         Some((
             cpu_ckpt,
+            core_ckpts,
             Arc::new(SystemInfo::new(
                 os_info.clone(),
                 memory,
                 cpu,
+                io.map(|(disks, networks)| Self::_sample_io(disks, networks)),
                 if cfg!(target_arch = "x86_64") {
                     "x86_64"
                 } else if cfg!(target_arch = "x86") {
@@ -87,11 +148,44 @@ impl BlockingSystemInfo {
         ))
     }
 
+    /// Refresh `disks` and `networks`, returning the throughput since their previous refresh.
+    fn _sample_io(disks: &mut Disks, networks: &mut Networks) -> IOInfo {
+        disks.refresh(true);
+        networks.refresh(true);
+
+        let mut io = IOInfo {
+            disk_read_bytes: 0,
+            disk_written_bytes: 0,
+            network_received_bytes: 0,
+            network_received_packets: 0,
+            network_transmitted_bytes: 0,
+            network_transmitted_packets: 0,
+        };
+        for disk in disks.list() {
+            let usage = disk.usage();
+            io.disk_read_bytes += usage.read_bytes;
+            io.disk_written_bytes += usage.written_bytes;
+        }
+        for data in networks.list().values() {
+            io.network_received_bytes += data.received();
+            io.network_received_packets += data.packets_received();
+            io.network_transmitted_bytes += data.transmitted();
+            io.network_transmitted_packets += data.packets_transmitted();
+        }
+
+        io
+    }
+
     pub fn system_info(&mut self) -> Arc<SystemInfo> {
         if self._last_update.elapsed() > self._system_refresh
-            && let Some(packed) = Self::_fetch_sysinfo(&self._last_cpu_ckpt, &self._os_info)
+            && let Some(packed) = Self::_fetch_sysinfo(
+                &self._last_cpu_ckpt,
+                self._last_core_ckpts.as_deref(),
+                self._io.as_mut(),
+                &self._os_info,
+            )
         {
-            (self._last_cpu_ckpt, self._info) = packed;
+            (self._last_cpu_ckpt, self._last_core_ckpts, self._info) = packed;
             self._last_update = Instant::now();
         }
 
@@ -99,14 +193,207 @@ impl BlockingSystemInfo {
     }
 }
 
+pub struct BlockingFileHasher {
+    _cache: LruCache<(PathBuf, SystemTime), String>,
+}
+
+impl BlockingFileHasher {
+    pub fn new(cache_size: usize) -> Self {
+        Self {
+            _cache: LruCache::new(
+                NonZeroUsize::new(cache_size).unwrap_or_else(|| panic!("{cache_size} > 0")),
+            ),
+        }
+    }
+
+    fn _hash_file(path: &Path) -> io::Result<String> {
+        let mut file = File::open(path)?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut file, &mut hasher)?;
+
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Compute the SHA-256 hash of a file, using the cached value if the file was not modified
+    /// since it was last hashed. Returns `None` if the file cannot be read.
+    pub fn sha256(&mut self, path: &Path) -> Option<String> {
+        let modified = match fs::metadata(path).and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(e) => {
+                debug!("Unable to query metadata of {}: {e}", path.display());
+                return None;
+            }
+        };
+
+        let key = (path.to_path_buf(), modified);
+        if let Some(hash) = self._cache.get(&key) {
+            return Some(hash.clone());
+        }
+
+        match Self::_hash_file(path) {
+            Ok(hash) => {
+                self._cache.put(key, hash.clone());
+                Some(hash)
+            }
+            Err(e) => {
+                debug!("Unable to hash {}: {e}", path.display());
+                None
+            }
+        }
+    }
+}
+
+pub struct BlockingUserResolver {
+    /// Failed lookups are cached as well, so unqueryable processes (e.g. System) are not queried
+    /// on each of their events
+    _cache: LruCache<u32, Option<EventUser>>,
+}
+
+impl BlockingUserResolver {
+    pub fn new(cache_size: usize) -> Self {
+        Self {
+            _cache: LruCache::new(
+                NonZeroUsize::new(cache_size).unwrap_or_else(|| panic!("{cache_size} > 0")),
+            ),
+        }
+    }
+
+    /// Forget the cached account of `process_id`, whose process id was reused by a new process.
+    pub fn invalidate(&mut self, process_id: u32) {
+        self._cache.pop(&process_id);
+    }
+
+    /// Resolve the account a process runs as, falling back to the cached value if the process
+    /// has exited. Returns `None` if the process could not be queried when first seen.
+    pub fn user(&mut self, process_id: u32) -> Option<EventUser> {
+        if let Some(user) = self._cache.get(&process_id) {
+            return user.clone();
+        }
+
+        let user = match process_user(process_id) {
+            Ok(user) => Some(EventUser {
+                id: user.sid,
+                name: user.name,
+                domain: user.domain,
+            }),
+            Err(e) => {
+                debug!("Unable to resolve user of process {process_id}: {e}");
+                None
+            }
+        };
+
+        self._cache.put(process_id, user.clone());
+        user
+    }
+}
+
 pub struct BlockingEventEnricher {
     pub system: BlockingSystemInfo,
+    pub hasher: Option<BlockingFileHasher>,
+    pub users: Option<BlockingUserResolver>,
+    pub redactor: Option<EventRedactor>,
 }
 
 impl BlockingEventEnricher {
-    pub async fn async_new(system_refresh: Duration) -> Self {
+    pub async fn async_new(config: &Configuration) -> Self {
         Self {
-            system: BlockingSystemInfo::async_new(system_refresh).await,
+            system: BlockingSystemInfo::async_new(
+                Duration::from_secs_f64(config.system_refresh_interval_seconds),
+                config.enrichment.per_core_cpu_usage,
+                config.enrichment.io_metrics,
+            )
+            .await,
+            hasher: config
+                .enrichment
+                .hash_executables
+                .then(|| BlockingFileHasher::new(config.enrichment.hash_cache_size)),
+            users: config
+                .enrichment
+                .user_context
+                .then(|| BlockingUserResolver::new(config.enrichment.user_cache_size)),
+            redactor: EventRedactor::new(config),
+        }
+    }
+
+    pub fn enrich(&mut self, event: &mut Event) {
+        if let Some(users) = &mut self.users {
+            // A started process may reuse the id of an exited one, and is the subject of the event
+            let process_id = match &event.data {
+                EventData::Process { process_id, .. } if event.opcode == 1 => {
+                    users.invalidate(*process_id);
+                    *process_id
+                }
+                _ => event.process_id,
+            };
+            event.user = users.user(process_id);
+        }
+
+        if let Some(hasher) = &mut self.hasher {
+            let opcode = event.opcode;
+            match &mut event.data {
+                // Image load
+                EventData::Image {
+                    file_name, sha256, ..
+                } if opcode == 10 => {
+                    *sha256 = hasher.sha256(&device_path_to_win32(file_name));
+                }
+                // Process start
+                EventData::Process {
+                    process_id, sha256, ..
+                } if opcode == 1 => {
+                    *sha256 = process_image_path(*process_id)
+                        .ok()
+                        .and_then(|path| hasher.sha256(&path));
+                }
+                _ => {}
+            }
+        }
+
+        // Last, as hashing executables needs the original paths
+        if let Some(redactor) = &self.redactor {
+            redactor.redact(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use sysinfo::MINIMUM_CPU_UPDATE_INTERVAL;
+
+    use super::BlockingSystemInfo;
+
+    #[test]
+    fn clamps_refresh_interval() {
+        assert_eq!(
+            BlockingSystemInfo::_clamp_refresh(Duration::ZERO),
+            MINIMUM_CPU_UPDATE_INTERVAL
+        );
+        assert_eq!(
+            BlockingSystemInfo::_clamp_refresh(MINIMUM_CPU_UPDATE_INTERVAL / 2),
+            MINIMUM_CPU_UPDATE_INTERVAL
+        );
+
+        let refresh = MINIMUM_CPU_UPDATE_INTERVAL * 2;
+        assert_eq!(BlockingSystemInfo::_clamp_refresh(refresh), refresh);
+    }
+
+    #[tokio::test]
+    async fn cpu_usage_is_meaningful_with_tiny_refresh_interval() {
+        let mut system =
+            BlockingSystemInfo::async_new(Duration::from_millis(1), false, false).await;
+
+        // Spinning keeps a processor busy, so every sample must report some usage. Samples
+        // computed over windows shorter than the resolution of system times would read as 0 or
+        // NaN instead.
+        let started = Instant::now();
+        while started.elapsed() < MINIMUM_CPU_UPDATE_INTERVAL * 3 {
+            let usage = system.system_info().cpu.usage;
+            assert!(
+                usage.is_finite() && usage > 0.0 && usage <= 100.0,
+                "unexpected CPU usage {usage}"
+            );
         }
     }
 }
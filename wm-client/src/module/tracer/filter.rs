@@ -0,0 +1,363 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::process;
+
+use parking_lot::Mutex as BlockingMutex;
+use wm_common::schema::event::{Event, EventData};
+use wm_common::utils::process_image_path;
+
+use crate::configuration::{Configuration, ProcessFilterMode};
+
+/// Number of processes whose match is remembered before the cache is cleared, bounding memory
+/// when process end events are not captured.
+const _MAX_CACHED_PROCESSES: usize = 65536;
+
+/// Filter dropping events originating from the agent itself, which would otherwise create
+/// noise and feedback loops (e.g. writing backup files generates file events).
+///
+/// When descendant tracking is enabled, processes spawned by the agent (and their own
+/// children) are tracked via the process provider's start/exit events and excluded as well.
+pub struct SelfExclusionFilter {
+    _process_id: Option<u32>,
+    _descendants: Option<BlockingMutex<HashSet<u32>>>,
+}
+
+impl SelfExclusionFilter {
+    pub fn new(config: &Configuration) -> Self {
+        let enabled = config.self_exclusion.enabled;
+        Self {
+            _process_id: enabled.then(process::id),
+            _descendants: (enabled && config.self_exclusion.descendants)
+                .then(|| BlockingMutex::new(HashSet::new())),
+        }
+    }
+
+    pub fn excluded(&self, event: &Event) -> bool {
+        let agent_id = match self._process_id {
+            Some(agent_id) => agent_id,
+            None => return false,
+        };
+
+        let descendants = match &self._descendants {
+            Some(descendants) => descendants,
+            None => return event.process_id == agent_id,
+        };
+
+        let mut descendants = descendants.lock();
+        if let EventData::Process {
+            process_id,
+            parent_id,
+            ..
+        } = &event.data
+        {
+            match event.opcode {
+                // Process start
+                1 => {
+                    if *parent_id == agent_id || descendants.contains(parent_id) {
+                        descendants.insert(*process_id);
+                        return true;
+                    }
+                }
+                // Process end: forget the PID so that it can be safely reused by the OS
+                2 => {
+                    if descendants.remove(process_id) {
+                        return true;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        event.process_id == agent_id || descendants.contains(&event.process_id)
+    }
+}
+
+/// Filter restricting events to (or excluding events of) the processes listed in the
+/// configuration.
+///
+/// Whether a process matches is resolved from its image path once and cached by process id.
+/// The process provider's start/exit events keep the cache consistent with PID reuse; process
+/// events themselves always pass, so that process trees remain complete.
+pub struct ProcessFilter {
+    _mode: ProcessFilterMode,
+    _processes: Vec<String>,
+    _matches: BlockingMutex<HashMap<u32, bool>>,
+}
+
+impl ProcessFilter {
+    pub fn new(config: &Configuration) -> Self {
+        Self {
+            _mode: config.process_filter.mode,
+            _processes: config
+                .process_filter
+                .processes
+                .iter()
+                .map(|process| process.to_lowercase())
+                .collect(),
+            _matches: BlockingMutex::new(HashMap::new()),
+        }
+    }
+
+    fn _matches_path(&self, path: &str) -> bool {
+        let path = path.to_lowercase();
+        let name = Path::new(&path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(&path);
+
+        self._processes.iter().any(|process| {
+            if process.contains(['\\', '/']) {
+                *process == path
+            } else {
+                process == name
+            }
+        })
+    }
+
+    /// Whether the process matches, falling back to `image_file_name` if its image path cannot
+    /// be queried (e.g. the process already exited).
+    fn _resolve(&self, process_id: u32, image_file_name: Option<&str>) -> bool {
+        match process_image_path(process_id) {
+            Ok(path) => self._matches_path(&path.to_string_lossy()),
+            Err(_) => image_file_name.is_some_and(|name| self._matches_path(name)),
+        }
+    }
+
+    fn _cache(&self, process_id: u32, matched: bool) {
+        let mut matches = self._matches.lock();
+        if matches.len() >= _MAX_CACHED_PROCESSES {
+            matches.clear();
+        }
+
+        matches.insert(process_id, matched);
+    }
+
+    pub fn excluded(&self, event: &Event) -> bool {
+        let allow = match self._mode {
+            ProcessFilterMode::Disabled => return false,
+            ProcessFilterMode::Allow => true,
+            ProcessFilterMode::Deny => false,
+        };
+
+        if let EventData::Process {
+            process_id,
+            image_file_name,
+            ..
+        } = &event.data
+        {
+            match event.opcode {
+                // Process start: the PID may have been reused, so resolve it again
+                1 => self._cache(
+                    *process_id,
+                    self._resolve(*process_id, Some(image_file_name)),
+                ),
+                // Process end
+                2 => {
+                    self._matches.lock().remove(process_id);
+                }
+                _ => {}
+            }
+
+            return false;
+        }
+
+        let cached = self._matches.lock().get(&event.process_id).copied();
+        let matched = cached.unwrap_or_else(|| {
+            let matched = self._resolve(event.process_id, None);
+            self._cache(event.process_id, matched);
+            matched
+        });
+
+        matched != allow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use wm_common::schema::event::{Event, EventData};
+
+    use super::{ProcessFilter, SelfExclusionFilter};
+    use crate::configuration::{Configuration, ProcessFilterMode};
+
+    // Windows process ids are multiples of 4, so these never resolve to a running process and
+    // matches fall back to the image name of the process start events
+    const _LISTED: u32 = 0xffff_fff1;
+    const _OTHER: u32 = 0xffff_fff5;
+    const _UNKNOWN: u32 = 0xffff_fff9;
+
+    fn _self_filter(enabled: bool, descendants: bool) -> SelfExclusionFilter {
+        let mut config = Configuration::default();
+        config.self_exclusion.enabled = enabled;
+        config.self_exclusion.descendants = descendants;
+        SelfExclusionFilter::new(&config)
+    }
+
+    fn _process_filter(mode: ProcessFilterMode, processes: &[&str]) -> ProcessFilter {
+        let mut config = Configuration::default();
+        config.process_filter.mode = mode;
+        config.process_filter.processes = processes.iter().map(|p| p.to_string()).collect();
+        ProcessFilter::new(&config)
+    }
+
+    fn _event(process_id: u32, opcode: u8, data: EventData) -> Event {
+        Event {
+            guid: "00000000-0000-0000-0000-000000000000".to_string(),
+            raw_timestamp: 133_000_000_000_000_000,
+            process_id,
+            thread_id: 5678,
+            event_id: 0,
+            opcode,
+            data,
+            user: None,
+        }
+    }
+
+    fn _process(opcode: u8, process_id: u32, parent_id: u32, image_file_name: &str) -> Event {
+        _event(
+            parent_id,
+            opcode,
+            EventData::Process {
+                unique_process_key: 0,
+                process_id,
+                parent_id,
+                session_id: 1,
+                exit_status: 0,
+                directory_table_base: 0,
+                image_file_name: image_file_name.to_string(),
+                command_line: image_file_name.to_string(),
+                sha256: None,
+                command_line_sha256: None,
+            },
+        )
+    }
+
+    fn _file(process_id: u32) -> Event {
+        _event(
+            process_id,
+            35,
+            EventData::FileDelete {
+                file_path: r"C:\Users\sample\file.txt".to_string(),
+            },
+        )
+    }
+
+    /// Start the listed and the other process, returning whether their file events are excluded.
+    fn _excluded(filter: &ProcessFilter, listed_image: &str) -> (bool, bool) {
+        assert!(!filter.excluded(&_process(1, _LISTED, 4, listed_image)));
+        assert!(!filter.excluded(&_process(1, _OTHER, 4, r"C:\Windows\notepad.exe")));
+        (
+            filter.excluded(&_file(_LISTED)),
+            filter.excluded(&_file(_OTHER)),
+        )
+    }
+
+    #[test]
+    fn captures_every_process_when_disabled() {
+        let filter = _process_filter(ProcessFilterMode::Disabled, &["powershell.exe"]);
+        assert_eq!(
+            _excluded(&filter, r"C:\Windows\powershell.exe"),
+            (false, false)
+        );
+        assert!(!filter.excluded(&_file(_UNKNOWN)));
+    }
+
+    #[test]
+    fn captures_allowed_processes_only() {
+        let filter = _process_filter(ProcessFilterMode::Allow, &["powershell.exe"]);
+        assert_eq!(
+            _excluded(&filter, r"C:\Windows\powershell.exe"),
+            (false, true)
+        );
+    }
+
+    #[test]
+    fn excludes_denied_processes() {
+        let filter = _process_filter(ProcessFilterMode::Deny, &["powershell.exe"]);
+        assert_eq!(
+            _excluded(&filter, r"C:\Windows\powershell.exe"),
+            (true, false)
+        );
+    }
+
+    #[test]
+    fn matches_names_and_paths_case_insensitively() {
+        let filter = _process_filter(ProcessFilterMode::Deny, &["PowerShell.EXE"]);
+        assert_eq!(
+            _excluded(&filter, r"C:\WINDOWS\POWERSHELL.exe"),
+            (true, false)
+        );
+
+        let filter = _process_filter(ProcessFilterMode::Deny, &[r"C:\Tools\PowerShell.exe"]);
+        assert_eq!(
+            _excluded(&filter, r"c:\tools\powershell.EXE"),
+            (true, false)
+        );
+
+        // Full paths must match entirely, unlike names
+        let filter = _process_filter(ProcessFilterMode::Deny, &[r"C:\Tools\powershell.exe"]);
+        assert_eq!(
+            _excluded(&filter, r"C:\Windows\powershell.exe"),
+            (false, false)
+        );
+    }
+
+    #[test]
+    fn treats_processes_without_an_image_as_unlisted() {
+        // No start event was captured and the image path cannot be queried
+        let filter = _process_filter(ProcessFilterMode::Allow, &["powershell.exe"]);
+        assert!(filter.excluded(&_file(_UNKNOWN)));
+
+        let filter = _process_filter(ProcessFilterMode::Deny, &["powershell.exe"]);
+        assert!(!filter.excluded(&_file(_UNKNOWN)));
+    }
+
+    #[test]
+    fn forgets_processes_once_they_exit() {
+        let filter = _process_filter(ProcessFilterMode::Deny, &["powershell.exe"]);
+        assert!(!filter.excluded(&_process(1, _LISTED, 4, "powershell.exe")));
+        assert!(filter.excluded(&_file(_LISTED)));
+
+        // The process id may be reused by another process
+        assert!(!filter.excluded(&_process(2, _LISTED, 4, "powershell.exe")));
+        assert!(!filter.excluded(&_file(_LISTED)));
+    }
+
+    #[test]
+    fn excludes_the_agent_itself() {
+        let agent = process::id();
+        let filter = _self_filter(true, false);
+        assert!(filter.excluded(&_file(agent)));
+        assert!(!filter.excluded(&_file(_OTHER)));
+
+        // Without descendant tracking, children of the agent are captured
+        assert!(filter.excluded(&_process(1, _LISTED, agent, "cmd.exe")));
+        assert!(!filter.excluded(&_file(_LISTED)));
+
+        let filter = _self_filter(false, true);
+        assert!(!filter.excluded(&_process(1, _LISTED, agent, "cmd.exe")));
+        assert!(!filter.excluded(&_file(agent)));
+    }
+
+    #[test]
+    fn excludes_descendants_of_the_agent() {
+        let agent = process::id();
+        let filter = _self_filter(true, true);
+
+        assert!(filter.excluded(&_process(1, _LISTED, agent, "cmd.exe")));
+        assert!(filter.excluded(&_file(_LISTED)));
+        assert!(filter.excluded(&_process(1, _UNKNOWN, _LISTED, "conhost.exe")));
+        assert!(filter.excluded(&_file(_UNKNOWN)));
+
+        // Processes started by others pass
+        assert!(!filter.excluded(&_process(1, _OTHER, 4, "notepad.exe")));
+        assert!(!filter.excluded(&_file(_OTHER)));
+
+        // Exited descendants are forgotten, as their process id may be reused
+        assert!(filter.excluded(&_process(2, _LISTED, agent, "cmd.exe")));
+        assert!(!filter.excluded(&_process(1, _LISTED, 4, "notepad.exe")));
+        assert!(!filter.excluded(&_file(_LISTED)));
+        assert!(filter.excluded(&_file(_UNKNOWN)));
+    }
+}
@@ -1,31 +1,40 @@
 pub mod enricher;
+pub mod filter;
+pub mod offload;
 pub mod providers;
+pub mod redaction;
 
 use std::error::Error;
 use std::sync::Arc;
-use std::time::Duration;
+use std::{process, slice};
 
 use async_trait::async_trait;
 use ferrisetw::native::TraceHandle;
 use ferrisetw::trace::{
     KernelTrace, TraceBuilder, TraceError, TraceTrait, UserTrace, stop_trace_by_name,
 };
-use parking_lot::Mutex as BlockingMutex;
+use log::{error, info, warn};
 use tokio::sync::{Mutex, SetOnce, mpsc};
 use tokio::task;
+use windows::Win32::Security::SE_SYSTEM_PROFILE_NAME;
 use wm_common::error::RuntimeError;
+use wm_common::etw::trace_session_names;
+use wm_common::privilege::{has_privilege, is_elevated};
 use wm_common::schema::event::CapturedEventRecord;
+use wm_common::utils::process_image_path;
 
-use crate::backup::Backup;
-use crate::configuration::Configuration;
+use crate::backup::{Backup, BackupWriter};
+use crate::configuration::{Configuration, TraceName};
 use crate::module::Module;
-use crate::module::tracer::enricher::BlockingEventEnricher;
+use crate::module::tracer::filter::{ProcessFilter, SelfExclusionFilter};
+use crate::module::tracer::offload::Enrichment;
 use crate::module::tracer::providers::kernel::file::FileProviderWrapper;
 use crate::module::tracer::providers::kernel::image::ImageProviderWrapper;
 use crate::module::tracer::providers::kernel::process::ProcessProviderWrapper;
 use crate::module::tracer::providers::kernel::registry::RegistryProviderWrapper;
 use crate::module::tracer::providers::kernel::tcpip::TcpIpProviderWrapper;
 use crate::module::tracer::providers::kernel::udpip::UdpIpProviderWrapper;
+use crate::module::tracer::providers::user::dns::DnsProviderWrapper;
 use crate::module::tracer::providers::{KernelProviderWrapper, UserProviderWrapper};
 
 struct _TraceTask<T> {
@@ -62,10 +71,15 @@ where
 pub struct EventTracer {
     _config: Arc<Configuration>,
     _sender: mpsc::Sender<Arc<CapturedEventRecord>>,
-    _trace: Mutex<Option<(_TraceTask<KernelTrace>, _TraceTask<UserTrace>)>>,
+    _kernel_session: String,
+    _user_session: String,
+    _kernel_trace: Mutex<Option<_TraceTask<KernelTrace>>>,
+    _user_trace: Mutex<Option<_TraceTask<UserTrace>>>,
     _stopped: Arc<SetOnce<()>>,
-    _backup: Arc<Mutex<Backup>>,
-    _enricher: Arc<BlockingMutex<BlockingEventEnricher>>,
+    _backup: BackupWriter,
+    _enrichment: Arc<Enrichment>,
+    _self_filter: Arc<SelfExclusionFilter>,
+    _process_filter: Arc<ProcessFilter>,
 }
 
 impl EventTracer {
@@ -77,38 +91,114 @@ impl EventTracer {
     where
         Self: Sized,
     {
+        let suffix = config.trace_session_suffix();
+        let backup = BackupWriter::spawn(backup, &config);
+        let enrichment = Arc::new(Enrichment::async_new(&config, &sender, &backup).await);
         Self {
             _config: config.clone(),
             _sender: sender,
-            _trace: Mutex::new(None),
+            _kernel_session: TraceName::session(&config.trace_name.kernel, &suffix),
+            _user_session: TraceName::session(&config.trace_name.user, &suffix),
+            _kernel_trace: Mutex::new(None),
+            _user_trace: Mutex::new(None),
             _stopped: Arc::new(SetOnce::new()),
             _backup: backup,
-            _enricher: Arc::new(BlockingMutex::new(
-                BlockingEventEnricher::async_new(Duration::from_secs_f64(
-                    config.system_refresh_interval_seconds,
-                ))
-                .await,
-            )),
+            _enrichment: enrichment,
+            _self_filter: Arc::new(SelfExclusionFilter::new(&config)),
+            _process_filter: Arc::new(ProcessFilter::new(&config)),
         }
     }
 
-    fn _kernel_trace(self: &Arc<Self>) -> TraceBuilder<KernelTrace> {
-        let mut builder = KernelTrace::new().named(self._config.trace_name.kernel.clone());
-        let wrappers: Vec<Arc<dyn KernelProviderWrapper>> = vec![
+    /// Whether the process is privileged enough to start ETW traces, which otherwise fail with
+    /// an opaque access denied error.
+    fn _can_trace() -> bool {
+        match is_elevated() {
+            Ok(true) => true,
+            Ok(false) => has_privilege(SE_SYSTEM_PROFILE_NAME).unwrap_or_else(|e| {
+                warn!("Unable to query process privileges: {e}");
+                true
+            }),
+            Err(e) => {
+                warn!("Unable to query process elevation: {e}");
+                true
+            }
+        }
+    }
+
+    /// Stop sessions left behind by agents that are no longer running (e.g. after a crash), which
+    /// would otherwise hold kernel resources until reboot and may prevent new sessions.
+    ///
+    /// Sessions suffixed with the process id of a running process belong to another agent and
+    /// are left alone, as are sessions with custom suffixes.
+    fn _stop_orphaned_sessions(&self) {
+        let sessions = match trace_session_names() {
+            Ok(sessions) => sessions,
+            Err(e) => {
+                warn!("Unable to enumerate ETW sessions: {e}");
+                return;
+            }
+        };
+
+        let bases = [
+            &self._config.trace_name.kernel,
+            &self._config.trace_name.user,
+        ];
+        for session in sessions {
+            let orphaned = bases.iter().any(|base| {
+                // Sessions started before session names were suffixed
+                if session == **base {
+                    return true;
+                }
+
+                session
+                    .strip_prefix(base.as_str())
+                    .and_then(|rest| rest.strip_prefix(" ["))
+                    .and_then(|rest| rest.strip_suffix(']'))
+                    .and_then(|suffix| suffix.parse::<u32>().ok())
+                    .is_some_and(|pid| pid != process::id() && process_image_path(pid).is_err())
+            });
+
+            if orphaned {
+                match stop_trace_by_name(&session) {
+                    Ok(_) => info!("Stopped orphaned ETW session \"{session}\""),
+                    Err(e) => warn!("Unable to stop orphaned ETW session \"{session}\": {e:?}"),
+                }
+            }
+        }
+    }
+
+    fn _kernel_wrappers(config: &Configuration) -> Vec<Arc<dyn KernelProviderWrapper>> {
+        vec![
             Arc::new(FileProviderWrapper::new(1000)),
-            Arc::new(ImageProviderWrapper {}),
+            Arc::new(ImageProviderWrapper::new(config)),
             Arc::new(ProcessProviderWrapper {}),
-            Arc::new(RegistryProviderWrapper {}),
+            Arc::new(RegistryProviderWrapper::new(1000, config)),
             Arc::new(TcpIpProviderWrapper {}),
             Arc::new(UdpIpProviderWrapper {}),
             // Add kernel provider wrappers here as needed
-        ];
+        ]
+    }
 
+    fn _user_wrappers() -> Vec<Arc<dyn UserProviderWrapper>> {
+        vec![
+            Arc::new(DnsProviderWrapper {}),
+            // Add user provider wrappers here as needed
+        ]
+    }
+
+    fn _kernel_trace(
+        self: &Arc<Self>,
+        wrappers: &[Arc<dyn KernelProviderWrapper>],
+    ) -> TraceBuilder<KernelTrace> {
+        let mut builder = KernelTrace::new().named(self._kernel_session.clone());
         for wrapper in wrappers {
-            builder = wrapper.attach(
+            builder = wrapper.clone().attach(
                 builder,
+                self._config.clone(),
                 self._sender.clone(),
-                self._enricher.clone(),
+                self._enrichment.clone(),
+                self._self_filter.clone(),
+                self._process_filter.clone(),
                 self._backup.clone(),
             );
         }
@@ -116,23 +206,84 @@ impl EventTracer {
         builder
     }
 
-    fn _user_trace(self: &Arc<Self>) -> TraceBuilder<UserTrace> {
-        let mut builder = UserTrace::new().named(self._config.trace_name.user.clone());
-        let wrappers: Vec<Arc<dyn UserProviderWrapper>> = vec![
-            // Add user provider wrappers here as needed
-        ];
-
+    fn _user_trace(
+        self: &Arc<Self>,
+        wrappers: &[Arc<dyn UserProviderWrapper>],
+    ) -> TraceBuilder<UserTrace> {
+        let mut builder = UserTrace::new().named(self._user_session.clone());
         for wrapper in wrappers {
-            builder = wrapper.attach(
+            builder = wrapper.clone().attach(
                 builder,
+                self._config.clone(),
                 self._sender.clone(),
-                self._enricher.clone(),
+                self._enrichment.clone(),
+                self._self_filter.clone(),
+                self._process_filter.clone(),
                 self._backup.clone(),
             );
         }
 
         builder
     }
+
+    /// Start a trace with all `wrappers`.
+    ///
+    /// Unless all providers are required, a trace failing to start (e.g. a provider requiring
+    /// privileges the service lacks) is retried with the providers that can be started on
+    /// their own, which are found by starting and immediately stopping a trace per provider.
+    fn _start_trace<T, W>(
+        &self,
+        kind: &str,
+        wrappers: Vec<Arc<W>>,
+        build: impl Fn(&[Arc<W>]) -> TraceBuilder<T>,
+        describe: impl Fn(&W) -> String,
+    ) -> Result<_TraceTask<T>, Box<dyn Error + Send + Sync>>
+    where
+        T: TraceTrait,
+        W: ?Sized,
+    {
+        let error = match build(&wrappers).start() {
+            Ok((trace, handle)) => return Ok(_TraceTask::start(trace, handle)),
+            Err(e) => e,
+        };
+
+        if self._config.require_all_providers || wrappers.is_empty() {
+            Err(RuntimeError::new(format!(
+                "Unable to start {kind} trace: {error:?}"
+            )))?;
+        }
+
+        warn!("Unable to start {kind} trace ({error:?}), probing providers individually");
+
+        let mut available = vec![];
+        for wrapper in wrappers {
+            match build(slice::from_ref(&wrapper)).start() {
+                Ok((trace, _)) => {
+                    if let Err(e) = trace.stop() {
+                        warn!("Unable to stop probing {kind} trace: {e:?}");
+                    }
+
+                    available.push(wrapper);
+                }
+                Err(e) => error!("Disabling {kind} provider {}: {e:?}", describe(&wrapper)),
+            }
+        }
+
+        if available.is_empty() {
+            Err(RuntimeError::new(format!(
+                "No {kind} provider can be started"
+            )))?;
+        }
+
+        let (trace, handle) = build(&available).start().map_err(|e| {
+            RuntimeError::new(format!(
+                "Unable to start {kind} trace with the remaining providers: {e:?}"
+            ))
+        })?;
+        info!("Started {kind} trace with {} provider(s)", available.len());
+
+        Ok(_TraceTask::start(trace, handle))
+    }
 }
 
 #[async_trait]
@@ -159,34 +310,72 @@ impl Module for EventTracer {
     }
 
     async fn before_hook(self: Arc<Self>) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let _ = stop_trace_by_name(&self._config.trace_name.kernel);
-        let _ = stop_trace_by_name(&self._config.trace_name.user);
+        if !Self::_can_trace() {
+            Err(RuntimeError::new(
+                "Insufficient privileges to start ETW traces: the agent must run as Administrator or LocalSystem (or hold SeSystemProfilePrivilege)",
+            ))?;
+        }
 
-        let kernel = self
-            ._kernel_trace()
-            .start()
-            .map_err(|e| RuntimeError::new(format!("Unable to start kernel trace: {e:?}")))?;
+        self._stop_orphaned_sessions();
 
-        let user = self
-            ._user_trace()
-            .start()
-            .map_err(|e| RuntimeError::new(format!("Unable to start user trace: {e:?}")))?;
+        // Only our own sessions, other agents or ETW consumers on the host are left alone
+        let _ = stop_trace_by_name(&self._kernel_session);
+        let _ = stop_trace_by_name(&self._user_session);
 
-        *self._trace.lock().await = Some((
-            _TraceTask::start(kernel.0, kernel.1),
-            _TraceTask::start(user.0, user.1),
-        ));
+        let kernel = self._start_trace(
+            "kernel",
+            Self::_kernel_wrappers(&self._config),
+            |wrappers| self._kernel_trace(wrappers),
+            |wrapper| format!("{:?}", wrapper.provider().guid),
+        );
+        let user = self._start_trace(
+            "user",
+            Self::_user_wrappers(),
+            |wrappers| self._user_trace(wrappers),
+            |wrapper| format!("{:?}", wrapper.guid()),
+        );
+
+        match (kernel, user) {
+            (Ok(kernel), Ok(user)) => {
+                *self._kernel_trace.lock().await = Some(kernel);
+                *self._user_trace.lock().await = Some(user);
+            }
+            (Err(e), _) | (_, Err(e)) if self._config.require_all_providers => return Err(e),
+            (Ok(kernel), Err(e)) => {
+                error!("Continuing without user trace: {e}");
+                *self._kernel_trace.lock().await = Some(kernel);
+            }
+            (Err(e), Ok(user)) => {
+                error!("Continuing without kernel trace: {e}");
+                *self._user_trace.lock().await = Some(user);
+            }
+            (Err(kernel), Err(user)) => {
+                Err(RuntimeError::new(format!(
+                    "Unable to start any trace (kernel: {kernel}, user: {user})"
+                )))?;
+            }
+        }
 
         Ok(())
     }
 
     async fn after_hook(self: Arc<Self>) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let mut self_trace = self._trace.lock().await;
-        if let Some((kernel, user)) = self_trace.take() {
+        if let Some(kernel) = self._kernel_trace.lock().await.take() {
             kernel.stop().await?;
+        }
+
+        if let Some(user) = self._user_trace.lock().await.take() {
             user.stop().await?;
         }
 
+        if let Enrichment::Offloaded(pool) = self._enrichment.as_ref() {
+            pool.shutdown().await;
+        }
+
+        // Nothing submits events past this point, write the overflowed ones before the agent
+        // finishes the backup file
+        self._backup.close().await;
+
         Ok(())
     }
 }
@@ -0,0 +1,154 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use log::{debug, error};
+use parking_lot::{Mutex as BlockingMutex, RwLock as BlockingRwLock};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{Mutex, mpsc};
+use tokio::task::JoinSet;
+use wm_common::schema::event::{CapturedEventRecord, Event};
+
+use crate::backup::BackupWriter;
+use crate::configuration::{Configuration, QueueFullPolicy};
+use crate::module::heartbeat::EVENT_COUNTERS;
+use crate::module::tracer::enricher::BlockingEventEnricher;
+use crate::module::tracer::providers::enqueue;
+
+/// Where captured events are enriched.
+pub enum Enrichment {
+    /// On the ETW callback thread, which must never wait for the enricher
+    Inline(BlockingMutex<BlockingEventEnricher>),
+
+    /// By a pool of workers, so that expensive enrichment does not delay ETW callbacks
+    Offloaded(EnrichmentPool),
+}
+
+impl Enrichment {
+    pub async fn async_new(
+        config: &Arc<Configuration>,
+        sender: &mpsc::Sender<Arc<CapturedEventRecord>>,
+        backup: &BackupWriter,
+    ) -> Self {
+        if config.enrichment.offload_workers > 0 {
+            Self::Offloaded(EnrichmentPool::async_new(config, sender, backup).await)
+        } else {
+            Self::Inline(BlockingMutex::new(
+                BlockingEventEnricher::async_new(config).await,
+            ))
+        }
+    }
+}
+
+/// Bounded pool of workers enriching events handed over by ETW callbacks.
+///
+/// Each worker owns an enricher, so workers never contend with each other.
+pub struct EnrichmentPool {
+    _sender: BlockingRwLock<Option<mpsc::Sender<(Event, DateTime<Utc>)>>>,
+    _workers: Mutex<JoinSet<()>>,
+}
+
+impl EnrichmentPool {
+    pub async fn async_new(
+        config: &Arc<Configuration>,
+        sender: &mpsc::Sender<Arc<CapturedEventRecord>>,
+        backup: &BackupWriter,
+    ) -> Self {
+        let (pool_sender, receiver) = mpsc::channel(config.enrichment.offload_queue_limit);
+        let receiver = Arc::new(BlockingMutex::new(receiver));
+
+        let mut workers = JoinSet::new();
+        for _ in 0..config.enrichment.offload_workers {
+            let enricher = BlockingEventEnricher::async_new(config).await;
+            let receiver = receiver.clone();
+            let config = config.clone();
+            let sender = sender.clone();
+            let backup = backup.clone();
+            workers.spawn_blocking(move || {
+                Self::_work(&receiver, enricher, &config, &sender, &backup);
+            });
+        }
+
+        debug!(
+            "Offloading enrichment to {} worker(s)",
+            config.enrichment.offload_workers
+        );
+        Self {
+            _sender: BlockingRwLock::new(Some(pool_sender)),
+            _workers: Mutex::new(workers),
+        }
+    }
+
+    fn _work(
+        receiver: &BlockingMutex<mpsc::Receiver<(Event, DateTime<Utc>)>>,
+        mut enricher: BlockingEventEnricher,
+        config: &Configuration,
+        sender: &mpsc::Sender<Arc<CapturedEventRecord>>,
+        backup: &BackupWriter,
+    ) {
+        loop {
+            // Release the receiver as soon as an event is received, letting other workers wait
+            let item = receiver.lock().blocking_recv();
+            let Some((mut event, captured)) = item else {
+                break;
+            };
+
+            enricher.enrich(&mut event);
+            let data = Arc::new(CapturedEventRecord {
+                event,
+                system: enricher.system.system_info(),
+                captured,
+            });
+
+            EVENT_COUNTERS.captured(1);
+            enqueue(data, config, sender, backup);
+        }
+    }
+
+    /// Hand `event` over to the workers.
+    ///
+    /// While all workers are busy and the pool queue is full, the event is discarded if its
+    /// type uses [`QueueFullPolicy::Drop`], otherwise the ETW callback waits for room.
+    pub fn submit(&self, event: Event, config: &Configuration) {
+        let sender = self._sender.read();
+        let Some(sender) = sender.as_ref() else {
+            debug!("Enrichment pool is closed, dropping event");
+            EVENT_COUNTERS.dropped(1);
+            return;
+        };
+
+        let item = match sender.try_send((event, Utc::now())) {
+            Ok(()) => return,
+            Err(TrySendError::Full(item)) => item,
+            Err(TrySendError::Closed(_)) => {
+                error!("Enrichment workers have stopped, dropping event");
+                EVENT_COUNTERS.dropped(1);
+                return;
+            }
+        };
+
+        match config.queue_full.policy(item.0.data.event_type()) {
+            QueueFullPolicy::Drop => {
+                debug!("Enrichment pool is full, dropping event");
+                EVENT_COUNTERS.dropped(1);
+            }
+            QueueFullPolicy::Backup | QueueFullPolicy::Block => {
+                if sender.blocking_send(item).is_err() {
+                    error!("Enrichment workers have stopped, dropping event");
+                    EVENT_COUNTERS.dropped(1);
+                }
+            }
+        }
+    }
+
+    /// Stop accepting events and wait for the workers to enrich those already submitted.
+    pub async fn shutdown(&self) {
+        self._sender.write().take();
+
+        let mut workers = self._workers.lock().await;
+        while let Some(result) = workers.join_next().await {
+            if let Err(e) = result {
+                error!("Enrichment worker failed: {e}");
+            }
+        }
+    }
+}
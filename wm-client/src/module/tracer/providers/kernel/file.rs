@@ -20,6 +20,9 @@ pub struct FileProviderWrapper {
 }
 
 impl FileProviderWrapper {
+    /// Opcodes of the events captured by default
+    pub const OPCODES: &'static [u8] = &[35, 64, 67, 68, 69, 70, 71, 74, 75];
+
     const _PROVIDER: KernelProvider = KernelProvider::new(
         GUID::from_values(
             0x90cbdc39,
@@ -40,18 +43,41 @@ impl FileProviderWrapper {
 }
 
 impl ProviderWrapper for FileProviderWrapper {
-    fn filter(&self, record: &EventRecord) -> bool {
-        record.opcode() == 0
-            || record.opcode() == 32
-            || record.opcode() == 35
-            || record.opcode() == 64
-            || record.opcode() == 69
-            || record.opcode() == 70
-            || record.opcode() == 71
-            || record.opcode() == 74
-            || record.opcode() == 75
-            || record.opcode() == 67
-            || record.opcode() == 68
+    fn event_type(&self) -> &'static str {
+        "file"
+    }
+
+    fn opcodes(&self) -> &'static [u8] {
+        Self::OPCODES
+    }
+
+    fn filter(&self, record: &EventRecord, opcodes: &[u8]) -> bool {
+        // Name events are always needed to resolve the paths of other events
+        matches!(record.opcode(), 0 | 32) || opcodes.contains(&record.opcode())
+    }
+
+    fn opcode_name(&self, opcode: u8) -> Option<&'static str> {
+        match opcode {
+            0 => Some("FileName"),
+            32 => Some("FileCreate"),
+            35 => Some("FileDelete"),
+            36 => Some("FileRundown"),
+            64 => Some("Create"),
+            65 => Some("Cleanup"),
+            66 => Some("Close"),
+            67 => Some("Read"),
+            68 => Some("Write"),
+            69 => Some("SetInfo"),
+            70 => Some("Delete"),
+            71 => Some("Rename"),
+            72 => Some("DirEnum"),
+            73 => Some("Flush"),
+            74 => Some("QueryInfo"),
+            75 => Some("FSControl"),
+            76 => Some("OperationEnd"),
+            77 => Some("DirNotify"),
+            _ => None,
+        }
     }
 
     fn callback(
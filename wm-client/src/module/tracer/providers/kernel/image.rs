@@ -1,19 +1,88 @@
 use std::error::Error;
+use std::num::NonZeroUsize;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use ferrisetw::parser::{Parser, Pointer};
 use ferrisetw::provider::kernel_providers::{IMAGE_LOAD_PROVIDER, KernelProvider};
 use ferrisetw::{EventRecord, SchemaLocator};
+use lru::LruCache;
+use parking_lot::Mutex as BlockingMutex;
 use wm_common::error::RuntimeError;
 use wm_common::schema::event::{Event, EventData};
 
+use crate::configuration::Configuration;
+use crate::module::heartbeat::EVENT_COUNTERS;
 use crate::module::tracer::providers::{KernelProviderWrapper, ProviderWrapper};
 
-pub struct ImageProviderWrapper;
+/// Process id, file name and checksum of a loaded image
+type _ImageLoad = (u32, String, u32);
+
+pub struct ImageProviderWrapper {
+    /// When each recently loaded image was last reported, if deduplication is enabled
+    _reported: Option<BlockingMutex<LruCache<_ImageLoad, Instant>>>,
+    _window: Duration,
+}
+
+impl ImageProviderWrapper {
+    /// Opcodes of the events captured by default
+    pub const OPCODES: &'static [u8] = &[2, 10];
+
+    pub fn new(config: &Configuration) -> Self {
+        let dedup = &config.image_dedup;
+        Self {
+            _reported: dedup.enabled.then(|| {
+                BlockingMutex::new(LruCache::new(
+                    NonZeroUsize::new(dedup.cache_size)
+                        .unwrap_or_else(|| panic!("{} > 0", dedup.cache_size)),
+                ))
+            }),
+            _window: Duration::from_secs(dedup.window_seconds),
+        }
+    }
+
+    /// Whether a load of `image` should be suppressed, as the same load was reported within the
+    /// window. Loads that are not suppressed are remembered as reported.
+    fn _suppress(&self, image: _ImageLoad) -> Result<bool, RuntimeError> {
+        let Some(reported) = &self._reported else {
+            return Ok(false);
+        };
+
+        let Some(mut reported) = reported.try_lock() else {
+            return Err(RuntimeError::new(
+                "Image deduplication mutex should never block",
+            ));
+        };
+
+        let now = Instant::now();
+        if let Some(last) = reported.get(&image)
+            && now.duration_since(*last) < self._window
+        {
+            return Ok(true);
+        }
+
+        reported.put(image, now);
+        Ok(false)
+    }
+}
 
 impl ProviderWrapper for ImageProviderWrapper {
-    fn filter(&self, record: &EventRecord) -> bool {
-        record.opcode() == 2 || record.opcode() == 10
+    fn event_type(&self) -> &'static str {
+        "image"
+    }
+
+    fn opcodes(&self) -> &'static [u8] {
+        Self::OPCODES
+    }
+
+    fn opcode_name(&self, opcode: u8) -> Option<&'static str> {
+        match opcode {
+            2 => Some("ImageUnload"),
+            3 => Some("ImageDCStart"),
+            4 => Some("ImageDCEnd"),
+            10 => Some("ImageLoad"),
+            _ => None,
+        }
     }
 
     fn callback(
@@ -37,6 +106,17 @@ impl ProviderWrapper for ImageProviderWrapper {
                     .try_parse::<String>("FileName")
                     .map_err(RuntimeError::from)?;
 
+                // Processes load dozens of images at start, most of them over and over again
+                if record.opcode() == 10 {
+                    let process_id = parser
+                        .try_parse::<u32>("ProcessId")
+                        .map_err(RuntimeError::from)?;
+                    if self._suppress((process_id, file_name.clone(), image_checksum))? {
+                        EVENT_COUNTERS.suppressed(1);
+                        return Ok(None);
+                    }
+                }
+
                 Ok(Some(Event::new(
                     record,
                     EventData::Image {
@@ -44,6 +124,7 @@ impl ProviderWrapper for ImageProviderWrapper {
                         image_size: *image_size,
                         image_checksum,
                         file_name,
+                        sha256: None,
                     },
                 )))
             }
@@ -4,3 +4,23 @@ pub mod process;
 pub mod registry;
 pub mod tcpip;
 pub mod udpip;
+
+use crate::module::tracer::providers::kernel::file::FileProviderWrapper;
+use crate::module::tracer::providers::kernel::image::ImageProviderWrapper;
+use crate::module::tracer::providers::kernel::process::ProcessProviderWrapper;
+use crate::module::tracer::providers::kernel::registry::RegistryProviderWrapper;
+use crate::module::tracer::providers::kernel::tcpip::TcpIpProviderWrapper;
+use crate::module::tracer::providers::kernel::udpip::UdpIpProviderWrapper;
+
+/// Opcodes captured by default by the kernel provider of the given event type.
+pub fn default_opcodes(event_type: &str) -> Option<&'static [u8]> {
+    match event_type {
+        "file" => Some(FileProviderWrapper::OPCODES),
+        "image" => Some(ImageProviderWrapper::OPCODES),
+        "process" => Some(ProcessProviderWrapper::OPCODES),
+        "registry" => Some(RegistryProviderWrapper::OPCODES),
+        "tcpip" => Some(TcpIpProviderWrapper::OPCODES),
+        "udpip" => Some(UdpIpProviderWrapper::OPCODES),
+        _ => None,
+    }
+}
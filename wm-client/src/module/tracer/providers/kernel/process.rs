@@ -11,9 +11,29 @@ use crate::module::tracer::providers::{KernelProviderWrapper, ProviderWrapper};
 
 pub struct ProcessProviderWrapper;
 
+impl ProcessProviderWrapper {
+    /// Opcodes of the events captured by default
+    pub const OPCODES: &'static [u8] = &[1, 2];
+}
+
 impl ProviderWrapper for ProcessProviderWrapper {
-    fn filter(&self, record: &EventRecord) -> bool {
-        record.opcode() == 1 || record.opcode() == 2
+    fn event_type(&self) -> &'static str {
+        "process"
+    }
+
+    fn opcodes(&self) -> &'static [u8] {
+        Self::OPCODES
+    }
+
+    fn opcode_name(&self, opcode: u8) -> Option<&'static str> {
+        match opcode {
+            1 => Some("ProcessStart"),
+            2 => Some("ProcessEnd"),
+            3 => Some("ProcessDCStart"),
+            4 => Some("ProcessDCEnd"),
+            39 => Some("ProcessDefunct"),
+            _ => None,
+        }
     }
 
     fn callback(
@@ -60,6 +80,8 @@ impl ProviderWrapper for ProcessProviderWrapper {
                         directory_table_base: *directory_table_base,
                         image_file_name,
                         command_line,
+                        sha256: None,
+                        command_line_sha256: None,
                     },
                 )))
             }
@@ -1,26 +1,117 @@
 use std::error::Error;
+use std::num::NonZeroUsize;
 use std::sync::Arc;
 
 use ferrisetw::parser::{Parser, Pointer};
 use ferrisetw::provider::kernel_providers::{KernelProvider, REGISTRY_PROVIDER};
 use ferrisetw::{EventRecord, SchemaLocator};
+use lru::LruCache;
+use parking_lot::Mutex as BlockingMutex;
 use wm_common::error::RuntimeError;
 use wm_common::schema::event::{Event, EventData};
 
+use crate::configuration::Configuration;
 use crate::module::tracer::providers::{KernelProviderWrapper, ProviderWrapper};
 
-pub struct RegistryProviderWrapper;
+pub struct RegistryProviderWrapper {
+    /// Full key names of key control blocks, which other events name their keys relative to
+    _kcb_names: BlockingMutex<LruCache<usize, String>>,
+
+    /// Opcodes of the events to capture
+    _opcodes: Vec<u8>,
+
+    /// Uppercase prefixes of the key paths to capture, all paths if empty
+    _path_prefixes: Vec<String>,
+}
+
+impl RegistryProviderWrapper {
+    /// Opcodes of the events captured by default
+    pub const OPCODES: &'static [u8] = &[10, 12, 14, 15, 20, 21, 22, 23];
+
+    /// Opcodes of events naming key control blocks
+    const _KCB_OPCODES: &'static [u8] = &[22, 23, 24, 25];
+
+    pub fn new(cache_size: usize, config: &Configuration) -> Self {
+        Self {
+            _kcb_names: BlockingMutex::new(LruCache::new(
+                NonZeroUsize::new(cache_size).unwrap_or_else(|| panic!("{cache_size} > 0")),
+            )),
+            _opcodes: config.provider_opcodes("registry", Self::OPCODES).to_vec(),
+            _path_prefixes: config
+                .registry_path_prefixes
+                .iter()
+                .map(|prefix| prefix.to_uppercase())
+                .collect(),
+        }
+    }
+
+    /// Resolve `key_name`, which is relative to the key control block `key_handle` unless the
+    /// latter is zero, to a full path.
+    fn _resolve(
+        &self,
+        kcb_names: &mut LruCache<usize, String>,
+        key_handle: usize,
+        key_name: String,
+    ) -> String {
+        if key_handle == 0 {
+            return key_name;
+        }
+
+        match kcb_names.get(&key_handle) {
+            Some(base) if key_name.is_empty() => base.clone(),
+            Some(base) => format!("{base}\\{}", key_name.trim_start_matches('\\')),
+            None => key_name,
+        }
+    }
+
+    fn _allowed(&self, path: &str) -> bool {
+        if self._path_prefixes.is_empty() {
+            return true;
+        }
+
+        let path = path.to_uppercase();
+        self._path_prefixes
+            .iter()
+            .any(|prefix| path.starts_with(prefix.as_str()))
+    }
+}
 
 impl ProviderWrapper for RegistryProviderWrapper {
-    fn filter(&self, record: &EventRecord) -> bool {
-        record.opcode() == 10
-            || record.opcode() == 12
-            || record.opcode() == 14
-            || record.opcode() == 15
-            || record.opcode() == 20
-            || record.opcode() == 21
-            || record.opcode() == 22
-            || record.opcode() == 23
+    fn event_type(&self) -> &'static str {
+        "registry"
+    }
+
+    fn opcodes(&self) -> &'static [u8] {
+        Self::OPCODES
+    }
+
+    fn filter(&self, record: &EventRecord, opcodes: &[u8]) -> bool {
+        // Key control block events are always needed to resolve the paths of other events
+        Self::_KCB_OPCODES.contains(&record.opcode()) || opcodes.contains(&record.opcode())
+    }
+
+    fn opcode_name(&self, opcode: u8) -> Option<&'static str> {
+        match opcode {
+            10 => Some("RegCreate"),
+            11 => Some("RegOpen"),
+            12 => Some("RegDelete"),
+            13 => Some("RegQuery"),
+            14 => Some("RegSetValue"),
+            15 => Some("RegDeleteValue"),
+            16 => Some("RegQueryValue"),
+            17 => Some("RegEnumerateKey"),
+            18 => Some("RegEnumerateValueKey"),
+            19 => Some("RegQueryMultipleValue"),
+            20 => Some("RegSetInformation"),
+            21 => Some("RegFlush"),
+            22 => Some("RegKCBCreate"),
+            23 => Some("RegKCBDelete"),
+            24 => Some("RegKCBRundownBegin"),
+            25 => Some("RegKCBRundownEnd"),
+            26 => Some("RegVirtualize"),
+            27 => Some("RegClose"),
+            _ => None,
+        }
     }
 
     fn callback(
@@ -47,6 +138,32 @@ impl ProviderWrapper for RegistryProviderWrapper {
                     .try_parse::<String>("KeyName")
                     .map_err(RuntimeError::from)?;
 
+                let opcode = record.opcode();
+                let key_name = match self._kcb_names.try_lock() {
+                    Some(mut kcb_names) => {
+                        if Self::_KCB_OPCODES.contains(&opcode) {
+                            // Key control block events name their own block, with a full path
+                            if opcode == 23 {
+                                kcb_names.pop(&*key_handle);
+                            } else {
+                                kcb_names.put(*key_handle, key_name.clone());
+                            }
+
+                            key_name
+                        } else {
+                            self._resolve(&mut kcb_names, *key_handle, key_name)
+                        }
+                    }
+                    None => Err(RuntimeError::new(
+                        "Registry KCB mapping mutex should never block",
+                    ))?,
+                };
+
+                // Paths still relative after resolution never match, as their key is unknown
+                if !self._opcodes.contains(&opcode) || !self._allowed(&key_name) {
+                    return Ok(None);
+                }
+
                 Ok(Some(Event::new(
                     record,
                     EventData::Registry {
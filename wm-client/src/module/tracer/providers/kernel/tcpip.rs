@@ -12,9 +12,39 @@ use crate::module::tracer::providers::{KernelProviderWrapper, ProviderWrapper};
 
 pub struct TcpIpProviderWrapper;
 
+impl TcpIpProviderWrapper {
+    /// Opcodes of the events captured by default
+    pub const OPCODES: &'static [u8] = &[12, 13, 15];
+}
+
 impl ProviderWrapper for TcpIpProviderWrapper {
-    fn filter(&self, record: &EventRecord) -> bool {
-        record.opcode() == 12 || record.opcode() == 13 || record.opcode() == 15
+    fn event_type(&self) -> &'static str {
+        "tcpip"
+    }
+
+    fn opcodes(&self) -> &'static [u8] {
+        Self::OPCODES
+    }
+
+    fn opcode_name(&self, opcode: u8) -> Option<&'static str> {
+        match opcode {
+            10 => Some("TcpSendIPV4"),
+            11 => Some("TcpRecvIPV4"),
+            12 => Some("TcpConnectIPV4"),
+            13 => Some("TcpDisconnectIPV4"),
+            14 => Some("TcpRetransmitIPV4"),
+            15 => Some("TcpAcceptIPV4"),
+            16 => Some("TcpReconnectIPV4"),
+            17 => Some("TcpFail"),
+            26 => Some("TcpSendIPV6"),
+            27 => Some("TcpRecvIPV6"),
+            28 => Some("TcpConnectIPV6"),
+            29 => Some("TcpDisconnectIPV6"),
+            30 => Some("TcpRetransmitIPV6"),
+            31 => Some("TcpAcceptIPV6"),
+            32 => Some("TcpReconnectIPV6"),
+            _ => None,
+        }
     }
 
     fn callback(
@@ -14,6 +14,9 @@ use crate::module::tracer::providers::{KernelProviderWrapper, ProviderWrapper};
 pub struct UdpIpProviderWrapper;
 
 impl UdpIpProviderWrapper {
+    /// Opcodes of the events captured by default
+    pub const OPCODES: &'static [u8] = &[10, 11];
+
     const _PROVIDER: KernelProvider = KernelProvider::new(
         GUID::from_values(
             0xbf3a50c5,
@@ -26,8 +29,23 @@ impl UdpIpProviderWrapper {
 }
 
 impl ProviderWrapper for UdpIpProviderWrapper {
-    fn filter(&self, record: &EventRecord) -> bool {
-        record.opcode() == 10 || record.opcode() == 11
+    fn event_type(&self) -> &'static str {
+        "udpip"
+    }
+
+    fn opcodes(&self) -> &'static [u8] {
+        Self::OPCODES
+    }
+
+    fn opcode_name(&self, opcode: u8) -> Option<&'static str> {
+        match opcode {
+            10 => Some("UdpSendIPV4"),
+            11 => Some("UdpRecvIPV4"),
+            17 => Some("UdpFail"),
+            26 => Some("UdpSendIPV6"),
+            27 => Some("UdpRecvIPV6"),
+            _ => None,
+        }
     }
 
     fn callback(
@@ -3,22 +3,43 @@ pub mod user;
 
 use std::error::Error;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use chrono::Utc;
-use ferrisetw::provider::Provider;
 use ferrisetw::provider::kernel_providers::KernelProvider;
+use ferrisetw::provider::{Provider, ProviderBuilder};
 use ferrisetw::trace::{KernelTrace, TraceBuilder};
 use ferrisetw::{EventRecord, GUID, SchemaLocator, UserTrace};
 use log::{debug, error, warn};
 use parking_lot::Mutex as BlockingMutex;
-use tokio::sync::{Mutex, mpsc};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::{SendError, TrySendError};
 use wm_common::schema::event::{CapturedEventRecord, Event};
 
-use crate::backup::Backup;
-use crate::module::tracer::enricher::BlockingEventEnricher;
+use crate::backup::BackupWriter;
+use crate::configuration::{Configuration, QueueFullPolicy};
+use crate::module::heartbeat::EVENT_COUNTERS;
+use crate::module::overload::PROVIDER_THROTTLE;
+use crate::module::tracer::filter::{ProcessFilter, SelfExclusionFilter};
+use crate::module::tracer::offload::Enrichment;
 
 pub trait ProviderWrapper: Send + Sync {
-    fn filter(&self, record: &EventRecord) -> bool;
+    /// Type of the captured events (see [`wm_common::schema::event::EventData::event_type`]),
+    /// keying per-provider configuration.
+    fn event_type(&self) -> &'static str;
+
+    /// Opcodes of the events captured unless overridden by `provider_opcodes`.
+    fn opcodes(&self) -> &'static [u8];
+
+    /// Whether to handle `record`, given the opcodes configured to be captured.
+    fn filter(&self, record: &EventRecord, opcodes: &[u8]) -> bool {
+        opcodes.contains(&record.opcode())
+    }
+
+    /// Name of the event with the given opcode (e.g. `ProcessStart`), for diagnostics only.
+    fn opcode_name(&self, _opcode: u8) -> Option<&'static str> {
+        None
+    }
 
     fn callback(
         self: Arc<Self>,
@@ -27,53 +48,171 @@ pub trait ProviderWrapper: Send + Sync {
     ) -> Result<Option<Event>, Box<dyn Error + Send + Sync>>;
 }
 
+/// Number of callback errors logged individually per provider before they are summarized.
+const _LOGGED_ERRORS: u64 = 10;
+
+const _ERROR_SUMMARY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Rate limiter of callback error logs of a single provider.
+///
+/// An incompatible provider fails on every event (e.g. when its schema cannot be located), so
+/// logging each failure would quickly fill the disk.
+struct _ErrorLogLimiter {
+    _total: u64,
+    _suppressed: u64,
+    _last_summary: Instant,
+}
+
+impl _ErrorLogLimiter {
+    fn new() -> Self {
+        Self {
+            _total: 0,
+            _suppressed: 0,
+            _last_summary: Instant::now(),
+        }
+    }
+
+    /// Record an error, returning whether it should be logged.
+    fn record(&mut self, provider: &GUID) -> bool {
+        self._total += 1;
+        if self._total <= _LOGGED_ERRORS {
+            if self._total == _LOGGED_ERRORS {
+                warn!(
+                    "Further errors from provider {provider:?} are summarized every {}s",
+                    _ERROR_SUMMARY_INTERVAL.as_secs()
+                );
+            }
+
+            return true;
+        }
+
+        self._suppressed += 1;
+        if self._last_summary.elapsed() >= _ERROR_SUMMARY_INTERVAL {
+            error!(
+                "Suppressed {} errors from provider {provider:?} ({} in total)",
+                self._suppressed, self._total
+            );
+            self._suppressed = 0;
+            self._last_summary = Instant::now();
+        }
+
+        false
+    }
+}
+
+/// Send a captured event to the message queue, applying the queue full policy of its type.
+pub fn enqueue(
+    data: Arc<CapturedEventRecord>,
+    config: &Configuration,
+    sender: &mpsc::Sender<Arc<CapturedEventRecord>>,
+    backup: &BackupWriter,
+) {
+    let data = match sender.try_send(data) {
+        Ok(()) => return,
+        Err(TrySendError::Full(data)) => data,
+        Err(TrySendError::Closed(data)) => {
+            warn!("Message queue is closed, backing up event to persistent file");
+            backup.submit(data);
+            return;
+        }
+    };
+
+    match config.queue_full.policy(data.event.data.event_type()) {
+        QueueFullPolicy::Backup => {
+            warn!("Message queue is full, backing up event to persistent file");
+            backup.submit(data);
+        }
+        QueueFullPolicy::Drop => {
+            debug!("Message queue is full, dropping event");
+            EVENT_COUNTERS.dropped(1);
+        }
+        QueueFullPolicy::Block => {
+            // We are on the ETW processing thread, so blocking it delays the delivery of
+            // subsequent events (ETW buffers them in the meantime)
+            if let Err(SendError(data)) = sender.blocking_send(data) {
+                warn!("Message queue is closed, backing up event to persistent file");
+                backup.submit(data);
+            }
+        }
+    }
+}
+
 fn _callback_impl<T>(
     wrapper: Arc<T>,
     record: &EventRecord,
     schema_locator: &SchemaLocator,
+    config: Arc<Configuration>,
     sender: mpsc::Sender<Arc<CapturedEventRecord>>,
-    enricher: Arc<BlockingMutex<BlockingEventEnricher>>,
-    backup: Arc<Mutex<Backup>>,
+    enrichment: Arc<Enrichment>,
+    self_filter: Arc<SelfExclusionFilter>,
+    process_filter: Arc<ProcessFilter>,
+    backup: BackupWriter,
+    opcodes: &[u8],
+    error_limiter: &BlockingMutex<_ErrorLogLimiter>,
 ) where
     T: ProviderWrapper + ?Sized,
 {
-    if wrapper.filter(record) {
+    if PROVIDER_THROTTLE.suspended(wrapper.event_type()) {
+        return;
+    }
+
+    if wrapper.filter(record, opcodes) {
+        PROVIDER_THROTTLE.record(wrapper.event_type());
+
         // cargo fmt error here: https://github.com/rust-lang/rustfmt/issues/5689
         match wrapper.clone().callback(record, schema_locator) {
-            Ok(Some(event)) => match enricher.try_lock() {
-                Some(mut enricher) => {
-                    let data = Arc::new(CapturedEventRecord {
-                        event,
-                        system: enricher.system.system_info(),
-                        captured: Utc::now(),
-                    });
-
-                    if sender.try_send(data.clone()).is_err() {
-                        warn!("Message queue is full, backing up event to persistent file");
-
-                        let backup = backup.clone();
-                        tokio::spawn(async move {
-                            let mut backup = backup.lock().await;
-                            backup.write_one(&data).await;
-                        });
+            Ok(Some(event)) if self_filter.excluded(&event) => {}
+            Ok(Some(event)) if process_filter.excluded(&event) => {}
+            Ok(Some(mut event)) => {
+                EVENT_COUNTERS.captured_variant(&event.data);
+
+                let enricher = match enrichment.as_ref() {
+                    Enrichment::Inline(enricher) => enricher,
+                    Enrichment::Offloaded(pool) => {
+                        pool.submit(event, &config);
+                        return;
                     }
-                }
-                None => {
-                    error!("Inconsistent state reached. This mutex should never block.");
-                }
-            },
+                };
+
+                // Release the enricher before enqueueing, which may block
+                let data = match enricher.try_lock() {
+                    Some(mut enricher) => {
+                        enricher.enrich(&mut event);
+
+                        Arc::new(CapturedEventRecord {
+                            event,
+                            system: enricher.system.system_info(),
+                            captured: Utc::now(),
+                        })
+                    }
+                    None => {
+                        error!("Inconsistent state reached. This mutex should never block.");
+                        return;
+                    }
+                };
+
+                EVENT_COUNTERS.captured(1);
+                enqueue(data, &config, &sender, &backup);
+            }
             Ok(None) => {}
-            Err(e) => error!(
-                "Error handling event from {:?} (event_id={}, opcode={}, version={}, level={}, keyword={}, pid={}, tid={}): {e}",
-                record.provider_id(),
-                record.event_id(),
-                record.opcode(),
-                record.version(),
-                record.level(),
-                record.keyword(),
-                record.process_id(),
-                record.thread_id(),
-            ),
+            Err(e) => {
+                if error_limiter.lock().record(&record.provider_id()) {
+                    error!(
+                        "Error handling {} (opcode {}) from {:?} (event_id={}, version={}, level={}, keyword={}, pid={}, tid={}): {e}",
+                        wrapper
+                            .opcode_name(record.opcode())
+                            .unwrap_or("unknown event"),
+                        record.opcode(),
+                        record.provider_id(),
+                        record.event_id(),
+                        record.version(),
+                        record.level(),
+                        record.keyword(),
+                        record.process_id(),
+                        record.thread_id(),
+                    );
+                }
+            }
         }
     }
 }
@@ -84,9 +223,12 @@ pub trait KernelProviderWrapper: ProviderWrapper {
     fn attach(
         self: Arc<Self>,
         trace: TraceBuilder<KernelTrace>,
+        config: Arc<Configuration>,
         sender: mpsc::Sender<Arc<CapturedEventRecord>>,
-        enricher: Arc<BlockingMutex<BlockingEventEnricher>>,
-        backup: Arc<Mutex<Backup>>,
+        enrichment: Arc<Enrichment>,
+        self_filter: Arc<SelfExclusionFilter>,
+        process_filter: Arc<ProcessFilter>,
+        backup: BackupWriter,
     ) -> TraceBuilder<KernelTrace>
     where
         Self: 'static,
@@ -94,15 +236,24 @@ pub trait KernelProviderWrapper: ProviderWrapper {
         let provider = self.provider();
         debug!("Attaching kernel provider {:?}", provider.guid);
 
+        let opcodes = config
+            .provider_opcodes(self.event_type(), self.opcodes())
+            .to_vec();
+        let error_limiter = BlockingMutex::new(_ErrorLogLimiter::new());
         let provider = Provider::kernel(provider)
             .add_callback(move |record, schema_locator| {
                 _callback_impl(
                     self.clone(),
                     record,
                     schema_locator,
+                    config.clone(),
                     sender.clone(),
-                    enricher.clone(),
+                    enrichment.clone(),
+                    self_filter.clone(),
+                    process_filter.clone(),
                     backup.clone(),
+                    &opcodes,
+                    &error_limiter,
                 );
             })
             .build();
@@ -111,15 +262,32 @@ pub trait KernelProviderWrapper: ProviderWrapper {
     }
 }
 
+/// Builder of the user provider `guid`, filtered by the keyword mask configured for `event_type`.
+fn _user_provider(guid: &GUID, event_type: &str, config: &Configuration) -> ProviderBuilder {
+    let mut builder = Provider::by_guid(*guid);
+    if let Some(mask) = config.provider_keywords.get(event_type) {
+        debug!(
+            "Filtering provider {guid:?} by keywords (any={:#x}, all={:#x})",
+            mask.any, mask.all
+        );
+        builder = builder.any(mask.any).all(mask.all);
+    }
+
+    builder
+}
+
 pub trait UserProviderWrapper: ProviderWrapper {
     fn guid(&self) -> &GUID;
 
     fn attach(
         self: Arc<Self>,
         trace: TraceBuilder<UserTrace>,
+        config: Arc<Configuration>,
         sender: mpsc::Sender<Arc<CapturedEventRecord>>,
-        enricher: Arc<BlockingMutex<BlockingEventEnricher>>,
-        backup: Arc<Mutex<Backup>>,
+        enrichment: Arc<Enrichment>,
+        self_filter: Arc<SelfExclusionFilter>,
+        process_filter: Arc<ProcessFilter>,
+        backup: BackupWriter,
     ) -> TraceBuilder<UserTrace>
     where
         Self: 'static,
@@ -127,15 +295,24 @@ pub trait UserProviderWrapper: ProviderWrapper {
         let guid = self.guid();
         debug!("Attaching user provider {guid:?}");
 
-        let provider = Provider::by_guid(*guid)
+        let opcodes = config
+            .provider_opcodes(self.event_type(), self.opcodes())
+            .to_vec();
+        let error_limiter = BlockingMutex::new(_ErrorLogLimiter::new());
+        let provider = _user_provider(guid, self.event_type(), &config)
             .add_callback(move |record, schema_locator| {
                 _callback_impl(
                     self.clone(),
                     record,
                     schema_locator,
+                    config.clone(),
                     sender.clone(),
-                    enricher.clone(),
+                    enrichment.clone(),
+                    self_filter.clone(),
+                    process_filter.clone(),
                     backup.clone(),
+                    &opcodes,
+                    &error_limiter,
                 );
             })
             .build();
@@ -143,3 +320,45 @@ pub trait UserProviderWrapper: ProviderWrapper {
         trace.enable(provider)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ferrisetw::provider::Provider;
+
+    use super::user::dns::DnsProviderWrapper;
+    use super::{_user_provider, ProviderWrapper, UserProviderWrapper};
+    use crate::configuration::{Configuration, KeywordMask};
+
+    #[test]
+    fn applies_keyword_masks_to_user_providers() {
+        let wrapper = DnsProviderWrapper {};
+        let mut config = Configuration::default();
+        config.provider_keywords.insert(
+            wrapper.event_type().to_string(),
+            KeywordMask {
+                any: 0x8000_0000_0000_0000,
+                all: 0x1,
+            },
+        );
+
+        let provider = _user_provider(wrapper.guid(), wrapper.event_type(), &config).build();
+        assert_eq!(provider.guid(), *wrapper.guid());
+        assert_eq!(provider.any(), 0x8000_0000_0000_0000);
+        assert_eq!(provider.all(), 0x1);
+    }
+
+    #[test]
+    fn keeps_default_keywords_without_masks() {
+        let wrapper = DnsProviderWrapper {};
+        let default = Provider::by_guid(*wrapper.guid()).build();
+
+        let provider = _user_provider(
+            wrapper.guid(),
+            wrapper.event_type(),
+            &Configuration::default(),
+        )
+        .build();
+        assert_eq!(provider.any(), default.any());
+        assert_eq!(provider.all(), default.all());
+    }
+}
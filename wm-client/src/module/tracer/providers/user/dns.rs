@@ -0,0 +1,80 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use ferrisetw::parser::Parser;
+use ferrisetw::{EventRecord, GUID, SchemaLocator};
+use wm_common::error::RuntimeError;
+use wm_common::schema::event::{Event, EventData};
+
+use crate::module::tracer::providers::{ProviderWrapper, UserProviderWrapper};
+
+/// Wrapper of the Microsoft-Windows-DNS-Client provider, capturing completed queries.
+pub struct DnsProviderWrapper;
+
+impl DnsProviderWrapper {
+    /// Ids of the events captured (query completed), which tell the events of the provider apart
+    /// instead of their opcodes
+    const _EVENT_IDS: &'static [u16] = &[3008];
+
+    const _GUID: GUID = GUID::from_values(
+        0x1c95126e,
+        0x7eea,
+        0x49a9,
+        [0xa3, 0xfe, 0xa3, 0x78, 0xb0, 0x3d, 0xdb, 0x4d],
+    );
+}
+
+impl ProviderWrapper for DnsProviderWrapper {
+    fn event_type(&self) -> &'static str {
+        "dns"
+    }
+
+    fn opcodes(&self) -> &'static [u8] {
+        &[]
+    }
+
+    fn filter(&self, record: &EventRecord, _opcodes: &[u8]) -> bool {
+        Self::_EVENT_IDS.contains(&record.event_id())
+    }
+
+    fn callback(
+        self: Arc<Self>,
+        record: &EventRecord,
+        schema_locator: &SchemaLocator,
+    ) -> Result<Option<Event>, Box<dyn Error + Send + Sync>> {
+        match schema_locator.event_schema(record) {
+            Ok(schema) => {
+                let parser = Parser::create(record, &schema);
+                let query_name = parser
+                    .try_parse::<String>("QueryName")
+                    .map_err(RuntimeError::from)?;
+                let query_type = parser
+                    .try_parse::<u32>("QueryType")
+                    .map_err(RuntimeError::from)?;
+                let query_status = parser
+                    .try_parse::<u32>("QueryStatus")
+                    .map_err(RuntimeError::from)?;
+                let query_results = parser
+                    .try_parse::<String>("QueryResults")
+                    .map_err(RuntimeError::from)?;
+
+                Ok(Some(Event::new(
+                    record,
+                    EventData::Dns {
+                        query_name,
+                        query_type,
+                        query_status,
+                        query_results,
+                    },
+                )))
+            }
+            Err(e) => Err(RuntimeError::new(format!("SchemaError: {e:?}")))?,
+        }
+    }
+}
+
+impl UserProviderWrapper for DnsProviderWrapper {
+    fn guid(&self) -> &GUID {
+        &Self::_GUID
+    }
+}
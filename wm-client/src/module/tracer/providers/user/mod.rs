@@ -1 +1,7 @@
+pub mod dns;
 
+/// Event types of the user provider wrappers, which support keyword masks.
+pub const EVENT_TYPES: &[&str] = &[
+    "dns",
+    // Add event types of user provider wrappers here as needed
+];
@@ -0,0 +1,241 @@
+use fancy_regex::{Captures, Regex};
+use sha2::{Digest, Sha256};
+use wm_common::schema::event::{Event, EventData};
+
+use crate::configuration::{Configuration, RedactedField, RedactionAction};
+
+/// Placeholder replacing text matched by [`RedactionAction::Mask`] rules.
+const _MASK: &str = "<redacted>";
+
+struct _Rule {
+    _field: RedactedField,
+    _pattern: Regex,
+    _action: RedactionAction,
+}
+
+/// Redactor removing sensitive text from captured events, according to the configured rules.
+///
+/// Events are redacted before being serialized, so neither the ECS fields nor `event.original`
+/// contain the matched text.
+pub struct EventRedactor {
+    _rules: Vec<_Rule>,
+    _hash_command_line: bool,
+    _salt: String,
+}
+
+impl EventRedactor {
+    /// Construct a redactor, or `None` if there is nothing to redact or hash.
+    pub fn new(config: &Configuration) -> Option<Self> {
+        if config.redaction.rules.is_empty() && !config.redaction.hash_command_line {
+            return None;
+        }
+
+        let rules = config
+            .redaction
+            .rules
+            .iter()
+            .map(|rule| _Rule {
+                _field: rule.field,
+                _pattern: Regex::new(&rule.pattern).expect("Invalid redaction pattern"),
+                _action: rule.action,
+            })
+            .collect();
+
+        Some(Self {
+            _rules: rules,
+            _hash_command_line: config.redaction.hash_command_line,
+            _salt: config.redaction.salt.clone().unwrap_or_default(),
+        })
+    }
+
+    /// Salted SHA-256 hash of `text`, in hexadecimal.
+    fn _hash(&self, text: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self._salt.as_bytes());
+        hasher.update(text.as_bytes());
+
+        format!("{:x}", hasher.finalize())
+    }
+
+    fn _redact(&self, field: RedactedField, value: &mut String) {
+        for rule in self._rules.iter().filter(|rule| rule._field == field) {
+            let redacted = rule
+                ._pattern
+                .replace_all(value.as_str(), |captures: &Captures| match rule._action {
+                    RedactionAction::Mask => _MASK.to_string(),
+                    RedactionAction::Hash => self._hash(&captures[0]),
+                });
+
+            *value = redacted.into_owned();
+        }
+    }
+
+    pub fn redact(&self, event: &mut Event) {
+        match &mut event.data {
+            EventData::FileCreate { open_path, .. } => {
+                self._redact(RedactedField::FilePath, open_path);
+            }
+            EventData::FileInfo { file_path, .. }
+            | EventData::FileReadWrite { file_path, .. }
+            | EventData::FileDelete { file_path } => {
+                self._redact(RedactedField::FilePath, file_path);
+            }
+            EventData::Image { file_name, .. } => {
+                self._redact(RedactedField::FilePath, file_name);
+            }
+            EventData::Process {
+                command_line,
+                command_line_sha256,
+                ..
+            } => {
+                if self._hash_command_line {
+                    *command_line_sha256 = Some(self._hash(command_line));
+                }
+
+                self._redact(RedactedField::CommandLine, command_line);
+            }
+            EventData::Registry { .. }
+            | EventData::TcpIp { .. }
+            | EventData::UdpIp { .. }
+            | EventData::Dns { .. } => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wm_common::schema::event::{Event, EventData};
+
+    use super::{_MASK, EventRedactor};
+    use crate::configuration::{Configuration, RedactedField, RedactionAction, RedactionRule};
+
+    fn _redactor(rules: Vec<(RedactedField, &str, RedactionAction)>) -> EventRedactor {
+        let mut config = Configuration::default();
+        config.redaction.rules = rules
+            .into_iter()
+            .map(|(field, pattern, action)| RedactionRule {
+                field,
+                pattern: pattern.to_string(),
+                action,
+            })
+            .collect();
+
+        EventRedactor::new(&config).unwrap()
+    }
+
+    fn _event(data: EventData) -> Event {
+        Event {
+            guid: "00000000-0000-0000-0000-000000000000".to_string(),
+            raw_timestamp: 133_000_000_000_000_000,
+            process_id: 1234,
+            thread_id: 5678,
+            event_id: 0,
+            opcode: 1,
+            data,
+            user: None,
+        }
+    }
+
+    fn _process(command_line: &str) -> Event {
+        _event(EventData::Process {
+            unique_process_key: 0,
+            process_id: 1234,
+            parent_id: 4,
+            session_id: 1,
+            exit_status: 0,
+            directory_table_base: 0,
+            image_file_name: "cmd.exe".to_string(),
+            command_line: command_line.to_string(),
+            sha256: None,
+            command_line_sha256: None,
+        })
+    }
+
+    fn _command_line(event: &Event) -> &str {
+        match &event.data {
+            EventData::Process { command_line, .. } => command_line,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn builds_nothing_without_rules() {
+        assert!(EventRedactor::new(&Configuration::default()).is_none());
+    }
+
+    #[test]
+    fn masks_matched_text() {
+        let redactor = _redactor(vec![(
+            RedactedField::CommandLine,
+            r"(?i)--password=\S+",
+            RedactionAction::Mask,
+        )]);
+
+        let mut event = _process("login.exe --user=admin --PASSWORD=hunter2 --verbose");
+        redactor.redact(&mut event);
+        assert_eq!(
+            _command_line(&event),
+            format!("login.exe --user=admin {_MASK} --verbose")
+        );
+    }
+
+    #[test]
+    fn hashes_matched_text_consistently() {
+        let redactor = _redactor(vec![(
+            RedactedField::CommandLine,
+            r"secret-\w+",
+            RedactionAction::Hash,
+        )]);
+
+        let mut first = _process("tool.exe secret-alpha");
+        let mut second = _process("other.exe secret-alpha");
+        let mut third = _process("tool.exe secret-beta");
+        for event in [&mut first, &mut second, &mut third] {
+            redactor.redact(event);
+        }
+
+        let hash = _command_line(&first).strip_prefix("tool.exe ").unwrap();
+        assert_eq!(hash.len(), 64);
+        assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(_command_line(&second), format!("other.exe {hash}"));
+        assert_ne!(_command_line(&third), _command_line(&first));
+    }
+
+    #[test]
+    fn applies_rules_to_their_field_only() {
+        let redactor = _redactor(vec![
+            (
+                RedactedField::FilePath,
+                r"(?i)^C:\\Users\\[^\\]+",
+                RedactionAction::Mask,
+            ),
+            (RedactedField::CommandLine, ".+", RedactionAction::Mask),
+        ]);
+
+        let mut file = _event(EventData::FileDelete {
+            file_path: r"C:\Users\alice\Documents\report.docx".to_string(),
+        });
+        redactor.redact(&mut file);
+        let EventData::FileDelete { file_path } = &file.data else {
+            unreachable!()
+        };
+        assert_eq!(file_path, &format!(r"{_MASK}\Documents\report.docx"));
+
+        let mut image = _event(EventData::Image {
+            image_base: 0,
+            image_size: 0,
+            image_checksum: 0,
+            file_name: r"C:\Windows\System32\kernel32.dll".to_string(),
+            sha256: None,
+        });
+        redactor.redact(&mut image);
+        let EventData::Image { file_name, .. } = &image.data else {
+            unreachable!()
+        };
+        assert_eq!(file_name, r"C:\Windows\System32\kernel32.dll");
+
+        let mut process = _process(r"C:\Users\alice\tool.exe");
+        redactor.redact(&mut process);
+        assert_eq!(_command_line(&process), _MASK);
+    }
+}
@@ -0,0 +1,144 @@
+use std::error::Error;
+use std::process;
+
+use async_compression::Level;
+use async_compression::tokio::bufread::ZstdEncoder;
+use chrono::Utc;
+use ferrisetw::provider::kernel_providers::PROCESS_PROVIDER;
+use log::{error, info};
+use reqwest::StatusCode;
+use tokio::io::AsyncReadExt;
+use wm_common::error::RuntimeError;
+use wm_common::schema::event::{CapturedEventRecord, Event, EventData};
+use wm_common::utils::process_image_path;
+
+use crate::configuration::Configuration;
+use crate::http::HttpClient;
+use crate::module::tracer::enricher::BlockingEventEnricher;
+
+/// Difference between the Windows epoch (1601-01-01) and the Unix epoch in 100ns intervals
+const _WINDOWS_EPOCH_OFFSET: i64 = 116_444_736_000_000_000;
+
+/// A process start event of the agent itself, as captured by the kernel process provider.
+fn _synthetic_event() -> Event {
+    let process_id = process::id();
+    let image_file_name = process_image_path(process_id)
+        .ok()
+        .and_then(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .unwrap_or_default();
+
+    Event {
+        guid: format!("{:?}", PROCESS_PROVIDER.guid),
+        raw_timestamp: Utc::now().timestamp_nanos_opt().unwrap_or_default() / 100
+            + _WINDOWS_EPOCH_OFFSET,
+        process_id,
+        thread_id: 0,
+        event_id: 0,
+        opcode: 1,
+        data: EventData::Process {
+            unique_process_key: 0,
+            process_id,
+            parent_id: 0,
+            session_id: 0,
+            exit_status: 0,
+            directory_table_base: 0,
+            command_line: image_file_name.clone(),
+            image_file_name,
+            sha256: None,
+            command_line_sha256: None,
+        },
+        user: None,
+    }
+}
+
+async fn _post_dummy_trace(
+    http: &HttpClient,
+    compressed: Vec<u8>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let response = http
+        .api()
+        .post("/trace?dummy")
+        .body(compressed)
+        .send()
+        .await?;
+    if response.status() != StatusCode::OK {
+        Err(RuntimeError::new(format!(
+            "Unexpected response status {}: {}",
+            response.status(),
+            response.text().await.unwrap_or_default()
+        )))?;
+    }
+
+    Ok(())
+}
+
+fn _report<T>(
+    stage: &str,
+    result: Result<T, Box<dyn Error + Send + Sync>>,
+) -> Result<T, Box<dyn Error + Send + Sync>> {
+    match &result {
+        Ok(_) => info!("Self-test stage \"{stage}\" passed"),
+        Err(e) => error!("Self-test stage \"{stage}\" failed: {e}"),
+    }
+
+    result
+}
+
+/// Run a synthetic event through the same enrichment, serialization and compression as captured
+/// events, reporting each stage.
+///
+/// If `http` is specified, the compressed event is also posted to the server as a dummy trace,
+/// which the server validates without storing.
+pub async fn run_self_test(
+    config: &Configuration,
+    http: Option<&HttpClient>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut enricher = BlockingEventEnricher::async_new(config).await;
+    let mut event = _synthetic_event();
+    enricher.enrich(&mut event);
+    let record = CapturedEventRecord {
+        event,
+        system: enricher.system.system_info(),
+        captured: Utc::now(),
+    };
+    info!("Self-test stage \"enrichment\" passed");
+
+    let mut payload = _report("serialization", {
+        let payload = record.serialize_to_vec();
+        serde_json::from_slice::<CapturedEventRecord>(&payload)
+            .map(|_| payload)
+            .map_err(Into::into)
+    })?;
+    payload.push(b'\n');
+    info!(
+        "Serialized event: {}",
+        String::from_utf8_lossy(&payload).trim_end()
+    );
+
+    let compressed = _report("compression", {
+        let mut compressor = ZstdEncoder::with_quality(
+            payload.as_slice(),
+            Level::Precise(config.zstd_compression_level),
+        );
+        let mut compressed = vec![];
+        compressor
+            .read_to_end(&mut compressed)
+            .await
+            .map(|_| compressed)
+            .map_err(Into::into)
+    })?;
+    info!(
+        "Compressed {} bytes to {} bytes",
+        payload.len(),
+        compressed.len()
+    );
+
+    if let Some(http) = http {
+        _report("server", _post_dummy_trace(http, compressed).await)?;
+    }
+
+    Ok(())
+}
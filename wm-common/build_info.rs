@@ -0,0 +1,38 @@
+//! Build script helper shared by the binaries, embedding the metadata read by
+//! `wm_common::version_info!`.
+//!
+//! Build scripts include it with `#[path = "../wm-common/build_info.rs"] mod build_info;`.
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+use chrono::{SecondsFormat, Utc};
+
+/// Emit the git hash, build timestamp and target triple as compile-time environment variables.
+pub fn emit(workspace_dir: &Path) {
+    let git_dir = workspace_dir.join(".git");
+    println!("cargo:rerun-if-changed={}", git_dir.join("HEAD").display());
+    println!("cargo:rerun-if-changed={}", git_dir.join("refs").display());
+
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .current_dir(workspace_dir)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map_or_else(
+            || "unknown".to_string(),
+            |output| String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        );
+
+    println!("cargo:rustc-env=WM_GIT_HASH={git_hash}");
+    println!(
+        "cargo:rustc-env=WM_BUILD_TIMESTAMP={}",
+        Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+    );
+    println!(
+        "cargo:rustc-env=WM_BUILD_TARGET={}",
+        env::var("TARGET").unwrap()
+    );
+}
@@ -0,0 +1,379 @@
+use std::io;
+
+use aes_gcm::aead::consts::U12;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::error::RuntimeError;
+
+/// Value of the `X-Backup-Encryption` header sent along with encrypted backups.
+pub const BACKUP_ENCRYPTION: &str = "aes-256-gcm-stream";
+
+/// Size of the random nonce prefix at the start of a stream.
+const _PREFIX_SIZE: usize = 7;
+
+/// Upper bound of a frame's ciphertext size, protecting readers from bogus length prefixes.
+const _MAX_FRAME_SIZE: usize = 16 << 20;
+
+fn _invalid_data<S>(message: S) -> io::Error
+where
+    S: Into<String>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Nonce of a frame: the stream's random prefix, the frame index and whether it is the last one.
+fn _nonce(prefix: &[u8; _PREFIX_SIZE], index: u32, last: bool) -> Nonce<U12> {
+    let mut nonce = Nonce::default();
+    nonce[.._PREFIX_SIZE].copy_from_slice(prefix);
+    nonce[_PREFIX_SIZE.._PREFIX_SIZE + 4].copy_from_slice(&index.to_be_bytes());
+    nonce[_PREFIX_SIZE + 4] = u8::from(last);
+    nonce
+}
+
+fn _random_prefix() -> [u8; _PREFIX_SIZE] {
+    let mut prefix = [0; _PREFIX_SIZE];
+    prefix.copy_from_slice(&Aes256Gcm::generate_nonce(&mut OsRng)[.._PREFIX_SIZE]);
+    prefix
+}
+
+/// AES-256-GCM cipher for backup files.
+///
+/// Backups are written incrementally, so the compressed stream is split into sealed frames
+/// following a random nonce prefix: `[prefix: 7 bytes]` then `[ciphertext length: u32 LE]
+/// [ciphertext]` per frame. The nonce of each frame is derived from the prefix, its index and
+/// a flag set on the final frame only, so that reordered, dropped or spliced frames as well as
+/// streams truncated at a frame boundary fail to open.
+#[derive(Clone)]
+pub struct FrameCipher {
+    _cipher: Aes256Gcm,
+}
+
+impl FrameCipher {
+    /// Construct a cipher from a 256-bit key encoded as 64 hexadecimal digits.
+    pub fn from_hex(key: &str) -> Result<Self, RuntimeError> {
+        if !key.is_ascii() || key.len() % 2 != 0 {
+            return Err(RuntimeError::new("Encryption key must be hex-encoded"));
+        }
+
+        let bytes = (0..key.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&key[i..i + 2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| RuntimeError::new(format!("Invalid encryption key: {e}")))?;
+
+        Ok(Self {
+            _cipher: Aes256Gcm::new_from_slice(&bytes)
+                .map_err(|_| RuntimeError::new("Encryption key must be 32 bytes long"))?,
+        })
+    }
+
+    /// Start sealing a new stream.
+    pub fn sealer(&self) -> FrameSealer {
+        FrameSealer {
+            _cipher: self._cipher.clone(),
+            _prefix: _random_prefix(),
+            _index: 0,
+        }
+    }
+
+    /// Start opening a stream written by a [`FrameSealer`].
+    pub fn opener(&self) -> FrameOpener {
+        FrameOpener {
+            _cipher: self._cipher.clone(),
+            _prefix: None,
+            _index: 0,
+            _finished: false,
+            _position: 0,
+        }
+    }
+}
+
+/// Writing side of an encrypted stream.
+pub struct FrameSealer {
+    _cipher: Aes256Gcm,
+    _prefix: [u8; _PREFIX_SIZE],
+
+    /// Index of the next frame
+    _index: u32,
+}
+
+impl FrameSealer {
+    /// Encrypt `plaintext` into the next frame, preceded by the stream prefix if it is the first
+    /// one. `last` must be set on the final frame of the stream only.
+    ///
+    /// The frame is not consumed until [`FrameSealer::advance`], so that a frame which could not
+    /// be written is sealed again with the same index.
+    pub fn seal(&self, plaintext: &[u8], last: bool) -> Result<Vec<u8>, RuntimeError> {
+        let ciphertext = self
+            ._cipher
+            .encrypt(&_nonce(&self._prefix, self._index, last), plaintext)
+            .map_err(|e| RuntimeError::new(format!("Unable to encrypt frame: {e}")))?;
+        let length =
+            u32::try_from(ciphertext.len()).map_err(|_| RuntimeError::new("Frame is too large"))?;
+
+        let mut frame = Vec::with_capacity(_PREFIX_SIZE + 4 + ciphertext.len());
+        if self._index == 0 {
+            frame.extend_from_slice(&self._prefix);
+        }
+        frame.extend_from_slice(&length.to_le_bytes());
+        frame.extend_from_slice(&ciphertext);
+        Ok(frame)
+    }
+
+    /// Move on to the next frame once the one returned by [`FrameSealer::seal`] was written.
+    pub fn advance(&mut self) -> Result<(), RuntimeError> {
+        self._index = self
+            ._index
+            .checked_add(1)
+            .ok_or_else(|| RuntimeError::new("Too many frames in a single stream"))?;
+        Ok(())
+    }
+}
+
+/// Reading side of an encrypted stream.
+pub struct FrameOpener {
+    _cipher: Aes256Gcm,
+    _prefix: Option<[u8; _PREFIX_SIZE]>,
+
+    /// Index of the next frame
+    _index: u32,
+    _finished: bool,
+
+    /// Number of bytes of the stream up to the end of the last opened frame
+    _position: u64,
+}
+
+impl FrameOpener {
+    /// Read and decrypt the next frame from `reader`, returning `None` after the final frame.
+    ///
+    /// Fails if the stream was tampered with, including when it ends before its final frame or
+    /// continues after it. The opener is left at the last valid frame on failure.
+    pub async fn open_next<R>(&mut self, reader: &mut R) -> io::Result<Option<Vec<u8>>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        if self._finished {
+            return match reader.read_u8().await {
+                Ok(_) => Err(_invalid_data("Unexpected data after the final frame")),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+                Err(e) => Err(e),
+            };
+        }
+
+        let truncated = |e: io::Error| {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                _invalid_data("Stream ended before its final frame")
+            } else {
+                e
+            }
+        };
+
+        let mut read = 0;
+        let prefix = match self._prefix {
+            Some(prefix) => prefix,
+            None => {
+                let mut prefix = [0; _PREFIX_SIZE];
+                reader.read_exact(&mut prefix).await.map_err(truncated)?;
+                read += _PREFIX_SIZE;
+                prefix
+            }
+        };
+
+        let length = reader.read_u32_le().await.map_err(truncated)? as usize;
+        if length > _MAX_FRAME_SIZE {
+            return Err(_invalid_data(format!(
+                "Frame of {length} bytes exceeds the size limit"
+            )));
+        }
+
+        let mut ciphertext = vec![0; length];
+        reader
+            .read_exact(&mut ciphertext)
+            .await
+            .map_err(truncated)?;
+        read += 4 + length;
+
+        let (plaintext, last) = match self
+            ._cipher
+            .decrypt(&_nonce(&prefix, self._index, false), ciphertext.as_slice())
+        {
+            Ok(plaintext) => (plaintext, false),
+            Err(_) => match self
+                ._cipher
+                .decrypt(&_nonce(&prefix, self._index, true), ciphertext.as_slice())
+            {
+                Ok(plaintext) => (plaintext, true),
+                Err(_) => {
+                    return Err(_invalid_data(format!(
+                        "Frame #{} failed authentication",
+                        self._index
+                    )));
+                }
+            },
+        };
+
+        self._prefix = Some(prefix);
+        self._index += 1;
+        self._finished = last;
+        self._position += read as u64;
+        Ok(Some(plaintext))
+    }
+
+    /// Number of bytes of the stream up to the end of the last opened frame.
+    pub fn position(&self) -> u64 {
+        self._position
+    }
+
+    /// Whether the final frame was opened.
+    pub fn finished(&self) -> bool {
+        self._finished
+    }
+
+    /// Continue writing the stream after the last opened frame (at [`FrameOpener::position`]),
+    /// e.g. to seal the final frame of a stream whose writer stopped unexpectedly. Returns
+    /// `None` if the stream is already finished.
+    pub fn into_sealer(self) -> Option<FrameSealer> {
+        if self._finished {
+            return None;
+        }
+
+        Some(FrameSealer {
+            _cipher: self._cipher,
+            _prefix: self._prefix.unwrap_or_else(_random_prefix),
+            _index: self._index,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{FrameCipher, FrameSealer};
+
+    const _KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    /// Seal `frames`, the last of which as the final frame, returning each sealed frame.
+    fn _seal(sealer: &mut FrameSealer, frames: &[&[u8]]) -> Vec<Vec<u8>> {
+        frames
+            .iter()
+            .enumerate()
+            .map(|(index, plaintext)| {
+                let frame = sealer.seal(plaintext, index + 1 == frames.len()).unwrap();
+                sealer.advance().unwrap();
+                frame
+            })
+            .collect()
+    }
+
+    async fn _open_all(cipher: &FrameCipher, mut stream: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        let mut opener = cipher.opener();
+        let mut frames = vec![];
+        while let Some(frame) = opener.open_next(&mut stream).await? {
+            frames.push(frame);
+        }
+
+        Ok(frames)
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let cipher = FrameCipher::from_hex(_KEY).unwrap();
+        let frames = _seal(&mut cipher.sealer(), &[b"first", b"second", b""]);
+
+        let opened = _open_all(&cipher, &frames.concat()).await.unwrap();
+        assert_eq!(opened, vec![b"first".to_vec(), b"second".to_vec(), vec![]]);
+    }
+
+    #[tokio::test]
+    async fn resealing_an_unwritten_frame_is_consistent() {
+        let cipher = FrameCipher::from_hex(_KEY).unwrap();
+        let mut sealer = cipher.sealer();
+
+        // A frame that failed to be written is sealed again without advancing
+        let _ = sealer.seal(b"lost", false).unwrap();
+        let frames = _seal(&mut sealer, &[b"first", b"second"]);
+
+        let opened = _open_all(&cipher, &frames.concat()).await.unwrap();
+        assert_eq!(opened, vec![b"first".to_vec(), b"second".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn rejects_reordered_frames() {
+        let cipher = FrameCipher::from_hex(_KEY).unwrap();
+        let frames = _seal(&mut cipher.sealer(), &[b"first", b"second", b"third", b""]);
+
+        // The first frame carries the stream prefix, swap the two following ones
+        let stream = [&frames[0], &frames[2], &frames[1], &frames[3]].map(Vec::as_slice);
+        assert!(_open_all(&cipher, &stream.concat()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_dropped_frames() {
+        let cipher = FrameCipher::from_hex(_KEY).unwrap();
+        let frames = _seal(&mut cipher.sealer(), &[b"first", b"second", b"third", b""]);
+
+        let stream = [&frames[0], &frames[2], &frames[3]].map(Vec::as_slice);
+        assert!(_open_all(&cipher, &stream.concat()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_truncation_at_frame_boundary() {
+        let cipher = FrameCipher::from_hex(_KEY).unwrap();
+        let frames = _seal(&mut cipher.sealer(), &[b"first", b"second", b""]);
+
+        assert!(_open_all(&cipher, &frames[..2].concat()).await.is_err());
+        assert!(_open_all(&cipher, b"").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_data_after_final_frame() {
+        let cipher = FrameCipher::from_hex(_KEY).unwrap();
+        let frames = _seal(&mut cipher.sealer(), &[b"first", b""]);
+
+        let mut stream = frames.concat();
+        stream.push(0);
+        assert!(_open_all(&cipher, &stream).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_frames_of_another_stream() {
+        let cipher = FrameCipher::from_hex(_KEY).unwrap();
+        let frames = _seal(&mut cipher.sealer(), &[b"first", b"second", b""]);
+        let other = _seal(&mut cipher.sealer(), &[b"first", b"other", b""]);
+
+        let stream = [&frames[0], &other[1], &frames[2]].map(Vec::as_slice);
+        assert!(_open_all(&cipher, &stream.concat()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn resumes_unfinished_stream() {
+        let cipher = FrameCipher::from_hex(_KEY).unwrap();
+        let mut sealer = cipher.sealer();
+        let mut stream = sealer.seal(b"first", false).unwrap();
+        sealer.advance().unwrap();
+        let complete = stream.len() as u64;
+
+        // A frame cut short by a crash
+        let partial = sealer.seal(b"second", false).unwrap();
+        stream.extend_from_slice(&partial[..partial.len() / 2]);
+
+        let mut opener = cipher.opener();
+        let mut reader = stream.as_slice();
+        assert_eq!(
+            opener.open_next(&mut reader).await.unwrap(),
+            Some(b"first".to_vec())
+        );
+        assert!(opener.open_next(&mut reader).await.is_err());
+        assert_eq!(opener.position(), complete);
+
+        let sealer = opener.into_sealer().unwrap();
+        stream.truncate(complete as usize);
+        stream.extend_from_slice(&sealer.seal(b"", true).unwrap());
+
+        let opened = _open_all(&cipher, &stream).await.unwrap();
+        assert_eq!(opened, vec![b"first".to_vec(), vec![]]);
+    }
+}
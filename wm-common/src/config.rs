@@ -0,0 +1,142 @@
+use std::error::Error;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, process};
+
+use serde::Serialize;
+
+/// Collector of configuration problems, so that all of them are reported at once instead of
+/// failing on the first one.
+#[derive(Default)]
+pub struct ConfigErrors {
+    _errors: Vec<String>,
+}
+
+impl ConfigErrors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, message: impl Into<String>) {
+        self._errors.push(message.into());
+    }
+
+    /// Record `message` unless `condition` holds.
+    pub fn require(&mut self, condition: bool, message: impl Into<String>) {
+        if !condition {
+            self.push(message);
+        }
+    }
+
+    pub fn into_result(self) -> Result<(), Vec<String>> {
+        if self._errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self._errors)
+        }
+    }
+}
+
+/// Extensions of supported configuration formats, in lookup order.
+const _CONFIG_EXTENSIONS: [&str; 4] = ["yml", "yaml", "toml", "json"];
+
+/// Locate the configuration file named `stem` in `directory`.
+///
+/// The format is detected from the extension by `config_file`, so the first existing
+/// `<stem>.yml`, `<stem>.yaml`, `<stem>.toml` or `<stem>.json` is returned, falling back to
+/// `<stem>.yml` if none exists.
+pub fn find_config_file(directory: &Path, stem: &str) -> PathBuf {
+    _CONFIG_EXTENSIONS
+        .iter()
+        .map(|extension| directory.join(format!("{stem}.{extension}")))
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| directory.join(format!("{stem}.yml")))
+}
+
+/// Report every problem of a configuration validation and exit the process if there are any.
+///
+/// This is meant to be called right after loading a configuration file, before the logger is
+/// initialized (which itself depends on the configuration). Stderr is lost when running as a
+/// service, so the problems are also written to a `<name>-<timestamp>.log` file in
+/// `log_directory`, next to the logs of successful starts.
+pub fn exit_if_invalid(result: Result<(), Vec<String>>, log_directory: &Path, name: &str) {
+    if let Err(errors) = result {
+        let mut report = String::from("Invalid configuration:\n");
+        for error in errors {
+            let _ = writeln!(report, "- {error}");
+        }
+        eprint!("{report}");
+
+        let path = log_directory.join(format!(
+            "{name}-{}.log",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis()
+        ));
+        if let Err(e) = fs::create_dir_all(log_directory).and_then(|()| fs::write(&path, report)) {
+            eprintln!("Unable to write {}: {e}", path.display());
+        }
+
+        process::exit(1);
+    }
+}
+
+/// Configuration with a default value and documented fields, from which a commented
+/// configuration file can be generated.
+///
+/// The generated file follows the serialized structure of [`Default::default`], so it cannot
+/// drift from the configuration struct.
+pub trait DefaultConfig: Default + Serialize {
+    /// Descriptions of fields, keyed by their dotted path (e.g. `event_post.flush_limit`).
+    const FIELD_DOCS: &'static [(&'static str, &'static str)];
+
+    fn to_commented_yaml() -> Result<String, Box<dyn Error + Send + Sync>> {
+        let yaml = serde_yaml::to_string(&Self::default())?;
+
+        let mut output = String::new();
+        let mut path = vec![];
+        for line in yaml.lines() {
+            let content = line.trim_start();
+            let indent = &line[..line.len() - content.len()];
+
+            // serde_yaml indents nested mappings by 2 spaces
+            if !content.starts_with('-')
+                && let Some((key, _)) = content.split_once(':')
+            {
+                path.truncate(indent.len() / 2);
+                path.push(key);
+
+                let dotted = path.join(".");
+                if let Some((_, doc)) = Self::FIELD_DOCS.iter().find(|(p, _)| *p == dotted) {
+                    if indent.is_empty() && !output.is_empty() {
+                        output.push('\n');
+                    }
+
+                    for doc_line in doc.lines() {
+                        writeln!(output, "{indent}# {doc_line}")?;
+                    }
+                }
+            }
+
+            writeln!(output, "{line}")?;
+        }
+
+        Ok(output)
+    }
+}
+
+/// Write the default configuration of `T` to `output`, or to stdout if not specified.
+pub fn write_default_config<T>(output: Option<&Path>) -> Result<(), Box<dyn Error + Send + Sync>>
+where
+    T: DefaultConfig,
+{
+    let yaml = T::to_commented_yaml()?;
+    match output {
+        Some(path) => fs::write(path, yaml)?,
+        None => print!("{yaml}"),
+    }
+
+    Ok(())
+}
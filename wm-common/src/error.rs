@@ -1,11 +1,15 @@
 use std::error::Error;
-use std::fmt;
+use std::time::Duration;
+use std::{fmt, io};
 
 use ferrisetw::parser::ParserError;
 use windows::core;
 
 pub struct RuntimeError {
     _message: String,
+
+    /// Underlying error, preserved so that the chain can be walked via [`Error::source`]
+    _source: Option<Box<dyn Error + Send + Sync>>,
 }
 
 impl fmt::Display for RuntimeError {
@@ -20,7 +24,12 @@ impl fmt::Debug for RuntimeError {
     }
 }
 
-impl Error for RuntimeError {}
+impl Error for RuntimeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self._source.as_deref().map(|e| e as &(dyn Error + 'static))
+    }
+}
+
 impl RuntimeError {
     pub fn new<S>(message: S) -> Self
     where
@@ -28,6 +37,18 @@ impl RuntimeError {
     {
         Self {
             _message: message.into(),
+            _source: None,
+        }
+    }
+
+    pub fn with_source<S, E>(message: S, source: E) -> Self
+    where
+        S: Into<String>,
+        E: Into<Box<dyn Error + Send + Sync>>,
+    {
+        Self {
+            _message: message.into(),
+            _source: Some(source.into()),
         }
     }
 }
@@ -40,19 +61,26 @@ impl From<ParserError> for RuntimeError {
 
 impl From<WindowsError> for RuntimeError {
     fn from(error: WindowsError) -> Self {
-        Self::new(error._message)
+        Self::with_source(error._message.clone(), error)
     }
 }
 
 impl From<core::Error> for RuntimeError {
     fn from(error: core::Error) -> Self {
-        Self::new(error.message())
+        Self::with_source(error.message(), error)
+    }
+}
+
+impl From<io::Error> for RuntimeError {
+    fn from(error: io::Error) -> Self {
+        Self::with_source(error.to_string(), error)
     }
 }
 
 pub struct WindowsError {
     _code: core::HRESULT,
     _message: String,
+    _source: core::Error,
 }
 
 impl fmt::Display for WindowsError {
@@ -67,18 +95,201 @@ impl fmt::Debug for WindowsError {
     }
 }
 
-impl Error for WindowsError {}
+impl Error for WindowsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self._source)
+    }
+}
+
 impl WindowsError {
     pub fn new(error: core::Error) -> Self {
         Self {
             _code: error.code(),
             _message: error.message(),
+            _source: error,
         }
     }
 }
 
+impl From<io::Error> for WindowsError {
+    fn from(error: io::Error) -> Self {
+        Self::new(error.into())
+    }
+}
+
 impl From<core::Error> for WindowsError {
     fn from(error: core::Error) -> Self {
         Self::new(error)
     }
 }
+
+/// Failure of a request to another service (API service, RabbitMQ, Elasticsearch, ...),
+/// classified so that callers can decide whether and when to retry.
+#[derive(Debug)]
+pub enum ServiceError {
+    /// The service is unreachable or the connection broke, retrying later may succeed
+    Network(Box<dyn Error + Send + Sync>),
+
+    /// The service is overloaded and asked not to retry before the given delay
+    Busy(Duration),
+
+    /// A payload could not be encoded or decoded, retrying with the same payload fails again
+    Serialization(Box<dyn Error + Send + Sync>),
+
+    /// The service received the request but answered with an error
+    Backend(String),
+
+    /// The service refused the request itself (e.g. as malformed), sending it again fails again
+    Rejected(String),
+}
+
+impl ServiceError {
+    pub fn network<E>(error: E) -> Self
+    where
+        E: Into<Box<dyn Error + Send + Sync>>,
+    {
+        Self::Network(error.into())
+    }
+
+    pub fn serialization<E>(error: E) -> Self
+    where
+        E: Into<Box<dyn Error + Send + Sync>>,
+    {
+        Self::Serialization(error.into())
+    }
+
+    pub fn backend<S>(message: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self::Backend(message.into())
+    }
+
+    pub fn rejected<S>(message: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self::Rejected(message.into())
+    }
+
+    /// Whether the same request may succeed if sent again.
+    pub fn retryable(&self) -> bool {
+        !matches!(self, Self::Serialization(_) | Self::Rejected(_))
+    }
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Network(e) => write!(f, "Network error: {e}"),
+            Self::Busy(retry_after) => write!(
+                f,
+                "Service is busy, retry after {} seconds",
+                retry_after.as_secs()
+            ),
+            Self::Serialization(e) => write!(f, "Serialization error: {e}"),
+            Self::Backend(message) => write!(f, "Backend error: {message}"),
+            Self::Rejected(message) => write!(f, "Request rejected: {message}"),
+        }
+    }
+}
+
+impl Error for ServiceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Network(e) | Self::Serialization(e) => Some(e.as_ref()),
+            Self::Busy(_) | Self::Backend(_) | Self::Rejected(_) => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for ServiceError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::serialization(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::{io, iter};
+
+    use windows::Win32::Foundation::E_ACCESSDENIED;
+    use windows::core;
+
+    use super::{RuntimeError, ServiceError, WindowsError};
+
+    /// Every error of the chain starting at `error`, outermost first.
+    fn _chain(error: &(dyn Error + 'static)) -> Vec<&(dyn Error + 'static)> {
+        iter::successors(Some(error), |e| e.source()).collect()
+    }
+
+    #[test]
+    fn runtime_error_keeps_io_source() {
+        let error = RuntimeError::from(io::Error::new(io::ErrorKind::NotFound, "missing.yml"));
+        assert_eq!(error.to_string(), "missing.yml");
+
+        let chain = _chain(&error);
+        assert_eq!(chain.len(), 2);
+        let source = chain[1].downcast_ref::<io::Error>().unwrap();
+        assert_eq!(source.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn windows_errors_are_walkable_through_runtime_errors() {
+        let error =
+            RuntimeError::from(WindowsError::new(core::Error::from_hresult(E_ACCESSDENIED)));
+
+        let chain = _chain(&error);
+        assert_eq!(chain.len(), 3);
+        assert!(chain[1].is::<WindowsError>());
+        assert_eq!(
+            chain[2].downcast_ref::<core::Error>().unwrap().code(),
+            E_ACCESSDENIED
+        );
+        assert_eq!(error.to_string(), chain[1].to_string());
+
+        let error = RuntimeError::from(core::Error::from_hresult(E_ACCESSDENIED));
+        let chain = _chain(&error);
+        assert_eq!(chain.len(), 2);
+        assert!(chain[1].is::<core::Error>());
+    }
+
+    #[test]
+    fn boxed_errors_keep_their_chain() {
+        let inner = RuntimeError::from(io::Error::other("connection reset"));
+        let boxed: Box<dyn Error + Send + Sync> =
+            RuntimeError::with_source("Failed to upload backup", inner).into();
+
+        let messages = _chain(boxed.as_ref())
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "Failed to upload backup",
+                "connection reset",
+                "connection reset"
+            ]
+        );
+        assert!(_chain(boxed.as_ref())[2].is::<io::Error>());
+        assert!(RuntimeError::new("no source").source().is_none());
+    }
+
+    #[test]
+    fn service_errors_expose_their_cause() {
+        let error = ServiceError::network(io::Error::from(io::ErrorKind::ConnectionRefused));
+        assert!(error.retryable());
+        assert!(error.source().unwrap().is::<io::Error>());
+
+        let error = ServiceError::from(serde_json::from_str::<u32>("{").unwrap_err());
+        assert!(!error.retryable());
+        assert!(error.source().unwrap().is::<serde_json::Error>());
+
+        assert!(ServiceError::backend("unavailable").source().is_none());
+        assert!(ServiceError::backend("unavailable").retryable());
+        assert!(ServiceError::rejected("malformed").source().is_none());
+        assert!(!ServiceError::rejected("malformed").retryable());
+    }
+}
@@ -0,0 +1,50 @@
+use windows::Win32::System::Diagnostics::Etw::{EVENT_TRACE_PROPERTIES, QueryAllTracesW};
+use windows::core::PCWSTR;
+
+use crate::error::WindowsError;
+
+/// Maximum number of sessions returned by [`QueryAllTracesW`]
+const _MAX_SESSIONS: usize = 64;
+
+/// Maximum length of session and log file names, in UTF-16 code units
+const _MAX_NAME_LENGTH: usize = 1024;
+
+/// Names of all ETW sessions running on the host.
+pub fn trace_session_names() -> Result<Vec<String>, WindowsError> {
+    let properties_size = size_of::<EVENT_TRACE_PROPERTIES>();
+    let name_size = _MAX_NAME_LENGTH * size_of::<u16>();
+    let buffer_size = properties_size + 2 * name_size;
+
+    // u64 elements keep each buffer aligned for EVENT_TRACE_PROPERTIES
+    let mut buffers = (0.._MAX_SESSIONS)
+        .map(|_| vec![0u64; buffer_size.div_ceil(size_of::<u64>())])
+        .collect::<Vec<_>>();
+    let mut properties = buffers
+        .iter_mut()
+        .map(|buffer| {
+            let properties = buffer.as_mut_ptr().cast::<EVENT_TRACE_PROPERTIES>();
+            unsafe {
+                (*properties).Wnode.BufferSize = buffer_size as u32;
+                (*properties).LoggerNameOffset = properties_size as u32;
+                (*properties).LogFileNameOffset = (properties_size + name_size) as u32;
+            }
+
+            properties
+        })
+        .collect::<Vec<_>>();
+
+    let mut count = 0;
+    unsafe { QueryAllTracesW(&mut properties, &mut count) }.ok()?;
+
+    Ok(properties
+        .iter()
+        .take(count as usize)
+        .map(|&properties| unsafe {
+            let name = properties
+                .cast::<u8>()
+                .add((*properties).LoggerNameOffset as usize)
+                .cast::<u16>();
+            PCWSTR(name).to_string().unwrap_or_default()
+        })
+        .collect())
+}
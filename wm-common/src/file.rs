@@ -7,7 +7,7 @@ use tokio::fs::File;
 use windows::Win32::Foundation::{GENERIC_ACCESS_RIGHTS, GENERIC_READ, GENERIC_WRITE};
 use windows::Win32::Storage::FileSystem::{
     CREATE_NEW, CreateFileW, FILE_ATTRIBUTE_NORMAL, FILE_CREATION_DISPOSITION, FILE_SHARE_NONE,
-    OPEN_ALWAYS, OPEN_EXISTING,
+    GetDiskFreeSpaceExW, OPEN_ALWAYS, OPEN_EXISTING,
 };
 use windows::core::PCWSTR;
 
@@ -48,3 +48,19 @@ pub fn create_exclusively(path: impl AsRef<Path>) -> Result<File, WindowsError>
 pub fn create_new_exclusively(path: impl AsRef<Path>) -> Result<File, WindowsError> {
     _exclusive_createfile(path.as_ref(), GENERIC_WRITE, CREATE_NEW)
 }
+
+/// Number of bytes available to the calling user on the volume containing `path`.
+pub fn available_space(path: impl AsRef<Path>) -> Result<u64, WindowsError> {
+    let temp_buf = _osstr_to_vec16(path.as_ref().as_os_str());
+    let mut available = 0;
+    unsafe {
+        GetDiskFreeSpaceExW(
+            PCWSTR::from_raw(temp_buf.as_ptr()),
+            Some(&mut available),
+            None,
+            None,
+        )?;
+    }
+
+    Ok(available)
+}
@@ -1,17 +1,22 @@
 use std::ffi::{CStr, c_void};
 
-use windows::Win32::Foundation::HANDLE;
+use log::error;
+use windows::Win32::Foundation::{CloseHandle, HANDLE};
 use windows::Win32::System::JobObjects::{
     AssignProcessToJobObject, CreateJobObjectA, JOB_OBJECT_CPU_RATE_CONTROL_ENABLE,
-    JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP, JOBOBJECT_CPU_RATE_CONTROL_INFORMATION,
-    JOBOBJECT_CPU_RATE_CONTROL_INFORMATION_0, JobObjectCpuRateControlInformation,
-    SetInformationJobObject,
+    JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP, JOB_OBJECT_LIMIT_JOB_MEMORY,
+    JOBOBJECT_BASIC_LIMIT_INFORMATION, JOBOBJECT_CPU_RATE_CONTROL_INFORMATION,
+    JOBOBJECT_CPU_RATE_CONTROL_INFORMATION_0, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+    JobObjectCpuRateControlInformation, JobObjectExtendedLimitInformation, SetInformationJobObject,
 };
 use windows::Win32::System::Threading::GetCurrentProcess;
 use windows::core::PCSTR;
 
 use crate::error::WindowsError;
 
+/// Job object the current process is assigned to, used to cap its resource usage.
+///
+/// Limits stay in effect for as long as the process runs, even after the guard is dropped.
 pub struct AssignJobGuard {
     _job: HANDLE,
 }
@@ -27,11 +32,13 @@ impl AssignJobGuard {
         }
     }
 
+    /// Cap the CPU usage to `rate` (from 0.0 to 1.0) of the total CPU time of all processors.
     pub fn cpu_limit(&self, rate: f64) -> Result<(), WindowsError> {
         let control_info = JOBOBJECT_CPU_RATE_CONTROL_INFORMATION {
             ControlFlags: JOB_OBJECT_CPU_RATE_CONTROL_ENABLE | JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP,
             Anonymous: JOBOBJECT_CPU_RATE_CONTROL_INFORMATION_0 {
-                CpuRate: (10000.0 * rate) as u32,
+                // The rate is expressed in 1/100th of a percent and must be non-zero
+                CpuRate: (10000.0 * rate).clamp(1.0, 10000.0) as u32,
             },
         };
 
@@ -47,4 +54,38 @@ impl AssignJobGuard {
         }
         Ok(())
     }
+
+    /// Cap the committed memory of the process to `bytes`, further allocations will fail.
+    pub fn memory_limit(&self, bytes: usize) -> Result<(), WindowsError> {
+        let limit_info = JOBOBJECT_EXTENDED_LIMIT_INFORMATION {
+            BasicLimitInformation: JOBOBJECT_BASIC_LIMIT_INFORMATION {
+                LimitFlags: JOB_OBJECT_LIMIT_JOB_MEMORY,
+                ..Default::default()
+            },
+            JobMemoryLimit: bytes,
+            ..Default::default()
+        };
+
+        unsafe {
+            SetInformationJobObject(
+                self._job,
+                JobObjectExtendedLimitInformation,
+                &limit_info as *const JOBOBJECT_EXTENDED_LIMIT_INFORMATION as *const c_void,
+                size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>()
+                    .try_into()
+                    .unwrap(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AssignJobGuard {
+    fn drop(&mut self) {
+        unsafe {
+            if let Err(e) = CloseHandle(self._job) {
+                error!("Failed to close job object handle: {e}");
+            }
+        }
+    }
 }
@@ -1,13 +1,19 @@
+pub mod cipher;
+pub mod config;
 pub mod credential;
 pub mod error;
+pub mod etw;
 pub mod file;
 pub mod job;
 pub mod logger;
 pub mod once_cell_no_retry;
 pub mod pool;
+pub mod privilege;
 pub mod ptr_guard;
 pub mod registry;
 pub mod schema;
 pub mod service;
 pub mod sysinfo;
+pub mod telemetry;
 pub mod utils;
+pub mod version;
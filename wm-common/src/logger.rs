@@ -1,6 +1,12 @@
+use std::backtrace::Backtrace;
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
 use std::io::Write;
+use std::panic;
+use std::sync::{Arc, Mutex};
 
-use log::{LevelFilter, SetLoggerError};
+use chrono::Local;
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError, error};
 use serde::{Deserialize, Serialize};
 use simplelog::{
     ColorChoice, CombinedLogger, ConfigBuilder, TermLogger, TerminalMode, WriteLogger,
@@ -29,25 +35,195 @@ impl LogLevel {
     }
 }
 
-pub fn initialize_logger<W>(level: LogLevel, writer: W) -> Result<(), SetLoggerError>
+/// Ring buffer holding the most recent log lines in memory.
+pub struct LogBuffer {
+    _lines: Mutex<VecDeque<String>>,
+    _capacity: usize,
+}
+
+impl LogBuffer {
+    /// Lines longer than this are truncated, so that the buffer size stays bounded
+    const _MAX_LINE_LENGTH: usize = 4096;
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            _lines: Mutex::new(VecDeque::with_capacity(capacity)),
+            _capacity: capacity,
+        }
+    }
+
+    fn _push(&self, mut line: String) {
+        if line.len() > Self::_MAX_LINE_LENGTH {
+            let mut end = Self::_MAX_LINE_LENGTH;
+            while !line.is_char_boundary(end) {
+                end -= 1;
+            }
+            line.truncate(end);
+        }
+
+        let mut lines = self._lines.lock().unwrap_or_else(|e| e.into_inner());
+        if lines.len() == self._capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    /// Buffered lines, oldest first.
+    pub fn lines(&self) -> Vec<String> {
+        let lines = self._lines.lock().unwrap_or_else(|e| e.into_inner());
+        lines.iter().cloned().collect()
+    }
+}
+
+/// Logger filtering records by the level of their module before passing them to the inner
+/// loggers, which accept every level.
+struct _ModuleLevelLogger {
+    _inner: Box<CombinedLogger>,
+    _default: LevelFilter,
+
+    /// Sorted by descending module path length, so that the most specific override wins
+    _overrides: Vec<(String, LevelFilter)>,
+
+    _buffer: Option<Arc<LogBuffer>>,
+}
+
+impl _ModuleLevelLogger {
+    fn _level(&self, target: &str) -> LevelFilter {
+        self._overrides
+            .iter()
+            .find(|(module, _)| {
+                target
+                    .strip_prefix(module.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .map_or(self._default, |(_, level)| *level)
+    }
+}
+
+impl Log for _ModuleLevelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self._level(metadata.target()) && self._inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self._inner.log(record);
+
+            if let Some(buffer) = &self._buffer
+                && buffer._capacity > 0
+            {
+                buffer._push(format!(
+                    "{} [{}] {}: {}",
+                    Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                    record.level(),
+                    record.target(),
+                    record.args()
+                ));
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self._inner.flush();
+    }
+}
+
+/// Log to `writer` and stderr at `level`, except for modules listed in `overrides` (e.g.
+/// `wm_client::module::connector`) which log at their own level.
+///
+/// Logged lines are also kept in `buffer`, if any.
+pub fn initialize_logger<W>(
+    level: LogLevel,
+    overrides: &HashMap<String, LogLevel>,
+    writer: W,
+    buffer: Option<Arc<LogBuffer>>,
+) -> Result<(), SetLoggerError>
 where
     W: Write + Send + 'static,
 {
-    CombinedLogger::init(vec![
+    let default = level.to_level_filter();
+    let mut overrides = overrides
+        .iter()
+        .map(|(module, level)| (module.clone(), level.to_level_filter()))
+        .collect::<Vec<_>>();
+    overrides.sort_by_key(|(module, _)| Reverse(module.len()));
+
+    let max_level = overrides
+        .iter()
+        .map(|(_, level)| *level)
+        .fold(default, LevelFilter::max);
+
+    let inner = CombinedLogger::new(vec![
         WriteLogger::new(
-            level.to_level_filter(),
+            max_level,
             ConfigBuilder::new()
                 .set_location_level(LevelFilter::Debug)
                 .build(),
             writer,
         ),
         TermLogger::new(
-            level.to_level_filter(),
+            max_level,
             ConfigBuilder::new()
                 .set_location_level(LevelFilter::Debug)
                 .build(),
             TerminalMode::Stderr,
             ColorChoice::Auto,
         ),
-    ])
+    ]);
+
+    log::set_boxed_logger(Box::new(_ModuleLevelLogger {
+        _inner: inner,
+        _default: default,
+        _overrides: overrides,
+        _buffer: buffer,
+    }))?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+/// Log panics with a backtrace and flush the logger, then run `on_panic` before the default
+/// hook.
+///
+/// Panics in spawned tasks are otherwise only printed to stderr, which is lost when running as
+/// a service.
+pub fn install_panic_hook<F>(on_panic: F)
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        error!("Panic: {info}\n{}", Backtrace::force_capture());
+        log::logger().flush();
+
+        on_panic();
+        default_hook(info);
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LogBuffer;
+
+    #[test]
+    fn keeps_most_recent_lines() {
+        let buffer = LogBuffer::new(2);
+        for line in ["a", "b", "c"] {
+            buffer._push(line.to_string());
+        }
+
+        assert_eq!(buffer.lines(), ["b", "c"]);
+    }
+
+    #[test]
+    fn truncates_long_lines_at_char_boundary() {
+        let buffer = LogBuffer::new(1);
+
+        // 3-byte characters straddle the length limit
+        buffer._push("\u{20ac}".repeat(LogBuffer::_MAX_LINE_LENGTH));
+
+        let line = &buffer.lines()[0];
+        assert!(line.len() <= LogBuffer::_MAX_LINE_LENGTH);
+        assert!(line.len() > LogBuffer::_MAX_LINE_LENGTH - 3);
+        assert!(line.chars().all(|c| c == '\u{20ac}'));
+    }
 }
@@ -1,9 +1,36 @@
+#[cfg(not(all(test, loom)))]
 use std::cell::UnsafeCell;
 use std::mem::MaybeUninit;
+use std::pin::pin;
+#[cfg(not(all(test, loom)))]
 use std::sync::atomic::{AtomicBool, Ordering};
 
+#[cfg(all(test, loom))]
+use loom::cell::UnsafeCell as _UnsafeCell;
+#[cfg(all(test, loom))]
+use loom::sync::atomic::{AtomicBool, Ordering};
 use tokio::sync::Notify;
 
+/// [`UnsafeCell`] with the closure-based API of `loom::cell::UnsafeCell`, so that loom can check
+/// every access to the value.
+#[cfg(not(all(test, loom)))]
+struct _UnsafeCell<T>(UnsafeCell<T>);
+
+#[cfg(not(all(test, loom)))]
+impl<T> _UnsafeCell<T> {
+    fn new(data: T) -> Self {
+        Self(UnsafeCell::new(data))
+    }
+
+    fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+        f(self.0.get())
+    }
+
+    fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+        f(self.0.get())
+    }
+}
+
 struct _DropGuard<'a, T> {
     _cell: &'a OnceCellNoRetry<T>,
 }
@@ -17,7 +44,7 @@ impl<T> Drop for _DropGuard<'_, T> {
 
 pub struct OnceCellNoRetry<T> {
     _waiter: Notify,
-    _inner: UnsafeCell<MaybeUninit<T>>,
+    _inner: _UnsafeCell<MaybeUninit<T>>,
     _initializing: AtomicBool,
     _initialized: AtomicBool,
 }
@@ -31,7 +58,7 @@ impl<T> OnceCellNoRetry<T> {
         let initialized = value.is_some();
         Self {
             _waiter: Notify::new(),
-            _inner: UnsafeCell::new(match value {
+            _inner: _UnsafeCell::new(match value {
                 Some(v) => MaybeUninit::new(v),
                 None => MaybeUninit::uninit(),
             }),
@@ -42,17 +69,14 @@ impl<T> OnceCellNoRetry<T> {
 
     /// The underlying value must not be uninitialized.
     unsafe fn _get_unchecked(&self) -> &T {
-        unsafe {
-            let init = &*self._inner.get();
-            init.assume_init_ref()
-        }
+        self._inner
+            .with(|init| unsafe { (*init).assume_init_ref() })
     }
 
     unsafe fn _set_unchecked(&self, value: T) {
-        unsafe {
-            let init = &mut *self._inner.get();
-            init.write(value);
-        }
+        self._inner.with_mut(|init| unsafe {
+            (*init).write(value);
+        });
     }
 
     pub async fn get_or_try_init<E, F, Fut>(&self, f: F) -> Option<&T>
@@ -75,6 +99,8 @@ impl<T> OnceCellNoRetry<T> {
                 let _guard = _DropGuard { _cell: self };
                 match f().await {
                     Ok(result) => {
+                        // The Release store publishes the value written above to every thread
+                        // observing `_initialized == true` with an Acquire load
                         unsafe { self._set_unchecked(result) };
                         self._initialized.store(true, Ordering::Release);
                         Some(unsafe { self._get_unchecked() })
@@ -83,7 +109,17 @@ impl<T> OnceCellNoRetry<T> {
                 }
             }
             Err(_) => {
-                self._waiter.notified().await;
+                // `notify_waiters` only wakes futures registered at the time of the call, so
+                // register before checking whether the initializer has already finished
+                let mut notified = pin!(self._waiter.notified());
+                notified.as_mut().enable();
+
+                // Observing `_initializing == false` (stored with Release by `_DropGuard` after
+                // `_initialized`) also makes the result of the initializer visible below
+                if self._initializing.load(Ordering::Acquire) {
+                    notified.await;
+                }
+
                 if self._initialized.load(Ordering::Acquire) {
                     Some(unsafe { self._get_unchecked() })
                 } else {
@@ -103,13 +139,179 @@ impl<T> Default for OnceCellNoRetry<T> {
 impl<T> Drop for OnceCellNoRetry<T> {
     fn drop(&mut self) {
         if self._initialized.load(Ordering::Acquire) {
-            unsafe {
-                let init = self._inner.get_mut();
-                init.assume_init_drop();
-            }
+            self._inner
+                .with_mut(|init| unsafe { (*init).assume_init_drop() });
         }
     }
 }
 
 unsafe impl<T: Send> Send for OnceCellNoRetry<T> {}
-unsafe impl<T: Sync> Sync for OnceCellNoRetry<T> {}
+// Sharing the cell lets any thread initialize the value, which is then dropped by the owner
+unsafe impl<T: Send + Sync> Sync for OnceCellNoRetry<T> {}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use tokio::sync::Barrier;
+    use tokio::task::JoinSet;
+    use tokio::time::sleep;
+
+    use super::OnceCellNoRetry;
+
+    const _TASKS: usize = 64;
+
+    /// Value counting how many times it was dropped.
+    struct _Counted {
+        _value: u32,
+        _drops: Arc<AtomicUsize>,
+    }
+
+    impl Drop for _Counted {
+        fn drop(&mut self) {
+            self._drops.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Call `get_or_try_init` from `_TASKS` tasks at once, each with an initializer that succeeds
+    /// if `succeed` is set, returning what each task got and how many initializers ran.
+    async fn _race(
+        cell: &Arc<OnceCellNoRetry<_Counted>>,
+        drops: &Arc<AtomicUsize>,
+        succeed: bool,
+    ) -> (Vec<Option<u32>>, usize) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(_TASKS));
+        let mut tasks = JoinSet::new();
+        for _ in 0.._TASKS {
+            let cell = cell.clone();
+            let drops = drops.clone();
+            let calls = calls.clone();
+            let barrier = barrier.clone();
+            tasks.spawn(async move {
+                barrier.wait().await;
+                cell.get_or_try_init(move || async move {
+                    calls.fetch_add(1, Ordering::Relaxed);
+
+                    // Keep initializing while the other tasks arrive
+                    sleep(Duration::from_millis(50)).await;
+                    if succeed {
+                        Ok(_Counted {
+                            _value: 42,
+                            _drops: drops,
+                        })
+                    } else {
+                        Err("connection refused")
+                    }
+                })
+                .await
+                .map(|counted| counted._value)
+            });
+        }
+
+        let results = tasks.join_all().await;
+        (results, calls.load(Ordering::Relaxed))
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn initializes_once_for_concurrent_callers() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let cell = Arc::new(OnceCellNoRetry::new());
+
+        let (results, calls) = _race(&cell, &drops, true).await;
+        assert_eq!(calls, 1);
+        assert_eq!(results, vec![Some(42); _TASKS]);
+
+        // Later callers get the value without running their initializer
+        let (results, calls) = _race(&cell, &drops, true).await;
+        assert_eq!(calls, 0);
+        assert_eq!(results, vec![Some(42); _TASKS]);
+
+        assert_eq!(drops.load(Ordering::Relaxed), 0);
+        drop(Arc::into_inner(cell).unwrap());
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn waiters_do_not_retry_failed_initialization() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let cell = Arc::new(OnceCellNoRetry::new());
+
+        // Callers waiting for the failing initializer share its failure instead of retrying
+        let (results, calls) = _race(&cell, &drops, false).await;
+        assert_eq!(calls, 1);
+        assert_eq!(results, vec![None; _TASKS]);
+
+        // The cell is left uninitialized, so the next caller may try again
+        let (results, calls) = _race(&cell, &drops, true).await;
+        assert_eq!(calls, 1);
+        assert_eq!(results, vec![Some(42); _TASKS]);
+
+        drop(Arc::into_inner(cell).unwrap());
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn drops_only_initialized_values() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        drop(OnceCellNoRetry::<_Counted>::new());
+        assert_eq!(drops.load(Ordering::Relaxed), 0);
+
+        drop(OnceCellNoRetry::new_with(Some(_Counted {
+            _value: 42,
+            _drops: drops.clone(),
+        })));
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+}
+
+/// Models of concurrent initialization, explored with
+/// `RUSTFLAGS="--cfg loom" cargo test -p wm-common --release once_cell_no_retry`.
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::future::block_on;
+    use loom::sync::Arc;
+    use loom::thread;
+
+    use super::OnceCellNoRetry;
+
+    /// Race two callers initializing the cell, the first one with `first` and the second one with
+    /// `second`, returning what each of them got.
+    fn _race(
+        first: Result<&'static str, ()>,
+        second: Result<&'static str, ()>,
+    ) -> (Option<&'static str>, Option<&'static str>) {
+        let cell = Arc::new(OnceCellNoRetry::new());
+        let other = cell.clone();
+        let thread = thread::spawn(move || {
+            block_on(other.get_or_try_init(|| async move { second })).copied()
+        });
+
+        let result = block_on(cell.get_or_try_init(|| async move { first })).copied();
+        (result, thread.join().unwrap())
+    }
+
+    #[test]
+    fn waiters_observe_the_initialized_value() {
+        loom::model(|| {
+            // Whoever initializes the cell, both callers see the fully written value and
+            // neither of them waits forever for a notification it missed
+            let (first, second) = _race(Ok("first"), Ok("second"));
+            assert!(first.is_some());
+            assert_eq!(first, second);
+        });
+    }
+
+    #[test]
+    fn waiters_are_woken_by_failed_initialization() {
+        loom::model(|| {
+            // The second caller either shares the failure of the first one, or runs its own
+            // initializer once the first one failed
+            let (first, second) = _race(Err(()), Ok("second"));
+            assert!(matches!(first, None | Some("second")));
+            assert!(matches!(second, None | Some("second")));
+        });
+    }
+}
@@ -1,5 +1,7 @@
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use tokio::sync::{Mutex, OwnedMutexGuard, mpsc};
 
@@ -25,6 +27,7 @@ impl<T> DerefMut for PoolGuard<'_, T> {
 
 impl<T> Drop for PoolGuard<'_, T> {
     fn drop(&mut self) {
+        self._pool._available.fetch_add(1, Ordering::Relaxed);
         self._pool
             ._sender
             .try_send(self._mutex.clone())
@@ -32,9 +35,44 @@ impl<T> Drop for PoolGuard<'_, T> {
     }
 }
 
+/// Snapshot of the usage of a [`Pool`].
+#[derive(Clone, Copy, Debug)]
+pub struct PoolMetrics {
+    /// Number of items not checked out
+    pub available: usize,
+
+    /// Number of completed [`Pool::acquire`] calls
+    pub acquisitions: u64,
+
+    /// Total time spent waiting in [`Pool::acquire`]
+    pub total_wait: Duration,
+
+    /// Longest time spent waiting in a single [`Pool::acquire`] call
+    pub max_wait: Duration,
+}
+
+impl PoolMetrics {
+    pub fn average_wait(&self) -> Duration {
+        if self.acquisitions == 0 {
+            Duration::ZERO
+        } else {
+            self.total_wait.div_f64(self.acquisitions as f64)
+        }
+    }
+}
+
+/// Fixed-size pool of reusable items.
+///
+/// Waiters are served in FIFO order: they queue on a fair [`Mutex`] guarding the receiving end
+/// of the channel holding available items, so no caller can be starved.
 pub struct Pool<T> {
     _sender: mpsc::Sender<Arc<Mutex<T>>>,
     _receiver: Mutex<mpsc::Receiver<Arc<Mutex<T>>>>,
+
+    _available: AtomicUsize,
+    _acquisitions: AtomicU64,
+    _total_wait_ns: AtomicU64,
+    _max_wait_ns: AtomicU64,
 }
 
 impl<T> Pool<T> {
@@ -51,19 +89,121 @@ impl<T> Pool<T> {
         Self {
             _sender: sender,
             _receiver: Mutex::new(receiver),
+            _available: AtomicUsize::new(size),
+            _acquisitions: AtomicU64::new(0),
+            _total_wait_ns: AtomicU64::new(0),
+            _max_wait_ns: AtomicU64::new(0),
         }
     }
 
     pub async fn acquire(&self) -> PoolGuard<'_, T> {
+        let started = Instant::now();
         let mut receiver = self._receiver.lock().await;
 
         let mutex = receiver.recv().await.expect("Pool channel closed");
         let item = mutex.clone().lock_owned().await;
 
+        let waited = started.elapsed().as_nanos() as u64;
+        self._available.fetch_sub(1, Ordering::Relaxed);
+        self._acquisitions.fetch_add(1, Ordering::Relaxed);
+        self._total_wait_ns.fetch_add(waited, Ordering::Relaxed);
+        self._max_wait_ns.fetch_max(waited, Ordering::Relaxed);
+
         PoolGuard {
             _pool: self,
             _mutex: mutex.clone(),
             _item: item,
         }
     }
+
+    pub fn metrics(&self) -> PoolMetrics {
+        PoolMetrics {
+            available: self._available.load(Ordering::Relaxed),
+            acquisitions: self._acquisitions.load(Ordering::Relaxed),
+            total_wait: Duration::from_nanos(self._total_wait_ns.load(Ordering::Relaxed)),
+            max_wait: Duration::from_nanos(self._max_wait_ns.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::sync::Mutex;
+    use tokio::task;
+
+    use super::Pool;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn never_checks_out_more_items_than_its_size() {
+        const SIZE: usize = 2;
+        const TASKS: usize = 16;
+        const ITERATIONS: usize = 100;
+
+        let pool = Arc::new(Pool::new(SIZE, |_| 0usize));
+        let checked_out = Arc::new(AtomicUsize::new(0));
+
+        let mut handles = vec![];
+        for _ in 0..TASKS {
+            let pool = pool.clone();
+            let checked_out = checked_out.clone();
+            handles.push(tokio::spawn(async move {
+                for _ in 0..ITERATIONS {
+                    let mut item = pool.acquire().await;
+                    assert!(checked_out.fetch_add(1, Ordering::SeqCst) < SIZE);
+                    *item += 1;
+                    task::yield_now().await;
+                    checked_out.fetch_sub(1, Ordering::SeqCst);
+                }
+            }));
+        }
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        let metrics = pool.metrics();
+        assert_eq!(metrics.available, SIZE);
+        assert_eq!(metrics.acquisitions, (TASKS * ITERATIONS) as u64);
+        assert!(metrics.max_wait >= metrics.average_wait());
+
+        let mut total = 0;
+        for _ in 0..SIZE {
+            let item = pool.acquire().await;
+            total += *item;
+            drop(item);
+        }
+        assert_eq!(total, TASKS * ITERATIONS);
+    }
+
+    #[tokio::test]
+    async fn serves_waiters_in_fifo_order() {
+        let pool = Arc::new(Pool::new(1, |_| ()));
+        let order = Arc::new(Mutex::new(vec![]));
+
+        let held = pool.acquire().await;
+        assert_eq!(pool.metrics().available, 0);
+
+        let mut handles = vec![];
+        for i in 0..8 {
+            let pool = pool.clone();
+            let order = order.clone();
+            handles.push(tokio::spawn(async move {
+                let _item = pool.acquire().await;
+                order.lock().await.push(i);
+            }));
+
+            // Let the waiter queue up before spawning the next one
+            task::yield_now().await;
+        }
+
+        drop(held);
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        assert_eq!(*order.lock().await, (0..8).collect::<Vec<_>>());
+        assert_eq!(pool.metrics().available, 1);
+    }
 }
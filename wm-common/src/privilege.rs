@@ -0,0 +1,165 @@
+use std::slice;
+
+use log::error;
+use windows::Win32::Foundation::{CloseHandle, HANDLE, HLOCAL, LUID, LocalFree};
+use windows::Win32::Security::Authorization::ConvertSidToStringSidW;
+use windows::Win32::Security::{
+    GetTokenInformation, LookupAccountSidW, LookupPrivilegeValueW, SID_NAME_USE, TOKEN_ELEVATION,
+    TOKEN_INFORMATION_CLASS, TOKEN_PRIVILEGES, TOKEN_QUERY, TOKEN_USER, TokenElevation,
+    TokenPrivileges, TokenUser,
+};
+use windows::Win32::System::Threading::{
+    GetCurrentProcess, OpenProcess, OpenProcessToken, PROCESS_QUERY_LIMITED_INFORMATION,
+};
+use windows::core::{PCWSTR, PWSTR};
+
+use crate::error::WindowsError;
+
+/// Maximum length of account and domain names, in UTF-16 code units
+const _MAX_NAME_LENGTH: usize = 256;
+
+/// Access token of a process, closed on drop.
+struct _ProcessToken {
+    _handle: HANDLE,
+}
+
+impl _ProcessToken {
+    fn open() -> Result<Self, WindowsError> {
+        let mut handle = HANDLE::default();
+        unsafe {
+            OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut handle)?;
+        }
+
+        Ok(Self { _handle: handle })
+    }
+
+    /// Open the access token of another process.
+    fn open_process(process_id: u32) -> Result<Self, WindowsError> {
+        let mut handle = HANDLE::default();
+        unsafe {
+            let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, process_id)?;
+            let result = OpenProcessToken(process, TOKEN_QUERY, &mut handle);
+            let _ = CloseHandle(process);
+            result?;
+        }
+
+        Ok(Self { _handle: handle })
+    }
+
+    /// Query a token information class, returning a buffer aligned for the structure it holds.
+    fn information(&self, class: TOKEN_INFORMATION_CLASS) -> Result<Vec<u64>, WindowsError> {
+        let mut length = 0;
+        unsafe {
+            // Expected to fail with ERROR_INSUFFICIENT_BUFFER, this only queries the length
+            let _ = GetTokenInformation(self._handle, class, None, 0, &mut length);
+        }
+
+        let mut buffer = vec![0u64; (length as usize).div_ceil(size_of::<u64>())];
+        unsafe {
+            GetTokenInformation(
+                self._handle,
+                class,
+                Some(buffer.as_mut_ptr().cast()),
+                length,
+                &mut length,
+            )?;
+        }
+
+        Ok(buffer)
+    }
+}
+
+impl Drop for _ProcessToken {
+    fn drop(&mut self) {
+        unsafe {
+            if let Err(e) = CloseHandle(self._handle) {
+                error!("Failed to close process token handle: {e}");
+            }
+        }
+    }
+}
+
+/// Whether the current process runs with an elevated token (e.g. as Administrator or
+/// LocalSystem).
+pub fn is_elevated() -> Result<bool, WindowsError> {
+    let buffer = _ProcessToken::open()?.information(TokenElevation)?;
+    let elevation = unsafe { &*buffer.as_ptr().cast::<TOKEN_ELEVATION>() };
+    Ok(elevation.TokenIsElevated != 0)
+}
+
+/// Whether the token of the current process holds the privilege `name` (e.g.
+/// [`SE_SYSTEM_PROFILE_NAME`](windows::Win32::Security::SE_SYSTEM_PROFILE_NAME)), enabled or not.
+pub fn has_privilege(name: PCWSTR) -> Result<bool, WindowsError> {
+    let mut luid = LUID::default();
+    unsafe {
+        LookupPrivilegeValueW(PCWSTR::null(), name, &mut luid)?;
+    }
+
+    let buffer = _ProcessToken::open()?.information(TokenPrivileges)?;
+    let privileges = unsafe {
+        let header = &*buffer.as_ptr().cast::<TOKEN_PRIVILEGES>();
+        slice::from_raw_parts(header.Privileges.as_ptr(), header.PrivilegeCount as usize)
+    };
+
+    Ok(privileges
+        .iter()
+        .any(|p| p.Luid.LowPart == luid.LowPart && p.Luid.HighPart == luid.HighPart))
+}
+
+/// Account a process runs as.
+#[derive(Clone, Debug)]
+pub struct ProcessUser {
+    /// String form of the SID (e.g. `S-1-5-18`)
+    pub sid: String,
+
+    /// Account name, `None` if the SID cannot be mapped to an account
+    pub name: Option<String>,
+
+    /// Domain of the account, `None` if the SID cannot be mapped to an account
+    pub domain: Option<String>,
+}
+
+/// Resolve the account owning the access token of a running process.
+pub fn process_user(process_id: u32) -> Result<ProcessUser, WindowsError> {
+    let buffer = _ProcessToken::open_process(process_id)?.information(TokenUser)?;
+    let sid = unsafe { (*buffer.as_ptr().cast::<TOKEN_USER>()).User.Sid };
+
+    let mut string_sid = PWSTR::null();
+    let sid_string = unsafe {
+        ConvertSidToStringSidW(sid, &mut string_sid)?;
+        let sid_string = string_sid.to_string().unwrap_or_default();
+        let _ = LocalFree(Some(HLOCAL(string_sid.0.cast())));
+        sid_string
+    };
+
+    let mut name = vec![0; _MAX_NAME_LENGTH];
+    let mut name_length = name.len() as u32;
+    let mut domain = vec![0; _MAX_NAME_LENGTH];
+    let mut domain_length = domain.len() as u32;
+    let mut sid_type = SID_NAME_USE::default();
+    let account = unsafe {
+        LookupAccountSidW(
+            PCWSTR::null(),
+            sid,
+            Some(PWSTR::from_raw(name.as_mut_ptr())),
+            &mut name_length,
+            Some(PWSTR::from_raw(domain.as_mut_ptr())),
+            &mut domain_length,
+            &mut sid_type,
+        )
+    };
+
+    let (name, domain) = match account {
+        Ok(()) => (
+            Some(String::from_utf16_lossy(&name[..name_length as usize])),
+            Some(String::from_utf16_lossy(&domain[..domain_length as usize])),
+        ),
+        Err(_) => (None, None),
+    };
+
+    Ok(ProcessUser {
+        sid: sid_string,
+        name,
+        domain,
+    })
+}
@@ -1,3 +1,6 @@
+use std::net::IpAddr;
+
+use windows::Wdk::Storage::FileSystem::{FileDispositionInformation, FileDispositionInformationEx};
 use windows::Win32::Storage::FileSystem::{
     FILE_ATTRIBUTE_ARCHIVE, FILE_ATTRIBUTE_ENCRYPTED, FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_NORMAL,
     FILE_ATTRIBUTE_OFFLINE, FILE_ATTRIBUTE_READONLY, FILE_ATTRIBUTE_SYSTEM,
@@ -40,3 +43,146 @@ pub fn file_attributes(attributes: u32) -> Vec<String> {
 
     results
 }
+
+/// `FILE_DELETE_ON_CLOSE` create option
+const _FILE_DELETE_ON_CLOSE: u32 = 0x0000_1000;
+
+/// Names of `FILE_INFORMATION_CLASS` values, starting from 1
+const _FILE_INFORMATION_CLASSES: [&str; 83] = [
+    "FileDirectoryInformation",
+    "FileFullDirectoryInformation",
+    "FileBothDirectoryInformation",
+    "FileBasicInformation",
+    "FileStandardInformation",
+    "FileInternalInformation",
+    "FileEaInformation",
+    "FileAccessInformation",
+    "FileNameInformation",
+    "FileRenameInformation",
+    "FileLinkInformation",
+    "FileNamesInformation",
+    "FileDispositionInformation",
+    "FilePositionInformation",
+    "FileFullEaInformation",
+    "FileModeInformation",
+    "FileAlignmentInformation",
+    "FileAllInformation",
+    "FileAllocationInformation",
+    "FileEndOfFileInformation",
+    "FileAlternateNameInformation",
+    "FileStreamInformation",
+    "FilePipeInformation",
+    "FilePipeLocalInformation",
+    "FilePipeRemoteInformation",
+    "FileMailslotQueryInformation",
+    "FileMailslotSetInformation",
+    "FileCompressionInformation",
+    "FileObjectIdInformation",
+    "FileCompletionInformation",
+    "FileMoveClusterInformation",
+    "FileQuotaInformation",
+    "FileReparsePointInformation",
+    "FileNetworkOpenInformation",
+    "FileAttributeTagInformation",
+    "FileTrackingInformation",
+    "FileIdBothDirectoryInformation",
+    "FileIdFullDirectoryInformation",
+    "FileValidDataLengthInformation",
+    "FileShortNameInformation",
+    "FileIoCompletionNotificationInformation",
+    "FileIoStatusBlockRangeInformation",
+    "FileIoPriorityHintInformation",
+    "FileSfioReserveInformation",
+    "FileSfioVolumeInformation",
+    "FileHardLinkInformation",
+    "FileProcessIdsUsingFileInformation",
+    "FileNormalizedNameInformation",
+    "FileNetworkPhysicalNameInformation",
+    "FileIdGlobalTxDirectoryInformation",
+    "FileIsRemoteDeviceInformation",
+    "FileUnusedInformation",
+    "FileNumaNodeInformation",
+    "FileStandardLinkInformation",
+    "FileRemoteProtocolInformation",
+    "FileRenameInformationBypassAccessCheck",
+    "FileLinkInformationBypassAccessCheck",
+    "FileVolumeNameInformation",
+    "FileIdInformation",
+    "FileIdExtdDirectoryInformation",
+    "FileReplaceCompletionInformation",
+    "FileHardLinkFullIdInformation",
+    "FileIdExtdBothDirectoryInformation",
+    "FileDispositionInformationEx",
+    "FileRenameInformationEx",
+    "FileRenameInformationExBypassAccessCheck",
+    "FileDesiredStorageClassInformation",
+    "FileStatInformation",
+    "FileMemoryPartitionInformation",
+    "FileStatLxInformation",
+    "FileCaseSensitiveInformation",
+    "FileLinkInformationEx",
+    "FileLinkInformationExBypassAccessCheck",
+    "FileStorageReserveIdInformation",
+    "FileCaseSensitiveInformationForceAccessCheck",
+    "FileKnownFolderInformation",
+    "FileStatBasicInformation",
+    "FileId64ExtdDirectoryInformation",
+    "FileId64ExtdBothDirectoryInformation",
+    "FileIdAllExtdDirectoryInformation",
+    "FileIdAllExtdBothDirectoryInformation",
+    "FileStreamReservationInformation",
+    "FileMupProviderInfo",
+];
+
+/// Name of the `FILE_INFORMATION_CLASS` of a file information event.
+pub fn file_info_class_name(info_class: u32) -> Option<&'static str> {
+    (info_class as usize)
+        .checked_sub(1)
+        .and_then(|index| _FILE_INFORMATION_CLASSES.get(index))
+        .copied()
+}
+
+/// Whether a file create event opened the file to be deleted once its last handle is closed.
+pub fn create_deletes_on_close(options: u32) -> bool {
+    // The high byte holds the create disposition, the rest holds the create options
+    options & 0x00ff_ffff & _FILE_DELETE_ON_CLOSE != 0
+}
+
+/// Whether a file set-information event marks the file for deletion.
+pub fn info_marks_deletion(info_class: u32, extra_info: usize) -> bool {
+    let info_class = info_class as i32;
+
+    // ExtraInfo holds the DeleteFile flag and the FILE_DISPOSITION_* flags respectively, of
+    // which FILE_DISPOSITION_DELETE is the lowest bit
+    (info_class == FileDispositionInformation.0 || info_class == FileDispositionInformationEx.0)
+        && extra_info & 1 != 0
+}
+
+/// Name of a DNS resource record type (e.g. `AAAA` for 28), for the common types.
+pub fn dns_type_name(query_type: u32) -> Option<&'static str> {
+    match query_type {
+        1 => Some("A"),
+        2 => Some("NS"),
+        5 => Some("CNAME"),
+        6 => Some("SOA"),
+        12 => Some("PTR"),
+        15 => Some("MX"),
+        16 => Some("TXT"),
+        28 => Some("AAAA"),
+        33 => Some("SRV"),
+        64 => Some("SVCB"),
+        65 => Some("HTTPS"),
+        255 => Some("ANY"),
+        _ => None,
+    }
+}
+
+/// Addresses listed in the `;`-separated results of a DNS client query, skipping the other
+/// records (e.g. `type: 5 example.com` for aliases).
+pub fn dns_resolved_ips(results: &str) -> Vec<IpAddr> {
+    results
+        .split(';')
+        .filter_map(|result| result.trim().parse::<IpAddr>().ok())
+        .map(|ip| ip.to_canonical())
+        .collect()
+}
@@ -6,15 +6,20 @@ use std::sync::Arc;
 use chrono::{DateTime, Utc};
 use ferrisetw::EventRecord;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{Map, Value};
 use windows::Wdk::Storage::FileSystem::{FileAllocationInformation, FileEndOfFileInformation};
 use wm_generated::ecs::{
-    ECS, ECS_Destination, ECS_Dll, ECS_Dll_CodeSignature, ECS_Event, ECS_File, ECS_Host,
-    ECS_Host_Cpu, ECS_Host_Os, ECS_Process, ECS_Process_Parent, ECS_Process_Thread, ECS_Registry,
-    ECS_Source,
+    ECS, ECS_Destination, ECS_Dll, ECS_Dll_CodeSignature, ECS_Dll_Hash, ECS_Dns, ECS_Dns_Question,
+    ECS_Event, ECS_Event_Category, ECS_Event_Kind, ECS_Event_Type, ECS_File, ECS_Host,
+    ECS_Host_Cpu, ECS_Host_Cpu_Cores, ECS_Host_Disk, ECS_Host_Disk_Read, ECS_Host_Disk_Write,
+    ECS_Host_Network, ECS_Host_Network_Egress, ECS_Host_Network_Ingress, ECS_Host_Os, ECS_Process,
+    ECS_Process_Hash, ECS_Process_Parent, ECS_Process_Thread, ECS_Registry, ECS_Source, ECS_User,
 };
 
-use crate::schema::ecs_converter::file_attributes;
+use crate::schema::ecs_converter::{
+    create_deletes_on_close, dns_resolved_ips, dns_type_name, file_attributes,
+    file_info_class_name, info_marks_deletion,
+};
 use crate::schema::sysinfo::SystemInfo;
 use crate::utils::{split_command_line, windows_timestamp};
 
@@ -49,6 +54,8 @@ pub enum EventData {
         image_size: usize,
         image_checksum: u32,
         file_name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sha256: Option<String>,
     },
     Process {
         unique_process_key: usize,
@@ -59,6 +66,12 @@ pub enum EventData {
         directory_table_base: usize,
         image_file_name: String,
         command_line: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sha256: Option<String>,
+
+        /// Salted SHA-256 hash of the unredacted command line
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        command_line_sha256: Option<String>,
     },
     Registry {
         initial_time: i64,
@@ -83,9 +96,50 @@ pub enum EventData {
         dport: u16,
         sport: u16,
     },
+    Dns {
+        query_name: String,
+        query_type: u32,
+        query_status: u32,
+        query_results: String,
+    },
 }
 
 impl EventData {
+    /// All values returned by [`Self::event_type`].
+    pub const EVENT_TYPES: [&'static str; 7] = [
+        "file", "image", "process", "registry", "tcpip", "udpip", "dns",
+    ];
+
+    /// All values returned by [`Self::variant_name`].
+    pub const VARIANT_NAMES: [&'static str; 10] = [
+        "FileCreate",
+        "FileInfo",
+        "FileReadWrite",
+        "FileDelete",
+        "Image",
+        "Process",
+        "Registry",
+        "TcpIp",
+        "UdpIp",
+        "Dns",
+    ];
+
+    /// Name of the variant, as in the `type` tag of the serialized data.
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            Self::FileCreate { .. } => "FileCreate",
+            Self::FileInfo { .. } => "FileInfo",
+            Self::FileReadWrite { .. } => "FileReadWrite",
+            Self::FileDelete { .. } => "FileDelete",
+            Self::Image { .. } => "Image",
+            Self::Process { .. } => "Process",
+            Self::Registry { .. } => "Registry",
+            Self::TcpIp { .. } => "TcpIp",
+            Self::UdpIp { .. } => "UdpIp",
+            Self::Dns { .. } => "Dns",
+        }
+    }
+
     pub fn event_type(&self) -> &'static str {
         match self {
             Self::FileCreate { .. }
@@ -97,10 +151,20 @@ impl EventData {
             Self::Registry { .. } => "registry",
             Self::TcpIp { .. } => "tcpip",
             Self::UdpIp { .. } => "udpip",
+            Self::Dns { .. } => "dns",
         }
     }
 }
 
+/// Account the process of an event runs as.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EventUser {
+    /// String form of the SID
+    pub id: String,
+    pub name: Option<String>,
+    pub domain: Option<String>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Event {
     pub guid: String,
@@ -110,6 +174,8 @@ pub struct Event {
     pub event_id: u16,
     pub opcode: u8,
     pub data: EventData,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<EventUser>,
 }
 
 impl Event {
@@ -122,6 +188,7 @@ impl Event {
             event_id: record.event_id(),
             opcode: record.opcode(),
             data,
+            user: None,
         }
     }
 }
@@ -178,10 +245,52 @@ impl CapturedEventRecord {
 
         let mut cpu = ECS_Host_Cpu::new();
         cpu.usage = Some(self.system.cpu.usage);
+        if !self.system.cpu.cores.is_empty() {
+            cpu.cores = Some(
+                self.system
+                    .cpu
+                    .cores
+                    .iter()
+                    .enumerate()
+                    .map(|(id, usage)| {
+                        let mut core = ECS_Host_Cpu_Cores::new();
+                        core.id = i16::try_from(id).ok();
+                        core.usage = Some(*usage);
+                        core
+                    })
+                    .collect(),
+            );
+        }
 
         let mut host = ECS_Host::new();
         host.architecture = Some(vec![self.system.architecture.clone()]);
         host.cpu = Some(cpu);
+        if let Some(io) = &self.system.io {
+            let mut read = ECS_Host_Disk_Read::new();
+            read.bytes = i64::try_from(io.disk_read_bytes).ok();
+
+            let mut write = ECS_Host_Disk_Write::new();
+            write.bytes = i64::try_from(io.disk_written_bytes).ok();
+
+            let mut disk = ECS_Host_Disk::new();
+            disk.read = Some(read);
+            disk.write = Some(write);
+
+            let mut ingress = ECS_Host_Network_Ingress::new();
+            ingress.bytes = i64::try_from(io.network_received_bytes).ok();
+            ingress.packets = i64::try_from(io.network_received_packets).ok();
+
+            let mut egress = ECS_Host_Network_Egress::new();
+            egress.bytes = i64::try_from(io.network_transmitted_bytes).ok();
+            egress.packets = i64::try_from(io.network_transmitted_packets).ok();
+
+            let mut network = ECS_Host_Network::new();
+            network.egress = Some(egress);
+            network.ingress = Some(ingress);
+
+            host.disk = Some(disk);
+            host.network = Some(network);
+        }
         host.hostname = Some(vec![self.system.hostname.clone()]);
         host.id = Some(vec![ip.to_string()]);
         host.ip = Some(ip);
@@ -195,7 +304,7 @@ impl CapturedEventRecord {
         ]);
         event.created = Some(self.captured);
         event.ingested = Some(Utc::now());
-        event.kind = Some(vec!["event".to_string()]);
+        event.kind = Some(vec![ECS_Event_Kind::Event]);
         event.module = Some(vec!["wm-client".to_string()]);
         event.original = Some(vec![self.serialize_to_string()]);
         event.provider = Some(vec!["kernel".to_string()]);
@@ -207,22 +316,37 @@ impl CapturedEventRecord {
         default_process.pid = Some(i64::from(self.event.process_id));
         default_process.thread = Some(thread);
 
+        let mut labels = Map::new();
+        labels.insert("application".to_string(), Value::from("windows-monitor"));
+
         let mut ecs = ECS::new(windows_timestamp(self.event.raw_timestamp));
-        ecs.labels = Some(json!({"application": "windows-monitor"}));
         ecs.process = Some(default_process);
         ecs.tags = Some(vec![self.event.data.event_type().into()]);
         ecs.host = Some(host);
+        if let Some(user) = &self.event.user {
+            let mut ecs_user = ECS_User::new();
+            ecs_user.domain = user.domain.clone().map(|domain| vec![domain]);
+            ecs_user.id = Some(vec![user.id.clone()]);
+            ecs_user.name = user.name.clone().map(|name| vec![name]);
+            ecs.user = Some(ecs_user);
+        }
 
         match &self.event.data {
             EventData::FileCreate {
+                options,
                 attributes,
                 share_access,
                 open_path,
                 ..
             } => {
                 event.action = Some(vec!["file-create".to_string()]);
-                event.category = Some(vec!["file".to_string()]);
-                event.type_ = Some(vec!["creation".to_string()]);
+                event.category = Some(vec![ECS_Event_Category::File]);
+                if create_deletes_on_close(*options) {
+                    event.type_ = Some(vec![ECS_Event_Type::Creation, ECS_Event_Type::Deletion]);
+                    labels.insert("file_delete_on_close".to_string(), Value::from("true"));
+                } else {
+                    event.type_ = Some(vec![ECS_Event_Type::Creation]);
+                }
 
                 let path = Path::new(open_path);
 
@@ -245,8 +369,12 @@ impl CapturedEventRecord {
                 file_path,
                 ..
             } => {
+                // Setting the disposition is how most deletions actually happen
+                let deletion =
+                    self.event.opcode == 69 && info_marks_deletion(*info_class, *extra_info);
                 event.action = Some(vec![
                     match self.event.opcode {
+                        69 if deletion => "file-delete",
                         69 => "file-set-info",
                         70 => "file-delete",
                         71 => "file-rename",
@@ -256,16 +384,14 @@ impl CapturedEventRecord {
                     }
                     .to_string(),
                 ]);
-                event.category = Some(vec!["file".to_string()]);
-                event.type_ = Some(vec![
-                    match self.event.opcode {
-                        69 | 71 => "change",
-                        70 => "deletion",
-                        74 | 75 => "access",
-                        _ => "info",
-                    }
-                    .to_string(),
-                ]);
+                event.category = Some(vec![ECS_Event_Category::File]);
+                event.type_ = Some(vec![match self.event.opcode {
+                    69 if deletion => ECS_Event_Type::Deletion,
+                    69 | 71 => ECS_Event_Type::Change,
+                    70 => ECS_Event_Type::Deletion,
+                    74 | 75 => ECS_Event_Type::Access,
+                    _ => ECS_Event_Type::Info,
+                }]);
 
                 let path = Path::new(file_path);
 
@@ -279,6 +405,10 @@ impl CapturedEventRecord {
                     .map(|s| vec![s.to_string_lossy().to_string()]);
                 file.path = Some(vec![file_path.clone()]);
 
+                if let Some(name) = file_info_class_name(*info_class) {
+                    labels.insert("file_info_class".to_string(), Value::from(name));
+                }
+
                 let info_class = *info_class as i32;
                 file.size = if info_class == FileAllocationInformation.0
                     || info_class == FileEndOfFileInformation.0
@@ -298,15 +428,12 @@ impl CapturedEventRecord {
                     }
                     .to_string(),
                 ]);
-                event.category = Some(vec!["file".to_string()]);
-                event.type_ = Some(vec![
-                    match self.event.opcode {
-                        67 => "access",
-                        68 => "change",
-                        _ => "info",
-                    }
-                    .to_string(),
-                ]);
+                event.category = Some(vec![ECS_Event_Category::File]);
+                event.type_ = Some(vec![match self.event.opcode {
+                    67 => ECS_Event_Type::Access,
+                    68 => ECS_Event_Type::Change,
+                    _ => ECS_Event_Type::Info,
+                }]);
 
                 let path = Path::new(file_path);
 
@@ -323,8 +450,8 @@ impl CapturedEventRecord {
             }
             EventData::FileDelete { file_path } => {
                 event.action = Some(vec!["file-delete".to_string()]);
-                event.category = Some(vec!["file".to_string()]);
-                event.type_ = Some(vec!["deletion".to_string()]);
+                event.category = Some(vec![ECS_Event_Category::File]);
+                event.type_ = Some(vec![ECS_Event_Type::Deletion]);
 
                 let path = Path::new(file_path);
 
@@ -339,7 +466,9 @@ impl CapturedEventRecord {
                 file.path = Some(vec![file_path.clone()]);
                 ecs.file = Some(file);
             }
-            EventData::Image { file_name, .. } => {
+            EventData::Image {
+                file_name, sha256, ..
+            } => {
                 event.action = Some(vec![
                     match self.event.opcode {
                         2 => "image-unload",
@@ -348,15 +477,12 @@ impl CapturedEventRecord {
                     }
                     .to_string(),
                 ]);
-                event.category = Some(vec!["library".to_string()]);
-                event.type_ = Some(vec![
-                    match self.event.opcode {
-                        2 => "end",
-                        10 => "start",
-                        _ => "info",
-                    }
-                    .to_string(),
-                ]);
+                event.category = Some(vec![ECS_Event_Category::Library]);
+                event.type_ = Some(vec![match self.event.opcode {
+                    2 => ECS_Event_Type::End,
+                    10 => ECS_Event_Type::Start,
+                    _ => ECS_Event_Type::Info,
+                }]);
 
                 let path = Path::new(file_name);
 
@@ -365,6 +491,11 @@ impl CapturedEventRecord {
 
                 let mut dll = ECS_Dll::new();
                 dll.code_signature = Some(signature);
+                dll.hash = sha256.as_ref().map(|sha256| {
+                    let mut hash = ECS_Dll_Hash::new();
+                    hash.sha256 = Some(vec![sha256.clone()]);
+                    hash
+                });
                 dll.name = path
                     .file_name()
                     .map(|s| vec![s.to_string_lossy().to_string()]);
@@ -378,6 +509,8 @@ impl CapturedEventRecord {
                 exit_status,
                 image_file_name,
                 command_line,
+                sha256,
+                command_line_sha256,
                 ..
             } => {
                 event.action = Some(vec![
@@ -388,15 +521,12 @@ impl CapturedEventRecord {
                     }
                     .to_string(),
                 ]);
-                event.category = Some(vec!["process".to_string()]);
-                event.type_ = Some(vec![
-                    match self.event.opcode {
-                        1 => "start",
-                        2 => "end",
-                        _ => "info",
-                    }
-                    .to_string(),
-                ]);
+                event.category = Some(vec![ECS_Event_Category::Process]);
+                event.type_ = Some(vec![match self.event.opcode {
+                    1 => ECS_Event_Type::Start,
+                    2 => ECS_Event_Type::End,
+                    _ => ECS_Event_Type::Info,
+                }]);
 
                 let args = split_command_line(command_line);
                 let args_count = args.len();
@@ -410,9 +540,18 @@ impl CapturedEventRecord {
                 process.command_line = Some(vec![command_line.clone()]);
                 process.executable = Some(vec![image_file_name.clone()]);
                 process.exit_code = Some(i64::from(*exit_status));
+                process.hash = sha256.as_ref().map(|sha256| {
+                    let mut hash = ECS_Process_Hash::new();
+                    hash.sha256 = Some(vec![sha256.clone()]);
+                    hash
+                });
                 process.parent = Some(parent);
                 process.pid = Some(i64::from(*process_id));
                 ecs.process = Some(process);
+
+                if let Some(hash) = command_line_sha256 {
+                    labels.insert("command_line_sha256".to_string(), Value::from(hash.clone()));
+                }
             }
             EventData::Registry { key_name, .. } => {
                 event.action = Some(vec![
@@ -427,16 +566,13 @@ impl CapturedEventRecord {
                     }
                     .to_string(),
                 ]);
-                event.category = Some(vec!["registry".to_string()]);
-                event.type_ = Some(vec![
-                    match self.event.opcode {
-                        10 | 22 => "creation",
-                        12 | 15 | 23 => "deletion",
-                        14 | 20 | 21 => "change",
-                        _ => "info",
-                    }
-                    .to_string(),
-                ]);
+                event.category = Some(vec![ECS_Event_Category::Registry]);
+                event.type_ = Some(vec![match self.event.opcode {
+                    10 | 22 => ECS_Event_Type::Creation,
+                    12 | 15 | 23 => ECS_Event_Type::Deletion,
+                    14 | 20 | 21 => ECS_Event_Type::Change,
+                    _ => ECS_Event_Type::Info,
+                }]);
 
                 // let path = Path::new(key_name);
 
@@ -471,8 +607,8 @@ impl CapturedEventRecord {
                     }
                     .to_string(),
                 ]);
-                event.category = Some(vec!["network".to_string()]);
-                event.type_ = Some(vec!["connection".to_string()]);
+                event.category = Some(vec![ECS_Event_Category::Network]);
+                event.type_ = Some(vec![ECS_Event_Type::Connection]);
 
                 let mut source = ECS_Source::new();
                 source.address = Some(vec![saddr.to_string()]);
@@ -488,9 +624,39 @@ impl CapturedEventRecord {
                 destination.port = Some(i64::from(*dport));
                 ecs.destination = Some(destination);
             }
+            EventData::Dns {
+                query_name,
+                query_type,
+                query_status,
+                query_results,
+            } => {
+                event.action = Some(vec!["dns-query".to_string()]);
+                event.category = Some(vec![ECS_Event_Category::Network]);
+                event.provider = Some(vec!["Microsoft-Windows-DNS-Client".to_string()]);
+                event.type_ = Some(vec![ECS_Event_Type::Protocol]);
+
+                let mut question = ECS_Dns_Question::new();
+                question.name = Some(vec![query_name.clone()]);
+                question.type_ = Some(vec![
+                    dns_type_name(*query_type).map_or_else(|| query_type.to_string(), String::from),
+                ]);
+
+                // The schema only holds a single resolved address
+                let mut dns = ECS_Dns::new();
+                dns.question = Some(question);
+                dns.resolved_ip = dns_resolved_ips(query_results).first().copied();
+                dns.type_ = Some(vec!["query".to_string()]);
+                ecs.dns = Some(dns);
+
+                labels.insert(
+                    "dns_query_status".to_string(),
+                    Value::from(query_status.to_string()),
+                );
+            }
         }
 
         ecs.event = Some(event);
+        ecs.labels = Some(labels);
 
         ecs
     }
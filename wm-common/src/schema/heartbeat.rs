@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// AMQP message type distinguishing heartbeats from trace events in the events queue.
+pub const HEARTBEAT_MESSAGE_KIND: &str = "heartbeat";
+
+/// Periodic liveness report of an agent.
+///
+/// Event counters are cumulative since the agent started.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Heartbeat {
+    pub agent_id: String,
+    pub version: String,
+    pub uptime_seconds: u64,
+    pub events_captured: u64,
+    pub events_sent: u64,
+    pub events_backed_up: u64,
+
+    /// Events discarded because the message queue was full
+    #[serde(default)]
+    pub events_dropped: u64,
+
+    /// Events discarded as repeats of recent identical events, e.g. image loads
+    #[serde(default)]
+    pub events_suppressed: u64,
+
+    /// Events discarded for being older than the maximum event age of the agent
+    #[serde(default)]
+    pub events_expired: u64,
+
+    /// Captured events per [`crate::schema::event::EventData`] variant
+    #[serde(default)]
+    pub events_by_variant: BTreeMap<String, u64>,
+
+    /// Requests not mirrored to a secondary server because it had too many requests in flight
+    #[serde(default)]
+    pub mirror_requests_dropped: u64,
+
+    /// Time since the oldest backup waiting for upload was last written to, if any
+    #[serde(default)]
+    pub oldest_backup_age_seconds: Option<u64>,
+
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Heartbeat {
+    /// Convert to the document stored in the heartbeat index, `ip` being the agent address as
+    /// seen by the API service.
+    pub fn to_document(&self, ip: IpAddr) -> serde_json::Value {
+        json!({
+            "@timestamp": self.timestamp,
+            "agent": {
+                "id": self.agent_id,
+                "version": self.version,
+                "uptime": self.uptime_seconds,
+            },
+            "host": {
+                "ip": ip,
+                "name": self.agent_id,
+            },
+            "events": {
+                "captured": self.events_captured,
+                "sent": self.events_sent,
+                "backed_up": self.events_backed_up,
+                "dropped": self.events_dropped,
+                "suppressed": self.events_suppressed,
+                "expired": self.events_expired,
+                "by_variant": self.events_by_variant,
+            },
+            "backup": {
+                "oldest_age": self.oldest_backup_age_seconds,
+            },
+            "mirror": {
+                "dropped": self.mirror_requests_dropped,
+            },
+        })
+    }
+}
@@ -1,5 +1,6 @@
 pub mod ecs_converter;
 pub mod event;
 pub mod github;
+pub mod heartbeat;
 pub mod responses;
 pub mod sysinfo;
@@ -25,25 +25,55 @@ pub struct MemoryInfo {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CPUInfo {
     pub usage: f64,
+
+    /// Usage of each logical processor, empty unless per-core reporting is enabled
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cores: Vec<f64>,
 }
 
 impl CPUInfo {
-    pub fn from_ckpt(before: &(u64, u64, u64), after: &(u64, u64, u64)) -> Self {
+    fn _usage(before: &(u64, u64, u64), after: &(u64, u64, u64)) -> f64 {
         let idle = after.0 - before.0;
         let kernel = after.1 - before.1;
         let user = after.2 - before.2;
         let total = kernel + user;
 
-        let usage = if total == 0 {
+        if total == 0 {
             0.0
         } else {
             (total - idle) as f64 * 100.0 / total as f64
-        };
+        }
+    }
+
+    pub fn from_ckpt(before: &(u64, u64, u64), after: &(u64, u64, u64)) -> Self {
+        Self {
+            usage: Self::_usage(before, after),
+            cores: vec![],
+        }
+    }
 
-        Self { usage }
+    /// Attach per-core usage computed from checkpoints of [`crate::sysinfo::get_processor_times`].
+    pub fn with_cores(mut self, before: &[(u64, u64, u64)], after: &[(u64, u64, u64)]) -> Self {
+        self.cores = before
+            .iter()
+            .zip(after)
+            .map(|(before, after)| Self::_usage(before, after))
+            .collect();
+        self
     }
 }
 
+/// Disk and network throughput since the previous sample.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct IOInfo {
+    pub disk_read_bytes: u64,
+    pub disk_written_bytes: u64,
+    pub network_received_bytes: u64,
+    pub network_received_packets: u64,
+    pub network_transmitted_bytes: u64,
+    pub network_transmitted_packets: u64,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct SystemInfo {
     #[serde(skip)]
@@ -52,6 +82,11 @@ pub struct SystemInfo {
     pub os: Arc<OSInfo>,
     pub memory: MemoryInfo,
     pub cpu: CPUInfo,
+
+    /// Only sampled if I/O metrics are enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub io: Option<IOInfo>,
+
     pub architecture: String,
     pub hostname: String,
 }
@@ -61,6 +96,7 @@ impl SystemInfo {
         os: Arc<OSInfo>,
         memory: MemoryInfo,
         cpu: CPUInfo,
+        io: Option<IOInfo>,
         architecture: String,
         hostname: String,
     ) -> Self {
@@ -69,6 +105,7 @@ impl SystemInfo {
             os,
             memory,
             cpu,
+            io,
             architecture,
             hostname,
         };
@@ -1,6 +1,12 @@
+use windows::Wdk::System::SystemInformation::{
+    NtQuerySystemInformation, SystemProcessorPerformanceInformation,
+};
 use windows::Win32::Foundation::FILETIME;
-use windows::Win32::System::SystemInformation::{GlobalMemoryStatusEx, MEMORYSTATUSEX};
+use windows::Win32::System::SystemInformation::{
+    GetSystemInfo, GlobalMemoryStatusEx, MEMORYSTATUSEX, SYSTEM_INFO,
+};
 use windows::Win32::System::Threading::GetSystemTimes;
+use windows::Win32::System::WindowsProgramming::SYSTEM_PROCESSOR_PERFORMANCE_INFORMATION;
 
 use crate::error::WindowsError;
 use crate::schema::sysinfo::MemoryInfo;
@@ -31,6 +37,33 @@ pub fn get_system_times() -> Result<(u64, u64, u64), WindowsError> {
     ))
 }
 
+/// Same as [`get_system_times`], but for each logical processor of the current processor group.
+pub fn get_processor_times() -> Result<Vec<(u64, u64, u64)>, WindowsError> {
+    let mut system_info = SYSTEM_INFO::default();
+    unsafe { GetSystemInfo(&mut system_info) };
+
+    let mut buffer = vec![
+        SYSTEM_PROCESSOR_PERFORMANCE_INFORMATION::default();
+        system_info.dwNumberOfProcessors as usize
+    ];
+    let mut return_length = 0;
+    unsafe {
+        NtQuerySystemInformation(
+            SystemProcessorPerformanceInformation,
+            buffer.as_mut_ptr().cast(),
+            size_of_val(buffer.as_slice()) as u32,
+            &mut return_length,
+        )
+    }
+    .ok()?;
+
+    buffer.truncate(return_length as usize / size_of::<SYSTEM_PROCESSOR_PERFORMANCE_INFORMATION>());
+    Ok(buffer
+        .into_iter()
+        .map(|p| (p.IdleTime as u64, p.KernelTime as u64, p.UserTime as u64))
+        .collect())
+}
+
 pub fn memory_status() -> Result<MemoryInfo, WindowsError> {
     let mut status = MEMORYSTATUSEX {
         dwLength: size_of::<MEMORYSTATUSEX>() as u32,
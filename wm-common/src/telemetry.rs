@@ -0,0 +1,232 @@
+//! Optional OpenTelemetry span export.
+//!
+//! Without the `otel` feature, [`Telemetry`] and [`Span`] are zero-sized and every method
+//! compiles to nothing, so instrumented hot paths pay no cost.
+
+use std::error::Error;
+#[cfg(feature = "otel")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "otel")]
+use log::error;
+#[cfg(not(feature = "otel"))]
+use log::warn;
+#[cfg(feature = "otel")]
+use opentelemetry::KeyValue;
+#[cfg(feature = "otel")]
+use opentelemetry::global::{self, BoxedSpan};
+#[cfg(feature = "otel")]
+use opentelemetry::trace::{
+    Span as _, SpanContext, SpanId, TraceFlags, TraceId, TraceState, Tracer,
+};
+#[cfg(feature = "otel")]
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::Resource;
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::trace::SdkTracerProvider;
+
+#[cfg(feature = "otel")]
+const _TRACER_NAME: &str = "windows-monitor";
+
+/// Name of the message header carrying the W3C trace context of the span that published it.
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Parse a W3C `traceparent` value (`00-<trace id>-<span id>-<flags>`).
+#[cfg(feature = "otel")]
+fn _parse_traceparent(value: &str) -> Option<SpanContext> {
+    let mut parts = value.trim().split('-');
+    let (version, trace_id, span_id, flags) =
+        (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    if version != "00"
+        || trace_id.len() != 32
+        || span_id.len() != 16
+        || flags.len() != 2
+        || parts.next().is_some()
+    {
+        return None;
+    }
+
+    let context = SpanContext::new(
+        TraceId::from_hex(trace_id).ok()?,
+        SpanId::from_hex(span_id).ok()?,
+        TraceFlags::new(u8::from_str_radix(flags, 16).ok()?),
+        true,
+        TraceState::default(),
+    );
+    context.is_valid().then_some(context)
+}
+
+/// Handle of the span export pipeline, which should be shut down before exiting to flush
+/// pending spans.
+pub struct Telemetry {
+    #[cfg(feature = "otel")]
+    _provider: Option<SdkTracerProvider>,
+}
+
+impl Telemetry {
+    /// Start exporting spans to the OTLP/HTTP `endpoint`, or do nothing if it is `None`.
+    pub fn initialize(
+        service_name: &'static str,
+        endpoint: Option<&str>,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        #[cfg(feature = "otel")]
+        {
+            let provider = match endpoint {
+                Some(endpoint) => {
+                    let exporter = SpanExporter::builder()
+                        .with_http()
+                        .with_endpoint(endpoint)
+                        .build()?;
+                    let provider = SdkTracerProvider::builder()
+                        .with_batch_exporter(exporter)
+                        .with_resource(Resource::builder().with_service_name(service_name).build())
+                        .build();
+
+                    global::set_tracer_provider(provider.clone());
+                    Some(provider)
+                }
+                None => None,
+            };
+
+            Ok(Self {
+                _provider: provider,
+            })
+        }
+
+        #[cfg(not(feature = "otel"))]
+        {
+            if endpoint.is_some() {
+                warn!(
+                    "OTLP endpoint is configured but {service_name} was built without the \"otel\" feature"
+                );
+            }
+
+            Ok(Self {})
+        }
+    }
+
+    pub fn shutdown(&self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = &self._provider
+            && let Err(e) = provider.shutdown()
+        {
+            error!("Unable to shut down span exporter: {e}");
+        }
+    }
+}
+
+/// A span ended when dropped.
+///
+/// Pipeline stages interleaved within a single span (e.g. decompressing and publishing each
+/// event in turn) are attributed with [`Span::lap`], which accumulates the time elapsed since
+/// the previous lap into a `<stage>_ms` attribute.
+///
+/// Spans of different services are joined through [`Span::traceparent`], carried along with
+/// the published messages, and [`Span::link`] on the consuming side.
+pub struct Span {
+    #[cfg(feature = "otel")]
+    _inner: BoxedSpan,
+    #[cfg(feature = "otel")]
+    _last_lap: Instant,
+    #[cfg(feature = "otel")]
+    _laps: Vec<(&'static str, Duration)>,
+
+    /// Span most recently linked to, consecutive messages usually come from the same span
+    #[cfg(feature = "otel")]
+    _last_link: Option<SpanContext>,
+}
+
+#[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+impl Span {
+    #[inline]
+    pub fn start(name: &'static str) -> Self {
+        Self {
+            #[cfg(feature = "otel")]
+            _inner: global::tracer(_TRACER_NAME).start(name),
+            #[cfg(feature = "otel")]
+            _last_lap: Instant::now(),
+            #[cfg(feature = "otel")]
+            _laps: vec![],
+            #[cfg(feature = "otel")]
+            _last_link: None,
+        }
+    }
+
+    /// W3C trace context of this span, `None` if it is not recorded (e.g. export is disabled).
+    #[inline]
+    pub fn traceparent(&self) -> Option<String> {
+        #[cfg(feature = "otel")]
+        {
+            let context = self._inner.span_context();
+            context.is_valid().then(|| {
+                format!(
+                    "00-{}-{}-{:02x}",
+                    context.trace_id(),
+                    context.span_id(),
+                    context.trace_flags().to_u8()
+                )
+            })
+        }
+
+        #[cfg(not(feature = "otel"))]
+        {
+            None
+        }
+    }
+
+    /// Link this span to the span of another service identified by its W3C trace context, e.g.
+    /// a batch consumer to the spans that published each message. Invalid values are ignored.
+    #[inline]
+    pub fn link(&mut self, traceparent: &str) {
+        #[cfg(feature = "otel")]
+        if let Some(context) = _parse_traceparent(traceparent)
+            && self._last_link.as_ref() != Some(&context)
+        {
+            self._inner.add_link(context.clone(), vec![]);
+            self._last_link = Some(context);
+        }
+    }
+
+    #[inline]
+    pub fn set_i64(&mut self, key: &'static str, value: i64) {
+        #[cfg(feature = "otel")]
+        self._inner.set_attribute(KeyValue::new(key, value));
+    }
+
+    #[inline]
+    pub fn set_str(&mut self, key: &'static str, value: impl Into<String>) {
+        #[cfg(feature = "otel")]
+        self._inner.set_attribute(KeyValue::new(key, value.into()));
+    }
+
+    /// Attribute the time elapsed since the previous lap (or the start of the span) to `stage`.
+    #[inline]
+    pub fn lap(&mut self, stage: &'static str) {
+        #[cfg(feature = "otel")]
+        {
+            let now = Instant::now();
+            let elapsed = now - self._last_lap;
+            self._last_lap = now;
+
+            match self._laps.iter_mut().find(|(name, _)| *name == stage) {
+                Some((_, total)) => *total += elapsed,
+                None => self._laps.push((stage, elapsed)),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "otel")]
+impl Drop for Span {
+    fn drop(&mut self) {
+        for (stage, total) in self._laps.drain(..) {
+            self._inner.set_attribute(KeyValue::new(
+                format!("{stage}_ms"),
+                total.as_secs_f64() * 1000.0,
+            ));
+        }
+
+        self._inner.end();
+    }
+}
@@ -1,14 +1,18 @@
 use std::ffi::{CStr, CString, c_void};
+use std::path::PathBuf;
 use std::slice;
 use std::sync::LazyLock;
 
 use chrono::{DateTime, Duration, TimeZone, Utc};
-use windows::Win32::Foundation::{HLOCAL, LocalFree};
+use windows::Win32::Foundation::{CloseHandle, HLOCAL, LocalFree};
 use windows::Win32::Security::Authorization::ConvertStringSidToSidA;
 use windows::Win32::Security::PSID;
+use windows::Win32::System::Threading::{
+    OpenProcess, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION, QueryFullProcessImageNameW,
+};
 use windows::Win32::System::WindowsProgramming::{GetComputerNameA, MAX_COMPUTERNAME_LENGTH};
 use windows::Win32::UI::Shell::CommandLineToArgvW;
-use windows::core::{PCSTR, PCWSTR, PSTR};
+use windows::core::{PCSTR, PCWSTR, PSTR, PWSTR};
 
 use crate::error::WindowsError;
 use crate::ptr_guard::PtrGuard;
@@ -78,6 +82,37 @@ pub fn convert_sid(stringsid: &CStr) -> Result<PtrGuard<c_void>, WindowsError> {
     Ok(sid)
 }
 
+/// Resolve the full Win32 path of the executable image of a running process.
+pub fn process_image_path(process_id: u32) -> Result<PathBuf, WindowsError> {
+    let mut buffer = vec![0; 32768];
+    let mut size = buffer.len() as u32;
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, process_id)?;
+        let result = QueryFullProcessImageNameW(
+            handle,
+            PROCESS_NAME_WIN32,
+            PWSTR::from_raw(buffer.as_mut_ptr()),
+            &mut size,
+        );
+        let _ = CloseHandle(handle);
+        result?;
+    }
+
+    Ok(PathBuf::from(String::from_utf16_lossy(
+        &buffer[..size as usize],
+    )))
+}
+
+/// Convert an NT device path (e.g. `\Device\HarddiskVolume3\Windows\notepad.exe`, as reported
+/// by kernel ETW providers) to a path that can be opened with Win32 file APIs.
+pub fn device_path_to_win32(path: &str) -> PathBuf {
+    if path.starts_with(r"\Device\") {
+        PathBuf::from(format!(r"\\?\GLOBALROOT{path}"))
+    } else {
+        PathBuf::from(path)
+    }
+}
+
 pub fn to_c_string(s: String) -> CString {
     let bytes = s.into_bytes();
     unsafe { CString::from_vec_unchecked(bytes) }
@@ -0,0 +1,18 @@
+/// Detailed version of the calling crate: crate version, git commit, build time and target triple.
+///
+/// The calling crate's build script must set the `WM_GIT_HASH`, `WM_BUILD_TIMESTAMP` and
+/// `WM_BUILD_TARGET` environment variables, e.g. with `emit` of `wm-common/build_info.rs`.
+#[macro_export]
+macro_rules! version_info {
+    () => {
+        concat!(
+            env!("CARGO_PKG_VERSION"),
+            "\ncommit: ",
+            env!("WM_GIT_HASH"),
+            "\nbuilt: ",
+            env!("WM_BUILD_TIMESTAMP"),
+            "\ntarget: ",
+            env!("WM_BUILD_TARGET"),
+        )
+    };
+}
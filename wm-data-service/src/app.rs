@@ -3,13 +3,18 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use futures_lite::stream::StreamExt;
-use lapin::options::{BasicConsumeOptions, BasicQosOptions, QueueDeclareOptions};
+use lapin::ExchangeKind;
+use lapin::options::{
+    BasicConsumeOptions, BasicQosOptions, ExchangeDeclareOptions, QueueBindOptions,
+    QueueDeclareOptions,
+};
 use lapin::types::FieldTable;
 use log::{error, info};
 use tokio::signal;
 use tokio::time::sleep;
 use wm_common::once_cell_no_retry::OnceCellNoRetry;
 
+use crate::backend::BulkBackend;
 use crate::configuration::Configuration;
 use crate::elastic::ElasticsearchWrapper;
 use crate::forwarder::MessageForwarder;
@@ -18,6 +23,7 @@ pub struct App {
     _config: Arc<Configuration>,
     _rabbitmq: OnceCellNoRetry<Arc<lapin::Channel>>,
     _elastic: OnceCellNoRetry<Arc<ElasticsearchWrapper>>,
+    _backend: Option<Arc<dyn BulkBackend>>,
 }
 
 impl App {
@@ -44,6 +50,29 @@ impl App {
                     auto_delete: false,
                     nowait: false,
                 },
+                self._config.rabbitmq.queue_arguments(),
+            )
+            .await?;
+
+        // Publishers go through a fanout exchange, so that `tail` can subscribe its own queue to
+        // the events without taking them away from the data service
+        rabbitmq
+            .exchange_declare(
+                "events",
+                ExchangeKind::Fanout,
+                ExchangeDeclareOptions {
+                    durable: true,
+                    ..ExchangeDeclareOptions::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+        rabbitmq
+            .queue_bind(
+                "events",
+                "events",
+                "",
+                QueueBindOptions::default(),
                 FieldTable::default(),
             )
             .await?;
@@ -53,17 +82,36 @@ impl App {
     }
 
     pub fn new(config: Arc<Configuration>) -> Result<Arc<Self>, Box<dyn Error + Send + Sync>> {
+        Self::_new(config, None)
+    }
+
+    /// Construct an application forwarding events to `backend` instead of Elasticsearch.
+    #[cfg(test)]
+    pub fn with_backend(
+        config: Arc<Configuration>,
+        backend: Arc<dyn BulkBackend>,
+    ) -> Result<Arc<Self>, Box<dyn Error + Send + Sync>> {
+        Self::_new(config, Some(backend))
+    }
+
+    fn _new(
+        config: Arc<Configuration>,
+        backend: Option<Arc<dyn BulkBackend>>,
+    ) -> Result<Arc<Self>, Box<dyn Error + Send + Sync>> {
         let this = Arc::new(Self {
             _config: config,
             _rabbitmq: OnceCellNoRetry::new(),
             _elastic: OnceCellNoRetry::new(),
+            _backend: backend,
         });
 
         // Try initializing Elasticsearch connection
-        let this_cloned = this.clone();
-        tokio::spawn(async move {
-            let _ = this_cloned.elastic().await;
-        });
+        if this._backend.is_none() {
+            let this_cloned = this.clone();
+            tokio::spawn(async move {
+                let _ = this_cloned.elastic().await;
+            });
+        }
 
         // Try initializing RabbitMQ connection
         let this_cloned = this.clone();
@@ -104,6 +152,17 @@ impl App {
             .cloned()
     }
 
+    /// Backend receiving forwarded events, which is Elasticsearch unless overridden.
+    pub async fn backend(&self) -> Option<Arc<dyn BulkBackend>> {
+        match &self._backend {
+            Some(backend) => Some(backend.clone()),
+            None => self
+                .elastic()
+                .await
+                .map(|elastic| elastic as Arc<dyn BulkBackend>),
+        }
+    }
+
     pub async fn run(self: &Arc<Self>) -> Result<(), Box<dyn Error + Send + Sync>> {
         let rabbitmq = tokio::select! {
             Some(rabbitmq) = self.rabbitmq() => Some(rabbitmq),
@@ -0,0 +1,385 @@
+#[cfg(test)]
+use std::collections::VecDeque;
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use elasticsearch::BulkParts;
+use log::error;
+#[cfg(test)]
+use tokio::sync::Mutex;
+use wm_common::error::ServiceError;
+
+use crate::elastic::ElasticsearchWrapper;
+
+/// Delay before retrying a request rejected by an overloaded cluster that did not specify one
+const _BUSY_DELAY: Duration = Duration::from_secs(1);
+
+/// Describe an Elasticsearch error object, e.g. `{"type": "...", "reason": "..."}`.
+fn _describe(error: &serde_json::Value) -> String {
+    format!(
+        "{}: {}",
+        error["type"].as_str().unwrap_or("unknown"),
+        error["reason"].as_str().unwrap_or_default()
+    )
+}
+
+/// Documents of a bulk request rejected for lack of capacity, to be sent again after `delay`.
+#[derive(Debug)]
+pub struct BulkRetry {
+    pub delay: Duration,
+
+    /// Bulk request body made of the action and document lines of the rejected documents
+    pub body: Vec<u8>,
+}
+
+/// Classify the response to a bulk `request`.
+///
+/// Elasticsearch answers `200 OK` even if some documents were rejected, so the per-item results
+/// are checked as well. Documents rejected for lack of capacity (`429 Too Many Requests`) are
+/// returned to be sent again, alone, since the others were created already. Documents rejected
+/// for any other reason (e.g. not matching the mapping) would be rejected again, and are only
+/// logged.
+fn _bulk_result(
+    status: u16,
+    retry_after: Option<Duration>,
+    response: &serde_json::Value,
+    request: &[u8],
+) -> Result<Option<BulkRetry>, ServiceError> {
+    let delay = retry_after.unwrap_or(_BUSY_DELAY);
+    if status == 429 || status == 503 {
+        return Err(ServiceError::Busy(delay));
+    }
+
+    if (400..500).contains(&status) {
+        return Err(ServiceError::rejected(format!(
+            "Bulk request rejected with status {status}, {}",
+            _describe(&response["error"])
+        )));
+    }
+
+    if !(200..300).contains(&status) {
+        return Err(ServiceError::backend(format!(
+            "Bulk request failed with status {status}, {}",
+            _describe(&response["error"])
+        )));
+    }
+
+    if response["errors"].as_bool() != Some(true) {
+        return Ok(None);
+    }
+
+    // Each item maps its action (e.g. `create`) to the result of the action, in the order of the
+    // action and document line pairs of the request
+    let items = response["items"].as_array().map_or(&[][..], Vec::as_slice);
+    let mut lines = request
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty());
+    let mut retry = vec![];
+    let mut rejected = vec![];
+    for item in items {
+        let (Some(action), Some(document)) = (lines.next(), lines.next()) else {
+            break;
+        };
+
+        let Some(result) = item.as_object().and_then(|item| item.values().next()) else {
+            continue;
+        };
+        if result.get("error").is_none() {
+            continue;
+        }
+
+        if result["status"].as_u64() == Some(429) {
+            for line in [action, document] {
+                retry.extend_from_slice(line);
+                retry.push(b'\n');
+            }
+        } else {
+            rejected.push(result);
+        }
+    }
+
+    if let Some(first) = rejected.first() {
+        error!(
+            "{} of {} documents rejected, first error {}",
+            rejected.len(),
+            items.len(),
+            _describe(&first["error"])
+        );
+    }
+
+    Ok((!retry.is_empty()).then(|| BulkRetry { delay, body: retry }))
+}
+
+/// Storage backend receiving the bulk requests built by the message forwarder.
+///
+/// This abstraction allows exercising the forwarding pipeline against [`RecordingBackend`]
+/// instead of a real Elasticsearch cluster.
+#[async_trait]
+pub trait BulkBackend: Send + Sync {
+    /// Send a bulk request `body` (newline-delimited action and document pairs) to `index`,
+    /// optionally processing documents with an ingest `pipeline`.
+    ///
+    /// Returns the documents to send again, if the backend lacked the capacity for some of them.
+    async fn bulk(
+        &self,
+        index: &str,
+        pipeline: Option<&str>,
+        body: Vec<u8>,
+    ) -> Result<Option<BulkRetry>, ServiceError>;
+}
+
+#[async_trait]
+impl BulkBackend for ElasticsearchWrapper {
+    async fn bulk(
+        &self,
+        index: &str,
+        pipeline: Option<&str>,
+        body: Vec<u8>,
+    ) -> Result<Option<BulkRetry>, ServiceError> {
+        // The body is kept to pick the documents to send again
+        let body = Bytes::from(body);
+        let mut request = self
+            .client()
+            .bulk(BulkParts::Index(index))
+            .refresh(self.refresh())
+            .body(vec![body.clone()]);
+        if let Some(pipeline) = pipeline {
+            request = request.pipeline(pipeline);
+        }
+
+        let response = request.send().await.map_err(ServiceError::network)?;
+        let status = response.status_code().as_u16();
+        let retry_after = response
+            .headers()
+            .get("retry-after")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok())
+            .map(Duration::from_secs);
+
+        // Error responses may not even be JSON, their status is enough to classify them
+        let response = response
+            .json::<serde_json::Value>()
+            .await
+            .unwrap_or_default();
+        _bulk_result(status, retry_after, &response, &body)
+    }
+}
+
+/// In-memory backend recording the documents it receives.
+#[cfg(test)]
+#[derive(Default)]
+pub struct RecordingBackend {
+    _documents: Mutex<Vec<(String, serde_json::Value)>>,
+    _requests: AtomicUsize,
+
+    /// Simulated status, `Retry-After` delay and body of the responses to the next bulk
+    /// requests, which succeed once these are exhausted
+    _responses: Mutex<VecDeque<(u16, Option<Duration>, serde_json::Value)>>,
+}
+
+#[cfg(test)]
+impl RecordingBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer the next bulk request with `response`, recording only the documents it accepts.
+    pub async fn push_response(
+        &self,
+        status: u16,
+        retry_after: Option<Duration>,
+        response: serde_json::Value,
+    ) {
+        self._responses
+            .lock()
+            .await
+            .push_back((status, retry_after, response));
+    }
+
+    /// Documents received so far, along with their target index.
+    pub async fn documents(&self) -> Vec<(String, serde_json::Value)> {
+        self._documents.lock().await.clone()
+    }
+
+    /// Number of bulk requests received so far.
+    pub fn requests(&self) -> usize {
+        self._requests.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl BulkBackend for RecordingBackend {
+    async fn bulk(
+        &self,
+        index: &str,
+        _: Option<&str>,
+        body: Vec<u8>,
+    ) -> Result<Option<BulkRetry>, ServiceError> {
+        self._requests.fetch_add(1, Ordering::Relaxed);
+
+        let (outcome, items) = match self._responses.lock().await.pop_front() {
+            Some((status, retry_after, response)) => (
+                _bulk_result(status, retry_after, &response, &body)?,
+                response["items"].as_array().cloned().unwrap_or_default(),
+            ),
+            None => (None, vec![]),
+        };
+
+        let mut documents = vec![];
+        let mut action_index = None;
+        for (i, line) in body
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .enumerate()
+        {
+            // Even lines are actions, odd lines are the documents themselves
+            if i % 2 == 0 {
+                let action = serde_json::from_slice::<serde_json::Value>(line)?;
+                action_index = action["create"]["_index"].as_str().map(str::to_string);
+            } else {
+                let index = action_index.take().unwrap_or_else(|| index.to_string());
+                let accepted = items
+                    .get(i / 2)
+                    .and_then(|item| item.as_object()?.values().next())
+                    .is_none_or(|result| result.get("error").is_none());
+                if accepted {
+                    documents.push((index, serde_json::from_slice(line)?));
+                }
+            }
+        }
+
+        self._documents.lock().await.extend(documents);
+        Ok(outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+    use wm_common::error::ServiceError;
+
+    use super::{_BUSY_DELAY, _bulk_result};
+
+    fn _item(status: u64, error: Option<&str>) -> serde_json::Value {
+        match error {
+            Some(kind) => json!({
+                "create": {
+                    "status": status,
+                    "error": { "type": kind, "reason": "rejected" },
+                },
+            }),
+            None => json!({ "create": { "status": status } }),
+        }
+    }
+
+    /// Bulk request body creating a document `{"n": i}` for each `i` in `0..count`.
+    fn _request(count: usize) -> Vec<u8> {
+        (0..count)
+            .flat_map(|i| format!("{{\"create\":{{}}}}\n{{\"n\":{i}}}\n").into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn accepts_successful_bulk_responses() {
+        let body = json!({ "errors": false, "items": [_item(201, None), _item(201, None)] });
+        assert!(
+            _bulk_result(200, None, &body, &_request(2))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn maps_overloaded_clusters_to_busy() {
+        for status in [429, 503] {
+            assert!(matches!(
+                _bulk_result(status, None, &serde_json::Value::Null, &_request(1)),
+                Err(ServiceError::Busy(delay)) if delay == _BUSY_DELAY
+            ));
+        }
+
+        assert!(matches!(
+            _bulk_result(503, Some(Duration::from_secs(30)), &serde_json::Value::Null, &_request(1)),
+            Err(ServiceError::Busy(delay)) if delay == Duration::from_secs(30)
+        ));
+    }
+
+    #[test]
+    fn resends_only_documents_rejected_for_capacity() {
+        // A full write thread pool rejects documents individually
+        let body = json!({
+            "errors": true,
+            "items": [
+                _item(201, None),
+                _item(429, Some("es_rejected_execution_exception")),
+                _item(400, Some("document_parsing_exception")),
+                _item(429, Some("es_rejected_execution_exception")),
+            ],
+        });
+
+        let retry = _bulk_result(200, Some(Duration::from_secs(5)), &body, &_request(4))
+            .unwrap()
+            .unwrap();
+        assert_eq!(retry.delay, Duration::from_secs(5));
+        assert_eq!(
+            retry.body,
+            b"{\"create\":{}}\n{\"n\":1}\n{\"create\":{}}\n{\"n\":3}\n"
+        );
+    }
+
+    #[test]
+    fn drops_documents_rejected_for_good() {
+        let body = json!({
+            "errors": true,
+            "items": [
+                _item(201, None),
+                _item(400, Some("document_parsing_exception")),
+                _item(400, Some("document_parsing_exception")),
+            ],
+        });
+        assert!(
+            _bulk_result(200, None, &body, &_request(3))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn maps_failed_requests_by_status() {
+        let body = json!({
+            "error": { "type": "index_closed_exception", "reason": "closed" },
+            "status": 400,
+        });
+        match _bulk_result(400, None, &body, &_request(1)) {
+            Err(error @ ServiceError::Rejected(_)) => {
+                assert!(!error.retryable());
+                assert!(
+                    error.to_string().contains("index_closed_exception"),
+                    "{error}"
+                );
+            }
+            result => panic!("Unexpected result {result:?}"),
+        }
+
+        let body = json!({
+            "error": { "type": "node_not_connected_exception", "reason": "disconnected" },
+            "status": 500,
+        });
+        match _bulk_result(500, None, &body, &_request(1)) {
+            Err(error @ ServiceError::Backend(_)) => {
+                assert!(error.retryable());
+                assert!(
+                    error.to_string().contains("node_not_connected_exception"),
+                    "{error}"
+                );
+            }
+            result => panic!("Unexpected result {result:?}"),
+        }
+    }
+}
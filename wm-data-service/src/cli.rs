@@ -1,10 +1,14 @@
+use std::path::PathBuf;
+
 use clap::{Parser, Subcommand, crate_description, crate_version};
+use wm_common::version_info;
 
 #[derive(Debug, Parser)]
 #[command(
     long_about = crate_description!(),
     propagate_version = true,
     version = crate_version!(),
+    long_version = version_info!(),
 )]
 pub struct Arguments {
     #[command(subcommand)]
@@ -22,4 +26,30 @@ pub enum ServiceAction {
 
     /// List ECS fields required by Elasticsearch detection rules
     RequiredFields,
+
+    /// Validate ECS documents produced from captured events against the events index template
+    ValidateEcs {
+        /// NDJSON file of captured event records, defaults to a sample event of each type
+        input: Option<PathBuf>,
+    },
+
+    /// Print a sample of live events as NDJSON ECS documents, without consuming them
+    Tail {
+        /// Stop after this many seconds
+        #[arg(long, default_value_t = 30)]
+        seconds: u64,
+
+        /// Stop after printing this many events
+        #[arg(long, default_value_t = 100)]
+        limit: u32,
+    },
+
+    /// Write a commented default configuration file
+    GenerateConfig {
+        /// Path to write the configuration to, defaults to stdout
+        output: Option<PathBuf>,
+    },
+
+    /// Print the version, git commit, build time and target triple
+    Version,
 }
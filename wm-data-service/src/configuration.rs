@@ -1,16 +1,118 @@
+use std::collections::HashMap;
+
+use lapin::types::{AMQPValue, FieldTable};
 use serde::{Deserialize, Serialize};
 use url::Url;
+use wm_common::config::{ConfigErrors, DefaultConfig};
 use wm_common::logger::LogLevel;
 
+use crate::elastic::EVENTS_INDEX;
+
 #[derive(Deserialize, Serialize)]
 pub struct ThroughputSettings {
     pub prefetch_count: u16,
     pub flush_limit: usize,
 }
 
+/// What the broker does with messages published to a full events queue.
+///
+/// The API service does not use publisher confirms, so either way the dropped events are lost
+/// without the agents being told.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum QueueOverflow {
+    /// Discard the oldest queued messages, keeping the most recent events
+    DropHead,
+
+    /// Discard newly published messages, keeping the oldest events
+    RejectPublish,
+}
+
+/// When documents written by a bulk request become searchable.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum BulkRefresh {
+    /// On the next periodic refresh (see `refresh_interval`), the fastest option
+    False,
+
+    /// Respond once the next periodic refresh made the documents searchable
+    WaitFor,
+
+    /// Refresh the affected shards immediately, expensive at high ingest rates
+    True,
+}
+
+/// Connection to RabbitMQ and limits of the events queue.
+///
+/// The API and data services both declare the queue, and RabbitMQ refuses a declaration whose
+/// limits differ from those of the existing queue, so both must be configured identically.
 #[derive(Deserialize, Serialize)]
 pub struct RabbitMQ {
     pub host: Url,
+
+    /// Messages queued for longer are discarded, never if not specified
+    pub message_ttl_ms: Option<u32>,
+
+    /// Maximum number of queued messages, unbounded if not specified
+    pub max_length: Option<u32>,
+
+    pub overflow: QueueOverflow,
+}
+
+impl RabbitMQ {
+    /// Arguments declaring the limits of the events queue.
+    pub fn queue_arguments(&self) -> FieldTable {
+        let mut arguments = FieldTable::default();
+        if let Some(ttl) = self.message_ttl_ms {
+            arguments.insert("x-message-ttl".into(), AMQPValue::LongLongInt(ttl.into()));
+        }
+        if let Some(length) = self.max_length {
+            arguments.insert("x-max-length".into(), AMQPValue::LongLongInt(length.into()));
+            arguments.insert(
+                "x-overflow".into(),
+                AMQPValue::LongString(
+                    match self.overflow {
+                        QueueOverflow::DropHead => "drop-head",
+                        QueueOverflow::RejectPublish => "reject-publish",
+                    }
+                    .into(),
+                ),
+            );
+        }
+
+        arguments
+    }
+}
+
+/// Phases of the ILM policy of the events indices, only applied to indices that roll over, i.e.
+/// data streams or indices behind a rollover alias.
+#[derive(Deserialize, Serialize)]
+pub struct LifecycleSettings {
+    pub warm_after_days: u32,
+    pub retention_days: u32,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct TemplateInstallSettings {
+    pub attempts: u32,
+    pub initial_backoff_seconds: u64,
+
+    /// Fail startup if the template cannot be installed, instead of continuing without it
+    pub strict: bool,
+}
+
+/// Rolling a plain events index over behind a write alias, for deployments without data streams.
+///
+/// The conditions make up the rollover action in the hot phase of the lifecycle policy, so that
+/// Elasticsearch checks them itself.
+#[derive(Deserialize, Serialize)]
+pub struct RolloverSettings {
+    pub enabled: bool,
+
+    /// Write alias receiving events, backing indices are named `<alias>-000001`, `<alias>-000002`...
+    pub alias: String,
+
+    pub max_age: Option<String>,
+    pub max_primary_shard_size: Option<String>,
+    pub max_docs: Option<u64>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -19,12 +121,316 @@ pub struct Elasticsearch {
     pub kibana: Url,
     pub username: String,
     pub password: String,
+
+    /// Write events to a data stream instead of a plain index
+    pub data_stream: bool,
+
+    /// Ingest pipeline processing events before they are indexed
+    pub pipeline: Option<String>,
+
+    /// Gzip request bodies, trading CPU time for bandwidth to the cluster
+    pub compress_requests: bool,
+
+    pub bulk_refresh: BulkRefresh,
+
+    /// Interval between periodic refreshes of the events index, e.g. `5s` or `-1` to disable
+    pub refresh_interval: String,
+
+    pub lifecycle: LifecycleSettings,
+    pub rollover: RolloverSettings,
+    pub template_install: TemplateInstallSettings,
+}
+
+impl Elasticsearch {
+    /// Index, data stream or write alias that events are written to.
+    pub fn events_index(&self) -> &str {
+        if self.rollover.enabled {
+            &self.rollover.alias
+        } else {
+            EVENTS_INDEX
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct Configuration {
     pub log_level: LogLevel,
+
+    /// Log levels of specific modules, overriding `log_level`
+    pub log_overrides: HashMap<String, LogLevel>,
+
+    /// OTLP/HTTP endpoint to export spans to, requires the `otel` feature
+    pub otlp_endpoint: Option<String>,
+
     pub throughput: ThroughputSettings,
     pub rabbitmq: RabbitMQ,
     pub elasticsearch: Elasticsearch,
 }
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Self {
+            log_level: LogLevel::Info,
+            log_overrides: HashMap::new(),
+            otlp_endpoint: None,
+            throughput: ThroughputSettings {
+                prefetch_count: 100,
+                flush_limit: 102400,
+            },
+            rabbitmq: RabbitMQ {
+                host: Url::parse("amqp://localhost:5672").expect("Invalid default RabbitMQ URL"),
+                message_ttl_ms: None,
+                max_length: None,
+                overflow: QueueOverflow::DropHead,
+            },
+            elasticsearch: Elasticsearch {
+                host: Url::parse("http://localhost:9200")
+                    .expect("Invalid default Elasticsearch URL"),
+                kibana: Url::parse("http://localhost:5601").expect("Invalid default Kibana URL"),
+                username: "elastic".to_string(),
+                password: "elastic-password".to_string(),
+                data_stream: true,
+                pipeline: None,
+                compress_requests: false,
+                bulk_refresh: BulkRefresh::False,
+                refresh_interval: "5s".to_string(),
+                lifecycle: LifecycleSettings {
+                    warm_after_days: 7,
+                    retention_days: 90,
+                },
+                rollover: RolloverSettings {
+                    enabled: false,
+                    alias: EVENTS_INDEX.to_string(),
+                    max_age: Some("1d".to_string()),
+                    max_primary_shard_size: Some("50gb".to_string()),
+                    max_docs: None,
+                },
+                template_install: TemplateInstallSettings {
+                    attempts: 5,
+                    initial_backoff_seconds: 1,
+                    strict: true,
+                },
+            },
+        }
+    }
+}
+
+impl DefaultConfig for Configuration {
+    const FIELD_DOCS: &'static [(&'static str, &'static str)] = &[
+        ("log_level", "One of Off, Error, Warn, Info, Debug, Trace"),
+        (
+            "log_overrides",
+            "Log levels of specific modules, e.g. wm_data_service::forwarder: Debug",
+        ),
+        (
+            "otlp_endpoint",
+            "OTLP/HTTP endpoint to export spans to, requires the otel feature",
+        ),
+        ("throughput", "Consuming messages from RabbitMQ"),
+        (
+            "throughput.prefetch_count",
+            "Maximum number of unacknowledged messages per consumer",
+        ),
+        (
+            "throughput.flush_limit",
+            "Size in bytes of buffered documents triggering a bulk request",
+        ),
+        ("rabbitmq", "Message queue receiving trace events"),
+        ("rabbitmq.host", "AMQP URL of the RabbitMQ broker"),
+        (
+            "rabbitmq.message_ttl_ms",
+            "Events queued for longer (e.g. during an Elasticsearch outage) are discarded, never if null. Must match the other service",
+        ),
+        (
+            "rabbitmq.max_length",
+            "Maximum number of queued events bounding broker memory, unbounded if null. Must match the other service",
+        ),
+        (
+            "rabbitmq.overflow",
+            "DropHead (lose the oldest events) or RejectPublish (lose the newest events) once max_length is reached. Must match the other service",
+        ),
+        ("elasticsearch", "Cluster storing processed events"),
+        ("elasticsearch.host", "URL of the Elasticsearch cluster"),
+        (
+            "elasticsearch.kibana",
+            "URL of Kibana, used to import detection rules",
+        ),
+        (
+            "elasticsearch.data_stream",
+            "Write events to a data stream instead of a plain index",
+        ),
+        (
+            "elasticsearch.pipeline",
+            "Ingest pipeline processing events before they are indexed, none if null",
+        ),
+        (
+            "elasticsearch.compress_requests",
+            "Gzip request bodies, usually shrinking bulk requests several times at the cost of CPU time. Worth it when the cluster is remote (e.g. in another availability zone)",
+        ),
+        (
+            "elasticsearch.bulk_refresh",
+            "False (documents become searchable on the next periodic refresh), WaitFor (bulk requests wait for that refresh, slowing down forwarding) or True (refresh after every bulk request, costly at high ingest rates)",
+        ),
+        (
+            "elasticsearch.refresh_interval",
+            "Interval between periodic refreshes of the events index, e.g. 5s. Longer intervals improve indexing throughput but delay searchability (and detection rules) by as much, -1 disables periodic refreshes",
+        ),
+        (
+            "elasticsearch.lifecycle",
+            "ILM policy of the events indices, only applied when data_stream or rollover.enabled is set since a single plain index would be deleted as a whole",
+        ),
+        (
+            "elasticsearch.lifecycle.warm_after_days",
+            "Age of an index before it is force-merged",
+        ),
+        (
+            "elasticsearch.lifecycle.retention_days",
+            "Age of an index before it is deleted",
+        ),
+        (
+            "elasticsearch.rollover",
+            "Rolling the plain events index over behind a write alias, requires data_stream: false",
+        ),
+        (
+            "elasticsearch.rollover.enabled",
+            "Write events to an alias whose backing index is rolled over by the lifecycle policy",
+        ),
+        (
+            "elasticsearch.rollover.alias",
+            "Name of the write alias, starting with events.windows-monitor-ecs so that detection rules search it",
+        ),
+        (
+            "elasticsearch.rollover.max_age",
+            "Age of the write index triggering a rollover (e.g. 1d), ignored if null",
+        ),
+        (
+            "elasticsearch.rollover.max_primary_shard_size",
+            "Size of the largest primary shard of the write index triggering a rollover (e.g. 50gb), ignored if null",
+        ),
+        (
+            "elasticsearch.rollover.max_docs",
+            "Number of documents in the write index triggering a rollover, ignored if null",
+        ),
+        (
+            "elasticsearch.template_install",
+            "Installing the events index template at startup",
+        ),
+        (
+            "elasticsearch.template_install.attempts",
+            "Number of attempts, the delay between them doubles up to 30s",
+        ),
+        (
+            "elasticsearch.template_install.initial_backoff_seconds",
+            "Delay before the second attempt",
+        ),
+        (
+            "elasticsearch.template_install.strict",
+            "Fail startup if every attempt fails, instead of indexing events with dynamic mappings",
+        ),
+    ];
+}
+
+impl Configuration {
+    /// Check value ranges and cross-field constraints that deserialization cannot express.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = ConfigErrors::new();
+
+        for module in self.log_overrides.keys() {
+            errors.require(
+                !module.is_empty() && !module.starts_with(':') && !module.ends_with(':'),
+                format!("log_overrides: invalid module path \"{module}\""),
+            );
+        }
+
+        if let Some(endpoint) = &self.otlp_endpoint
+            && let Err(e) = Url::parse(endpoint)
+        {
+            errors.push(format!("otlp_endpoint: {e}"));
+        }
+
+        errors.require(
+            self.throughput.prefetch_count > 0,
+            "throughput.prefetch_count: must be positive",
+        );
+        errors.require(
+            self.throughput.flush_limit > 0,
+            "throughput.flush_limit: must be positive",
+        );
+        errors.require(
+            matches!(self.rabbitmq.host.scheme(), "amqp" | "amqps"),
+            format!(
+                "rabbitmq.host: expected an AMQP URL, got {}",
+                self.rabbitmq.host
+            ),
+        );
+        errors.require(
+            self.rabbitmq.message_ttl_ms != Some(0),
+            "rabbitmq.message_ttl_ms: must be positive",
+        );
+        errors.require(
+            self.rabbitmq.max_length != Some(0),
+            "rabbitmq.max_length: must be positive",
+        );
+
+        let elasticsearch = &self.elasticsearch;
+        for (name, url) in [
+            ("elasticsearch.host", &elasticsearch.host),
+            ("elasticsearch.kibana", &elasticsearch.kibana),
+        ] {
+            errors.require(
+                matches!(url.scheme(), "http" | "https"),
+                format!("{name}: expected an HTTP(S) URL, got {url}"),
+            );
+        }
+
+        if let Some(pipeline) = &elasticsearch.pipeline {
+            errors.require(
+                !pipeline.is_empty(),
+                "elasticsearch.pipeline: must not be empty",
+            );
+        }
+
+        errors.require(
+            !elasticsearch.refresh_interval.is_empty(),
+            "elasticsearch.refresh_interval: must not be empty",
+        );
+
+        errors.require(
+            elasticsearch.lifecycle.retention_days > 0,
+            "elasticsearch.lifecycle.retention_days: must be positive",
+        );
+        errors.require(
+            elasticsearch.lifecycle.warm_after_days < elasticsearch.lifecycle.retention_days,
+            "elasticsearch.lifecycle.warm_after_days: must be less than retention_days",
+        );
+
+        let rollover = &elasticsearch.rollover;
+        if rollover.enabled {
+            errors.require(
+                !elasticsearch.data_stream,
+                "elasticsearch.rollover.enabled: data streams are rolled over by their lifecycle policy, set data_stream: false",
+            );
+            errors.require(
+                rollover.alias.starts_with(EVENTS_INDEX)
+                    && rollover.alias == rollover.alias.to_lowercase(),
+                format!(
+                    "elasticsearch.rollover.alias: expected a lowercase name starting with {EVENTS_INDEX}, got {}",
+                    rollover.alias
+                ),
+            );
+            errors.require(
+                rollover.max_age.is_some()
+                    || rollover.max_primary_shard_size.is_some()
+                    || rollover.max_docs.is_some(),
+                "elasticsearch.rollover: at least one of max_age, max_primary_shard_size and max_docs must be set",
+            );
+        }
+
+        errors.require(
+            elasticsearch.template_install.attempts > 0,
+            "elasticsearch.template_install.attempts: must be positive",
+        );
+
+        errors.into_result()
+    }
+}
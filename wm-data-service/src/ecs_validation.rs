@@ -0,0 +1,300 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::Utc;
+use serde_json::Value;
+use wm_common::error::RuntimeError;
+use wm_common::schema::event::{CapturedEventRecord, Event, EventData, EventUser};
+use wm_common::schema::sysinfo::{CPUInfo, IOInfo, MemoryInfo, OSInfo, SystemInfo};
+
+/// Whether `value` can be indexed into a leaf field of the given mapping type.
+fn _matches_type(field_type: &str, value: &Value) -> bool {
+    match field_type {
+        "keyword" | "constant_keyword" | "wildcard" | "match_only_text" | "text" => {
+            value.is_string()
+        }
+        "long" | "integer" | "short" | "byte" => value.is_i64() || value.is_u64(),
+        "unsigned_long" => value.is_u64(),
+        "double" | "float" | "half_float" | "scaled_float" => value.is_number(),
+        "boolean" => value.is_boolean(),
+        "date" => value.is_string() || value.is_number(),
+        "ip" => value.as_str().is_some_and(|s| s.parse::<IpAddr>().is_ok()),
+        "flattened" => value.is_object(),
+        // geo_point accepts strings, arrays and objects alike
+        _ => true,
+    }
+}
+
+fn _validate_field(path: &str, mapping: &Value, value: &Value, errors: &mut Vec<String>) {
+    match value {
+        Value::Null => {}
+        // Arrays of values are indexed as multiple values of the same field
+        Value::Array(items) => {
+            for item in items {
+                _validate_field(path, mapping, item, errors);
+            }
+        }
+        _ => {
+            let field_type = mapping["type"].as_str().unwrap_or("object");
+            if matches!(field_type, "object" | "nested") {
+                match value {
+                    Value::Object(_) => _validate_object(path, mapping, value, errors),
+                    _ => errors.push(format!("{path}: expected an object, got {value}")),
+                }
+            } else if !_matches_type(field_type, value) {
+                errors.push(format!("{path}: expected {field_type}, got {value}"));
+            }
+        }
+    }
+}
+
+fn _validate_object(path: &str, mapping: &Value, document: &Value, errors: &mut Vec<String>) {
+    // Fields of dynamic objects (e.g. labels) are mapped on the fly
+    if mapping["dynamic"] == Value::Bool(true) || mapping["dynamic"] == "true" {
+        return;
+    }
+
+    let Some(fields) = document.as_object() else {
+        return;
+    };
+    for (name, value) in fields {
+        let field_path = if path.is_empty() {
+            name.clone()
+        } else {
+            format!("{path}.{name}")
+        };
+
+        match mapping["properties"].get(name) {
+            Some(field_mapping) => _validate_field(&field_path, field_mapping, value, errors),
+            None if !value.is_null() => {
+                errors.push(format!("{field_path}: not mapped by the template"));
+            }
+            None => {}
+        }
+    }
+}
+
+/// Check `document` against the `mappings` section of an index template, returning a
+/// description of each field Elasticsearch would reject or map differently.
+pub fn validate_document(mappings: &Value, document: &Value) -> Vec<String> {
+    let mut errors = vec![];
+    _validate_object("", mappings, document, &mut errors);
+    errors
+}
+
+/// One captured event record of each [`EventData`] variant, with every optional field set so
+/// that the resulting documents cover as many mapped fields as possible.
+pub fn sample_records() -> Vec<CapturedEventRecord> {
+    let system = Arc::new(SystemInfo::new(
+        Arc::new(OSInfo {
+            full: "Windows 11 Pro 24H2".to_string(),
+            kernel: "26100".to_string(),
+            name: "Windows".to_string(),
+            platform: "windows".to_string(),
+            version: "11 (26100)".to_string(),
+        }),
+        MemoryInfo {
+            memory_load: 50,
+            total_physical: 17_179_869_184,
+            available_physical: 8_589_934_592,
+            total_page_file: 21_474_836_480,
+            available_page_file: 10_737_418_240,
+            total_virtual: 140_737_488_355_328,
+            available_virtual: 140_737_488_355_328,
+        },
+        CPUInfo {
+            usage: 12.5,
+            cores: vec![10.0, 15.0],
+        },
+        Some(IOInfo {
+            disk_read_bytes: 4096,
+            disk_written_bytes: 8192,
+            network_received_bytes: 1500,
+            network_received_packets: 1,
+            network_transmitted_bytes: 3000,
+            network_transmitted_packets: 2,
+        }),
+        "x86_64".to_string(),
+        "SAMPLE-HOST".to_string(),
+    ));
+
+    let sha256 = Some("0".repeat(64));
+    let samples = [
+        (
+            64,
+            EventData::FileCreate {
+                file_object: 0x1000,
+                options: 0x0100_0060,
+                attributes: 0x80,
+                share_access: 0x7,
+                open_path: r"C:\Users\sample\file.txt".to_string(),
+            },
+        ),
+        (
+            71,
+            EventData::FileInfo {
+                file_object: 0x1000,
+                extra_info: 0,
+                info_class: 10,
+                file_path: r"C:\Users\sample\file.txt".to_string(),
+            },
+        ),
+        (
+            67,
+            EventData::FileReadWrite {
+                offset: 0,
+                file_object: 0x1000,
+                size: 4096,
+                flags: 0,
+                file_path: r"C:\Users\sample\file.txt".to_string(),
+            },
+        ),
+        (
+            35,
+            EventData::FileDelete {
+                file_path: r"C:\Users\sample\file.txt".to_string(),
+            },
+        ),
+        (
+            10,
+            EventData::Image {
+                image_base: 0x7ff8_0000_0000,
+                image_size: 0x1000,
+                image_checksum: 0x1234,
+                file_name: r"\Device\HarddiskVolume3\Windows\System32\kernel32.dll".to_string(),
+                sha256: sha256.clone(),
+            },
+        ),
+        (
+            1,
+            EventData::Process {
+                unique_process_key: 0x2000,
+                process_id: 1234,
+                parent_id: 4321,
+                session_id: 1,
+                exit_status: 0,
+                directory_table_base: 0x3000,
+                image_file_name: "notepad.exe".to_string(),
+                command_line: r"C:\Windows\notepad.exe C:\Users\sample\file.txt".to_string(),
+                sha256,
+                command_line_sha256: Some(
+                    "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae".to_string(),
+                ),
+            },
+        ),
+        (
+            14,
+            EventData::Registry {
+                initial_time: 0,
+                status: 0,
+                index: 0,
+                key_handle: 0x4000,
+                key_name: r"\REGISTRY\MACHINE\SOFTWARE\Sample".to_string(),
+            },
+        ),
+        (
+            12,
+            EventData::TcpIp {
+                pid: 1234,
+                size: 0,
+                daddr: IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34)),
+                saddr: IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2)),
+                dport: 443,
+                sport: 50000,
+            },
+        ),
+        (
+            10,
+            EventData::UdpIp {
+                pid: 1234,
+                size: 64,
+                daddr: IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
+                saddr: IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2)),
+                dport: 53,
+                sport: 50001,
+            },
+        ),
+        (
+            0,
+            EventData::Dns {
+                query_name: "example.com".to_string(),
+                query_type: 1,
+                query_status: 0,
+                query_results: "type:  5 example.net;::ffff:93.184.216.34;".to_string(),
+            },
+        ),
+    ];
+
+    samples
+        .into_iter()
+        .map(|(opcode, data)| CapturedEventRecord {
+            event: Event {
+                guid: "00000000-0000-0000-0000-000000000000".to_string(),
+                raw_timestamp: 133_000_000_000_000_000,
+                process_id: 1234,
+                thread_id: 5678,
+                event_id: 0,
+                opcode,
+                data,
+                user: Some(EventUser {
+                    id: "S-1-5-18".to_string(),
+                    name: Some("SYSTEM".to_string()),
+                    domain: Some("NT AUTHORITY".to_string()),
+                }),
+            },
+            system: system.clone(),
+            captured: Utc::now(),
+        })
+        .collect()
+}
+
+/// Convert the captured event records in the NDJSON file at `input` (or [`sample_records`]) to
+/// ECS documents and validate them against the events index template, printing each mismatch.
+pub fn validate_template(input: Option<&Path>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let template =
+        serde_json::from_str::<Value>(include_str!("../../services/elastic/ecs-template.json"))?;
+
+    let records = match input {
+        Some(path) => {
+            let mut records = vec![];
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    records.push(serde_json::from_str::<CapturedEventRecord>(&line)?);
+                }
+            }
+
+            records
+        }
+        None => sample_records(),
+    };
+
+    let mut failed = 0;
+    for (index, record) in records.iter().enumerate() {
+        let document = serde_json::to_value(record.to_ecs(IpAddr::V4(Ipv4Addr::LOCALHOST)))?;
+        let errors = validate_document(&template["mappings"], &document);
+        let variant = record.event.data.variant_name();
+        if errors.is_empty() {
+            println!("#{index} {variant}: OK");
+        } else {
+            failed += 1;
+            println!("#{index} {variant}: {} mismatch(es)", errors.len());
+            for error in errors {
+                println!("    {error}");
+            }
+        }
+    }
+
+    if failed > 0 {
+        Err(RuntimeError::new(format!(
+            "{failed} of {} documents do not match the index template",
+            records.len()
+        )))?;
+    }
+
+    Ok(())
+}
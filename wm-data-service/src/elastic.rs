@@ -1,14 +1,115 @@
 use std::error::Error;
 use std::sync::Arc;
+use std::time::Duration;
 
 use elasticsearch::Elasticsearch;
 use elasticsearch::auth::Credentials;
+use elasticsearch::http::Url;
 use elasticsearch::http::response::Response;
-use elasticsearch::http::transport::Transport;
-use elasticsearch::indices::IndicesCreateParts;
-use log::{debug, warn};
+use elasticsearch::http::transport::{SingleNodeConnectionPool, TransportBuilder};
+use elasticsearch::ilm::IlmPutLifecycleParts;
+use elasticsearch::indices::{
+    IndicesCreateParts, IndicesExistsAliasParts, IndicesPutIndexTemplateParts,
+    IndicesPutMappingParts,
+};
+use elasticsearch::params::Refresh;
+use log::{debug, error, warn};
+use serde_json::json;
+use tokio::time::sleep;
+use wm_common::error::RuntimeError;
+use wm_common::schema::event::EventData;
 
-use crate::configuration::Configuration;
+use crate::configuration::{BulkRefresh, Configuration, RolloverSettings};
+
+/// Name of the index (or data stream) events are written to
+pub const EVENTS_INDEX: &str = "events.windows-monitor-ecs";
+
+/// Name of the index agent heartbeats are written to
+pub const HEARTBEATS_INDEX: &str = "heartbeats.windows-monitor";
+
+/// Name of the ILM policy managing [`EVENTS_INDEX`]
+pub const EVENTS_POLICY: &str = "events.windows-monitor-ecs-policy";
+
+/// Upper bound of the delay between attempts to install the index template
+const _MAX_TEMPLATE_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Conditions of the rollover action of the events indices behind a write alias.
+fn _rollover_conditions(settings: &RolloverSettings) -> serde_json::Value {
+    let mut conditions = json!({});
+    if let Some(max_age) = &settings.max_age {
+        conditions["max_age"] = json!(max_age);
+    }
+    if let Some(max_primary_shard_size) = &settings.max_primary_shard_size {
+        conditions["max_primary_shard_size"] = json!(max_primary_shard_size);
+    }
+    if let Some(max_docs) = settings.max_docs {
+        conditions["max_docs"] = json!(max_docs);
+    }
+
+    conditions
+}
+
+/// ILM policy of the events indices.
+///
+/// Indices roll over in the hot phase, so that the later phases (and deletion in particular) only
+/// ever apply to indices no longer written to.
+fn _events_policy(config: &Configuration) -> serde_json::Value {
+    let elasticsearch = &config.elasticsearch;
+    let mut hot_actions = json!({});
+    if elasticsearch.rollover.enabled {
+        hot_actions["rollover"] = _rollover_conditions(&elasticsearch.rollover);
+    } else if elasticsearch.data_stream {
+        hot_actions["rollover"] = json!({
+            "max_age": "1d",
+            "max_primary_shard_size": "50gb",
+        });
+    }
+
+    let lifecycle = &elasticsearch.lifecycle;
+    json!({
+        "policy": {
+            "phases": {
+                "hot": {
+                    "min_age": "0ms",
+                    "actions": hot_actions,
+                },
+                "warm": {
+                    "min_age": format!("{}d", lifecycle.warm_after_days),
+                    "actions": {
+                        "forcemerge": {
+                            "max_num_segments": 1,
+                        },
+                    },
+                },
+                "delete": {
+                    "min_age": format!("{}d", lifecycle.retention_days),
+                    "actions": {
+                        "delete": {},
+                    },
+                },
+            },
+        },
+    })
+}
+
+/// `index.lifecycle` settings of the events indices, [`None`] if they cannot roll over.
+///
+/// The phases apply to whole indices, so a single plain index that never rolls over would have
+/// all of its events deleted at once when it reaches the retention age.
+fn _lifecycle_settings(config: &Configuration) -> Option<serde_json::Value> {
+    let elasticsearch = &config.elasticsearch;
+    if elasticsearch.rollover.enabled {
+        // Indices behind an alias are rolled over through the alias, not by their own name
+        Some(json!({
+            "name": EVENTS_POLICY,
+            "rollover_alias": elasticsearch.rollover.alias,
+        }))
+    } else if elasticsearch.data_stream {
+        Some(json!({ "name": EVENTS_POLICY }))
+    } else {
+        None
+    }
+}
 
 async fn _log_error(r: Response) -> bool {
     if r.status_code().is_success() {
@@ -85,29 +186,117 @@ impl KibanaClient {
 pub struct ElasticsearchWrapper {
     _client: Elasticsearch,
     _kibana: KibanaClient,
+    _refresh: Refresh,
 }
 
 impl ElasticsearchWrapper {
     pub async fn async_new(
         config: Arc<Configuration>,
     ) -> Result<Arc<Self>, Box<dyn Error + Send + Sync>> {
-        let transport = Transport::single_node(config.elasticsearch.host.as_str())?;
-        transport.set_auth(Credentials::Basic(
-            config.elasticsearch.username.clone(),
-            config.elasticsearch.password.clone(),
-        ));
+        let pool = SingleNodeConnectionPool::new(Url::parse(config.elasticsearch.host.as_str())?);
+        let transport = TransportBuilder::new(pool)
+            .auth(Credentials::Basic(
+                config.elasticsearch.username.clone(),
+                config.elasticsearch.password.clone(),
+            ))
+            .request_body_compression(config.elasticsearch.compress_requests)
+            .build()?;
         let elastic = Self {
             _client: Elasticsearch::new(transport),
             _kibana: KibanaClient::new(config.clone()),
+            _refresh: match config.elasticsearch.bulk_refresh {
+                BulkRefresh::False => Refresh::False,
+                BulkRefresh::WaitFor => Refresh::WaitFor,
+                BulkRefresh::True => Refresh::True,
+            },
         };
 
+        let response = elastic
+            ._client
+            .ilm()
+            .put_lifecycle(IlmPutLifecycleParts::Policy(EVENTS_POLICY))
+            .body(_events_policy(&config))
+            .send()
+            .await?;
+        _log_error(response).await;
+
+        let mut template = serde_json::from_str::<serde_json::Value>(include_str!(
+            "../../services/elastic/ecs-template.json"
+        ))?;
+
+        if let Some(lifecycle) = _lifecycle_settings(&config) {
+            template["settings"]["index"]["lifecycle"] = lifecycle;
+        } else {
+            warn!(
+                "{EVENTS_INDEX} cannot roll over, events are kept regardless of elasticsearch.lifecycle"
+            );
+        }
+
+        template["settings"]["index"]["refresh_interval"] =
+            json!(config.elasticsearch.refresh_interval);
+
+        elastic._install_template(&config, &template).await?;
+
+        let by_variant = EventData::VARIANT_NAMES
+            .iter()
+            .map(|name| (name.to_string(), json!({ "type": "long" })))
+            .collect::<serde_json::Map<_, _>>();
+        let heartbeat_mappings = json!({
+            "dynamic": "strict",
+            "properties": {
+                "@timestamp": { "type": "date" },
+                "agent": {
+                    "properties": {
+                        "id": { "type": "keyword" },
+                        "version": { "type": "keyword" },
+                        "uptime": { "type": "long" },
+                    },
+                },
+                "host": {
+                    "properties": {
+                        "ip": { "type": "ip" },
+                        "name": { "type": "keyword" },
+                    },
+                },
+                "events": {
+                    "properties": {
+                        "captured": { "type": "long" },
+                        "sent": { "type": "long" },
+                        "backed_up": { "type": "long" },
+                        "dropped": { "type": "long" },
+                        "suppressed": { "type": "long" },
+                        "expired": { "type": "long" },
+                        "by_variant": { "properties": by_variant },
+                    },
+                },
+                "backup": {
+                    "properties": {
+                        "oldest_age": { "type": "long" },
+                    },
+                },
+                "mirror": {
+                    "properties": {
+                        "dropped": { "type": "long" },
+                    },
+                },
+            },
+        });
+
         let response = elastic
             ._client
             .indices()
-            .create(IndicesCreateParts::Index("events.windows-monitor-ecs"))
-            .body(serde_json::from_str::<serde_json::Value>(include_str!(
-                "../../services/elastic/ecs-template.json"
-            ))?)
+            .create(IndicesCreateParts::Index(HEARTBEATS_INDEX))
+            .body(json!({ "mappings": heartbeat_mappings }))
+            .send()
+            .await?;
+        _log_error(response).await;
+
+        // Add fields introduced since the index was created, existing fields are left unchanged
+        let response = elastic
+            ._client
+            .indices()
+            .put_mapping(IndicesPutMappingParts::Index(&[HEARTBEATS_INDEX]))
+            .body(heartbeat_mappings)
             .send()
             .await?;
         _log_error(response).await;
@@ -115,11 +304,224 @@ impl ElasticsearchWrapper {
         Ok(Arc::new(elastic))
     }
 
+    /// Put the index template of the indices behind the rollover alias, and create the first of
+    /// them unless the alias already exists.
+    async fn _put_rollover_template(
+        &self,
+        alias: &str,
+        template: &serde_json::Value,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let response = self
+            ._client
+            .indices()
+            .put_index_template(IndicesPutIndexTemplateParts::Name(alias))
+            .body(json!({
+                "index_patterns": [format!("{alias}-*")],
+                "template": template,
+            }))
+            .send()
+            .await?;
+
+        let status = response.status_code();
+        if !status.is_success() {
+            let text = response.text().await.unwrap_or_default();
+            Err(RuntimeError::new(format!("HTTP response {status}: {text}")))?;
+        }
+
+        // The first index may have been deleted by the lifecycle policy since, so check the alias
+        // rather than the index itself
+        let response = self
+            ._client
+            .indices()
+            .exists_alias(IndicesExistsAliasParts::Name(&[alias]))
+            .send()
+            .await?;
+        if response.status_code().is_success() {
+            return Ok(());
+        }
+
+        let mut aliases = serde_json::Map::new();
+        aliases.insert(alias.to_string(), json!({ "is_write_index": true }));
+        let response = self
+            ._client
+            .indices()
+            .create(IndicesCreateParts::Index(&format!("{alias}-000001")))
+            .body(json!({ "aliases": aliases }))
+            .send()
+            .await?;
+
+        let status = response.status_code();
+        if !status.is_success() {
+            let text = response.text().await.unwrap_or_default();
+            Err(RuntimeError::new(format!("HTTP response {status}: {text}")))?;
+        }
+
+        Ok(())
+    }
+
+    /// Put the events index template (or create the plain events index) once.
+    async fn _put_template(
+        &self,
+        config: &Configuration,
+        template: &serde_json::Value,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let data_stream = config.elasticsearch.data_stream;
+        if config.elasticsearch.rollover.enabled {
+            return self
+                ._put_rollover_template(&config.elasticsearch.rollover.alias, template)
+                .await;
+        }
+
+        let response = if data_stream {
+            // The data stream itself is created by the first `create` bulk operation
+            self._client
+                .indices()
+                .put_index_template(IndicesPutIndexTemplateParts::Name(EVENTS_INDEX))
+                .body(json!({
+                    "index_patterns": [EVENTS_INDEX],
+                    "data_stream": {},
+                    "template": template,
+                }))
+                .send()
+                .await?
+        } else {
+            self._client
+                .indices()
+                .create(IndicesCreateParts::Index(EVENTS_INDEX))
+                .body(template)
+                .send()
+                .await?
+        };
+
+        let status = response.status_code();
+        if status.is_success() {
+            return Ok(());
+        }
+
+        let text = response.text().await.unwrap_or_default();
+
+        // The plain index already exists on every restart
+        if !data_stream && text.contains("resource_already_exists_exception") {
+            return Ok(());
+        }
+
+        Err(RuntimeError::new(format!("HTTP response {status}: {text}")))?
+    }
+
+    /// Install the events index template, retrying with exponential backoff.
+    ///
+    /// Documents indexed without it get dynamic mappings, so in strict mode startup fails if
+    /// every attempt does.
+    async fn _install_template(
+        &self,
+        config: &Configuration,
+        template: &serde_json::Value,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let settings = &config.elasticsearch.template_install;
+        let mut backoff = Duration::from_secs(settings.initial_backoff_seconds);
+        for attempt in 1..=settings.attempts {
+            match self._put_template(config, template).await {
+                Ok(()) => {
+                    debug!("Installed index template of {EVENTS_INDEX}");
+                    return Ok(());
+                }
+                Err(e) => warn!(
+                    "Unable to install index template of {EVENTS_INDEX} (attempt {attempt}/{}): {e}",
+                    settings.attempts
+                ),
+            }
+
+            if attempt < settings.attempts {
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(_MAX_TEMPLATE_BACKOFF);
+            }
+        }
+
+        if settings.strict {
+            Err(RuntimeError::new(format!(
+                "Unable to install index template of {EVENTS_INDEX} after {} attempts",
+                settings.attempts
+            )))?;
+        }
+
+        error!(
+            "Continuing without the index template of {EVENTS_INDEX}, events will get dynamic mappings"
+        );
+        Ok(())
+    }
+
     pub fn client(&self) -> &Elasticsearch {
         &self._client
     }
 
+    /// Refresh behavior of bulk requests writing events.
+    pub fn refresh(&self) -> Refresh {
+        self._refresh
+    }
+
     pub fn kibana(&self) -> &KibanaClient {
         &self._kibana
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{_events_policy, _lifecycle_settings, EVENTS_POLICY};
+    use crate::configuration::Configuration;
+
+    #[test]
+    fn rolls_data_streams_over_in_hot_phase() {
+        let config = Configuration::default();
+        assert!(config.elasticsearch.data_stream);
+
+        let policy = _events_policy(&config);
+        assert_eq!(
+            policy["policy"]["phases"]["hot"]["actions"]["rollover"],
+            json!({ "max_age": "1d", "max_primary_shard_size": "50gb" })
+        );
+        assert_eq!(policy["policy"]["phases"]["warm"]["min_age"], "7d");
+        assert_eq!(policy["policy"]["phases"]["delete"]["min_age"], "90d");
+        assert_eq!(
+            _lifecycle_settings(&config),
+            Some(json!({ "name": EVENTS_POLICY }))
+        );
+    }
+
+    #[test]
+    fn rolls_write_alias_over_in_hot_phase() {
+        let mut config = Configuration::default();
+        config.elasticsearch.data_stream = false;
+        config.elasticsearch.rollover.enabled = true;
+        config.elasticsearch.rollover.alias = "events.windows-monitor-ecs-alias".to_string();
+        config.elasticsearch.rollover.max_age = Some("7d".to_string());
+        config.elasticsearch.rollover.max_primary_shard_size = None;
+        config.elasticsearch.rollover.max_docs = Some(1_000_000);
+        assert!(config.validate().is_ok());
+
+        assert_eq!(
+            _events_policy(&config)["policy"]["phases"]["hot"]["actions"]["rollover"],
+            json!({ "max_age": "7d", "max_docs": 1_000_000 })
+        );
+        assert_eq!(
+            _lifecycle_settings(&config),
+            Some(json!({
+                "name": EVENTS_POLICY,
+                "rollover_alias": "events.windows-monitor-ecs-alias",
+            }))
+        );
+    }
+
+    #[test]
+    fn leaves_plain_index_out_of_lifecycle() {
+        let mut config = Configuration::default();
+        config.elasticsearch.data_stream = false;
+
+        assert_eq!(
+            _events_policy(&config)["policy"]["phases"]["hot"]["actions"],
+            json!({})
+        );
+        assert_eq!(_lifecycle_settings(&config), None);
+    }
+}
@@ -1,15 +1,87 @@
-use std::mem;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::sync::{Arc, Weak};
+use std::time::Duration;
+use std::{mem, str};
 
-use elasticsearch::BulkParts;
+use lapin::BasicProperties;
 use lapin::acker::Acker;
 use lapin::message::Delivery;
 use lapin::options::{BasicAckOptions, BasicNackOptions};
-use log::{debug, error};
+use lapin::types::AMQPValue;
+use log::{debug, error, warn};
+use serde_json::json;
+use tokio::time::sleep;
+use wm_common::error::ServiceError;
 use wm_common::schema::event::CapturedEventRecord;
+use wm_common::schema::heartbeat::{HEARTBEAT_MESSAGE_KIND, Heartbeat};
+use wm_common::telemetry::{Span, TRACEPARENT_HEADER};
+use wm_generated::ecs::{ECS, ECS_Observer};
 
 use crate::app::App;
+use crate::backend::{BulkBackend, BulkRetry};
+use crate::elastic::HEARTBEATS_INDEX;
+
+/// Strip the client IP address appended by the API service from the end of a message body.
+///
+/// Returns [`None`] if the body is too short to contain an address.
+pub fn split_client_ip(data: &mut Vec<u8>) -> Option<IpAddr> {
+    if data.len() < 17 {
+        return None;
+    }
+
+    let is_ipv4 = data.pop()?;
+    let ip_native_order = u128::from_be_bytes(
+        data[data.len() - 16..]
+            .try_into()
+            .expect("Slice does not have 16 bytes"),
+    );
+    data.truncate(data.len() - 16);
+
+    Some(if is_ipv4 != 0 {
+        IpAddr::V4(Ipv4Addr::from(
+            u32::try_from(ip_native_order & 0xFFFFFFFF).expect("Cannot convert to u32"),
+        ))
+    } else {
+        IpAddr::V6(Ipv6Addr::from(ip_native_order))
+    })
+}
+
+/// Stamp the collector that received a message, if it is named, onto its ECS document.
+pub fn set_collector(ecs: &mut ECS, properties: &BasicProperties) {
+    if let Some(collector_id) = properties.app_id() {
+        let mut observer = ECS_Observer::new();
+        observer.name = Some(vec![collector_id.as_str().to_string()]);
+        ecs.observer = Some(observer);
+    }
+}
+
+/// W3C trace context of the span that published a message, if any.
+pub fn traceparent(properties: &BasicProperties) -> Option<&str> {
+    let (_, value) = properties
+        .headers()
+        .as_ref()?
+        .inner()
+        .iter()
+        .find(|(key, _)| key.as_str() == TRACEPARENT_HEADER)?;
+    match value {
+        AMQPValue::LongString(value) => str::from_utf8(value.as_bytes()).ok(),
+        _ => None,
+    }
+}
+
+/// Delay before sending rejected documents again after a failure that did not specify one
+const _RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How the messages of a bulk request are settled with RabbitMQ.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum _Settlement {
+    Ack,
+
+    /// Reject the messages, requeueing them unless processing them again cannot succeed
+    Nack {
+        requeue: bool,
+    },
+}
 
 /// Message forwarder transforms messages coming from RabbitMQ, construct
 /// an appropriate HTTP request and send it to Elasticsearch HTTP API.
@@ -17,6 +89,10 @@ pub struct MessageForwarder {
     _app: Weak<App>,
     _body: Vec<u8>,
     _acker: Option<Acker>,
+
+    /// Span covering the batch currently being accumulated
+    _span: Option<Span>,
+    _events_count: i64,
 }
 
 impl MessageForwarder {
@@ -25,6 +101,8 @@ impl MessageForwarder {
             _app: Arc::downgrade(app),
             _body: Vec::with_capacity(app.config().throughput.flush_limit * 3 / 2),
             _acker: None,
+            _span: None,
+            _events_count: 0,
         }
     }
 
@@ -37,13 +115,14 @@ impl MessageForwarder {
         }
     }
 
-    async fn _nack(&mut self) {
+    /// Reject the pending messages, requeueing them unless processing them again cannot succeed.
+    async fn _nack(&mut self, requeue: bool) {
         if let Some(acker) = self._acker.take() {
-            debug!("Sending NACK to RabbitMQ");
+            debug!("Sending NACK to RabbitMQ (requeue: {requeue})");
             if let Err(e) = acker
                 .nack(BasicNackOptions {
                     multiple: true,
-                    requeue: true,
+                    requeue,
                 })
                 .await
             {
@@ -52,83 +131,455 @@ impl MessageForwarder {
         }
     }
 
+    /// Append the document of a message to the pending bulk request, returning whether the
+    /// request reached the flush limit.
+    fn _append(&mut self, app: &App, mut data: Vec<u8>, properties: &BasicProperties) -> bool {
+        let span = self._span.get_or_insert_with(|| Span::start("forward"));
+        span.lap("consume");
+        if let Some(traceparent) = traceparent(properties) {
+            span.link(traceparent);
+        }
+
+        let Some(ip) = split_client_ip(&mut data) else {
+            return false;
+        };
+
+        let is_heartbeat = properties
+            .kind()
+            .as_ref()
+            .is_some_and(|kind| kind.as_str() == HEARTBEAT_MESSAGE_KIND);
+        let parsed = if is_heartbeat {
+            serde_json::from_slice::<Heartbeat>(&data).map(|heartbeat| {
+                // Heartbeats share the bulk request, but neither the index nor the ingest
+                // pipeline, of events
+                let action = json!({
+                    "create": {"_index": HEARTBEATS_INDEX, "pipeline": "_none"},
+                });
+                serde_json::to_writer(&mut self._body, &action).unwrap();
+                self._body.push(b'\n');
+                serde_json::to_writer(&mut self._body, &heartbeat.to_document(ip)).unwrap();
+            })
+        } else {
+            serde_json::from_slice::<CapturedEventRecord>(&data).map(|event| {
+                self._body.extend_from_slice(b"{\"create\":{}}\n");
+
+                let mut ecs = event.to_ecs(ip);
+                set_collector(&mut ecs, properties);
+                serde_json::to_writer(&mut self._body, &ecs).unwrap();
+            })
+        };
+
+        match parsed {
+            Ok(()) => {
+                self._body.push(b'\n');
+
+                span.lap("parse");
+                self._events_count += 1;
+
+                self._body.len() >= app.config().throughput.flush_limit
+            }
+            Err(e) => {
+                // Correlation id is the request id assigned by the API service
+                error!(
+                    "[{}] Invalid {} JSON: {e}",
+                    properties
+                        .correlation_id()
+                        .as_ref()
+                        .map_or("-", |id| id.as_str()),
+                    if is_heartbeat { "heartbeat" } else { "event" },
+                );
+                false
+            }
+        }
+    }
+
+    /// Send `body` to `backend` until no document is left to send again, returning how the
+    /// messages it was built from must be settled.
+    async fn _send(
+        app: &App,
+        backend: &dyn BulkBackend,
+        body: Vec<u8>,
+        span: &mut Span,
+    ) -> _Settlement {
+        let index = app.config().elasticsearch.events_index();
+        let pipeline = app.config().elasticsearch.pipeline.as_deref();
+
+        // Once part of the batch is created, redelivering the messages would duplicate it, so
+        // the remaining documents are sent again from here instead
+        let mut retry = match backend.bulk(index, pipeline, body).await {
+            Ok(None) => return _Settlement::Ack,
+            Ok(Some(retry)) => retry,
+            Err(ServiceError::Busy(delay)) => {
+                // Redelivering right away would only add to the load of the cluster
+                warn!(
+                    "Elasticsearch is overloaded, retrying in {} seconds",
+                    delay.as_secs_f64()
+                );
+                sleep(delay).await;
+                return _Settlement::Nack { requeue: true };
+            }
+            Err(e) => {
+                error!("Elasticsearch API error: {e}");
+                return _Settlement::Nack {
+                    requeue: e.retryable(),
+                };
+            }
+        };
+
+        loop {
+            span.lap("bulk");
+            warn!(
+                "Elasticsearch is overloaded, sending {} bytes of rejected documents again in {} seconds",
+                retry.body.len(),
+                retry.delay.as_secs_f64()
+            );
+            sleep(retry.delay).await;
+
+            retry = match backend.bulk(index, pipeline, retry.body.clone()).await {
+                Ok(None) => return _Settlement::Ack,
+                Ok(Some(next)) => next,
+                Err(e) if e.retryable() => {
+                    let delay = match &e {
+                        ServiceError::Busy(delay) => *delay,
+                        _ => _RETRY_DELAY,
+                    };
+                    warn!("Elasticsearch API error when sending rejected documents again: {e}");
+                    BulkRetry {
+                        delay,
+                        body: retry.body,
+                    }
+                }
+                Err(e) => {
+                    error!("Elasticsearch API error, rejected documents are lost: {e}");
+                    return _Settlement::Ack;
+                }
+            };
+        }
+    }
+
+    /// Send the pending bulk request to the backend, then acknowledge or reject the messages it
+    /// was built from.
+    async fn _flush(&mut self, app: &App) -> Option<_Settlement> {
+        if self._body.is_empty() {
+            return None;
+        }
+
+        let mut moved_body = Vec::with_capacity(self._body.capacity());
+        mem::swap(&mut moved_body, &mut self._body);
+
+        let mut span = self._span.take().unwrap_or_else(|| Span::start("forward"));
+        span.lap("consume");
+        span.set_i64("events", mem::take(&mut self._events_count));
+        span.set_i64("bytes", i64::try_from(moved_body.len()).unwrap_or(i64::MAX));
+
+        let settlement = match app.backend().await {
+            Some(backend) => Self::_send(app, backend.as_ref(), moved_body, &mut span).await,
+            None => _Settlement::Nack { requeue: true },
+        };
+        span.lap("bulk");
+
+        match settlement {
+            _Settlement::Ack => self._ack().await,
+            _Settlement::Nack { requeue } => self._nack(requeue).await,
+        }
+
+        Some(settlement)
+    }
+
     pub async fn process(&mut self, delivery: Option<Delivery>) {
         if let Some(app) = self._app.upgrade() {
-            let push_to_elastic = if let Some(delivery) = delivery {
-                let Delivery {
-                    mut data, acker, ..
-                } = delivery;
-                self._acker = Some(acker);
-
-                match data.pop() {
-                    Some(is_ipv4) => {
-                        let ip_native_order = u128::from_be_bytes(
-                            data[data.len() - 16..]
-                                .try_into()
-                                .expect("Slice does not have 16 bytes"),
-                        );
-                        data.truncate(data.len() - 16);
-                        let ip = if is_ipv4 != 0 {
-                            IpAddr::V4(Ipv4Addr::from(
-                                u32::try_from(ip_native_order & 0xFFFFFFFF)
-                                    .expect("Cannot convert to u32"),
-                            ))
-                        } else {
-                            IpAddr::V6(Ipv6Addr::from(ip_native_order))
-                        };
-
-                        match serde_json::from_slice::<CapturedEventRecord>(&data) {
-                            Ok(event) => {
-                                self._body.extend_from_slice(b"{\"create\":{}}\n");
-
-                                let ecs = event.to_ecs(ip);
-                                serde_json::to_writer(&mut self._body, &ecs).unwrap();
-                                self._body.push(b'\n');
-
-                                self._body.len() >= app.config().throughput.flush_limit
-                            }
-                            Err(e) => {
-                                error!("Invalid event JSON: {e}");
-                                false
-                            }
-                        }
-                    }
-                    None => false,
+            let push_to_elastic = match delivery {
+                Some(Delivery {
+                    data,
+                    acker,
+                    properties,
+                    ..
+                }) => {
+                    self._acker = Some(acker);
+                    self._append(&app, data, &properties)
                 }
-            } else {
+
                 // Push to Elasticsearch on timeout
-                true
+                None => true,
             };
 
-            if push_to_elastic && !self._body.is_empty() {
-                let app = app.clone();
-
-                let mut moved_body = Vec::with_capacity(self._body.capacity());
-                mem::swap(&mut moved_body, &mut self._body);
-
-                match app.elastic().await {
-                    Some(elastic) => {
-                        match elastic
-                            .client()
-                            .bulk(BulkParts::Index("events.windows-monitor-ecs"))
-                            .body(vec![moved_body])
-                            .send()
-                            .await
-                        {
-                            Ok(_) => {
-                                self._ack().await;
-                            }
-                            Err(e) => {
-                                error!("Elasticsearch API error: {e}");
-                                self._nack().await;
-                            }
-                        }
-                    }
-                    None => {
-                        self._nack().await;
-                    }
-                }
+            if push_to_elastic {
+                self._flush(&app).await;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use lapin::BasicProperties;
+    use serde_json::json;
+    use wm_common::schema::event::CapturedEventRecord;
+    use wm_common::schema::heartbeat::HEARTBEAT_MESSAGE_KIND;
+
+    use super::{_Settlement, MessageForwarder, split_client_ip};
+    use crate::app::App;
+    use crate::backend::RecordingBackend;
+    use crate::configuration::Configuration;
+    use crate::ecs_validation::sample_records;
+    use crate::elastic::HEARTBEATS_INDEX;
+
+    /// Message body as published by the API service, i.e. with the client address appended.
+    fn _message(payload: &[u8], ip: IpAddr) -> Vec<u8> {
+        let mut message = payload.to_vec();
+        let bits = match ip {
+            IpAddr::V4(ipv4) => u128::from(ipv4.to_bits()),
+            IpAddr::V6(ipv6) => ipv6.to_bits(),
+        };
+        message.extend_from_slice(&bits.to_be_bytes());
+        message.push(u8::from(ip.is_ipv4()));
+        message
+    }
+
+    fn _forwarder(config: Configuration) -> (Arc<App>, Arc<RecordingBackend>, MessageForwarder) {
+        let backend = Arc::new(RecordingBackend::new());
+        let app = App::with_backend(Arc::new(config), backend.clone()).unwrap();
+        let forwarder = MessageForwarder::new(&app);
+        (app, backend, forwarder)
+    }
+
+    const _CLIENT_IP: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7));
+
+    #[test]
+    fn splits_client_ip() {
+        let mut data = _message(b"{}", _CLIENT_IP);
+        assert_eq!(split_client_ip(&mut data), Some(_CLIENT_IP));
+        assert_eq!(data, b"{}");
+
+        let ipv6 = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+        let mut data = _message(b"{}", ipv6);
+        assert_eq!(split_client_ip(&mut data), Some(ipv6));
+        assert_eq!(data, b"{}");
+
+        assert_eq!(split_client_ip(&mut vec![0; 16]), None);
+    }
+
+    #[tokio::test]
+    async fn forwards_ecs_documents_of_each_event_type() {
+        let (app, backend, mut forwarder) = _forwarder(Configuration::default());
+
+        let payloads = sample_records()
+            .iter()
+            .map(CapturedEventRecord::serialize_to_vec)
+            .collect::<Vec<_>>();
+        for payload in &payloads {
+            let flush = forwarder._append(
+                &app,
+                _message(payload, _CLIENT_IP),
+                &BasicProperties::default(),
+            );
+            assert!(!flush);
+        }
+        forwarder._flush(&app).await;
+
+        let documents = backend.documents().await;
+        assert_eq!(documents.len(), payloads.len());
+        for ((index, document), payload) in documents.iter().zip(&payloads) {
+            let record = serde_json::from_slice::<CapturedEventRecord>(payload).unwrap();
+            assert_eq!(index, app.config().elasticsearch.events_index());
+            let expected = serde_json::to_vec(&record.to_ecs(_CLIENT_IP)).unwrap();
+            assert_eq!(
+                document,
+                &serde_json::from_slice::<serde_json::Value>(&expected).unwrap()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn forwards_heartbeats_to_their_index() {
+        let (app, backend, mut forwarder) = _forwarder(Configuration::default());
+
+        let heartbeat = json!({
+            "agent_id": "agent",
+            "version": "0.1.0",
+            "uptime_seconds": 60,
+            "events_captured": 10,
+            "events_sent": 8,
+            "events_backed_up": 2,
+            "timestamp": "2026-01-01T00:00:00Z",
+        });
+        forwarder._append(
+            &app,
+            _message(heartbeat.to_string().as_bytes(), _CLIENT_IP),
+            &BasicProperties::default().with_kind(HEARTBEAT_MESSAGE_KIND.into()),
+        );
+        forwarder._flush(&app).await;
+
+        let documents = backend.documents().await;
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0].0, HEARTBEATS_INDEX);
+    }
+
+    #[tokio::test]
+    async fn stamps_collector_of_events() {
+        let (app, backend, mut forwarder) = _forwarder(Configuration::default());
+
+        let payload = sample_records()[0].serialize_to_vec();
+        forwarder._append(
+            &app,
+            _message(&payload, _CLIENT_IP),
+            &BasicProperties::default().with_app_id("collector-1".into()),
+        );
+        forwarder._flush(&app).await;
+
+        let documents = backend.documents().await;
+        assert_eq!(documents[0].1["observer"]["name"], json!(["collector-1"]));
+    }
+
+    #[tokio::test]
+    async fn skips_invalid_messages() {
+        let (app, backend, mut forwarder) = _forwarder(Configuration::default());
+
+        let properties = BasicProperties::default();
+        forwarder._append(&app, _message(b"not json", _CLIENT_IP), &properties);
+        forwarder._append(&app, b"short".to_vec(), &properties);
+        forwarder._append(
+            &app,
+            _message(&sample_records()[0].serialize_to_vec(), _CLIENT_IP),
+            &properties,
+        );
+        forwarder._flush(&app).await;
+
+        assert_eq!(backend.documents().await.len(), 1);
+    }
+
+    /// Append the first `count` of `records`, in order, to the pending bulk request.
+    fn _append_records(
+        app: &App,
+        forwarder: &mut MessageForwarder,
+        records: &[CapturedEventRecord],
+        count: usize,
+    ) {
+        for record in &records[..count] {
+            forwarder._append(
+                app,
+                _message(&record.serialize_to_vec(), _CLIENT_IP),
+                &BasicProperties::default(),
+            );
+        }
+    }
+
+    fn _item(status: u64) -> serde_json::Value {
+        if status < 300 {
+            json!({ "create": { "status": status } })
+        } else {
+            json!({
+                "create": {
+                    "status": status,
+                    "error": { "type": "rejected", "reason": "rejected" },
+                },
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn waits_for_busy_backend_before_requeueing() {
+        const DELAY: Duration = Duration::from_millis(200);
+
+        let (app, backend, mut forwarder) = _forwarder(Configuration::default());
+        backend
+            .push_response(429, Some(DELAY), serde_json::Value::Null)
+            .await;
+        _append_records(&app, &mut forwarder, &sample_records(), 1);
+
+        let started = Instant::now();
+        let settlement = forwarder._flush(&app).await;
+        assert!(started.elapsed() >= DELAY);
+        assert_eq!(settlement, Some(_Settlement::Nack { requeue: true }));
+        assert!(backend.documents().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn does_not_requeue_rejected_documents() {
+        let (app, backend, mut forwarder) = _forwarder(Configuration::default());
+        backend
+            .push_response(
+                200,
+                None,
+                json!({ "errors": true, "items": [_item(201), _item(400)] }),
+            )
+            .await;
+        _append_records(&app, &mut forwarder, &sample_records(), 2);
+
+        assert_eq!(forwarder._flush(&app).await, Some(_Settlement::Ack));
+        assert_eq!(backend.requests(), 1);
+        assert_eq!(backend.documents().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_requeue_rejected_requests() {
+        let (app, backend, mut forwarder) = _forwarder(Configuration::default());
+        backend
+            .push_response(
+                400,
+                None,
+                json!({ "error": { "type": "parse_exception", "reason": "malformed" } }),
+            )
+            .await;
+        _append_records(&app, &mut forwarder, &sample_records(), 1);
+
+        assert_eq!(
+            forwarder._flush(&app).await,
+            Some(_Settlement::Nack { requeue: false })
+        );
+        assert_eq!(backend.requests(), 1);
+    }
+
+    #[tokio::test]
+    async fn resends_only_documents_rejected_for_capacity() {
+        let (app, backend, mut forwarder) = _forwarder(Configuration::default());
+        backend
+            .push_response(
+                200,
+                Some(Duration::from_millis(10)),
+                json!({ "errors": true, "items": [_item(201), _item(429), _item(201)] }),
+            )
+            .await;
+        let records = sample_records();
+        _append_records(&app, &mut forwarder, &records, 3);
+
+        assert_eq!(forwarder._flush(&app).await, Some(_Settlement::Ack));
+        assert_eq!(backend.requests(), 2);
+
+        // Accepted documents first, then the one sent again
+        let expected = [&records[0], &records[2], &records[1]]
+            .iter()
+            .map(|record| {
+                let record =
+                    serde_json::from_slice::<CapturedEventRecord>(&record.serialize_to_vec())
+                        .unwrap();
+                serde_json::to_value(record.to_ecs(_CLIENT_IP)).unwrap()
+            })
+            .collect::<Vec<_>>();
+        let documents = backend
+            .documents()
+            .await
+            .into_iter()
+            .map(|(_, document)| document)
+            .collect::<Vec<_>>();
+        assert_eq!(documents, expected);
+    }
+
+    #[tokio::test]
+    async fn flushes_at_flush_limit() {
+        let mut config = Configuration::default();
+        config.throughput.flush_limit = 1;
+        let (app, _, mut forwarder) = _forwarder(config);
+
+        let payload = sample_records()[0].serialize_to_vec();
+        assert!(forwarder._append(
+            &app,
+            _message(&payload, _CLIENT_IP),
+            &BasicProperties::default()
+        ));
+    }
+}
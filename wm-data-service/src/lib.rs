@@ -1,6 +1,9 @@
 pub mod app;
+pub mod backend;
 pub mod cli;
 pub mod configuration;
+pub mod ecs_validation;
 pub mod elastic;
 pub mod forwarder;
 pub mod rules;
+pub mod tail;
@@ -12,11 +12,13 @@ use log::{debug, error, info};
 use mimalloc::MiMalloc;
 use reqwest::multipart::{Form, Part};
 use tokio::fs;
-use wm_common::logger::initialize_logger;
+use wm_common::logger::{initialize_logger, install_panic_hook};
+use wm_common::telemetry::Telemetry;
+use wm_common::{config, version_info};
 use wm_data_service::app::App;
 use wm_data_service::cli::{Arguments, ServiceAction};
 use wm_data_service::configuration::Configuration;
-use wm_data_service::rules;
+use wm_data_service::{ecs_validation, rules, tail};
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
@@ -24,6 +26,16 @@ static GLOBAL: MiMalloc = MiMalloc;
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let arguments = Arguments::parse();
+    if let ServiceAction::GenerateConfig { output } = &arguments.command {
+        return config::write_default_config::<Configuration>(output.as_deref());
+    }
+    if let ServiceAction::ValidateEcs { input } = &arguments.command {
+        return ecs_validation::validate_template(input.as_deref());
+    }
+    if let ServiceAction::Version = arguments.command {
+        println!("{} {}", env!("CARGO_PKG_NAME"), version_info!());
+        return Ok(());
+    }
 
     let executable_path = env::current_exe().expect("Failed to get current executable path");
     let app_directory = executable_path
@@ -32,8 +44,16 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         .to_path_buf();
 
     let configuration = Arc::new(
-        Configuration::from_config_file(app_directory.join("data-service-config.yml"))
-            .expect("Failed to load configuration"),
+        Configuration::from_config_file(config::find_config_file(
+            &app_directory,
+            "data-service-config",
+        ))
+        .expect("Failed to load configuration"),
+    );
+    config::exit_if_invalid(
+        configuration.validate(),
+        &app_directory.join("logs"),
+        "wm-data-service",
     );
 
     let log_directory = app_directory.join("logs");
@@ -43,15 +63,22 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
 
     initialize_logger(
         configuration.log_level,
+        &configuration.log_overrides,
         File::create(log_directory.join(format!(
                 "wm-data-service-{}.log",
                 SystemTime::now()
                     .duration_since(UNIX_EPOCH)
                     .expect("Time went backwards").as_millis()
             )))?,
+        None,
     )?;
     debug!("Initialized logger");
 
+    install_panic_hook(|| {});
+
+    let telemetry =
+        Telemetry::initialize("wm-data-service", configuration.otlp_endpoint.as_deref())?;
+
     let app = App::new(configuration.clone()).expect("Failed to initialize application");
     match arguments.command {
         ServiceAction::Start => {
@@ -112,7 +139,16 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
                 info!("{field}");
             }
         }
+        ServiceAction::Tail { seconds, limit } => {
+            tail::tail_events(&app, seconds, limit).await?;
+        }
+        ServiceAction::GenerateConfig { .. }
+        | ServiceAction::ValidateEcs { .. }
+        | ServiceAction::Version => {
+            // Handled before loading the configuration
+        }
     }
 
+    telemetry.shutdown();
     Ok(())
 }
@@ -5,6 +5,8 @@ use reqwest::header::USER_AGENT;
 use serde_json::Value;
 use wm_common::schema::github::GitHubDirectoryEntry;
 
+use crate::elastic::EVENTS_INDEX;
+
 fn _extract_key(value: &mut Value, key: &str) -> Value {
     value
         .as_object_mut()
@@ -33,7 +35,9 @@ async fn _query_rule_toml(
     rule["rule_id"] = format!("custom-{old_rule_id}").into(); // Trick Kibana into thinking that this is not a prebuilt rule
     rule["references"] = references.into();
     rule["enabled"] = true.into();
-    rule["index"] = vec![".ds-events.windows-monitor-ecs-*"].into();
+    // Matches a plain index, a data stream (whose backing indices are resolved by name) and the
+    // indices behind a rollover alias
+    rule["index"] = vec![format!("{EVENTS_INDEX}*")].into();
 
     // Field transform (possible bug in elastic/detection-rules?)
     if let Some(mut new_terms) = rule["new_terms"].as_object_mut().cloned() {
@@ -0,0 +1,115 @@
+use std::error::Error;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use futures_lite::stream::StreamExt;
+use lapin::options::{BasicConsumeOptions, QueueBindOptions, QueueDeclareOptions};
+use lapin::types::{AMQPValue, FieldTable};
+use log::{info, warn};
+use tokio::time::{Instant, timeout_at};
+use wm_common::error::RuntimeError;
+use wm_common::schema::event::CapturedEventRecord;
+use wm_common::schema::heartbeat::{HEARTBEAT_MESSAGE_KIND, Heartbeat};
+
+use crate::app::App;
+use crate::forwarder::{set_collector, split_client_ip};
+
+/// Print live events to stdout as NDJSON ECS documents, for a quick look at what agents are sending.
+///
+/// A temporary queue is bound to the events exchange, so the data service keeps receiving every
+/// event. The queue holds at most `limit` messages and is deleted once this function returns.
+pub async fn tail_events(
+    app: &App,
+    seconds: u64,
+    limit: u32,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let Some(channel) = app.rabbitmq().await else {
+        Err(RuntimeError::new("Unable to connect to RabbitMQ"))?
+    };
+
+    let mut arguments = FieldTable::default();
+    arguments.insert("x-max-length".into(), AMQPValue::LongLongInt(limit.into()));
+    arguments.insert(
+        "x-overflow".into(),
+        AMQPValue::LongString("drop-head".into()),
+    );
+
+    let queue = channel
+        .queue_declare(
+            "",
+            QueueDeclareOptions {
+                exclusive: true,
+                auto_delete: true,
+                ..QueueDeclareOptions::default()
+            },
+            arguments,
+        )
+        .await?;
+    channel
+        .queue_bind(
+            queue.name().as_str(),
+            "events",
+            "",
+            QueueBindOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+
+    let mut consumer = channel
+        .basic_consume(
+            queue.name().as_str(),
+            "wm-data-service-tail",
+            BasicConsumeOptions {
+                no_ack: true,
+                exclusive: true,
+                ..BasicConsumeOptions::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+    info!("Sampling up to {limit} events for {seconds}s");
+
+    let deadline = Instant::now() + Duration::from_secs(seconds);
+    let mut stdout = io::stdout();
+    let mut printed = 0;
+    while printed < limit {
+        let delivery = match timeout_at(deadline, consumer.next()).await {
+            Ok(Some(delivery)) => delivery?,
+            Ok(None) | Err(_) => break,
+        };
+
+        let mut data = delivery.data;
+        let Some(ip) = split_client_ip(&mut data) else {
+            continue;
+        };
+
+        let is_heartbeat = delivery
+            .properties
+            .kind()
+            .as_ref()
+            .is_some_and(|kind| kind.as_str() == HEARTBEAT_MESSAGE_KIND);
+        let written = if is_heartbeat {
+            serde_json::from_slice::<Heartbeat>(&data).and_then(|heartbeat| {
+                serde_json::to_writer(&mut stdout, &heartbeat.to_document(ip))
+            })
+        } else {
+            serde_json::from_slice::<CapturedEventRecord>(&data).and_then(|event| {
+                let mut ecs = event.to_ecs(ip);
+                set_collector(&mut ecs, &delivery.properties);
+                serde_json::to_writer(&mut stdout, &ecs)
+            })
+        };
+
+        match written {
+            Ok(()) => {
+                writeln!(stdout)?;
+                printed += 1;
+            }
+            Err(e) => warn!("Skipping invalid message: {e}"),
+        }
+    }
+
+    stdout.flush()?;
+    info!("Printed {printed} events");
+    Ok(())
+}
@@ -1,5 +1,4 @@
 use std::collections::HashMap;
-use std::io::Write;
 use std::path::Path;
 use std::{env, fs};
 
@@ -46,10 +45,26 @@ fn key_to_qualifier(name: &str) -> String {
         .join("")
 }
 
+fn process_enum(enum_name: &str, values: &[String]) -> String {
+    let mut code = String::new();
+    code.push_str("#[allow(non_camel_case_types)]\n");
+    code.push_str("#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]\n");
+    code.push_str(&format!("pub enum {enum_name} {{\n"));
+
+    for value in values {
+        code.push_str(&format!("    #[serde(rename = \"{value}\")]\n"));
+        code.push_str(&format!("    {},\n", key_to_qualifier(value)));
+    }
+
+    code.push_str("}\n");
+    code
+}
+
 fn process_object(
     key: String,
     data: &serde_json::Value,
     rust_identifier: &Regex,
+    allowed_values: &HashMap<String, Vec<String>>,
     qualified_path: &mut Vec<String>,
 ) -> (String, String) {
     let mut append = VecPushGuard::new(qualified_path, key);
@@ -61,9 +76,16 @@ fn process_object(
         .join("_");
     // assert!(rust_identifier.is_match(&struct_name).unwrap());
 
+    // Objects without properties are either dynamic or disabled (`enabled: false`), their
+    // contents are arbitrary key-value pairs either way
     let properties = match data.get("properties") {
         Some(props) => props.as_object().unwrap(),
-        None => return ("serde_json::Value".to_string(), String::new()),
+        None => {
+            return (
+                "serde_json::Map<String, serde_json::Value>".to_string(),
+                String::new(),
+            );
+        }
     };
 
     let mut code = String::new();
@@ -100,29 +122,50 @@ fn process_object(
             "byte" => "i8".to_string(),
             "date" => "DateTime<Utc>".to_string(),
             "double" | "scaled_float" => "f64".to_string(),
-            "float" => "f32".to_string(),
-            "geo_point" => "(f64, f64)".to_string(),
-            "half_float" => "f16".to_string(),
+            "flattened" => "serde_json::Map<String, serde_json::Value>".to_string(),
+            // f16 is unstable, f32 represents every half-precision value exactly
+            "float" | "half_float" => "f32".to_string(),
+            "geo_point" => "GeoPoint".to_string(),
             "integer" => "i32".to_string(),
             "ip" => "IpAddr".to_string(),
-            "keyword" | "text" | "wildcard" => "Vec<String>".to_string(),
+            "keyword" | "constant_keyword" | "match_only_text" | "text" | "wildcard" => {
+                // Skip the root "ECS" qualifier to get the dotted path of the field
+                let mut path = append.collection()[1..].to_vec();
+                path.push(attribute.clone());
+
+                match allowed_values.get(&path.join(".")) {
+                    Some(values) => {
+                        let enum_name = format!("{struct_name}_{}", key_to_qualifier(&field_name));
+                        other_defines.push(process_enum(&enum_name, values));
+                        format!("Vec<{enum_name}>")
+                    }
+                    None => "Vec<String>".to_string(),
+                }
+            }
             "long" => "i64".to_string(),
             "short" => "i16".to_string(),
             "unsigned_long" => "u64".to_string(),
-            "object" => {
+            "nested" | "object" => {
                 let (nested_type, nested_code) = process_object(
                     field_name.clone(),
                     props,
                     rust_identifier,
+                    allowed_values,
                     append.collection_mut(),
                 );
 
                 if !nested_code.is_empty() {
                     other_defines.push(nested_code);
                 }
-                nested_type
+
+                // Nested fields hold arrays of objects indexed independently of each other
+                if elastic_type == "nested" {
+                    format!("Vec<{nested_type}>")
+                } else {
+                    nested_type
+                }
             }
-            _ => "serde_json::Value".to_string(),
+            _ => panic!("Unsupported type {elastic_type:?} of field {attribute:?}"),
         };
 
         if attribute == "@timestamp" {
@@ -191,33 +234,84 @@ fn main() {
         .join("ecs-template.json");
     println!("cargo:rerun-if-changed={}", source.display());
 
+    let allowed_values_source = workspace_dir
+        .join("services")
+        .join("elastic")
+        .join("ecs-allowed-values.json");
+    println!("cargo:rerun-if-changed={}", allowed_values_source.display());
+
     let input_file = fs::File::open(source).unwrap();
-    let mut output_file = fs::File::create(out_dir.join("ecs.rs")).unwrap();
 
-    output_file.write_all(b"use std::net::IpAddr;\n\n").unwrap();
-    output_file
-        .write_all(b"use chrono::{DateTime, Utc};\n")
-        .unwrap();
-    output_file
-        .write_all(b"use serde::{Deserialize, Serialize};\n\n")
-        .unwrap();
+    let mut code = String::new();
+    code.push_str("use std::net::IpAddr;\n\n");
+    code.push_str("use chrono::{DateTime, Utc};\n");
+    code.push_str("use serde::{Deserialize, Serialize};\n\n");
 
     let rust_identifier =
         Regex::new(r"^(?!(?:as|async|await|break|const|continue|crate|dyn|else|enum|extern|false|fn|for|if|impl|in|let|loop|match|mod|move|mut|pub|ref|return|self|Self|static|struct|super|trait|true|type|unsafe|use|where|while)$)[a-zA-Z_][a-zA-Z0-9_]*$")
             .unwrap();
     let data = serde_json::from_reader::<_, serde_json::Value>(input_file).unwrap();
 
+    // Elasticsearch rejects unknown mapping parameters, so the values allowed by ECS for
+    // constrained keyword fields are kept next to the template, keyed by dotted field path
+    let allowed_values = serde_json::from_reader::<_, serde_json::Value>(
+        fs::File::open(allowed_values_source).unwrap(),
+    )
+    .unwrap()
+    .as_object()
+    .unwrap()
+    .iter()
+    .map(|(path, field)| {
+        let values = field["allowed_values"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|value| value.as_str().unwrap().to_string())
+            .collect();
+        (path.clone(), values)
+    })
+    .collect::<HashMap<_, _>>();
+
+    // A tuple would serialize as [lat, lon], which Elasticsearch reads as [lon, lat]
+    code.push_str(
+        "/// A `geo_point` value, serialized in the unambiguous `{\"lat\": .., \"lon\": ..}` form.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct GeoPoint {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+",
+    );
+
     let mut qualified_path = vec![];
-    output_file
-        .write_all(
-            process_object(
-                "ECS".into(),
-                &data["mappings"],
-                &rust_identifier,
-                &mut qualified_path,
-            )
-            .1
-            .as_bytes(),
+    code.push_str(
+        &process_object(
+            "ECS".into(),
+            &data["mappings"],
+            &rust_identifier,
+            &allowed_values,
+            &mut qualified_path,
         )
-        .unwrap();
+        .1,
+    );
+
+    // Report malformed output here, rather than as confusing errors in the crates using it
+    if let Err(e) = syn::parse_file(&code) {
+        let start = e.span().start();
+        let line = code
+            .lines()
+            .nth(start.line.saturating_sub(1))
+            .unwrap_or_default();
+        panic!(
+            "Generated ECS code is malformed at line {}, column {}: {e}\n{line}",
+            start.line, start.column
+        );
+    }
+
+    // Leave unchanged output untouched, so that dependents are not rebuilt for nothing
+    let output = out_dir.join("ecs.rs");
+    if fs::read_to_string(&output).ok().as_deref() != Some(code.as_str()) {
+        fs::write(output, code).unwrap();
+    }
 }
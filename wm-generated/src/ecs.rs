@@ -1 +1,27 @@
 include!(concat!(env!("OUT_DIR"), "/ecs.rs"));
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{ECS_Destination_Geo, GeoPoint};
+
+    #[test]
+    fn serializes_geo_points_as_lat_lon_objects() {
+        // Paris, where a swapped [lat, lon] array would land in Somalia
+        let paris = GeoPoint {
+            lat: 48.8566,
+            lon: 2.3522,
+        };
+        let value = serde_json::to_value(paris).unwrap();
+        assert_eq!(value, json!({"lat": 48.8566, "lon": 2.3522}));
+        assert_eq!(serde_json::from_value::<GeoPoint>(value).unwrap(), paris);
+
+        let mut geo = ECS_Destination_Geo::new();
+        geo.location = Some(paris);
+        assert_eq!(
+            serde_json::to_value(&geo).unwrap(),
+            json!({"location": {"lat": 48.8566, "lon": 2.3522}})
+        );
+    }
+}